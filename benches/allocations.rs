@@ -0,0 +1,116 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+// Reports the heap allocations of operations which are benchmarked for time
+// elsewhere. It has its own target, as counting allocations slows down every
+// allocation of the binary.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::black_box;
+use prometheus::core::Collector;
+use prometheus::proto::MetricFamily;
+use prometheus::{CounterVec, Histogram, HistogramOpts, Opts, Registry};
+
+/// Counts allocations, so that benchmarks can report them.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+/// Returns the number of allocations `f` makes, taking the minimum over a
+/// few runs to skip one-off allocations, e.g. of lazily initialized statics.
+fn count_allocations<F: FnMut()>(mut f: F) -> usize {
+    (0..10)
+        .map(|_| {
+            let before = ALLOCATIONS.load(Ordering::Relaxed);
+            f();
+            ALLOCATIONS.load(Ordering::Relaxed) - before
+        })
+        .min()
+        .unwrap()
+}
+
+/// Prints the allocations of one call of a benchmarked operation.
+fn report(name: &str, allocations: usize) {
+    println!("{:<50} {:>8} allocations", name, allocations);
+}
+
+/// A registry with common labels and 10 collectors of 1000 series each, all
+/// merged into the same metric family.
+fn registry_with_merged_families() -> Registry {
+    let mut labels = HashMap::new();
+    labels.insert("instance".to_owned(), "localhost".to_owned());
+    let registry = Registry::new_custom(None, Some(labels)).unwrap();
+
+    for shard in 0..10 {
+        let counter = CounterVec::new(
+            Opts::new("benchmark_counter", "A counter to benchmark it.")
+                .const_label("shard", shard.to_string()),
+            &["one"],
+        )
+        .unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        for i in 0..1000 {
+            counter.with_label_values(&[&i.to_string()]).inc();
+        }
+    }
+    registry
+}
+
+fn registry_gather_merged() {
+    let registry = registry_with_merged_families();
+    report(
+        "registry_gather_merged_families",
+        count_allocations(|| drop(black_box(registry.gather()))),
+    );
+}
+
+fn histogram_collect() {
+    let histogram = Histogram::with_opts(HistogramOpts::new(
+        "benchmark_histogram",
+        "A histogram to benchmark it.",
+    ))
+    .unwrap();
+    histogram.observe(std::f64::consts::PI);
+
+    let collect = count_allocations(|| drop(black_box(histogram.collect())));
+    report("histogram_collect", collect);
+
+    let mut mf = MetricFamily::default();
+    let first = ALLOCATIONS.load(Ordering::Relaxed);
+    histogram.collect_into(&mut mf);
+    let first = ALLOCATIONS.load(Ordering::Relaxed) - first;
+    report("histogram_collect_into_first", first);
+    let collect_into = count_allocations(|| histogram.collect_into(black_box(&mut mf)));
+    report("histogram_collect_into", collect_into);
+
+    assert!(collect > 0);
+    // Only the first call allocates, the following ones refill the buffer.
+    assert!(first > 0);
+    assert_eq!(collect_into, 0);
+}
+
+fn main() {
+    registry_gather_merged();
+    histogram_collect();
+}
@@ -75,6 +75,35 @@ fn bench_int_counter_no_labels(c: &mut Criterion) {
     c.bench_function("int_counter_no_labels", |b| b.iter(|| counter.inc()));
 }
 
+fn bench_local_counter(c: &mut Criterion) {
+    let counter = Counter::new("benchmark_local_counter", "A local_counter to benchmark.").unwrap();
+    let local = counter.local();
+    c.bench_function("local_counter", |b| b.iter(|| local.inc()));
+    local.flush();
+}
+
+fn bench_local_int_counter(c: &mut Criterion) {
+    let counter = IntCounter::new(
+        "benchmark_local_int_counter",
+        "A local_int_counter to benchmark.",
+    )
+    .unwrap();
+    let local = counter.local();
+    c.bench_function("local_int_counter", |b| b.iter(|| local.inc()));
+    local.flush();
+}
+
+fn bench_flushable_local_counter(c: &mut Criterion) {
+    let counter = Counter::new(
+        "benchmark_flushable_local_counter",
+        "A flushable_local_counter to benchmark.",
+    )
+    .unwrap();
+    let local = counter.flushable_local();
+    c.bench_function("flushable_local_counter", |b| b.iter(|| local.inc()));
+    local.flush();
+}
+
 fn bench_counter_no_labels_concurrent_nop(c: &mut Criterion) {
     let signal_exit = Arc::new(atomic::AtomicBool::new(false));
     let counter = Counter::new("foo", "bar").unwrap();
@@ -195,5 +224,8 @@ criterion_group!(
     bench_counter_with_prepared_mapped_labels,
     bench_int_counter_no_labels,
     bench_int_counter_no_labels_concurrent_write,
+    bench_local_counter,
+    bench_flushable_local_counter,
+    bench_local_int_counter,
 );
 criterion_main!(benches);
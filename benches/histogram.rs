@@ -26,7 +26,7 @@ fn bench_histogram_with_label_values(c: &mut Criterion) {
         b.iter(|| {
             histogram
                 .with_label_values(&["eins", "zwei", "drei"])
-                .observe(3.1415)
+                .observe(std::f64::consts::PI)
         })
     });
 }
@@ -38,7 +38,7 @@ fn bench_histogram_no_labels(c: &mut Criterion) {
     ))
     .unwrap();
     c.bench_function("bench_histogram_no_labels", |b| {
-        b.iter(|| histogram.observe(3.1415))
+        b.iter(|| histogram.observe(std::f64::consts::PI))
     });
 }
 
@@ -60,7 +60,7 @@ fn bench_histogram_local(c: &mut Criterion) {
     .unwrap();
     let local = histogram.local();
     c.bench_function("bench_histogram_local", |b| {
-        b.iter(|| local.observe(3.1415));
+        b.iter(|| local.observe(std::f64::consts::PI));
     });
     local.flush();
 }
@@ -78,6 +78,33 @@ fn bench_local_histogram_timer(c: &mut Criterion) {
     local.flush();
 }
 
+fn bench_histogram_collect(c: &mut Criterion) {
+    let histogram = Histogram::with_opts(HistogramOpts::new(
+        "benchmark_histogram_collect",
+        "A histogram to benchmark it.",
+    ))
+    .unwrap();
+    histogram.observe(std::f64::consts::PI);
+    c.bench_function("bench_histogram_collect", |b| {
+        b.iter(|| histogram.collect())
+    });
+}
+
+fn bench_histogram_collect_into(c: &mut Criterion) {
+    let histogram = Histogram::with_opts(HistogramOpts::new(
+        "benchmark_histogram_collect_into",
+        "A histogram to benchmark it.",
+    ))
+    .unwrap();
+    histogram.observe(std::f64::consts::PI);
+    // Only the first call allocates, the following ones refill the buffer,
+    // which the allocations bench checks.
+    let mut mf = Default::default();
+    c.bench_function("bench_histogram_collect_into", |b| {
+        b.iter(|| histogram.collect_into(&mut mf))
+    });
+}
+
 fn concurrent_observe_and_collect(c: &mut Criterion) {
     let signal_exit = Arc::new(atomic::AtomicBool::new(false));
     let opts = HistogramOpts::new("test_name", "test help").buckets(vec![1.0]);
@@ -116,6 +143,8 @@ criterion_group!(
     bench_histogram_timer,
     bench_histogram_local,
     bench_local_histogram_timer,
+    bench_histogram_collect,
+    bench_histogram_collect_into,
     concurrent_observe_and_collect,
 );
 criterion_main!(benches);
@@ -0,0 +1,102 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::collections::HashMap;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use prometheus::core::{Labels, MetricValue, MetricVisitor};
+use prometheus::proto::MetricType;
+use prometheus::{CounterVec, Opts, Registry};
+
+/// Sums up all counter values, so that visited values are consumed.
+struct SumVisitor(f64);
+
+impl MetricVisitor for SumVisitor {
+    fn visit_family(&mut self, _: &str, _: &str, _: MetricType) {}
+
+    fn visit_metric(&mut self, _: Labels<'_>, value: MetricValue<'_>) {
+        if let MetricValue::Counter(v) = value {
+            self.0 += v;
+        }
+    }
+}
+
+fn registry_with_10k_series() -> Registry {
+    let registry = Registry::new();
+    let counter = CounterVec::new(
+        Opts::new("benchmark_counter", "A counter to benchmark it."),
+        &["one", "two"],
+    )
+    .unwrap();
+    registry.register(Box::new(counter.clone())).unwrap();
+
+    for i in 0..100 {
+        let i = i.to_string();
+        for j in 0..100 {
+            counter.with_label_values(&[&i, &j.to_string()]).inc();
+        }
+    }
+    registry
+}
+
+fn bench_registry_gather(c: &mut Criterion) {
+    let registry = registry_with_10k_series();
+    c.bench_function("registry_gather_10k_series", |b| {
+        b.iter(|| {
+            let sum: f64 = registry
+                .gather()
+                .iter()
+                .flat_map(|mf| mf.get_metric())
+                .map(|m| m.get_counter().get_value())
+                .sum();
+            criterion::black_box(sum)
+        })
+    });
+}
+
+/// A registry with common labels and 10 collectors of 1000 series each, all
+/// merged into the same metric family.
+fn registry_with_merged_families() -> Registry {
+    let mut labels = HashMap::new();
+    labels.insert("instance".to_owned(), "localhost".to_owned());
+    let registry = Registry::new_custom(None, Some(labels)).unwrap();
+
+    for shard in 0..10 {
+        let counter = CounterVec::new(
+            Opts::new("benchmark_counter", "A counter to benchmark it.")
+                .const_label("shard", shard.to_string()),
+            &["one"],
+        )
+        .unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        for i in 0..1000 {
+            counter.with_label_values(&[&i.to_string()]).inc();
+        }
+    }
+    registry
+}
+
+fn bench_registry_gather_merged(c: &mut Criterion) {
+    let registry = registry_with_merged_families();
+    c.bench_function("registry_gather_merged_families", |b| {
+        b.iter(|| criterion::black_box(registry.gather()))
+    });
+}
+
+fn bench_registry_visit(c: &mut Criterion) {
+    let registry = registry_with_10k_series();
+    c.bench_function("registry_visit_10k_series", |b| {
+        b.iter(|| {
+            let mut visitor = SumVisitor(0.0);
+            registry.visit(&mut visitor);
+            criterion::black_box(visitor.0)
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_registry_gather,
+    bench_registry_gather_merged,
+    bench_registry_visit
+);
+criterion_main!(benches);
@@ -1,11 +1,12 @@
-// This file is generated by rust-protobuf 2.2.5. Do not edit
+// This file is generated by rust-protobuf 2.28.0. Do not edit
 // @generated
 
-// https://github.com/Manishearth/rust-clippy/issues/702
+// https://github.com/rust-lang/rust-clippy/issues/702
 #![allow(unknown_lints)]
-#![allow(clippy)]
+#![allow(clippy::all)]
 
-#![cfg_attr(rustfmt, rustfmt_skip)]
+#![allow(unused_attributes)]
+#![cfg_attr(rustfmt, rustfmt::skip)]
 
 #![allow(box_pointers)]
 #![allow(dead_code)]
@@ -14,12 +15,13 @@
 #![allow(non_snake_case)]
 #![allow(non_upper_case_globals)]
 #![allow(trivial_casts)]
-#![allow(unsafe_code)]
 #![allow(unused_imports)]
 #![allow(unused_results)]
+//! Generated file from `proto_model.proto`
 
-use protobuf::Message as Message_imported_for_functions;
-use protobuf::ProtobufEnum as ProtobufEnum_imported_for_functions;
+/// Generated files are compatible only with the same version
+/// of protobuf runtime.
+// const _PROTOBUF_VERSION_CHECK: () = ::protobuf::VERSION_2_28_0;
 
 #[derive(PartialEq,Clone,Default)]
 pub struct LabelPair {
@@ -31,6 +33,12 @@ pub struct LabelPair {
     pub cached_size: ::protobuf::CachedSize,
 }
 
+impl<'a> ::std::default::Default for &'a LabelPair {
+    fn default() -> &'a LabelPair {
+        <LabelPair as ::protobuf::Message>::default_instance()
+    }
+}
+
 impl LabelPair {
     pub fn new() -> LabelPair {
         ::std::default::Default::default()
@@ -38,6 +46,13 @@ impl LabelPair {
 
     // optional string name = 1;
 
+
+    pub fn get_name(&self) -> &str {
+        match self.name.as_ref() {
+            Some(v) => &v,
+            None => "",
+        }
+    }
     pub fn clear_name(&mut self) {
         self.name.clear();
     }
@@ -65,15 +80,15 @@ impl LabelPair {
         self.name.take().unwrap_or_else(|| ::std::string::String::new())
     }
 
-    pub fn get_name(&self) -> &str {
-        match self.name.as_ref() {
+    // optional string value = 2;
+
+
+    pub fn get_value(&self) -> &str {
+        match self.value.as_ref() {
             Some(v) => &v,
             None => "",
         }
     }
-
-    // optional string value = 2;
-
     pub fn clear_value(&mut self) {
         self.value.clear();
     }
@@ -100,13 +115,6 @@ impl LabelPair {
     pub fn take_value(&mut self) -> ::std::string::String {
         self.value.take().unwrap_or_else(|| ::std::string::String::new())
     }
-
-    pub fn get_value(&self) -> &str {
-        match self.value.as_ref() {
-            Some(v) => &v,
-            None => "",
-        }
-    }
 }
 
 impl ::protobuf::Message for LabelPair {
@@ -170,13 +178,13 @@ impl ::protobuf::Message for LabelPair {
         &mut self.unknown_fields
     }
 
-    fn as_any(&self) -> &::std::any::Any {
-        self as &::std::any::Any
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
     }
-    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
-        self as &mut ::std::any::Any
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
     }
-    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+    fn into_any(self: ::std::boxed::Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
         self
     }
 
@@ -189,47 +197,37 @@ impl ::protobuf::Message for LabelPair {
     }
 
     fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
-        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
-        };
-        unsafe {
-            descriptor.get(|| {
-                let mut fields = ::std::vec::Vec::new();
-                fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
-                    "name",
-                    |m: &LabelPair| { &m.name },
-                    |m: &mut LabelPair| { &mut m.name },
-                ));
-                fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
-                    "value",
-                    |m: &LabelPair| { &m.value },
-                    |m: &mut LabelPair| { &mut m.value },
-                ));
-                ::protobuf::reflect::MessageDescriptor::new::<LabelPair>(
-                    "LabelPair",
-                    fields,
-                    file_descriptor_proto()
-                )
-            })
-        }
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::MessageDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            let mut fields = ::std::vec::Vec::new();
+            fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
+                "name",
+                |m: &LabelPair| { &m.name },
+                |m: &mut LabelPair| { &mut m.name },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
+                "value",
+                |m: &LabelPair| { &m.value },
+                |m: &mut LabelPair| { &mut m.value },
+            ));
+            ::protobuf::reflect::MessageDescriptor::new_pb_name::<LabelPair>(
+                "LabelPair",
+                fields,
+                file_descriptor_proto()
+            )
+        })
     }
 
     fn default_instance() -> &'static LabelPair {
-        static mut instance: ::protobuf::lazy::Lazy<LabelPair> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const LabelPair,
-        };
-        unsafe {
-            instance.get(LabelPair::new)
-        }
+        static instance: ::protobuf::rt::LazyV2<LabelPair> = ::protobuf::rt::LazyV2::INIT;
+        instance.get(LabelPair::new)
     }
 }
 
 impl ::protobuf::Clear for LabelPair {
     fn clear(&mut self) {
-        self.clear_name();
-        self.clear_value();
+        self.name.clear();
+        self.value.clear();
         self.unknown_fields.clear();
     }
 }
@@ -241,8 +239,8 @@ impl ::std::fmt::Debug for LabelPair {
 }
 
 impl ::protobuf::reflect::ProtobufValue for LabelPair {
-    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef<'_> {
-        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Message(self)
     }
 }
 
@@ -255,6 +253,12 @@ pub struct Gauge {
     pub cached_size: ::protobuf::CachedSize,
 }
 
+impl<'a> ::std::default::Default for &'a Gauge {
+    fn default() -> &'a Gauge {
+        <Gauge as ::protobuf::Message>::default_instance()
+    }
+}
+
 impl Gauge {
     pub fn new() -> Gauge {
         ::std::default::Default::default()
@@ -262,6 +266,10 @@ impl Gauge {
 
     // optional double value = 1;
 
+
+    pub fn get_value(&self) -> f64 {
+        self.value.unwrap_or(0.)
+    }
     pub fn clear_value(&mut self) {
         self.value = ::std::option::Option::None;
     }
@@ -274,10 +282,6 @@ impl Gauge {
     pub fn set_value(&mut self, v: f64) {
         self.value = ::std::option::Option::Some(v);
     }
-
-    pub fn get_value(&self) -> f64 {
-        self.value.unwrap_or(0.)
-    }
 }
 
 impl ::protobuf::Message for Gauge {
@@ -336,13 +340,13 @@ impl ::protobuf::Message for Gauge {
         &mut self.unknown_fields
     }
 
-    fn as_any(&self) -> &::std::any::Any {
-        self as &::std::any::Any
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
     }
-    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
-        self as &mut ::std::any::Any
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
     }
-    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+    fn into_any(self: ::std::boxed::Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
         self
     }
 
@@ -355,41 +359,31 @@ impl ::protobuf::Message for Gauge {
     }
 
     fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
-        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
-        };
-        unsafe {
-            descriptor.get(|| {
-                let mut fields = ::std::vec::Vec::new();
-                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
-                    "value",
-                    |m: &Gauge| { &m.value },
-                    |m: &mut Gauge| { &mut m.value },
-                ));
-                ::protobuf::reflect::MessageDescriptor::new::<Gauge>(
-                    "Gauge",
-                    fields,
-                    file_descriptor_proto()
-                )
-            })
-        }
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::MessageDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            let mut fields = ::std::vec::Vec::new();
+            fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
+                "value",
+                |m: &Gauge| { &m.value },
+                |m: &mut Gauge| { &mut m.value },
+            ));
+            ::protobuf::reflect::MessageDescriptor::new_pb_name::<Gauge>(
+                "Gauge",
+                fields,
+                file_descriptor_proto()
+            )
+        })
     }
 
     fn default_instance() -> &'static Gauge {
-        static mut instance: ::protobuf::lazy::Lazy<Gauge> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const Gauge,
-        };
-        unsafe {
-            instance.get(Gauge::new)
-        }
+        static instance: ::protobuf::rt::LazyV2<Gauge> = ::protobuf::rt::LazyV2::INIT;
+        instance.get(Gauge::new)
     }
 }
 
 impl ::protobuf::Clear for Gauge {
     fn clear(&mut self) {
-        self.clear_value();
+        self.value = ::std::option::Option::None;
         self.unknown_fields.clear();
     }
 }
@@ -401,8 +395,8 @@ impl ::std::fmt::Debug for Gauge {
 }
 
 impl ::protobuf::reflect::ProtobufValue for Gauge {
-    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef<'_> {
-        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Message(self)
     }
 }
 
@@ -410,11 +404,19 @@ impl ::protobuf::reflect::ProtobufValue for Gauge {
 pub struct Counter {
     // message fields
     value: ::std::option::Option<f64>,
+    pub exemplar: ::protobuf::SingularPtrField<Exemplar>,
+    pub created_timestamp: ::protobuf::SingularPtrField<::protobuf::well_known_types::Timestamp>,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
 }
 
+impl<'a> ::std::default::Default for &'a Counter {
+    fn default() -> &'a Counter {
+        <Counter as ::protobuf::Message>::default_instance()
+    }
+}
+
 impl Counter {
     pub fn new() -> Counter {
         ::std::default::Default::default()
@@ -422,6 +424,10 @@ impl Counter {
 
     // optional double value = 1;
 
+
+    pub fn get_value(&self) -> f64 {
+        self.value.unwrap_or(0.)
+    }
     pub fn clear_value(&mut self) {
         self.value = ::std::option::Option::None;
     }
@@ -435,13 +441,85 @@ impl Counter {
         self.value = ::std::option::Option::Some(v);
     }
 
-    pub fn get_value(&self) -> f64 {
-        self.value.unwrap_or(0.)
+    // optional .io.prometheus.client.Exemplar exemplar = 2;
+
+
+    pub fn get_exemplar(&self) -> &Exemplar {
+        self.exemplar.as_ref().unwrap_or_else(|| <Exemplar as ::protobuf::Message>::default_instance())
+    }
+    pub fn clear_exemplar(&mut self) {
+        self.exemplar.clear();
+    }
+
+    pub fn has_exemplar(&self) -> bool {
+        self.exemplar.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_exemplar(&mut self, v: Exemplar) {
+        self.exemplar = ::protobuf::SingularPtrField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_exemplar(&mut self) -> &mut Exemplar {
+        if self.exemplar.is_none() {
+            self.exemplar.set_default();
+        }
+        self.exemplar.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_exemplar(&mut self) -> Exemplar {
+        self.exemplar.take().unwrap_or_else(|| Exemplar::new())
+    }
+
+    // optional .google.protobuf.Timestamp created_timestamp = 3;
+
+
+    pub fn get_created_timestamp(&self) -> &::protobuf::well_known_types::Timestamp {
+        self.created_timestamp.as_ref().unwrap_or_else(|| <::protobuf::well_known_types::Timestamp as ::protobuf::Message>::default_instance())
+    }
+    pub fn clear_created_timestamp(&mut self) {
+        self.created_timestamp.clear();
+    }
+
+    pub fn has_created_timestamp(&self) -> bool {
+        self.created_timestamp.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_created_timestamp(&mut self, v: ::protobuf::well_known_types::Timestamp) {
+        self.created_timestamp = ::protobuf::SingularPtrField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_created_timestamp(&mut self) -> &mut ::protobuf::well_known_types::Timestamp {
+        if self.created_timestamp.is_none() {
+            self.created_timestamp.set_default();
+        }
+        self.created_timestamp.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_created_timestamp(&mut self) -> ::protobuf::well_known_types::Timestamp {
+        self.created_timestamp.take().unwrap_or_else(|| ::protobuf::well_known_types::Timestamp::new())
     }
 }
 
 impl ::protobuf::Message for Counter {
     fn is_initialized(&self) -> bool {
+        for v in &self.exemplar {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
+        for v in &self.created_timestamp {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
         true
     }
 
@@ -456,6 +534,12 @@ impl ::protobuf::Message for Counter {
                     let tmp = is.read_double()?;
                     self.value = ::std::option::Option::Some(tmp);
                 },
+                2 => {
+                    ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.exemplar)?;
+                },
+                3 => {
+                    ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.created_timestamp)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -471,6 +555,14 @@ impl ::protobuf::Message for Counter {
         if let Some(v) = self.value {
             my_size += 9;
         }
+        if let Some(ref v) = self.exemplar.as_ref() {
+            let len = v.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        }
+        if let Some(ref v) = self.created_timestamp.as_ref() {
+            let len = v.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -480,6 +572,16 @@ impl ::protobuf::Message for Counter {
         if let Some(v) = self.value {
             os.write_double(1, v)?;
         }
+        if let Some(ref v) = self.exemplar.as_ref() {
+            os.write_tag(2, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        }
+        if let Some(ref v) = self.created_timestamp.as_ref() {
+            os.write_tag(3, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -496,13 +598,13 @@ impl ::protobuf::Message for Counter {
         &mut self.unknown_fields
     }
 
-    fn as_any(&self) -> &::std::any::Any {
-        self as &::std::any::Any
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
     }
-    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
-        self as &mut ::std::any::Any
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
     }
-    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+    fn into_any(self: ::std::boxed::Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
         self
     }
 
@@ -515,41 +617,43 @@ impl ::protobuf::Message for Counter {
     }
 
     fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
-        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
-        };
-        unsafe {
-            descriptor.get(|| {
-                let mut fields = ::std::vec::Vec::new();
-                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
-                    "value",
-                    |m: &Counter| { &m.value },
-                    |m: &mut Counter| { &mut m.value },
-                ));
-                ::protobuf::reflect::MessageDescriptor::new::<Counter>(
-                    "Counter",
-                    fields,
-                    file_descriptor_proto()
-                )
-            })
-        }
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::MessageDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            let mut fields = ::std::vec::Vec::new();
+            fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
+                "value",
+                |m: &Counter| { &m.value },
+                |m: &mut Counter| { &mut m.value },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Exemplar>>(
+                "exemplar",
+                |m: &Counter| { &m.exemplar },
+                |m: &mut Counter| { &mut m.exemplar },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<::protobuf::well_known_types::Timestamp>>(
+                "created_timestamp",
+                |m: &Counter| { &m.created_timestamp },
+                |m: &mut Counter| { &mut m.created_timestamp },
+            ));
+            ::protobuf::reflect::MessageDescriptor::new_pb_name::<Counter>(
+                "Counter",
+                fields,
+                file_descriptor_proto()
+            )
+        })
     }
 
     fn default_instance() -> &'static Counter {
-        static mut instance: ::protobuf::lazy::Lazy<Counter> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const Counter,
-        };
-        unsafe {
-            instance.get(Counter::new)
-        }
+        static instance: ::protobuf::rt::LazyV2<Counter> = ::protobuf::rt::LazyV2::INIT;
+        instance.get(Counter::new)
     }
 }
 
 impl ::protobuf::Clear for Counter {
     fn clear(&mut self) {
-        self.clear_value();
+        self.value = ::std::option::Option::None;
+        self.exemplar.clear();
+        self.created_timestamp.clear();
         self.unknown_fields.clear();
     }
 }
@@ -561,8 +665,8 @@ impl ::std::fmt::Debug for Counter {
 }
 
 impl ::protobuf::reflect::ProtobufValue for Counter {
-    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef<'_> {
-        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Message(self)
     }
 }
 
@@ -576,6 +680,12 @@ pub struct Quantile {
     pub cached_size: ::protobuf::CachedSize,
 }
 
+impl<'a> ::std::default::Default for &'a Quantile {
+    fn default() -> &'a Quantile {
+        <Quantile as ::protobuf::Message>::default_instance()
+    }
+}
+
 impl Quantile {
     pub fn new() -> Quantile {
         ::std::default::Default::default()
@@ -583,6 +693,10 @@ impl Quantile {
 
     // optional double quantile = 1;
 
+
+    pub fn get_quantile(&self) -> f64 {
+        self.quantile.unwrap_or(0.)
+    }
     pub fn clear_quantile(&mut self) {
         self.quantile = ::std::option::Option::None;
     }
@@ -596,12 +710,12 @@ impl Quantile {
         self.quantile = ::std::option::Option::Some(v);
     }
 
-    pub fn get_quantile(&self) -> f64 {
-        self.quantile.unwrap_or(0.)
-    }
-
     // optional double value = 2;
 
+
+    pub fn get_value(&self) -> f64 {
+        self.value.unwrap_or(0.)
+    }
     pub fn clear_value(&mut self) {
         self.value = ::std::option::Option::None;
     }
@@ -614,10 +728,6 @@ impl Quantile {
     pub fn set_value(&mut self, v: f64) {
         self.value = ::std::option::Option::Some(v);
     }
-
-    pub fn get_value(&self) -> f64 {
-        self.value.unwrap_or(0.)
-    }
 }
 
 impl ::protobuf::Message for Quantile {
@@ -689,13 +799,13 @@ impl ::protobuf::Message for Quantile {
         &mut self.unknown_fields
     }
 
-    fn as_any(&self) -> &::std::any::Any {
-        self as &::std::any::Any
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
     }
-    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
-        self as &mut ::std::any::Any
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
     }
-    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+    fn into_any(self: ::std::boxed::Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
         self
     }
 
@@ -708,47 +818,37 @@ impl ::protobuf::Message for Quantile {
     }
 
     fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
-        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
-        };
-        unsafe {
-            descriptor.get(|| {
-                let mut fields = ::std::vec::Vec::new();
-                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
-                    "quantile",
-                    |m: &Quantile| { &m.quantile },
-                    |m: &mut Quantile| { &mut m.quantile },
-                ));
-                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
-                    "value",
-                    |m: &Quantile| { &m.value },
-                    |m: &mut Quantile| { &mut m.value },
-                ));
-                ::protobuf::reflect::MessageDescriptor::new::<Quantile>(
-                    "Quantile",
-                    fields,
-                    file_descriptor_proto()
-                )
-            })
-        }
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::MessageDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            let mut fields = ::std::vec::Vec::new();
+            fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
+                "quantile",
+                |m: &Quantile| { &m.quantile },
+                |m: &mut Quantile| { &mut m.quantile },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
+                "value",
+                |m: &Quantile| { &m.value },
+                |m: &mut Quantile| { &mut m.value },
+            ));
+            ::protobuf::reflect::MessageDescriptor::new_pb_name::<Quantile>(
+                "Quantile",
+                fields,
+                file_descriptor_proto()
+            )
+        })
     }
 
     fn default_instance() -> &'static Quantile {
-        static mut instance: ::protobuf::lazy::Lazy<Quantile> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const Quantile,
-        };
-        unsafe {
-            instance.get(Quantile::new)
-        }
+        static instance: ::protobuf::rt::LazyV2<Quantile> = ::protobuf::rt::LazyV2::INIT;
+        instance.get(Quantile::new)
     }
 }
 
 impl ::protobuf::Clear for Quantile {
     fn clear(&mut self) {
-        self.clear_quantile();
-        self.clear_value();
+        self.quantile = ::std::option::Option::None;
+        self.value = ::std::option::Option::None;
         self.unknown_fields.clear();
     }
 }
@@ -760,8 +860,8 @@ impl ::std::fmt::Debug for Quantile {
 }
 
 impl ::protobuf::reflect::ProtobufValue for Quantile {
-    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef<'_> {
-        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Message(self)
     }
 }
 
@@ -770,12 +870,19 @@ pub struct Summary {
     // message fields
     sample_count: ::std::option::Option<u64>,
     sample_sum: ::std::option::Option<f64>,
-    quantile: ::protobuf::RepeatedField<Quantile>,
+    pub quantile: ::protobuf::RepeatedField<Quantile>,
+    pub created_timestamp: ::protobuf::SingularPtrField<::protobuf::well_known_types::Timestamp>,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
 }
 
+impl<'a> ::std::default::Default for &'a Summary {
+    fn default() -> &'a Summary {
+        <Summary as ::protobuf::Message>::default_instance()
+    }
+}
+
 impl Summary {
     pub fn new() -> Summary {
         ::std::default::Default::default()
@@ -783,6 +890,10 @@ impl Summary {
 
     // optional uint64 sample_count = 1;
 
+
+    pub fn get_sample_count(&self) -> u64 {
+        self.sample_count.unwrap_or(0)
+    }
     pub fn clear_sample_count(&mut self) {
         self.sample_count = ::std::option::Option::None;
     }
@@ -796,12 +907,12 @@ impl Summary {
         self.sample_count = ::std::option::Option::Some(v);
     }
 
-    pub fn get_sample_count(&self) -> u64 {
-        self.sample_count.unwrap_or(0)
-    }
-
     // optional double sample_sum = 2;
 
+
+    pub fn get_sample_sum(&self) -> f64 {
+        self.sample_sum.unwrap_or(0.)
+    }
     pub fn clear_sample_sum(&mut self) {
         self.sample_sum = ::std::option::Option::None;
     }
@@ -815,12 +926,12 @@ impl Summary {
         self.sample_sum = ::std::option::Option::Some(v);
     }
 
-    pub fn get_sample_sum(&self) -> f64 {
-        self.sample_sum.unwrap_or(0.)
-    }
-
     // repeated .io.prometheus.client.Quantile quantile = 3;
 
+
+    pub fn get_quantile(&self) -> &[Quantile] {
+        &self.quantile
+    }
     pub fn clear_quantile(&mut self) {
         self.quantile.clear();
     }
@@ -840,8 +951,37 @@ impl Summary {
         ::std::mem::replace(&mut self.quantile, ::protobuf::RepeatedField::new())
     }
 
-    pub fn get_quantile(&self) -> &[Quantile] {
-        &self.quantile
+    // optional .google.protobuf.Timestamp created_timestamp = 4;
+
+
+    pub fn get_created_timestamp(&self) -> &::protobuf::well_known_types::Timestamp {
+        self.created_timestamp.as_ref().unwrap_or_else(|| <::protobuf::well_known_types::Timestamp as ::protobuf::Message>::default_instance())
+    }
+    pub fn clear_created_timestamp(&mut self) {
+        self.created_timestamp.clear();
+    }
+
+    pub fn has_created_timestamp(&self) -> bool {
+        self.created_timestamp.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_created_timestamp(&mut self, v: ::protobuf::well_known_types::Timestamp) {
+        self.created_timestamp = ::protobuf::SingularPtrField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_created_timestamp(&mut self) -> &mut ::protobuf::well_known_types::Timestamp {
+        if self.created_timestamp.is_none() {
+            self.created_timestamp.set_default();
+        }
+        self.created_timestamp.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_created_timestamp(&mut self) -> ::protobuf::well_known_types::Timestamp {
+        self.created_timestamp.take().unwrap_or_else(|| ::protobuf::well_known_types::Timestamp::new())
     }
 }
 
@@ -852,6 +992,11 @@ impl ::protobuf::Message for Summary {
                 return false;
             }
         };
+        for v in &self.created_timestamp {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
         true
     }
 
@@ -876,6 +1021,9 @@ impl ::protobuf::Message for Summary {
                 3 => {
                     ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.quantile)?;
                 },
+                4 => {
+                    ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.created_timestamp)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -898,6 +1046,10 @@ impl ::protobuf::Message for Summary {
             let len = value.compute_size();
             my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
         };
+        if let Some(ref v) = self.created_timestamp.as_ref() {
+            let len = v.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -915,6 +1067,11 @@ impl ::protobuf::Message for Summary {
             os.write_raw_varint32(v.get_cached_size())?;
             v.write_to_with_cached_sizes(os)?;
         };
+        if let Some(ref v) = self.created_timestamp.as_ref() {
+            os.write_tag(4, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -931,13 +1088,13 @@ impl ::protobuf::Message for Summary {
         &mut self.unknown_fields
     }
 
-    fn as_any(&self) -> &::std::any::Any {
-        self as &::std::any::Any
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
     }
-    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
-        self as &mut ::std::any::Any
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
     }
-    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+    fn into_any(self: ::std::boxed::Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
         self
     }
 
@@ -950,53 +1107,49 @@ impl ::protobuf::Message for Summary {
     }
 
     fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
-        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
-        };
-        unsafe {
-            descriptor.get(|| {
-                let mut fields = ::std::vec::Vec::new();
-                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
-                    "sample_count",
-                    |m: &Summary| { &m.sample_count },
-                    |m: &mut Summary| { &mut m.sample_count },
-                ));
-                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
-                    "sample_sum",
-                    |m: &Summary| { &m.sample_sum },
-                    |m: &mut Summary| { &mut m.sample_sum },
-                ));
-                fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Quantile>>(
-                    "quantile",
-                    |m: &Summary| { &m.quantile },
-                    |m: &mut Summary| { &mut m.quantile },
-                ));
-                ::protobuf::reflect::MessageDescriptor::new::<Summary>(
-                    "Summary",
-                    fields,
-                    file_descriptor_proto()
-                )
-            })
-        }
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::MessageDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            let mut fields = ::std::vec::Vec::new();
+            fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                "sample_count",
+                |m: &Summary| { &m.sample_count },
+                |m: &mut Summary| { &mut m.sample_count },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
+                "sample_sum",
+                |m: &Summary| { &m.sample_sum },
+                |m: &mut Summary| { &mut m.sample_sum },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Quantile>>(
+                "quantile",
+                |m: &Summary| { &m.quantile },
+                |m: &mut Summary| { &mut m.quantile },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<::protobuf::well_known_types::Timestamp>>(
+                "created_timestamp",
+                |m: &Summary| { &m.created_timestamp },
+                |m: &mut Summary| { &mut m.created_timestamp },
+            ));
+            ::protobuf::reflect::MessageDescriptor::new_pb_name::<Summary>(
+                "Summary",
+                fields,
+                file_descriptor_proto()
+            )
+        })
     }
 
     fn default_instance() -> &'static Summary {
-        static mut instance: ::protobuf::lazy::Lazy<Summary> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const Summary,
-        };
-        unsafe {
-            instance.get(Summary::new)
-        }
+        static instance: ::protobuf::rt::LazyV2<Summary> = ::protobuf::rt::LazyV2::INIT;
+        instance.get(Summary::new)
     }
 }
 
 impl ::protobuf::Clear for Summary {
     fn clear(&mut self) {
-        self.clear_sample_count();
-        self.clear_sample_sum();
-        self.clear_quantile();
+        self.sample_count = ::std::option::Option::None;
+        self.sample_sum = ::std::option::Option::None;
+        self.quantile.clear();
+        self.created_timestamp.clear();
         self.unknown_fields.clear();
     }
 }
@@ -1008,8 +1161,8 @@ impl ::std::fmt::Debug for Summary {
 }
 
 impl ::protobuf::reflect::ProtobufValue for Summary {
-    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef<'_> {
-        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Message(self)
     }
 }
 
@@ -1022,6 +1175,12 @@ pub struct Untyped {
     pub cached_size: ::protobuf::CachedSize,
 }
 
+impl<'a> ::std::default::Default for &'a Untyped {
+    fn default() -> &'a Untyped {
+        <Untyped as ::protobuf::Message>::default_instance()
+    }
+}
+
 impl Untyped {
     pub fn new() -> Untyped {
         ::std::default::Default::default()
@@ -1029,6 +1188,10 @@ impl Untyped {
 
     // optional double value = 1;
 
+
+    pub fn get_value(&self) -> f64 {
+        self.value.unwrap_or(0.)
+    }
     pub fn clear_value(&mut self) {
         self.value = ::std::option::Option::None;
     }
@@ -1041,10 +1204,6 @@ impl Untyped {
     pub fn set_value(&mut self, v: f64) {
         self.value = ::std::option::Option::Some(v);
     }
-
-    pub fn get_value(&self) -> f64 {
-        self.value.unwrap_or(0.)
-    }
 }
 
 impl ::protobuf::Message for Untyped {
@@ -1103,13 +1262,13 @@ impl ::protobuf::Message for Untyped {
         &mut self.unknown_fields
     }
 
-    fn as_any(&self) -> &::std::any::Any {
-        self as &::std::any::Any
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
     }
-    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
-        self as &mut ::std::any::Any
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
     }
-    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+    fn into_any(self: ::std::boxed::Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
         self
     }
 
@@ -1122,41 +1281,31 @@ impl ::protobuf::Message for Untyped {
     }
 
     fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
-        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
-        };
-        unsafe {
-            descriptor.get(|| {
-                let mut fields = ::std::vec::Vec::new();
-                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
-                    "value",
-                    |m: &Untyped| { &m.value },
-                    |m: &mut Untyped| { &mut m.value },
-                ));
-                ::protobuf::reflect::MessageDescriptor::new::<Untyped>(
-                    "Untyped",
-                    fields,
-                    file_descriptor_proto()
-                )
-            })
-        }
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::MessageDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            let mut fields = ::std::vec::Vec::new();
+            fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
+                "value",
+                |m: &Untyped| { &m.value },
+                |m: &mut Untyped| { &mut m.value },
+            ));
+            ::protobuf::reflect::MessageDescriptor::new_pb_name::<Untyped>(
+                "Untyped",
+                fields,
+                file_descriptor_proto()
+            )
+        })
     }
 
     fn default_instance() -> &'static Untyped {
-        static mut instance: ::protobuf::lazy::Lazy<Untyped> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const Untyped,
-        };
-        unsafe {
-            instance.get(Untyped::new)
-        }
+        static instance: ::protobuf::rt::LazyV2<Untyped> = ::protobuf::rt::LazyV2::INIT;
+        instance.get(Untyped::new)
     }
 }
 
 impl ::protobuf::Clear for Untyped {
     fn clear(&mut self) {
-        self.clear_value();
+        self.value = ::std::option::Option::None;
         self.unknown_fields.clear();
     }
 }
@@ -1168,8 +1317,8 @@ impl ::std::fmt::Debug for Untyped {
 }
 
 impl ::protobuf::reflect::ProtobufValue for Untyped {
-    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef<'_> {
-        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Message(self)
     }
 }
 
@@ -1178,12 +1327,19 @@ pub struct Histogram {
     // message fields
     sample_count: ::std::option::Option<u64>,
     sample_sum: ::std::option::Option<f64>,
-    bucket: ::protobuf::RepeatedField<Bucket>,
+    pub bucket: ::protobuf::RepeatedField<Bucket>,
+    pub created_timestamp: ::protobuf::SingularPtrField<::protobuf::well_known_types::Timestamp>,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
 }
 
+impl<'a> ::std::default::Default for &'a Histogram {
+    fn default() -> &'a Histogram {
+        <Histogram as ::protobuf::Message>::default_instance()
+    }
+}
+
 impl Histogram {
     pub fn new() -> Histogram {
         ::std::default::Default::default()
@@ -1191,6 +1347,10 @@ impl Histogram {
 
     // optional uint64 sample_count = 1;
 
+
+    pub fn get_sample_count(&self) -> u64 {
+        self.sample_count.unwrap_or(0)
+    }
     pub fn clear_sample_count(&mut self) {
         self.sample_count = ::std::option::Option::None;
     }
@@ -1204,12 +1364,12 @@ impl Histogram {
         self.sample_count = ::std::option::Option::Some(v);
     }
 
-    pub fn get_sample_count(&self) -> u64 {
-        self.sample_count.unwrap_or(0)
-    }
-
     // optional double sample_sum = 2;
 
+
+    pub fn get_sample_sum(&self) -> f64 {
+        self.sample_sum.unwrap_or(0.)
+    }
     pub fn clear_sample_sum(&mut self) {
         self.sample_sum = ::std::option::Option::None;
     }
@@ -1223,12 +1383,12 @@ impl Histogram {
         self.sample_sum = ::std::option::Option::Some(v);
     }
 
-    pub fn get_sample_sum(&self) -> f64 {
-        self.sample_sum.unwrap_or(0.)
-    }
-
     // repeated .io.prometheus.client.Bucket bucket = 3;
 
+
+    pub fn get_bucket(&self) -> &[Bucket] {
+        &self.bucket
+    }
     pub fn clear_bucket(&mut self) {
         self.bucket.clear();
     }
@@ -1248,8 +1408,37 @@ impl Histogram {
         ::std::mem::replace(&mut self.bucket, ::protobuf::RepeatedField::new())
     }
 
-    pub fn get_bucket(&self) -> &[Bucket] {
-        &self.bucket
+    // optional .google.protobuf.Timestamp created_timestamp = 15;
+
+
+    pub fn get_created_timestamp(&self) -> &::protobuf::well_known_types::Timestamp {
+        self.created_timestamp.as_ref().unwrap_or_else(|| <::protobuf::well_known_types::Timestamp as ::protobuf::Message>::default_instance())
+    }
+    pub fn clear_created_timestamp(&mut self) {
+        self.created_timestamp.clear();
+    }
+
+    pub fn has_created_timestamp(&self) -> bool {
+        self.created_timestamp.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_created_timestamp(&mut self, v: ::protobuf::well_known_types::Timestamp) {
+        self.created_timestamp = ::protobuf::SingularPtrField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_created_timestamp(&mut self) -> &mut ::protobuf::well_known_types::Timestamp {
+        if self.created_timestamp.is_none() {
+            self.created_timestamp.set_default();
+        }
+        self.created_timestamp.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_created_timestamp(&mut self) -> ::protobuf::well_known_types::Timestamp {
+        self.created_timestamp.take().unwrap_or_else(|| ::protobuf::well_known_types::Timestamp::new())
     }
 }
 
@@ -1260,6 +1449,11 @@ impl ::protobuf::Message for Histogram {
                 return false;
             }
         };
+        for v in &self.created_timestamp {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
         true
     }
 
@@ -1284,6 +1478,9 @@ impl ::protobuf::Message for Histogram {
                 3 => {
                     ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.bucket)?;
                 },
+                15 => {
+                    ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.created_timestamp)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -1306,6 +1503,10 @@ impl ::protobuf::Message for Histogram {
             let len = value.compute_size();
             my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
         };
+        if let Some(ref v) = self.created_timestamp.as_ref() {
+            let len = v.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -1323,6 +1524,11 @@ impl ::protobuf::Message for Histogram {
             os.write_raw_varint32(v.get_cached_size())?;
             v.write_to_with_cached_sizes(os)?;
         };
+        if let Some(ref v) = self.created_timestamp.as_ref() {
+            os.write_tag(15, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -1339,13 +1545,13 @@ impl ::protobuf::Message for Histogram {
         &mut self.unknown_fields
     }
 
-    fn as_any(&self) -> &::std::any::Any {
-        self as &::std::any::Any
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
     }
-    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
-        self as &mut ::std::any::Any
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
     }
-    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+    fn into_any(self: ::std::boxed::Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
         self
     }
 
@@ -1358,53 +1564,49 @@ impl ::protobuf::Message for Histogram {
     }
 
     fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
-        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
-        };
-        unsafe {
-            descriptor.get(|| {
-                let mut fields = ::std::vec::Vec::new();
-                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
-                    "sample_count",
-                    |m: &Histogram| { &m.sample_count },
-                    |m: &mut Histogram| { &mut m.sample_count },
-                ));
-                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
-                    "sample_sum",
-                    |m: &Histogram| { &m.sample_sum },
-                    |m: &mut Histogram| { &mut m.sample_sum },
-                ));
-                fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Bucket>>(
-                    "bucket",
-                    |m: &Histogram| { &m.bucket },
-                    |m: &mut Histogram| { &mut m.bucket },
-                ));
-                ::protobuf::reflect::MessageDescriptor::new::<Histogram>(
-                    "Histogram",
-                    fields,
-                    file_descriptor_proto()
-                )
-            })
-        }
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::MessageDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            let mut fields = ::std::vec::Vec::new();
+            fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                "sample_count",
+                |m: &Histogram| { &m.sample_count },
+                |m: &mut Histogram| { &mut m.sample_count },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
+                "sample_sum",
+                |m: &Histogram| { &m.sample_sum },
+                |m: &mut Histogram| { &mut m.sample_sum },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Bucket>>(
+                "bucket",
+                |m: &Histogram| { &m.bucket },
+                |m: &mut Histogram| { &mut m.bucket },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<::protobuf::well_known_types::Timestamp>>(
+                "created_timestamp",
+                |m: &Histogram| { &m.created_timestamp },
+                |m: &mut Histogram| { &mut m.created_timestamp },
+            ));
+            ::protobuf::reflect::MessageDescriptor::new_pb_name::<Histogram>(
+                "Histogram",
+                fields,
+                file_descriptor_proto()
+            )
+        })
     }
 
     fn default_instance() -> &'static Histogram {
-        static mut instance: ::protobuf::lazy::Lazy<Histogram> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const Histogram,
-        };
-        unsafe {
-            instance.get(Histogram::new)
-        }
+        static instance: ::protobuf::rt::LazyV2<Histogram> = ::protobuf::rt::LazyV2::INIT;
+        instance.get(Histogram::new)
     }
 }
 
 impl ::protobuf::Clear for Histogram {
     fn clear(&mut self) {
-        self.clear_sample_count();
-        self.clear_sample_sum();
-        self.clear_bucket();
+        self.sample_count = ::std::option::Option::None;
+        self.sample_sum = ::std::option::Option::None;
+        self.bucket.clear();
+        self.created_timestamp.clear();
         self.unknown_fields.clear();
     }
 }
@@ -1416,8 +1618,8 @@ impl ::std::fmt::Debug for Histogram {
 }
 
 impl ::protobuf::reflect::ProtobufValue for Histogram {
-    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef<'_> {
-        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Message(self)
     }
 }
 
@@ -1426,11 +1628,18 @@ pub struct Bucket {
     // message fields
     cumulative_count: ::std::option::Option<u64>,
     upper_bound: ::std::option::Option<f64>,
+    pub exemplar: ::protobuf::SingularPtrField<Exemplar>,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
 }
 
+impl<'a> ::std::default::Default for &'a Bucket {
+    fn default() -> &'a Bucket {
+        <Bucket as ::protobuf::Message>::default_instance()
+    }
+}
+
 impl Bucket {
     pub fn new() -> Bucket {
         ::std::default::Default::default()
@@ -1438,6 +1647,10 @@ impl Bucket {
 
     // optional uint64 cumulative_count = 1;
 
+
+    pub fn get_cumulative_count(&self) -> u64 {
+        self.cumulative_count.unwrap_or(0)
+    }
     pub fn clear_cumulative_count(&mut self) {
         self.cumulative_count = ::std::option::Option::None;
     }
@@ -1451,12 +1664,12 @@ impl Bucket {
         self.cumulative_count = ::std::option::Option::Some(v);
     }
 
-    pub fn get_cumulative_count(&self) -> u64 {
-        self.cumulative_count.unwrap_or(0)
-    }
-
     // optional double upper_bound = 2;
 
+
+    pub fn get_upper_bound(&self) -> f64 {
+        self.upper_bound.unwrap_or(0.)
+    }
     pub fn clear_upper_bound(&mut self) {
         self.upper_bound = ::std::option::Option::None;
     }
@@ -1470,13 +1683,47 @@ impl Bucket {
         self.upper_bound = ::std::option::Option::Some(v);
     }
 
-    pub fn get_upper_bound(&self) -> f64 {
-        self.upper_bound.unwrap_or(0.)
+    // optional .io.prometheus.client.Exemplar exemplar = 3;
+
+
+    pub fn get_exemplar(&self) -> &Exemplar {
+        self.exemplar.as_ref().unwrap_or_else(|| <Exemplar as ::protobuf::Message>::default_instance())
+    }
+    pub fn clear_exemplar(&mut self) {
+        self.exemplar.clear();
+    }
+
+    pub fn has_exemplar(&self) -> bool {
+        self.exemplar.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_exemplar(&mut self, v: Exemplar) {
+        self.exemplar = ::protobuf::SingularPtrField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_exemplar(&mut self) -> &mut Exemplar {
+        if self.exemplar.is_none() {
+            self.exemplar.set_default();
+        }
+        self.exemplar.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_exemplar(&mut self) -> Exemplar {
+        self.exemplar.take().unwrap_or_else(|| Exemplar::new())
     }
 }
 
 impl ::protobuf::Message for Bucket {
     fn is_initialized(&self) -> bool {
+        for v in &self.exemplar {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
         true
     }
 
@@ -1498,6 +1745,9 @@ impl ::protobuf::Message for Bucket {
                     let tmp = is.read_double()?;
                     self.upper_bound = ::std::option::Option::Some(tmp);
                 },
+                3 => {
+                    ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.exemplar)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -1516,6 +1766,10 @@ impl ::protobuf::Message for Bucket {
         if let Some(v) = self.upper_bound {
             my_size += 9;
         }
+        if let Some(ref v) = self.exemplar.as_ref() {
+            let len = v.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -1528,6 +1782,11 @@ impl ::protobuf::Message for Bucket {
         if let Some(v) = self.upper_bound {
             os.write_double(2, v)?;
         }
+        if let Some(ref v) = self.exemplar.as_ref() {
+            os.write_tag(3, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -1544,13 +1803,13 @@ impl ::protobuf::Message for Bucket {
         &mut self.unknown_fields
     }
 
-    fn as_any(&self) -> &::std::any::Any {
-        self as &::std::any::Any
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
     }
-    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
-        self as &mut ::std::any::Any
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
     }
-    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+    fn into_any(self: ::std::boxed::Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
         self
     }
 
@@ -1563,47 +1822,43 @@ impl ::protobuf::Message for Bucket {
     }
 
     fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
-        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
-        };
-        unsafe {
-            descriptor.get(|| {
-                let mut fields = ::std::vec::Vec::new();
-                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
-                    "cumulative_count",
-                    |m: &Bucket| { &m.cumulative_count },
-                    |m: &mut Bucket| { &mut m.cumulative_count },
-                ));
-                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
-                    "upper_bound",
-                    |m: &Bucket| { &m.upper_bound },
-                    |m: &mut Bucket| { &mut m.upper_bound },
-                ));
-                ::protobuf::reflect::MessageDescriptor::new::<Bucket>(
-                    "Bucket",
-                    fields,
-                    file_descriptor_proto()
-                )
-            })
-        }
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::MessageDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            let mut fields = ::std::vec::Vec::new();
+            fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                "cumulative_count",
+                |m: &Bucket| { &m.cumulative_count },
+                |m: &mut Bucket| { &mut m.cumulative_count },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
+                "upper_bound",
+                |m: &Bucket| { &m.upper_bound },
+                |m: &mut Bucket| { &mut m.upper_bound },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Exemplar>>(
+                "exemplar",
+                |m: &Bucket| { &m.exemplar },
+                |m: &mut Bucket| { &mut m.exemplar },
+            ));
+            ::protobuf::reflect::MessageDescriptor::new_pb_name::<Bucket>(
+                "Bucket",
+                fields,
+                file_descriptor_proto()
+            )
+        })
     }
 
     fn default_instance() -> &'static Bucket {
-        static mut instance: ::protobuf::lazy::Lazy<Bucket> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const Bucket,
-        };
-        unsafe {
-            instance.get(Bucket::new)
-        }
+        static instance: ::protobuf::rt::LazyV2<Bucket> = ::protobuf::rt::LazyV2::INIT;
+        instance.get(Bucket::new)
     }
 }
 
 impl ::protobuf::Clear for Bucket {
     fn clear(&mut self) {
-        self.clear_cumulative_count();
-        self.clear_upper_bound();
+        self.cumulative_count = ::std::option::Option::None;
+        self.upper_bound = ::std::option::Option::None;
+        self.exemplar.clear();
         self.unknown_fields.clear();
     }
 }
@@ -1615,33 +1870,39 @@ impl ::std::fmt::Debug for Bucket {
 }
 
 impl ::protobuf::reflect::ProtobufValue for Bucket {
-    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef<'_> {
-        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Message(self)
     }
 }
 
 #[derive(PartialEq,Clone,Default)]
-pub struct Metric {
+pub struct Exemplar {
     // message fields
-    label: ::protobuf::RepeatedField<LabelPair>,
-    gauge: ::protobuf::SingularPtrField<Gauge>,
-    counter: ::protobuf::SingularPtrField<Counter>,
-    summary: ::protobuf::SingularPtrField<Summary>,
-    untyped: ::protobuf::SingularPtrField<Untyped>,
-    histogram: ::protobuf::SingularPtrField<Histogram>,
-    timestamp_ms: ::std::option::Option<i64>,
+    pub label: ::protobuf::RepeatedField<LabelPair>,
+    value: ::std::option::Option<f64>,
+    pub timestamp: ::protobuf::SingularPtrField<::protobuf::well_known_types::Timestamp>,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
 }
 
-impl Metric {
-    pub fn new() -> Metric {
+impl<'a> ::std::default::Default for &'a Exemplar {
+    fn default() -> &'a Exemplar {
+        <Exemplar as ::protobuf::Message>::default_instance()
+    }
+}
+
+impl Exemplar {
+    pub fn new() -> Exemplar {
         ::std::default::Default::default()
     }
 
     // repeated .io.prometheus.client.LabelPair label = 1;
 
+
+    pub fn get_label(&self) -> &[LabelPair] {
+        &self.label
+    }
     pub fn clear_label(&mut self) {
         self.label.clear();
     }
@@ -1661,12 +1922,278 @@ impl Metric {
         ::std::mem::replace(&mut self.label, ::protobuf::RepeatedField::new())
     }
 
+    // optional double value = 2;
+
+
+    pub fn get_value(&self) -> f64 {
+        self.value.unwrap_or(0.)
+    }
+    pub fn clear_value(&mut self) {
+        self.value = ::std::option::Option::None;
+    }
+
+    pub fn has_value(&self) -> bool {
+        self.value.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_value(&mut self, v: f64) {
+        self.value = ::std::option::Option::Some(v);
+    }
+
+    // optional .google.protobuf.Timestamp timestamp = 3;
+
+
+    pub fn get_timestamp(&self) -> &::protobuf::well_known_types::Timestamp {
+        self.timestamp.as_ref().unwrap_or_else(|| <::protobuf::well_known_types::Timestamp as ::protobuf::Message>::default_instance())
+    }
+    pub fn clear_timestamp(&mut self) {
+        self.timestamp.clear();
+    }
+
+    pub fn has_timestamp(&self) -> bool {
+        self.timestamp.is_some()
+    }
+
+    // Param is passed by value, moved
+    pub fn set_timestamp(&mut self, v: ::protobuf::well_known_types::Timestamp) {
+        self.timestamp = ::protobuf::SingularPtrField::some(v);
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_timestamp(&mut self) -> &mut ::protobuf::well_known_types::Timestamp {
+        if self.timestamp.is_none() {
+            self.timestamp.set_default();
+        }
+        self.timestamp.as_mut().unwrap()
+    }
+
+    // Take field
+    pub fn take_timestamp(&mut self) -> ::protobuf::well_known_types::Timestamp {
+        self.timestamp.take().unwrap_or_else(|| ::protobuf::well_known_types::Timestamp::new())
+    }
+}
+
+impl ::protobuf::Message for Exemplar {
+    fn is_initialized(&self) -> bool {
+        for v in &self.label {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
+        for v in &self.timestamp {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.label)?;
+                },
+                2 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeFixed64 {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_double()?;
+                    self.value = ::std::option::Option::Some(tmp);
+                },
+                3 => {
+                    ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.timestamp)?;
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        for value in &self.label {
+            let len = value.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        };
+        if let Some(v) = self.value {
+            my_size += 9;
+        }
+        if let Some(ref v) = self.timestamp.as_ref() {
+            let len = v.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        }
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        for v in &self.label {
+            os.write_tag(1, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        };
+        if let Some(v) = self.value {
+            os.write_double(2, v)?;
+        }
+        if let Some(ref v) = self.timestamp.as_ref() {
+            os.write_tag(3, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        }
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
+    }
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
+    }
+    fn into_any(self: ::std::boxed::Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> Exemplar {
+        Exemplar::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::MessageDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            let mut fields = ::std::vec::Vec::new();
+            fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<LabelPair>>(
+                "label",
+                |m: &Exemplar| { &m.label },
+                |m: &mut Exemplar| { &mut m.label },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeDouble>(
+                "value",
+                |m: &Exemplar| { &m.value },
+                |m: &mut Exemplar| { &mut m.value },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<::protobuf::well_known_types::Timestamp>>(
+                "timestamp",
+                |m: &Exemplar| { &m.timestamp },
+                |m: &mut Exemplar| { &mut m.timestamp },
+            ));
+            ::protobuf::reflect::MessageDescriptor::new_pb_name::<Exemplar>(
+                "Exemplar",
+                fields,
+                file_descriptor_proto()
+            )
+        })
+    }
+
+    fn default_instance() -> &'static Exemplar {
+        static instance: ::protobuf::rt::LazyV2<Exemplar> = ::protobuf::rt::LazyV2::INIT;
+        instance.get(Exemplar::new)
+    }
+}
+
+impl ::protobuf::Clear for Exemplar {
+    fn clear(&mut self) {
+        self.label.clear();
+        self.value = ::std::option::Option::None;
+        self.timestamp.clear();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for Exemplar {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for Exemplar {
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Message(self)
+    }
+}
+
+#[derive(PartialEq,Clone,Default)]
+pub struct Metric {
+    // message fields
+    pub label: ::protobuf::RepeatedField<LabelPair>,
+    pub gauge: ::protobuf::SingularPtrField<Gauge>,
+    pub counter: ::protobuf::SingularPtrField<Counter>,
+    pub summary: ::protobuf::SingularPtrField<Summary>,
+    pub untyped: ::protobuf::SingularPtrField<Untyped>,
+    pub histogram: ::protobuf::SingularPtrField<Histogram>,
+    timestamp_ms: ::std::option::Option<i64>,
+    // special fields
+    pub unknown_fields: ::protobuf::UnknownFields,
+    pub cached_size: ::protobuf::CachedSize,
+}
+
+impl<'a> ::std::default::Default for &'a Metric {
+    fn default() -> &'a Metric {
+        <Metric as ::protobuf::Message>::default_instance()
+    }
+}
+
+impl Metric {
+    pub fn new() -> Metric {
+        ::std::default::Default::default()
+    }
+
+    // repeated .io.prometheus.client.LabelPair label = 1;
+
+
     pub fn get_label(&self) -> &[LabelPair] {
         &self.label
     }
+    pub fn clear_label(&mut self) {
+        self.label.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_label(&mut self, v: ::protobuf::RepeatedField<LabelPair>) {
+        self.label = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_label(&mut self) -> &mut ::protobuf::RepeatedField<LabelPair> {
+        &mut self.label
+    }
+
+    // Take field
+    pub fn take_label(&mut self) -> ::protobuf::RepeatedField<LabelPair> {
+        ::std::mem::replace(&mut self.label, ::protobuf::RepeatedField::new())
+    }
 
     // optional .io.prometheus.client.Gauge gauge = 2;
 
+
+    pub fn get_gauge(&self) -> &Gauge {
+        self.gauge.as_ref().unwrap_or_else(|| <Gauge as ::protobuf::Message>::default_instance())
+    }
     pub fn clear_gauge(&mut self) {
         self.gauge.clear();
     }
@@ -1694,12 +2221,12 @@ impl Metric {
         self.gauge.take().unwrap_or_else(|| Gauge::new())
     }
 
-    pub fn get_gauge(&self) -> &Gauge {
-        self.gauge.as_ref().unwrap_or_else(|| Gauge::default_instance())
-    }
-
     // optional .io.prometheus.client.Counter counter = 3;
 
+
+    pub fn get_counter(&self) -> &Counter {
+        self.counter.as_ref().unwrap_or_else(|| <Counter as ::protobuf::Message>::default_instance())
+    }
     pub fn clear_counter(&mut self) {
         self.counter.clear();
     }
@@ -1727,12 +2254,12 @@ impl Metric {
         self.counter.take().unwrap_or_else(|| Counter::new())
     }
 
-    pub fn get_counter(&self) -> &Counter {
-        self.counter.as_ref().unwrap_or_else(|| Counter::default_instance())
-    }
-
     // optional .io.prometheus.client.Summary summary = 4;
 
+
+    pub fn get_summary(&self) -> &Summary {
+        self.summary.as_ref().unwrap_or_else(|| <Summary as ::protobuf::Message>::default_instance())
+    }
     pub fn clear_summary(&mut self) {
         self.summary.clear();
     }
@@ -1760,12 +2287,12 @@ impl Metric {
         self.summary.take().unwrap_or_else(|| Summary::new())
     }
 
-    pub fn get_summary(&self) -> &Summary {
-        self.summary.as_ref().unwrap_or_else(|| Summary::default_instance())
-    }
-
     // optional .io.prometheus.client.Untyped untyped = 5;
 
+
+    pub fn get_untyped(&self) -> &Untyped {
+        self.untyped.as_ref().unwrap_or_else(|| <Untyped as ::protobuf::Message>::default_instance())
+    }
     pub fn clear_untyped(&mut self) {
         self.untyped.clear();
     }
@@ -1793,12 +2320,12 @@ impl Metric {
         self.untyped.take().unwrap_or_else(|| Untyped::new())
     }
 
-    pub fn get_untyped(&self) -> &Untyped {
-        self.untyped.as_ref().unwrap_or_else(|| Untyped::default_instance())
-    }
-
     // optional .io.prometheus.client.Histogram histogram = 7;
 
+
+    pub fn get_histogram(&self) -> &Histogram {
+        self.histogram.as_ref().unwrap_or_else(|| <Histogram as ::protobuf::Message>::default_instance())
+    }
     pub fn clear_histogram(&mut self) {
         self.histogram.clear();
     }
@@ -1826,12 +2353,12 @@ impl Metric {
         self.histogram.take().unwrap_or_else(|| Histogram::new())
     }
 
-    pub fn get_histogram(&self) -> &Histogram {
-        self.histogram.as_ref().unwrap_or_else(|| Histogram::default_instance())
-    }
-
     // optional int64 timestamp_ms = 6;
 
+
+    pub fn get_timestamp_ms(&self) -> i64 {
+        self.timestamp_ms.unwrap_or(0)
+    }
     pub fn clear_timestamp_ms(&mut self) {
         self.timestamp_ms = ::std::option::Option::None;
     }
@@ -1844,10 +2371,6 @@ impl Metric {
     pub fn set_timestamp_ms(&mut self, v: i64) {
         self.timestamp_ms = ::std::option::Option::Some(v);
     }
-
-    pub fn get_timestamp_ms(&self) -> i64 {
-        self.timestamp_ms.unwrap_or(0)
-    }
 }
 
 impl ::protobuf::Message for Metric {
@@ -2008,13 +2531,13 @@ impl ::protobuf::Message for Metric {
         &mut self.unknown_fields
     }
 
-    fn as_any(&self) -> &::std::any::Any {
-        self as &::std::any::Any
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
     }
-    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
-        self as &mut ::std::any::Any
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
     }
-    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+    fn into_any(self: ::std::boxed::Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
         self
     }
 
@@ -2027,77 +2550,67 @@ impl ::protobuf::Message for Metric {
     }
 
     fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
-        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
-        };
-        unsafe {
-            descriptor.get(|| {
-                let mut fields = ::std::vec::Vec::new();
-                fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<LabelPair>>(
-                    "label",
-                    |m: &Metric| { &m.label },
-                    |m: &mut Metric| { &mut m.label },
-                ));
-                fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Gauge>>(
-                    "gauge",
-                    |m: &Metric| { &m.gauge },
-                    |m: &mut Metric| { &mut m.gauge },
-                ));
-                fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Counter>>(
-                    "counter",
-                    |m: &Metric| { &m.counter },
-                    |m: &mut Metric| { &mut m.counter },
-                ));
-                fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Summary>>(
-                    "summary",
-                    |m: &Metric| { &m.summary },
-                    |m: &mut Metric| { &mut m.summary },
-                ));
-                fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Untyped>>(
-                    "untyped",
-                    |m: &Metric| { &m.untyped },
-                    |m: &mut Metric| { &mut m.untyped },
-                ));
-                fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Histogram>>(
-                    "histogram",
-                    |m: &Metric| { &m.histogram },
-                    |m: &mut Metric| { &mut m.histogram },
-                ));
-                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeInt64>(
-                    "timestamp_ms",
-                    |m: &Metric| { &m.timestamp_ms },
-                    |m: &mut Metric| { &mut m.timestamp_ms },
-                ));
-                ::protobuf::reflect::MessageDescriptor::new::<Metric>(
-                    "Metric",
-                    fields,
-                    file_descriptor_proto()
-                )
-            })
-        }
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::MessageDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            let mut fields = ::std::vec::Vec::new();
+            fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<LabelPair>>(
+                "label",
+                |m: &Metric| { &m.label },
+                |m: &mut Metric| { &mut m.label },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Gauge>>(
+                "gauge",
+                |m: &Metric| { &m.gauge },
+                |m: &mut Metric| { &mut m.gauge },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Counter>>(
+                "counter",
+                |m: &Metric| { &m.counter },
+                |m: &mut Metric| { &mut m.counter },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Summary>>(
+                "summary",
+                |m: &Metric| { &m.summary },
+                |m: &mut Metric| { &mut m.summary },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Untyped>>(
+                "untyped",
+                |m: &Metric| { &m.untyped },
+                |m: &mut Metric| { &mut m.untyped },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_singular_ptr_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Histogram>>(
+                "histogram",
+                |m: &Metric| { &m.histogram },
+                |m: &mut Metric| { &mut m.histogram },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeInt64>(
+                "timestamp_ms",
+                |m: &Metric| { &m.timestamp_ms },
+                |m: &mut Metric| { &mut m.timestamp_ms },
+            ));
+            ::protobuf::reflect::MessageDescriptor::new_pb_name::<Metric>(
+                "Metric",
+                fields,
+                file_descriptor_proto()
+            )
+        })
     }
 
     fn default_instance() -> &'static Metric {
-        static mut instance: ::protobuf::lazy::Lazy<Metric> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const Metric,
-        };
-        unsafe {
-            instance.get(Metric::new)
-        }
+        static instance: ::protobuf::rt::LazyV2<Metric> = ::protobuf::rt::LazyV2::INIT;
+        instance.get(Metric::new)
     }
 }
 
 impl ::protobuf::Clear for Metric {
     fn clear(&mut self) {
-        self.clear_label();
-        self.clear_gauge();
-        self.clear_counter();
-        self.clear_summary();
-        self.clear_untyped();
-        self.clear_histogram();
-        self.clear_timestamp_ms();
+        self.label.clear();
+        self.gauge.clear();
+        self.counter.clear();
+        self.summary.clear();
+        self.untyped.clear();
+        self.histogram.clear();
+        self.timestamp_ms = ::std::option::Option::None;
         self.unknown_fields.clear();
     }
 }
@@ -2109,8 +2622,8 @@ impl ::std::fmt::Debug for Metric {
 }
 
 impl ::protobuf::reflect::ProtobufValue for Metric {
-    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef<'_> {
-        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Message(self)
     }
 }
 
@@ -2120,12 +2633,18 @@ pub struct MetricFamily {
     name: ::protobuf::SingularField<::std::string::String>,
     help: ::protobuf::SingularField<::std::string::String>,
     field_type: ::std::option::Option<MetricType>,
-    metric: ::protobuf::RepeatedField<Metric>,
+    pub metric: ::protobuf::RepeatedField<Metric>,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
 }
 
+impl<'a> ::std::default::Default for &'a MetricFamily {
+    fn default() -> &'a MetricFamily {
+        <MetricFamily as ::protobuf::Message>::default_instance()
+    }
+}
+
 impl MetricFamily {
     pub fn new() -> MetricFamily {
         ::std::default::Default::default()
@@ -2133,6 +2652,13 @@ impl MetricFamily {
 
     // optional string name = 1;
 
+
+    pub fn get_name(&self) -> &str {
+        match self.name.as_ref() {
+            Some(v) => &v,
+            None => "",
+        }
+    }
     pub fn clear_name(&mut self) {
         self.name.clear();
     }
@@ -2160,15 +2686,15 @@ impl MetricFamily {
         self.name.take().unwrap_or_else(|| ::std::string::String::new())
     }
 
-    pub fn get_name(&self) -> &str {
-        match self.name.as_ref() {
+    // optional string help = 2;
+
+
+    pub fn get_help(&self) -> &str {
+        match self.help.as_ref() {
             Some(v) => &v,
             None => "",
         }
     }
-
-    // optional string help = 2;
-
     pub fn clear_help(&mut self) {
         self.help.clear();
     }
@@ -2196,15 +2722,12 @@ impl MetricFamily {
         self.help.take().unwrap_or_else(|| ::std::string::String::new())
     }
 
-    pub fn get_help(&self) -> &str {
-        match self.help.as_ref() {
-            Some(v) => &v,
-            None => "",
-        }
-    }
-
     // optional .io.prometheus.client.MetricType type = 3;
 
+
+    pub fn get_field_type(&self) -> MetricType {
+        self.field_type.unwrap_or(MetricType::COUNTER)
+    }
     pub fn clear_field_type(&mut self) {
         self.field_type = ::std::option::Option::None;
     }
@@ -2218,12 +2741,12 @@ impl MetricFamily {
         self.field_type = ::std::option::Option::Some(v);
     }
 
-    pub fn get_field_type(&self) -> MetricType {
-        self.field_type.unwrap_or(MetricType::COUNTER)
-    }
-
     // repeated .io.prometheus.client.Metric metric = 4;
 
+
+    pub fn get_metric(&self) -> &[Metric] {
+        &self.metric
+    }
     pub fn clear_metric(&mut self) {
         self.metric.clear();
     }
@@ -2242,10 +2765,6 @@ impl MetricFamily {
     pub fn take_metric(&mut self) -> ::protobuf::RepeatedField<Metric> {
         ::std::mem::replace(&mut self.metric, ::protobuf::RepeatedField::new())
     }
-
-    pub fn get_metric(&self) -> &[Metric] {
-        &self.metric
-    }
 }
 
 impl ::protobuf::Message for MetricFamily {
@@ -2312,7 +2831,7 @@ impl ::protobuf::Message for MetricFamily {
             os.write_string(2, &v)?;
         }
         if let Some(v) = self.field_type {
-            os.write_enum(3, v.value())?;
+            os.write_enum(3, ::protobuf::ProtobufEnum::value(&v))?;
         }
         for v in &self.metric {
             os.write_tag(4, ::protobuf::wire_format::WireTypeLengthDelimited)?;
@@ -2335,13 +2854,13 @@ impl ::protobuf::Message for MetricFamily {
         &mut self.unknown_fields
     }
 
-    fn as_any(&self) -> &::std::any::Any {
-        self as &::std::any::Any
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
     }
-    fn as_any_mut(&mut self) -> &mut ::std::any::Any {
-        self as &mut ::std::any::Any
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
     }
-    fn into_any(self: Box<Self>) -> ::std::boxed::Box<::std::any::Any> {
+    fn into_any(self: ::std::boxed::Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
         self
     }
 
@@ -2354,59 +2873,49 @@ impl ::protobuf::Message for MetricFamily {
     }
 
     fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
-        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
-        };
-        unsafe {
-            descriptor.get(|| {
-                let mut fields = ::std::vec::Vec::new();
-                fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
-                    "name",
-                    |m: &MetricFamily| { &m.name },
-                    |m: &mut MetricFamily| { &mut m.name },
-                ));
-                fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
-                    "help",
-                    |m: &MetricFamily| { &m.help },
-                    |m: &mut MetricFamily| { &mut m.help },
-                ));
-                fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeEnum<MetricType>>(
-                    "type",
-                    |m: &MetricFamily| { &m.field_type },
-                    |m: &mut MetricFamily| { &mut m.field_type },
-                ));
-                fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Metric>>(
-                    "metric",
-                    |m: &MetricFamily| { &m.metric },
-                    |m: &mut MetricFamily| { &mut m.metric },
-                ));
-                ::protobuf::reflect::MessageDescriptor::new::<MetricFamily>(
-                    "MetricFamily",
-                    fields,
-                    file_descriptor_proto()
-                )
-            })
-        }
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::MessageDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            let mut fields = ::std::vec::Vec::new();
+            fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
+                "name",
+                |m: &MetricFamily| { &m.name },
+                |m: &mut MetricFamily| { &mut m.name },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_singular_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
+                "help",
+                |m: &MetricFamily| { &m.help },
+                |m: &mut MetricFamily| { &mut m.help },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_option_accessor::<_, ::protobuf::types::ProtobufTypeEnum<MetricType>>(
+                "type",
+                |m: &MetricFamily| { &m.field_type },
+                |m: &mut MetricFamily| { &mut m.field_type },
+            ));
+            fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<Metric>>(
+                "metric",
+                |m: &MetricFamily| { &m.metric },
+                |m: &mut MetricFamily| { &mut m.metric },
+            ));
+            ::protobuf::reflect::MessageDescriptor::new_pb_name::<MetricFamily>(
+                "MetricFamily",
+                fields,
+                file_descriptor_proto()
+            )
+        })
     }
 
     fn default_instance() -> &'static MetricFamily {
-        static mut instance: ::protobuf::lazy::Lazy<MetricFamily> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const MetricFamily,
-        };
-        unsafe {
-            instance.get(MetricFamily::new)
-        }
+        static instance: ::protobuf::rt::LazyV2<MetricFamily> = ::protobuf::rt::LazyV2::INIT;
+        instance.get(MetricFamily::new)
     }
 }
 
 impl ::protobuf::Clear for MetricFamily {
     fn clear(&mut self) {
-        self.clear_name();
-        self.clear_help();
-        self.clear_field_type();
-        self.clear_metric();
+        self.name.clear();
+        self.help.clear();
+        self.field_type = ::std::option::Option::None;
+        self.metric.clear();
         self.unknown_fields.clear();
     }
 }
@@ -2418,8 +2927,8 @@ impl ::std::fmt::Debug for MetricFamily {
 }
 
 impl ::protobuf::reflect::ProtobufValue for MetricFamily {
-    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef<'_> {
-        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Message(self)
     }
 }
 
@@ -2460,73 +2969,82 @@ impl ::protobuf::ProtobufEnum for MetricType {
     }
 
     fn enum_descriptor_static() -> &'static ::protobuf::reflect::EnumDescriptor {
-        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::EnumDescriptor> = ::protobuf::lazy::Lazy {
-            lock: ::protobuf::lazy::ONCE_INIT,
-            ptr: 0 as *const ::protobuf::reflect::EnumDescriptor,
-        };
-        unsafe {
-            descriptor.get(|| {
-                ::protobuf::reflect::EnumDescriptor::new("MetricType", file_descriptor_proto())
-            })
-        }
+        static descriptor: ::protobuf::rt::LazyV2<::protobuf::reflect::EnumDescriptor> = ::protobuf::rt::LazyV2::INIT;
+        descriptor.get(|| {
+            ::protobuf::reflect::EnumDescriptor::new_pb_name::<MetricType>("MetricType", file_descriptor_proto())
+        })
     }
 }
 
 impl ::std::marker::Copy for MetricType {
 }
 
+impl ::std::default::Default for MetricType {
+    fn default() -> Self {
+        MetricType::COUNTER
+    }
+}
+
 impl ::protobuf::reflect::ProtobufValue for MetricType {
-    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef<'_> {
-        ::protobuf::reflect::ProtobufValueRef::Enum(self.descriptor())
+    fn as_ref(&self) -> ::protobuf::reflect::ReflectValueRef {
+        ::protobuf::reflect::ReflectValueRef::Enum(::protobuf::ProtobufEnum::descriptor(self))
     }
 }
 
 static file_descriptor_proto_data: &'static [u8] = b"\
-    \n\x11proto_model.proto\x12\x14io.prometheus.client\"0\n\tLabelPair\x12\
-    \x10\n\x04name\x18\x01\x20\x01(\tB\x02\x18\0\x12\x11\n\x05value\x18\x02\
-    \x20\x01(\tB\x02\x18\0\"\x1a\n\x05Gauge\x12\x11\n\x05value\x18\x01\x20\
-    \x01(\x01B\x02\x18\0\"\x1c\n\x07Counter\x12\x11\n\x05value\x18\x01\x20\
-    \x01(\x01B\x02\x18\0\"3\n\x08Quantile\x12\x14\n\x08quantile\x18\x01\x20\
-    \x01(\x01B\x02\x18\0\x12\x11\n\x05value\x18\x02\x20\x01(\x01B\x02\x18\0\
-    \"q\n\x07Summary\x12\x18\n\x0csample_count\x18\x01\x20\x01(\x04B\x02\x18\
-    \0\x12\x16\n\nsample_sum\x18\x02\x20\x01(\x01B\x02\x18\0\x124\n\x08quant\
-    ile\x18\x03\x20\x03(\x0b2\x1e.io.prometheus.client.QuantileB\x02\x18\0\"\
-    \x1c\n\x07Untyped\x12\x11\n\x05value\x18\x01\x20\x01(\x01B\x02\x18\0\"o\
-    \n\tHistogram\x12\x18\n\x0csample_count\x18\x01\x20\x01(\x04B\x02\x18\0\
-    \x12\x16\n\nsample_sum\x18\x02\x20\x01(\x01B\x02\x18\0\x120\n\x06bucket\
-    \x18\x03\x20\x03(\x0b2\x1c.io.prometheus.client.BucketB\x02\x18\0\"?\n\
-    \x06Bucket\x12\x1c\n\x10cumulative_count\x18\x01\x20\x01(\x04B\x02\x18\0\
-    \x12\x17\n\x0bupper_bound\x18\x02\x20\x01(\x01B\x02\x18\0\"\xda\x02\n\
-    \x06Metric\x122\n\x05label\x18\x01\x20\x03(\x0b2\x1f.io.prometheus.clien\
-    t.LabelPairB\x02\x18\0\x12.\n\x05gauge\x18\x02\x20\x01(\x0b2\x1b.io.prom\
-    etheus.client.GaugeB\x02\x18\0\x122\n\x07counter\x18\x03\x20\x01(\x0b2\
-    \x1d.io.prometheus.client.CounterB\x02\x18\0\x122\n\x07summary\x18\x04\
-    \x20\x01(\x0b2\x1d.io.prometheus.client.SummaryB\x02\x18\0\x122\n\x07unt\
-    yped\x18\x05\x20\x01(\x0b2\x1d.io.prometheus.client.UntypedB\x02\x18\0\
-    \x126\n\thistogram\x18\x07\x20\x01(\x0b2\x1f.io.prometheus.client.Histog\
-    ramB\x02\x18\0\x12\x18\n\x0ctimestamp_ms\x18\x06\x20\x01(\x03B\x02\x18\0\
-    \"\x98\x01\n\x0cMetricFamily\x12\x10\n\x04name\x18\x01\x20\x01(\tB\x02\
-    \x18\0\x12\x10\n\x04help\x18\x02\x20\x01(\tB\x02\x18\0\x122\n\x04type\
-    \x18\x03\x20\x01(\x0e2\x20.io.prometheus.client.MetricTypeB\x02\x18\0\
-    \x120\n\x06metric\x18\x04\x20\x03(\x0b2\x1c.io.prometheus.client.MetricB\
-    \x02\x18\0*Q\n\nMetricType\x12\x0b\n\x07COUNTER\x10\0\x12\t\n\x05GAUGE\
-    \x10\x01\x12\x0b\n\x07SUMMARY\x10\x02\x12\x0b\n\x07UNTYPED\x10\x03\x12\r\
-    \n\tHISTOGRAM\x10\x04\x1a\x02\x10\0B\0b\x06proto2\
+    \n\x11proto_model.proto\x12\x14io.prometheus.client\x1a\x1fgoogle/protob\
+    uf/timestamp.proto\";\n\tLabelPair\x12\x14\n\x04name\x18\x01\x20\x01(\tR\
+    \x04nameB\0\x12\x16\n\x05value\x18\x02\x20\x01(\tR\x05valueB\0:\0\"!\n\
+    \x05Gauge\x12\x16\n\x05value\x18\x01\x20\x01(\x01R\x05valueB\0:\0\"\xac\
+    \x01\n\x07Counter\x12\x16\n\x05value\x18\x01\x20\x01(\x01R\x05valueB\0\
+    \x12<\n\x08exemplar\x18\x02\x20\x01(\x0b2\x1e.io.prometheus.client.Exemp\
+    larR\x08exemplarB\0\x12I\n\x11created_timestamp\x18\x03\x20\x01(\x0b2\
+    \x1a.google.protobuf.TimestampR\x10createdTimestampB\0:\0\"B\n\x08Quanti\
+    le\x12\x1c\n\x08quantile\x18\x01\x20\x01(\x01R\x08quantileB\0\x12\x16\n\
+    \x05value\x18\x02\x20\x01(\x01R\x05valueB\0:\0\"\xda\x01\n\x07Summary\
+    \x12#\n\x0csample_count\x18\x01\x20\x01(\x04R\x0bsampleCountB\0\x12\x1f\
+    \n\nsample_sum\x18\x02\x20\x01(\x01R\tsampleSumB\0\x12<\n\x08quantile\
+    \x18\x03\x20\x03(\x0b2\x1e.io.prometheus.client.QuantileR\x08quantileB\0\
+    \x12I\n\x11created_timestamp\x18\x04\x20\x01(\x0b2\x1a.google.protobuf.T\
+    imestampR\x10createdTimestampB\0:\0\"#\n\x07Untyped\x12\x16\n\x05value\
+    \x18\x01\x20\x01(\x01R\x05valueB\0:\0\"\xd6\x01\n\tHistogram\x12#\n\x0cs\
+    ample_count\x18\x01\x20\x01(\x04R\x0bsampleCountB\0\x12\x1f\n\nsample_su\
+    m\x18\x02\x20\x01(\x01R\tsampleSumB\0\x126\n\x06bucket\x18\x03\x20\x03(\
+    \x0b2\x1c.io.prometheus.client.BucketR\x06bucketB\0\x12I\n\x11created_ti\
+    mestamp\x18\x0f\x20\x01(\x0b2\x1a.google.protobuf.TimestampR\x10createdT\
+    imestampB\0:\0\"\x98\x01\n\x06Bucket\x12+\n\x10cumulative_count\x18\x01\
+    \x20\x01(\x04R\x0fcumulativeCountB\0\x12!\n\x0bupper_bound\x18\x02\x20\
+    \x01(\x01R\nupperBoundB\0\x12<\n\x08exemplar\x18\x03\x20\x01(\x0b2\x1e.i\
+    o.prometheus.client.ExemplarR\x08exemplarB\0:\0\"\x99\x01\n\x08Exemplar\
+    \x127\n\x05label\x18\x01\x20\x03(\x0b2\x1f.io.prometheus.client.LabelPai\
+    rR\x05labelB\0\x12\x16\n\x05value\x18\x02\x20\x01(\x01R\x05valueB\0\x12:\
+    \n\ttimestamp\x18\x03\x20\x01(\x0b2\x1a.google.protobuf.TimestampR\ttime\
+    stampB\0:\0\"\x8f\x03\n\x06Metric\x127\n\x05label\x18\x01\x20\x03(\x0b2\
+    \x1f.io.prometheus.client.LabelPairR\x05labelB\0\x123\n\x05gauge\x18\x02\
+    \x20\x01(\x0b2\x1b.io.prometheus.client.GaugeR\x05gaugeB\0\x129\n\x07cou\
+    nter\x18\x03\x20\x01(\x0b2\x1d.io.prometheus.client.CounterR\x07counterB\
+    \0\x129\n\x07summary\x18\x04\x20\x01(\x0b2\x1d.io.prometheus.client.Summ\
+    aryR\x07summaryB\0\x129\n\x07untyped\x18\x05\x20\x01(\x0b2\x1d.io.promet\
+    heus.client.UntypedR\x07untypedB\0\x12?\n\thistogram\x18\x07\x20\x01(\
+    \x0b2\x1f.io.prometheus.client.HistogramR\thistogramB\0\x12#\n\x0ctimest\
+    amp_ms\x18\x06\x20\x01(\x03R\x0btimestampMsB\0:\0\"\xac\x01\n\x0cMetricF\
+    amily\x12\x14\n\x04name\x18\x01\x20\x01(\tR\x04nameB\0\x12\x14\n\x04help\
+    \x18\x02\x20\x01(\tR\x04helpB\0\x126\n\x04type\x18\x03\x20\x01(\x0e2\x20\
+    .io.prometheus.client.MetricTypeR\x04typeB\0\x126\n\x06metric\x18\x04\
+    \x20\x03(\x0b2\x1c.io.prometheus.client.MetricR\x06metricB\0:\0*O\n\nMet\
+    ricType\x12\x0b\n\x07COUNTER\x10\0\x12\t\n\x05GAUGE\x10\x01\x12\x0b\n\
+    \x07SUMMARY\x10\x02\x12\x0b\n\x07UNTYPED\x10\x03\x12\r\n\tHISTOGRAM\x10\
+    \x04\x1a\0B\0b\x06proto2\
 ";
 
-static mut file_descriptor_proto_lazy: ::protobuf::lazy::Lazy<::protobuf::descriptor::FileDescriptorProto> = ::protobuf::lazy::Lazy {
-    lock: ::protobuf::lazy::ONCE_INIT,
-    ptr: 0 as *const ::protobuf::descriptor::FileDescriptorProto,
-};
+static file_descriptor_proto_lazy: ::protobuf::rt::LazyV2<::protobuf::descriptor::FileDescriptorProto> = ::protobuf::rt::LazyV2::INIT;
 
 fn parse_descriptor_proto() -> ::protobuf::descriptor::FileDescriptorProto {
-    ::protobuf::parse_from_bytes(file_descriptor_proto_data).unwrap()
+    ::protobuf::Message::parse_from_bytes(file_descriptor_proto_data).unwrap()
 }
 
 pub fn file_descriptor_proto() -> &'static ::protobuf::descriptor::FileDescriptorProto {
-    unsafe {
-        file_descriptor_proto_lazy.get(|| {
-            parse_descriptor_proto()
-        })
-    }
+    file_descriptor_proto_lazy.get(|| {
+        parse_descriptor_proto()
+    })
 }
@@ -7,7 +7,7 @@ use std::ops::*;
 use std::sync::atomic::{AtomicI64 as StdAtomicI64, AtomicU64 as StdAtomicU64, Ordering};
 
 /// An interface for numbers. Used to generically model float metrics and integer metrics, i.e.
-/// [`Counter`](crate::Counter) and [`IntCounter`](crate::Counter).
+/// [`Counter`](crate::Counter) and [`IntCounter`](crate::IntCounter).
 pub trait Number:
     Sized + AddAssign + SubAssign + PartialOrd + PartialEq + Copy + Send + Sync
 {
@@ -68,9 +68,47 @@ pub trait Atomic: Send + Sync {
     fn inc_by(&self, delta: Self::T);
     /// Decrement the value by a given amount.
     fn dec_by(&self, delta: Self::T);
+    /// Set the value to the provided value, returning the previous value.
+    ///
+    /// The default implementation is not atomic, an update between reading
+    /// and setting the value is lost. The atomics of this crate override it
+    /// with an atomic swap.
+    fn swap(&self, val: Self::T) -> Self::T {
+        let previous = self.get();
+        self.set(val);
+        previous
+    }
+    /// Increment the value by a given amount, returning the previous value.
+    ///
+    /// The default implementation is not atomic, the returned value may
+    /// already include concurrent updates, which are not lost though. The
+    /// atomics of this crate override it with an atomic increment.
+    fn fetch_add(&self, delta: Self::T) -> Self::T {
+        let previous = self.get();
+        self.inc_by(delta);
+        previous
+    }
 }
 
 /// A atomic float.
+///
+/// This is the lock-free primitive backing [`Counter`](crate::Counter) and
+/// [`Gauge`](crate::Gauge), it can be used to build custom metrics.
+///
+/// # Examples
+///
+/// ```
+/// use prometheus::core::{Atomic, AtomicF64};
+///
+/// let bytes = AtomicF64::new(0.0);
+/// bytes.inc_by(1.5);
+/// bytes.inc_by(2.5);
+/// assert_eq!(bytes.get(), 4.0);
+///
+/// bytes.dec_by(1.0);
+/// bytes.fetch_max(10.0);
+/// assert_eq!(bytes.get(), 10.0);
+/// ```
 #[derive(Debug)]
 pub struct AtomicF64 {
     inner: StdAtomicU64,
@@ -107,6 +145,21 @@ impl Atomic for AtomicF64 {
 
     #[inline]
     fn inc_by(&self, delta: Self::T) {
+        self.fetch_add(delta);
+    }
+
+    #[inline]
+    fn dec_by(&self, delta: Self::T) {
+        self.inc_by(-delta);
+    }
+
+    #[inline]
+    fn swap(&self, val: Self::T) -> Self::T {
+        self.swap(val, Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn fetch_add(&self, delta: Self::T) -> Self::T {
         loop {
             let current = self.inner.load(Ordering::Acquire);
             let new = u64_to_f64(current) + delta;
@@ -117,15 +170,10 @@ impl Atomic for AtomicF64 {
                 Ordering::Relaxed,
             );
             if result.is_ok() {
-                return;
+                return u64_to_f64(current);
             }
         }
     }
-
-    #[inline]
-    fn dec_by(&self, delta: Self::T) {
-        self.inc_by(-delta);
-    }
 }
 
 impl AtomicF64 {
@@ -133,6 +181,27 @@ impl AtomicF64 {
     pub fn swap(&self, val: f64, ordering: Ordering) -> f64 {
         u64_to_f64(self.inner.swap(f64_to_u64(val), ordering))
     }
+
+    /// Store the maximum of the value and `val`, returning the previous value.
+    #[inline]
+    pub fn fetch_max(&self, val: f64) -> f64 {
+        loop {
+            let current = self.inner.load(Ordering::Acquire);
+            let previous = u64_to_f64(current);
+            if val <= previous || val.is_nan() {
+                return previous;
+            }
+            let result = self.inner.compare_exchange_weak(
+                current,
+                f64_to_u64(val),
+                Ordering::Release,
+                Ordering::Relaxed,
+            );
+            if result.is_ok() {
+                return previous;
+            }
+        }
+    }
 }
 
 /// A atomic signed integer.
@@ -169,6 +238,16 @@ impl Atomic for AtomicI64 {
     fn dec_by(&self, delta: Self::T) {
         self.inner.fetch_sub(delta, Ordering::Relaxed);
     }
+
+    #[inline]
+    fn swap(&self, val: Self::T) -> Self::T {
+        self.inner.swap(val, Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn fetch_add(&self, delta: Self::T) -> Self::T {
+        self.inner.fetch_add(delta, Ordering::Relaxed)
+    }
 }
 
 /// A atomic unsigned integer.
@@ -205,6 +284,16 @@ impl Atomic for AtomicU64 {
     fn dec_by(&self, delta: Self::T) {
         self.inner.fetch_sub(delta, Ordering::Relaxed);
     }
+
+    #[inline]
+    fn swap(&self, val: Self::T) -> Self::T {
+        self.swap(val, Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn fetch_add(&self, delta: Self::T) -> Self::T {
+        self.inner.fetch_add(delta, Ordering::Relaxed)
+    }
 }
 
 impl AtomicU64 {
@@ -242,7 +331,6 @@ impl AtomicU64 {
 #[cfg(test)]
 mod test {
     use std::f64::consts::PI;
-    use std::f64::{self, EPSILON};
 
     use super::*;
 
@@ -251,10 +339,24 @@ mod test {
         let table: Vec<f64> = vec![0.0, 1.0, PI, f64::MIN, f64::MAX];
 
         for f in table {
-            assert!((f - AtomicF64::new(f).get()).abs() < EPSILON);
+            assert!((f - AtomicF64::new(f).get()).abs() < f64::EPSILON);
         }
     }
 
+    #[test]
+    fn test_atomic_f64_fetch() {
+        let af64 = AtomicF64::new(1.0);
+        assert_eq!(af64.fetch_add(2.0), 1.0);
+        assert_eq!(af64.get(), 3.0);
+
+        assert_eq!(af64.fetch_max(2.0), 3.0);
+        assert_eq!(af64.get(), 3.0);
+        assert_eq!(af64.fetch_max(4.0), 3.0);
+        assert_eq!(af64.get(), 4.0);
+        af64.fetch_max(f64::NAN);
+        assert_eq!(af64.get(), 4.0);
+    }
+
     #[test]
     fn test_atomic_i64() {
         let ai64 = AtomicI64::new(0);
@@ -275,4 +377,63 @@ mod test {
         au64.inc_by(123);
         assert_eq!(au64.get(), 123);
     }
+
+    #[test]
+    fn test_atomic_swap() {
+        let af64 = AtomicF64::new(1.5);
+        assert_eq!(Atomic::swap(&af64, 2.5), 1.5);
+        assert_eq!(af64.get(), 2.5);
+
+        let ai64 = AtomicI64::new(-3);
+        assert_eq!(ai64.swap(4), -3);
+        assert_eq!(ai64.get(), 4);
+
+        let au64 = AtomicU64::new(5);
+        assert_eq!(Atomic::swap(&au64, 6), 5);
+        assert_eq!(au64.get(), 6);
+    }
+
+    #[test]
+    fn test_atomic_fetch_add() {
+        let af64 = AtomicF64::new(1.5);
+        assert_eq!(af64.fetch_add(2.0), 1.5);
+        assert_eq!(af64.get(), 3.5);
+
+        let ai64 = AtomicI64::new(-3);
+        assert_eq!(ai64.fetch_add(4), -3);
+        assert_eq!(ai64.get(), 1);
+
+        let au64 = AtomicU64::new(5);
+        assert_eq!(au64.fetch_add(6), 5);
+        assert_eq!(au64.get(), 11);
+    }
+
+    #[test]
+    fn test_atomic_default_methods() {
+        // An implementation with only the required methods.
+        struct Minimal(AtomicI64);
+        impl Atomic for Minimal {
+            type T = i64;
+            fn new(val: i64) -> Self {
+                Minimal(AtomicI64::new(val))
+            }
+            fn set(&self, val: i64) {
+                self.0.set(val)
+            }
+            fn get(&self) -> i64 {
+                self.0.get()
+            }
+            fn inc_by(&self, delta: i64) {
+                self.0.inc_by(delta)
+            }
+            fn dec_by(&self, delta: i64) {
+                self.0.dec_by(delta)
+            }
+        }
+
+        let m = Minimal::new(1);
+        assert_eq!(m.fetch_add(2), 1);
+        assert_eq!(m.swap(5), 3);
+        assert_eq!(m.get(), 5);
+    }
 }
@@ -0,0 +1,658 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Re-expose the metrics printed by an external command.
+//!
+//! This module is only available with the `command` feature.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::desc::Desc;
+use crate::errors::{Error, Result};
+use crate::gauge::IntGauge;
+use crate::metrics::{Collector, Opts};
+use crate::proto::{self, MetricFamily, MetricType};
+
+/// The default time a command may take before it is killed.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often to check whether the command exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// A collector which runs a command on each collection and exposes the metrics
+/// it prints to stdout in the Prometheus text format, e.g. to wrap a script
+/// based exporter.
+///
+/// If the command can not be run, fails, times out or prints invalid text,
+/// no metrics of the command are collected. Along with them, a
+/// `command_success` gauge labeled with the program tells whether the last run
+/// succeeded. Use [`success_name`](CommandCollector::success_name) to rename
+/// it, e.g. to register two collectors running the same program.
+///
+/// The metrics of the command are not described by [`desc`](Collector::desc),
+/// so they are not checked for consistency against other collectors.
+#[derive(Debug)]
+pub struct CommandCollector {
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+    success: IntGauge,
+}
+
+impl CommandCollector {
+    /// Create a `CommandCollector` running `program` with `args`.
+    pub fn new<S: Into<String>>(program: S, args: Vec<String>) -> Result<CommandCollector> {
+        let program = program.into();
+        let success = success_gauge("command_success", &program)?;
+
+        Ok(CommandCollector {
+            program,
+            args,
+            timeout: DEFAULT_TIMEOUT,
+            success,
+        })
+    }
+
+    /// Name the gauge telling whether the last run succeeded `name` instead of
+    /// `command_success`.
+    pub fn success_name<S: Into<String>>(mut self, name: S) -> Result<CommandCollector> {
+        self.success = success_gauge(name, &self.program)?;
+        Ok(self)
+    }
+
+    /// Kill the command if it does not finish within `timeout`, 5 seconds by
+    /// default.
+    pub fn timeout(mut self, timeout: Duration) -> CommandCollector {
+        self.timeout = timeout;
+        self
+    }
+
+    /// `run` runs the command and returns its stdout, or `None` if it failed
+    /// or timed out.
+    fn run(&self) -> Option<String> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+
+        // Read stdout on another thread, so that neither a full pipe nor a
+        // command which never closes it can block past the timeout.
+        let mut stdout = child.stdout.take()?;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut output = String::new();
+            let _ = tx.send(stdout.read_to_string(&mut output).map(|_| output));
+        });
+
+        let deadline = Instant::now() + self.timeout;
+        let output = rx.recv_timeout(self.timeout);
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break Some(status),
+                Ok(None) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+                _ => break None,
+            }
+        };
+
+        match (output, status) {
+            (Ok(Ok(output)), Some(status)) if status.success() => Some(output),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                None
+            }
+        }
+    }
+}
+
+fn success_gauge<S: Into<String>>(name: S, program: &str) -> Result<IntGauge> {
+    IntGauge::with_opts(
+        Opts::new(name, "Whether the last run of the command succeeded.")
+            .const_label("command", program),
+    )
+}
+
+impl Collector for CommandCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.success.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let mfs = self
+            .run()
+            .and_then(|output| parse_text_format(&output).ok());
+
+        self.success.set(mfs.is_some() as i64);
+        let mut mfs = mfs.unwrap_or_default();
+        mfs.extend(self.success.collect());
+        mfs
+    }
+}
+
+/// A sample of the text format: its name, sorted labels, value and timestamp.
+type Sample = (String, Vec<(String, String)>, f64, Option<i64>);
+
+#[derive(Default)]
+struct ParsedMetric {
+    labels: Vec<(String, String)>,
+    value: f64,
+    timestamp_ms: Option<i64>,
+    buckets: Vec<(f64, u64)>,
+    quantiles: Vec<(f64, f64)>,
+    sum: f64,
+    count: u64,
+}
+
+struct ParsedFamily {
+    name: String,
+    help: String,
+    metric_type: MetricType,
+    metrics: Vec<ParsedMetric>,
+    metrics_by_labels: HashMap<Vec<(String, String)>, usize>,
+}
+
+impl ParsedFamily {
+    fn new(name: &str) -> ParsedFamily {
+        ParsedFamily {
+            name: name.to_owned(),
+            help: String::new(),
+            metric_type: MetricType::UNTYPED,
+            metrics: Vec::new(),
+            metrics_by_labels: HashMap::new(),
+        }
+    }
+
+    /// `metric` returns the metric with the given labels, creating it if
+    /// needed.
+    fn metric(&mut self, labels: Vec<(String, String)>) -> &mut ParsedMetric {
+        let metrics = &mut self.metrics;
+        let i = *self
+            .metrics_by_labels
+            .entry(labels.clone())
+            .or_insert_with(|| {
+                metrics.push(ParsedMetric {
+                    labels,
+                    ..ParsedMetric::default()
+                });
+                metrics.len() - 1
+            });
+        &mut self.metrics[i]
+    }
+
+    fn add_sample(
+        &mut self,
+        suffix: &str,
+        mut labels: Vec<(String, String)>,
+        value: f64,
+    ) -> Result<()> {
+        match (self.metric_type, suffix) {
+            (MetricType::HISTOGRAM, "_bucket") | (MetricType::SUMMARY, "") => {
+                let label = if self.metric_type == MetricType::HISTOGRAM {
+                    "le"
+                } else {
+                    "quantile"
+                };
+                let i = labels
+                    .iter()
+                    .position(|(name, _)| name == label)
+                    .ok_or_else(|| {
+                        Error::Msg(format!("missing {} label in {}", label, self.name))
+                    })?;
+                let bound = parse_float(&labels.remove(i).1)?;
+                let metric = self.metric(labels);
+                if label == "le" {
+                    // The +Inf bucket is implied by the sample count.
+                    if bound == f64::INFINITY {
+                        metric.count = value as u64;
+                    } else {
+                        metric.buckets.push((bound, value as u64));
+                    }
+                } else {
+                    metric.quantiles.push((bound, value));
+                }
+            }
+            (MetricType::HISTOGRAM, "_sum") | (MetricType::SUMMARY, "_sum") => {
+                self.metric(labels).sum = value
+            }
+            (MetricType::HISTOGRAM, "_count") | (MetricType::SUMMARY, "_count") => {
+                self.metric(labels).count = value as u64
+            }
+            (MetricType::COUNTER, "") | (MetricType::GAUGE, "") | (MetricType::UNTYPED, "") => {
+                self.metric(labels).value = value
+            }
+            _ => {
+                return Err(Error::Msg(format!(
+                    "unexpected sample {}{}",
+                    self.name, suffix
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    #[allow(deprecated)]
+    fn into_metric_family(self) -> MetricFamily {
+        let metric_type = self.metric_type;
+        let metrics = self
+            .metrics
+            .into_iter()
+            .map(|pm| {
+                let mut m = proto::Metric::default();
+                let labels = pm
+                    .labels
+                    .into_iter()
+                    .map(|(name, value)| {
+                        let mut lp = proto::LabelPair::default();
+                        lp.set_name(name);
+                        lp.set_value(value);
+                        lp
+                    })
+                    .collect();
+                m.set_label(from_vec!(labels));
+                if let Some(timestamp_ms) = pm.timestamp_ms {
+                    m.set_timestamp_ms(timestamp_ms);
+                }
+                match metric_type {
+                    MetricType::COUNTER => {
+                        let mut c = proto::Counter::default();
+                        c.set_value(pm.value);
+                        m.set_counter(c);
+                    }
+                    MetricType::GAUGE => {
+                        let mut g = proto::Gauge::default();
+                        g.set_value(pm.value);
+                        m.set_gauge(g);
+                    }
+                    MetricType::HISTOGRAM => {
+                        let mut h = proto::Histogram::default();
+                        let buckets = pm
+                            .buckets
+                            .into_iter()
+                            .map(|(upper_bound, count)| {
+                                let mut b = proto::Bucket::default();
+                                b.set_upper_bound(upper_bound);
+                                b.set_cumulative_count(count);
+                                b
+                            })
+                            .collect();
+                        h.set_bucket(from_vec!(buckets));
+                        h.set_sample_sum(pm.sum);
+                        h.set_sample_count(pm.count);
+                        m.set_histogram(h);
+                    }
+                    MetricType::SUMMARY => {
+                        let mut s = proto::Summary::default();
+                        let quantiles = pm
+                            .quantiles
+                            .into_iter()
+                            .map(|(quantile, value)| {
+                                let mut q = proto::Quantile::default();
+                                q.set_quantile(quantile);
+                                q.set_value(value);
+                                q
+                            })
+                            .collect();
+                        s.set_quantile(from_vec!(quantiles));
+                        s.set_sample_sum(pm.sum);
+                        s.set_sample_count(pm.count);
+                        m.set_summary(s);
+                    }
+                    MetricType::UNTYPED => {
+                        let mut u = proto::Untyped::default();
+                        u.set_value(pm.value);
+                        m.set_untyped(u);
+                    }
+                }
+                m
+            })
+            .collect();
+
+        let mut mf = MetricFamily::default();
+        mf.set_name(self.name);
+        mf.set_help(self.help);
+        mf.set_field_type(metric_type);
+        mf.set_metric(from_vec!(metrics));
+        mf
+    }
+}
+
+/// `parse_text_format` parses metric families from the Prometheus text
+/// format, in the order they first appear.
+fn parse_text_format(text: &str) -> Result<Vec<MetricFamily>> {
+    let mut families: Vec<ParsedFamily> = Vec::new();
+    let mut families_by_name: HashMap<String, usize> = HashMap::new();
+    let family = |families: &mut Vec<ParsedFamily>,
+                  families_by_name: &mut HashMap<String, usize>,
+                  name: &str|
+     -> usize {
+        *families_by_name.entry(name.to_owned()).or_insert_with(|| {
+            families.push(ParsedFamily::new(name));
+            families.len() - 1
+        })
+    };
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut parts = comment.trim_start().splitn(3, ' ');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some("HELP"), Some(name), help) => {
+                    let i = family(&mut families, &mut families_by_name, name);
+                    families[i].help = unescape(help.unwrap_or(""), false)?;
+                }
+                (Some("TYPE"), Some(name), Some(metric_type)) => {
+                    let i = family(&mut families, &mut families_by_name, name);
+                    families[i].metric_type = match metric_type.trim() {
+                        "counter" => MetricType::COUNTER,
+                        "gauge" => MetricType::GAUGE,
+                        "histogram" => MetricType::HISTOGRAM,
+                        "summary" => MetricType::SUMMARY,
+                        "untyped" => MetricType::UNTYPED,
+                        other => return Err(Error::Msg(format!("unknown metric type {}", other))),
+                    };
+                }
+                // Other comments are ignored.
+                _ => {}
+            }
+            continue;
+        }
+
+        let (name, labels, value, timestamp_ms) = parse_sample(line)?;
+        let mut target = None;
+        for suffix in &["_bucket", "_sum", "_count"] {
+            if let Some(base) = name.strip_suffix(suffix) {
+                if let Some(&i) = families_by_name.get(base) {
+                    if let MetricType::HISTOGRAM | MetricType::SUMMARY = families[i].metric_type {
+                        target = Some((i, *suffix));
+                    }
+                }
+            }
+        }
+        let (i, suffix) = match target {
+            Some(target) => target,
+            None => (family(&mut families, &mut families_by_name, &name), ""),
+        };
+
+        let f = &mut families[i];
+        f.add_sample(suffix, labels.clone(), value)?;
+        if suffix.is_empty() && f.metric_type != MetricType::SUMMARY {
+            f.metric(labels).timestamp_ms = timestamp_ms;
+        }
+    }
+
+    Ok(families
+        .into_iter()
+        .filter(|f| !f.metrics.is_empty())
+        .map(ParsedFamily::into_metric_family)
+        .collect())
+}
+
+/// `parse_sample` parses a sample line, e.g. `name{label="value"} 1 1000`.
+fn parse_sample(line: &str) -> Result<Sample> {
+    let invalid = || Error::Msg(format!("invalid sample line {:?}", line));
+
+    let name_end = line
+        .find(|c: char| c == '{' || c.is_whitespace())
+        .ok_or_else(invalid)?;
+    let name = &line[..name_end];
+    let mut rest = &line[name_end..];
+
+    let mut labels = Vec::new();
+    if let Some(mut s) = rest.strip_prefix('{') {
+        loop {
+            s = s.trim_start();
+            if let Some(after) = s.strip_prefix('}') {
+                rest = after;
+                break;
+            }
+            let eq = s.find('=').ok_or_else(invalid)?;
+            let label_name = s[..eq].trim();
+            s = s[eq + 1..]
+                .trim_start()
+                .strip_prefix('"')
+                .ok_or_else(invalid)?;
+
+            // Find the closing quote, skipping escaped characters.
+            let mut end = None;
+            let mut escaped = false;
+            for (i, c) in s.char_indices() {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => {
+                        end = Some(i);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            let end = end.ok_or_else(invalid)?;
+            labels.push((label_name.to_owned(), unescape(&s[..end], true)?));
+            s = s[end + 1..].trim_start();
+            s = s.strip_prefix(',').unwrap_or(s);
+        }
+    }
+    labels.sort();
+
+    let mut fields = rest.split_whitespace();
+    let value = parse_float(fields.next().ok_or_else(invalid)?)?;
+    let timestamp_ms = match fields.next() {
+        Some(ts) => Some(ts.parse().map_err(|_| invalid())?),
+        None => None,
+    };
+    if fields.next().is_some() {
+        return Err(invalid());
+    }
+
+    Ok((name.to_owned(), labels, value, timestamp_ms))
+}
+
+fn parse_float(s: &str) -> Result<f64> {
+    match s {
+        "+Inf" | "Inf" => Ok(f64::INFINITY),
+        "-Inf" => Ok(f64::NEG_INFINITY),
+        "NaN" => Ok(f64::NAN),
+        _ => s
+            .parse()
+            .map_err(|_| Error::Msg(format!("invalid value {:?}", s))),
+    }
+}
+
+/// `unescape` reverts the escaping of help strings, and of label values if
+/// `include_double_quote` is true.
+fn unescape(s: &str, include_double_quote: bool) -> Result<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('n') => unescaped.push('\n'),
+            Some('"') if include_double_quote => unescaped.push('"'),
+            // Unknown escape sequences are kept as they are.
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => return Err(Error::Msg(format!("invalid escaping in {:?}", s))),
+        }
+    }
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counter::CounterVec;
+    use crate::encoder::{Encoder, TextEncoder};
+    use crate::histogram::{Histogram, HistogramOpts};
+    use crate::registry::Registry;
+
+    #[test]
+    fn test_command_collector() {
+        let cc = CommandCollector::new(
+            "echo",
+            vec!["# TYPE test_counter counter\ntest_counter{a=\"1\"} 42".to_owned()],
+        )
+        .unwrap();
+        let r = Registry::new();
+        r.register(Box::new(cc)).unwrap();
+
+        let mfs = r.gather();
+        assert_eq!(mfs.len(), 2);
+        assert_eq!(mfs[0].get_name(), "command_success");
+        assert_eq!(mfs[0].get_metric()[0].get_gauge().get_value(), 1.0);
+        assert_eq!(mfs[1].get_name(), "test_counter");
+        assert_eq!(mfs[1].get_field_type(), MetricType::COUNTER);
+        let m = &mfs[1].get_metric()[0];
+        assert_eq!(m.get_label()[0].get_name(), "a");
+        assert_eq!(m.get_label()[0].get_value(), "1");
+        assert_eq!(m.get_counter().get_value(), 42.0);
+
+        // A command without metrics still succeeds.
+        let mfs = CommandCollector::new("true", vec![]).unwrap().collect();
+        assert_eq!(mfs.len(), 1);
+        assert_eq!(mfs[0].get_metric()[0].get_gauge().get_value(), 1.0);
+    }
+
+    #[test]
+    fn test_command_collector_untyped() {
+        let cc =
+            CommandCollector::new("echo", vec!["legacy_metric{a=\"1\"} 1".to_owned()]).unwrap();
+        let r = Registry::new();
+        r.register(Box::new(cc)).unwrap();
+
+        let mut text = Vec::new();
+        TextEncoder::new().encode(&r.gather(), &mut text).unwrap();
+        assert!(std::str::from_utf8(&text)
+            .unwrap()
+            .contains("# TYPE legacy_metric untyped\nlegacy_metric{a=\"1\"} 1\n"));
+    }
+
+    #[test]
+    fn test_command_collector_success_name() {
+        let r = Registry::new();
+        let cc = CommandCollector::new("echo", vec!["a".to_owned()]).unwrap();
+        r.register(Box::new(cc)).unwrap();
+        let cc = CommandCollector::new("echo", vec!["b".to_owned()]).unwrap();
+        assert!(r.register(Box::new(cc)).is_err());
+
+        let cc = CommandCollector::new("echo", vec!["b".to_owned()])
+            .unwrap()
+            .success_name("echo_b_success")
+            .unwrap();
+        r.register(Box::new(cc)).unwrap();
+        let names: Vec<_> = r
+            .gather()
+            .iter()
+            .map(|mf| mf.get_name().to_owned())
+            .collect();
+        assert_eq!(names, vec!["command_success", "echo_b_success"]);
+        assert!(CommandCollector::new("true", vec![])
+            .unwrap()
+            .success_name("invalid name")
+            .is_err());
+    }
+
+    #[test]
+    fn test_command_collector_failure() {
+        for cc in &[
+            CommandCollector::new("false", vec![]).unwrap(),
+            CommandCollector::new("echo", vec!["invalid{".to_owned()]).unwrap(),
+            CommandCollector::new("/nonexistent/command", vec![]).unwrap(),
+            CommandCollector::new("sleep", vec!["10".to_owned()])
+                .unwrap()
+                .timeout(Duration::from_millis(100)),
+        ] {
+            let start = Instant::now();
+            let mfs = cc.collect();
+            assert!(start.elapsed() < Duration::from_secs(5));
+            assert_eq!(mfs.len(), 1, "{:?}", cc);
+            assert_eq!(mfs[0].get_metric()[0].get_gauge().get_value(), 0.0);
+        }
+    }
+
+    #[test]
+    fn test_parse_text_format_roundtrip() {
+        let vec = CounterVec::new(
+            Opts::new("test_vec", "test \\ help\nwith newline"),
+            &["a", "b"],
+        )
+        .unwrap();
+        vec.with_label_values(&["1", "quote \" backslash \\ newline \n"])
+            .inc_by(2.5);
+        vec.with_label_values(&["2", ""]).inc();
+        let histogram = Histogram::with_opts(
+            HistogramOpts::new("test_histogram", "test help").buckets(vec![1.0, 2.0]),
+        )
+        .unwrap();
+        histogram.observe(0.5);
+        histogram.observe(3.0);
+
+        let mut mfs = vec.collect();
+        mfs.extend(histogram.collect());
+        let mut text = Vec::new();
+        TextEncoder::new().encode(&mfs, &mut text).unwrap();
+
+        let parsed = parse_text_format(std::str::from_utf8(&text).unwrap()).unwrap();
+        let mut reencoded = Vec::new();
+        TextEncoder::new().encode(&parsed, &mut reencoded).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&reencoded).unwrap(),
+            std::str::from_utf8(&text).unwrap()
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_parse_text_format() {
+        let text = r#"
+# HELP test_summary A summary.
+# TYPE test_summary summary
+test_summary{quantile="0.5"} 1
+test_summary{quantile="0.9"} +Inf
+test_summary_sum 10
+test_summary_count 3
+# A comment.
+test_untyped{a="1",} 1 1000
+"#;
+        let mfs = parse_text_format(text).unwrap();
+        assert_eq!(mfs.len(), 2);
+
+        assert_eq!(mfs[0].get_help(), "A summary.");
+        let s = mfs[0].get_metric()[0].get_summary();
+        assert_eq!(s.get_quantile().len(), 2);
+        assert_eq!(s.get_quantile()[1].get_quantile(), 0.9);
+        assert_eq!(s.get_quantile()[1].get_value(), f64::INFINITY);
+        assert_eq!(s.get_sample_sum(), 10.0);
+        assert_eq!(s.get_sample_count(), 3);
+
+        assert_eq!(mfs[1].get_field_type(), MetricType::UNTYPED);
+        assert_eq!(mfs[1].get_metric()[0].get_untyped().get_value(), 1.0);
+        assert_eq!(mfs[1].get_metric()[0].get_timestamp_ms(), 1000);
+
+        for invalid in &[
+            "test",
+            "test{a=\"1\" 1",
+            "test{a=1} 1",
+            "test one",
+            "test 1 2 3",
+        ] {
+            assert!(parse_text_format(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
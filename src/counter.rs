@@ -2,22 +2,43 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
 use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::marker::PhantomData;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{Arc, Weak};
+
+use parking_lot::Mutex;
 
 use crate::atomic64::{Atomic, AtomicF64, AtomicU64, Number};
 use crate::desc::Desc;
-use crate::errors::Result;
-use crate::metrics::{Collector, LocalMetric, Metric, Opts};
+use crate::errors::{Error, Result};
+use crate::metrics::{Collector, LocalMetric, Metric, MetricSnapshotLine, MetricVisitor, Opts};
 use crate::proto;
-use crate::value::{Value, ValueType};
+use crate::registry::{default_registry, Registry};
+use crate::timer;
+use crate::value::{make_timestamp, Value, ValueType};
 use crate::vec::{MetricVec, MetricVecBuilder};
 
 /// The underlying implementation for [`Counter`] and [`IntCounter`].
 #[derive(Debug)]
 pub struct GenericCounter<P: Atomic> {
     v: Arc<Value<P>>,
+    state: Arc<CounterState<P>>,
+}
+
+/// The state only counters keep next to their [`Value`].
+#[derive(Debug)]
+struct CounterState<P: Atomic> {
+    /// Seconds since the Unix epoch when the counter was created or last
+    /// reset.
+    created: AtomicF64,
+    /// Whether the counter was ever reset, from when on the creation time is
+    /// exposed as its created timestamp.
+    reset: AtomicBool,
+    /// The values of the flushable local counters issued for the counter, see
+    /// [`GenericCounter::flush_all_locals`].
+    locals: Mutex<Vec<Weak<P>>>,
 }
 
 /// A [`Metric`] represents a single numerical value that only ever goes up.
@@ -31,11 +52,23 @@ impl<P: Atomic> Clone for GenericCounter<P> {
     fn clone(&self) -> Self {
         Self {
             v: Arc::clone(&self.v),
+            state: Arc::clone(&self.state),
         }
     }
 }
 
 impl<P: Atomic> GenericCounter<P> {
+    fn from_value(v: Value<P>) -> Self {
+        Self {
+            v: Arc::new(v),
+            state: Arc::new(CounterState {
+                created: AtomicF64::new(timer::unix_secs()),
+                reset: AtomicBool::new(false),
+                locals: Mutex::new(vec![]),
+            }),
+        }
+    }
+
     /// Create a [`GenericCounter`] with the `name` and `help` arguments.
     pub fn new<S1: Into<String>, S2: Into<String>>(name: S1, help: S2) -> Result<Self> {
         let opts = Opts::new(name, help);
@@ -47,9 +80,46 @@ impl<P: Atomic> GenericCounter<P> {
         Self::with_opts_and_label_values(&opts, &[])
     }
 
+    /// Create a [`GenericCounter`] with the `opts` options, starting at
+    /// `value` instead of 0, e.g. to reflect a count which already exists at
+    /// startup. An error is returned if `value` is negative.
+    pub fn with_initial_value(opts: Opts, value: P::T) -> Result<Self> {
+        // NaN is not comparable and rejected as well.
+        let zero = P::T::from_i64(0);
+        if matches!(value.partial_cmp(&zero), None | Some(Ordering::Less)) {
+            return Err(Error::Msg(format!(
+                "initial value of counter {} must not be negative",
+                opts.fq_name()
+            )));
+        }
+        let v = Value::new(&opts, ValueType::Counter, value, &[])?;
+        Ok(Self::from_value(v))
+    }
+
+    /// Create a [`GenericCounter`] with the `opts` options and register it to
+    /// `registry`, returning the registered counter.
+    pub fn register(opts: Opts, registry: &Registry) -> Result<Self>
+    where
+        P: 'static,
+    {
+        let counter = Self::with_opts(opts)?;
+        registry.register(Box::new(counter.clone()))?;
+        Ok(counter)
+    }
+
+    /// Create a [`GenericCounter`] with the `opts` options and register it to the
+    /// [default registry](crate::default_registry), returning the registered
+    /// counter.
+    pub fn register_default(opts: Opts) -> Result<Self>
+    where
+        P: 'static,
+    {
+        Self::register(opts, default_registry())
+    }
+
     fn with_opts_and_label_values(opts: &Opts, label_values: &[&str]) -> Result<Self> {
         let v = Value::new(opts, ValueType::Counter, P::T::from_i64(0), label_values)?;
-        Ok(Self { v: Arc::new(v) })
+        Ok(Self::from_value(v))
     }
 
     /// Increase the given value to the counter.
@@ -63,28 +133,177 @@ impl<P: Atomic> GenericCounter<P> {
         self.v.inc_by(v);
     }
 
+    /// Increase the given value to the counter, converting it first. This
+    /// avoids casts for smaller numeric types, e.g. `u32` byte counts. Types
+    /// whose conversion may lose precision, like `usize` or `u64` into `f64`,
+    /// have no such conversion and still need an explicit cast.
+    ///
+    /// # Panics
+    ///
+    /// Panics in debug build if the value is < 0.
+    #[inline]
+    pub fn add<N: Into<P::T>>(&self, v: N) {
+        self.inc_by(v.into());
+    }
+
+    /// Increase the counter by all values of `it`. The values are summed up
+    /// first, so the counter is only updated once.
+    ///
+    /// # Panics
+    ///
+    /// Panics in debug build if a value is < 0.
+    pub fn inc_iter<I: IntoIterator<Item = P::T>>(&self, it: I) {
+        let zero = P::T::from_i64(0);
+        let mut sum = zero;
+        for v in it {
+            debug_assert!(v >= zero);
+            sum += v;
+        }
+        if sum != zero {
+            self.v.inc_by(sum);
+        }
+    }
+
     /// Increase the counter by 1.
     #[inline]
     pub fn inc(&self) {
         self.v.inc();
     }
 
+    /// Increase the given value to the counter and return the new value.
+    ///
+    /// Unlike an [`inc_by`](GenericCounter::inc_by) followed by a
+    /// [`get`](GenericCounter::get), the value is read by the same atomic
+    /// operation, so concurrent callers never see the same value, e.g. to act
+    /// on every Nth event.
+    ///
+    /// # Panics
+    ///
+    /// Panics in debug build if the value is < 0.
+    #[inline]
+    pub fn inc_by_return(&self, v: P::T) -> P::T {
+        debug_assert!(v >= P::T::from_i64(0));
+        self.v.inc_by_return(v)
+    }
+
+    /// Increase the counter by 1 and return the new value, see
+    /// [`inc_by_return`](GenericCounter::inc_by_return).
+    #[inline]
+    pub fn inc_return(&self) -> P::T {
+        self.v.inc_by_return(P::T::from_i64(1))
+    }
+
     /// Return the counter value.
     #[inline]
     pub fn get(&self) -> P::T {
         self.v.get()
     }
 
-    /// Restart the counter, resetting its value back to 0.
+    /// Restart the counter, resetting its value back to 0 and its creation
+    /// time to now. From the first reset on, the creation time is exposed as
+    /// the created timestamp, so scrapers can tell the reset apart from a drop
+    /// of the value. Counters which are never reset are exposed without one.
     #[inline]
     pub fn reset(&self) {
-        self.v.set(P::T::from_i64(0))
+        self.v.reset();
+        self.advance_created();
+    }
+
+    /// Atomically read the counter value and reset it back to 0, returning the
+    /// value accumulated since the counter was created or last reset.
+    ///
+    /// Unlike a [`get`](GenericCounter::get) followed by a
+    /// [`reset`](GenericCounter::reset), no increment in between is lost, so
+    /// it can be used to report per-interval deltas.
+    #[inline]
+    pub fn swap_reset(&self) -> P::T {
+        let v = self.v.swap_reset();
+        self.advance_created();
+        v
+    }
+
+    /// `advance_created` moves the creation time to now. It never goes
+    /// backwards, even if the system clock does.
+    fn advance_created(&self) {
+        self.state.created.fetch_max(timer::unix_secs());
+        self.state.reset.store(true, AtomicOrdering::Relaxed);
+    }
+
+    /// `fill_created` sets the created timestamp of the counter `m` if the
+    /// counter was ever reset.
+    fn fill_created(&self, m: &mut proto::Metric) {
+        if self.state.reset.load(AtomicOrdering::Relaxed) {
+            *m.mut_counter().mut_created_timestamp() = make_timestamp(self.state.created.get());
+        }
+    }
+
+    /// Return the time the counter was created or last reset, in seconds
+    /// since the Unix epoch. It never decreases.
+    #[inline]
+    pub fn created_at(&self) -> f64 {
+        self.state.created.get()
+    }
+
+    /// Return a readable [`MetricSnapshotLine`] of the counter, e.g. for
+    /// logging its current value.
+    pub fn snapshot(&self) -> MetricSnapshotLine {
+        MetricSnapshotLine::new(
+            &self.v.desc.fq_name,
+            proto::MetricType::COUNTER,
+            Metric::metric(self),
+        )
     }
 
     /// Return a [`GenericLocalCounter`] for single thread usage.
     pub fn local(&self) -> GenericLocalCounter<P> {
         GenericLocalCounter::new(self.clone())
     }
+
+    /// Return a [`GenericFlushableLocalCounter`] for single thread usage,
+    /// which [`flush_all_locals`](GenericCounter::flush_all_locals) can flush
+    /// from any thread.
+    pub fn flushable_local(&self) -> GenericFlushableLocalCounter<P> {
+        GenericFlushableLocalCounter::new(self.clone())
+    }
+
+    /// Flush all outstanding [`GenericFlushableLocalCounter`]s of this
+    /// counter, on whichever thread they live, e.g. right before a scrape
+    /// instead of relying on every thread to flush in time. Dropped local
+    /// counters have already flushed themselves. The unsync
+    /// [`GenericLocalCounter`]s are not tracked.
+    pub fn flush_all_locals(&self) {
+        let zero = P::T::from_i64(0);
+        let mut locals = self.state.locals.lock();
+        locals.retain(|local| match local.upgrade() {
+            Some(local) => {
+                let v = Atomic::swap(&*local, zero);
+                if v != zero {
+                    self.v.inc_by(v);
+                }
+                true
+            }
+            None => false,
+        });
+    }
+
+    /// Like [`collect`](Collector::collect), but refills `mf` in place instead
+    /// of allocating a new metric family, e.g. to reuse the same buffer for
+    /// every scrape. `mf` may be empty or hold the result of a previous call
+    /// for any metric, its allocations are only reused for the same one.
+    pub fn collect_into(&self, mf: &mut proto::MetricFamily) {
+        self.v.collect_into(mf);
+        self.fill_created(&mut mf.mut_metric()[0]);
+    }
+
+    /// Change the help string of the counter.
+    ///
+    /// The help can only be changed before the counter is registered, as a
+    /// [`Registry`] keeps the descriptor it was registered
+    /// with. It fails once the counter has been cloned, which registering it
+    /// requires.
+    pub fn set_help(&mut self, help: &str) -> Result<()> {
+        Value::set_help(&mut self.v, help)
+    }
 }
 
 impl<P: Atomic> Collector for GenericCounter<P> {
@@ -93,13 +312,29 @@ impl<P: Atomic> Collector for GenericCounter<P> {
     }
 
     fn collect(&self) -> Vec<proto::MetricFamily> {
-        vec![self.v.collect()]
+        let mut mf = self.v.collect();
+        self.fill_created(&mut mf.mut_metric()[0]);
+        vec![mf]
+    }
+
+    fn visit(&self, visitor: &mut dyn MetricVisitor) {
+        self.v.visit(visitor)
+    }
+
+    fn metric_type(&self, _: &Desc) -> Option<proto::MetricType> {
+        Some(self.v.val_type.metric_type())
     }
 }
 
 impl<P: Atomic> Metric for GenericCounter<P> {
     fn metric(&self) -> proto::Metric {
-        self.v.metric()
+        let mut m = self.v.metric();
+        self.fill_created(&mut m);
+        m
+    }
+
+    fn visit(&self, _: proto::MetricType, visitor: &mut dyn MetricVisitor) {
+        self.v.visit_metric(visitor)
     }
 }
 
@@ -219,6 +454,13 @@ impl<P: Atomic> GenericLocalCounter<P> {
         *self.val.borrow()
     }
 
+    /// Return the value of the shared [`Counter`], which does not include the
+    /// unflushed local value.
+    #[inline]
+    pub fn shared_value(&self) -> P::T {
+        self.counter.get()
+    }
+
     /// Restart the counter, resetting its value back to 0.
     #[inline]
     pub fn reset(&self) {
@@ -244,12 +486,106 @@ impl<P: Atomic> LocalMetric for GenericLocalCounter<P> {
     }
 }
 
+impl<P: Atomic> Drop for GenericLocalCounter<P> {
+    fn drop(&mut self) {
+        self.flush()
+    }
+}
+
 impl<P: Atomic> Clone for GenericLocalCounter<P> {
     fn clone(&self) -> Self {
         Self::new(self.counter.clone())
     }
 }
 
+/// The underlying implementation for [`FlushableLocalCounter`] and
+/// [`FlushableLocalIntCounter`].
+///
+/// Unlike [`GenericLocalCounter`], the local value is an atomic which only the
+/// owning thread updates, so it stays uncontended but costs an atomic
+/// operation per increment. In exchange, it can be flushed from another
+/// thread by [`GenericCounter::flush_all_locals`]. It flushes itself when
+/// dropped, so no increments are lost.
+#[derive(Debug)]
+pub struct GenericFlushableLocalCounter<P: Atomic> {
+    counter: GenericCounter<P>,
+    val: Arc<P>,
+}
+
+/// A [`LocalCounter`] which [`Counter::flush_all_locals`] can flush.
+pub type FlushableLocalCounter = GenericFlushableLocalCounter<AtomicF64>;
+
+/// The integer version of [`FlushableLocalCounter`].
+pub type FlushableLocalIntCounter = GenericFlushableLocalCounter<AtomicU64>;
+
+impl<P: Atomic> GenericFlushableLocalCounter<P> {
+    fn new(counter: GenericCounter<P>) -> Self {
+        let val = Arc::new(P::new(P::T::from_i64(0)));
+        let mut locals = counter.state.locals.lock();
+        // Forget the dropped local counters while at it.
+        locals.retain(|local| local.strong_count() > 0);
+        locals.push(Arc::downgrade(&val));
+        drop(locals);
+        Self { counter, val }
+    }
+
+    /// Increase the given value to the local counter.
+    ///
+    /// # Panics
+    ///
+    /// Panics in debug build if the value is < 0.
+    #[inline]
+    pub fn inc_by(&self, v: P::T) {
+        debug_assert!(v >= P::T::from_i64(0));
+        self.val.inc_by(v);
+    }
+
+    /// Increase the local counter by 1.
+    #[inline]
+    pub fn inc(&self) {
+        self.val.inc_by(P::T::from_i64(1));
+    }
+
+    /// Return the local counter value.
+    #[inline]
+    pub fn get(&self) -> P::T {
+        self.val.get()
+    }
+
+    /// Flush the local metrics to the [`Counter`].
+    #[inline]
+    pub fn flush(&self) {
+        let zero = P::T::from_i64(0);
+        if self.val.get() == zero {
+            return;
+        }
+        let v = Atomic::swap(&*self.val, zero);
+        if v != zero {
+            self.counter.inc_by(v);
+        }
+    }
+}
+
+impl<P: Atomic> LocalMetric for GenericFlushableLocalCounter<P> {
+    /// Flush the local metrics to the [`Counter`].
+    #[inline]
+    fn flush(&self) {
+        GenericFlushableLocalCounter::flush(self);
+    }
+}
+
+impl<P: Atomic> Drop for GenericFlushableLocalCounter<P> {
+    fn drop(&mut self) {
+        self.flush()
+    }
+}
+
+impl<P: Atomic> Clone for GenericFlushableLocalCounter<P> {
+    fn clone(&self) -> Self {
+        Self::new(self.counter.clone())
+    }
+}
+
 /// The underlying implementation for [`LocalCounterVec`]
 /// and [`LocalIntCounterVec`].
 pub struct GenericLocalCounterVec<P: Atomic> {
@@ -323,11 +659,191 @@ impl<P: Atomic> Clone for GenericLocalCounterVec<P> {
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
-    use std::f64::EPSILON;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
 
     use super::*;
     use crate::metrics::{Collector, Opts};
 
+    #[test]
+    fn test_counter_reset_created_at() {
+        let counter = Counter::new("test_counter", "test help").unwrap();
+        let created = counter.created_at();
+        assert!(created > 0.0);
+        counter.inc();
+        // The created timestamp is only exposed once the counter is reset.
+        let m = counter.metric();
+        assert_eq!(m.get_counter().get_created_timestamp().get_seconds(), 0);
+
+        thread::sleep(Duration::from_millis(10));
+        counter.reset();
+        assert_eq!(counter.get(), 0.0);
+        assert!(counter.created_at() > created);
+
+        let created = counter
+            .metric()
+            .get_counter()
+            .get_created_timestamp()
+            .clone();
+        let secs = created.get_seconds() as f64 + f64::from(created.get_nanos()) / 1e9;
+        assert!((secs - counter.created_at()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_counter_swap_reset() {
+        let counter = IntCounter::new("test_counter", "test help").unwrap();
+        let (threads, incs_per_thread) = (4, 10_000);
+        let done = Arc::new(AtomicBool::new(false));
+
+        let reporter = {
+            let counter = counter.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                let mut reported = 0;
+                while !done.load(Ordering::Acquire) {
+                    reported += counter.swap_reset();
+                }
+                reported + counter.swap_reset()
+            })
+        };
+        let handlers: Vec<_> = (0..threads)
+            .map(|_| {
+                let counter = counter.clone();
+                thread::spawn(move || {
+                    for _ in 0..incs_per_thread {
+                        counter.inc();
+                    }
+                })
+            })
+            .collect();
+        for h in handlers {
+            h.join().unwrap();
+        }
+        done.store(true, Ordering::Release);
+
+        assert_eq!(reporter.join().unwrap(), threads * incs_per_thread);
+        assert_eq!(counter.get(), 0);
+    }
+
+    #[test]
+    fn test_counter_with_initial_value() {
+        let counter = IntCounter::with_initial_value(Opts::new("test", "test help"), 42).unwrap();
+        assert_eq!(counter.get(), 42);
+        counter.inc();
+        assert_eq!(counter.get(), 43);
+
+        let opts = Opts::new("test", "test help");
+        assert!(Counter::with_initial_value(opts.clone(), -1.0).is_err());
+        assert!(Counter::with_initial_value(opts, f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_counter_set_help() {
+        use crate::encoder::{Encoder, TextEncoder};
+
+        let mut counter = Counter::new("test_counter", "test help").unwrap();
+        counter.set_help("new help").unwrap();
+        assert!(counter.set_help("").is_err());
+
+        let mut writer = Vec::new();
+        TextEncoder::new()
+            .encode(&counter.collect(), &mut writer)
+            .unwrap();
+        let text = String::from_utf8(writer).unwrap();
+        assert!(
+            text.starts_with("# HELP test_counter new help\n"),
+            "{}",
+            text
+        );
+
+        let r = Registry::new();
+        r.register(Box::new(counter.clone())).unwrap();
+        assert!(counter.set_help("other help").is_err());
+        assert_eq!(r.gather()[0].get_help(), "new help");
+    }
+
+    #[test]
+    fn test_counter_inc_iter() {
+        let values = vec![1.5, 0.0, 2.0, 3.25];
+        let counter = Counter::new("test_counter", "test help").unwrap();
+        counter.inc_iter(values.iter().copied());
+        let expected = Counter::new("test_counter", "test help").unwrap();
+        for v in &values {
+            expected.inc_by(*v);
+        }
+        assert_eq!(counter.get(), expected.get());
+
+        let counter = IntCounter::new("test_int_counter", "test help").unwrap();
+        counter.inc_iter(1..=10);
+        counter.inc_iter(Vec::new());
+        assert_eq!(counter.get(), 55);
+    }
+
+    #[test]
+    fn test_counter_register() {
+        let r = Registry::new();
+        let counter = Counter::register(Opts::new("test_counter", "test help"), &r).unwrap();
+        counter.inc();
+        let int_counter =
+            IntCounter::register(Opts::new("test_int_counter", "test help"), &r).unwrap();
+        int_counter.inc_by(2);
+        assert!(Counter::register(Opts::new("test_counter", "test help"), &r).is_err());
+
+        let mfs = r.gather();
+        assert_eq!(mfs.len(), 2);
+        assert_eq!(mfs[0].get_name(), "test_counter");
+        assert_eq!(mfs[0].get_metric()[0].get_counter().get_value(), 1.0);
+        assert_eq!(mfs[1].get_metric()[0].get_counter().get_value(), 2.0);
+
+        let _guard = crate::registry::DEFAULT_REGISTRY_TEST_LOCK.lock().unwrap();
+        let counter =
+            Counter::register_default(Opts::new("test_register_default", "test help")).unwrap();
+        assert!(crate::gather()
+            .iter()
+            .any(|mf| mf.get_name() == "test_register_default"));
+        crate::unregister(Box::new(counter)).unwrap();
+    }
+
+    #[test]
+    fn test_counter_inc_return() {
+        let counter = Counter::new("test_counter", "test help").unwrap();
+        assert_eq!(counter.inc_return(), 1.0);
+        assert_eq!(counter.inc_by_return(2.5), 3.5);
+
+        let counter = IntCounter::new("test_int_counter", "test help").unwrap();
+        let threads = 8;
+        let n = 1000;
+        let handlers: Vec<_> = (0..threads)
+            .map(|_| {
+                let counter = counter.clone();
+                thread::spawn(move || (0..n).map(|_| counter.inc_return()).collect::<Vec<_>>())
+            })
+            .collect();
+        let mut values: Vec<u64> = handlers
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect();
+        values.sort_unstable();
+        assert_eq!(values, (1..=threads * n).collect::<Vec<_>>());
+        assert_eq!(counter.inc_by_return(10), threads * n + 10);
+    }
+
+    #[test]
+    fn test_counter_add_into() {
+        let counter = Counter::new("test_counter", "test help").unwrap();
+        let bytes: u32 = 1024;
+        counter.add(bytes);
+        counter.add(2u8);
+        assert_eq!(counter.get(), 1026.0);
+
+        let int_counter = IntCounter::new("test_int_counter", "test help").unwrap();
+        int_counter.add(bytes);
+        int_counter.add(1u64);
+        assert_eq!(int_counter.get(), 1025);
+    }
+
     #[test]
     fn test_counter() {
         let opts = Opts::new("test", "test help")
@@ -343,7 +859,7 @@ mod tests {
         assert_eq!(mfs.len(), 1);
 
         let mf = mfs.pop().unwrap();
-        let m = mf.get_metric().get(0).unwrap();
+        let m = mf.get_metric().first().unwrap();
         assert_eq!(m.get_label().len(), 2);
         assert_eq!(m.get_counter().get_value() as u64, 43);
 
@@ -363,12 +879,81 @@ mod tests {
         assert_eq!(mfs.len(), 1);
 
         let mf = mfs.pop().unwrap();
-        let m = mf.get_metric().get(0).unwrap();
+        let m = mf.get_metric().first().unwrap();
         assert_eq!(m.get_label().len(), 0);
         assert_eq!(m.get_counter().get_value() as u64, 12);
 
         counter.reset();
-        assert_eq!(counter.get() as u64, 0);
+        assert_eq!(counter.get(), 0);
+    }
+
+    #[test]
+    fn test_counter_flush_all_locals() {
+        let counter = IntCounter::new("test_counter", "test help").unwrap();
+        let (incremented_tx, incremented_rx) = std::sync::mpsc::channel();
+        let (flushed_tx, flushed_rx) = std::sync::mpsc::channel::<()>();
+        let flushed_rx = Arc::new(std::sync::Mutex::new(flushed_rx));
+
+        let n = 4;
+        let handles: Vec<_> = (0..n)
+            .map(|i| {
+                let local = counter.flushable_local();
+                let incremented_tx = incremented_tx.clone();
+                let flushed_rx = flushed_rx.clone();
+                thread::spawn(move || {
+                    local.inc_by(i + 1);
+                    incremented_tx.send(()).unwrap();
+                    // Keep the local counter alive until it was flushed.
+                    flushed_rx.lock().unwrap().recv().unwrap();
+                    assert_eq!(local.get(), 0);
+                })
+            })
+            .collect();
+        // A dropped local counter flushes itself and is forgotten.
+        counter.flushable_local().inc();
+        // Unsync local counters are not tracked.
+        let unsync = counter.local();
+        unsync.inc();
+
+        for _ in 0..n {
+            incremented_rx.recv().unwrap();
+        }
+        assert_eq!(counter.get(), 1);
+        counter.flush_all_locals();
+        assert_eq!(counter.get(), 1 + 1 + 2 + 3 + 4);
+        assert_eq!(counter.state.locals.lock().len(), n as usize);
+
+        for _ in 0..n {
+            flushed_tx.send(()).unwrap();
+        }
+        for h in handles {
+            h.join().unwrap();
+        }
+        counter.flush_all_locals();
+        assert_eq!(counter.get(), 11);
+        assert!(counter.state.locals.lock().is_empty());
+        unsync.flush();
+        assert_eq!(counter.get(), 12);
+    }
+
+    #[test]
+    fn test_local_counter_flush_on_drop() {
+        let counter = IntCounter::new("counter", "counter helper").unwrap();
+        let local = counter.local();
+        local.inc_by(3);
+        assert_eq!(counter.get(), 0);
+        drop(local);
+        assert_eq!(counter.get(), 3);
+
+        // Increments are not lost when the owning thread panics.
+        let local = counter.local();
+        let res = thread::spawn(move || {
+            local.inc();
+            panic!("boom");
+        })
+        .join();
+        assert!(res.is_err());
+        assert_eq!(counter.get(), 4);
     }
 
     #[test]
@@ -388,6 +973,14 @@ mod tests {
         local_counter2.flush();
         assert_eq!(counter.get() as u64, 2);
 
+        // The shared value only includes flushed increments.
+        local_counter1.inc();
+        assert_eq!(local_counter1.shared_value() as u64, 2);
+        local_counter1.flush();
+        assert_eq!(local_counter1.get() as u64, 0);
+        assert_eq!(local_counter1.shared_value() as u64, 3);
+        assert_eq!(local_counter2.shared_value() as u64, 3);
+
         local_counter1.reset();
         local_counter2.reset();
         counter.reset();
@@ -414,9 +1007,9 @@ mod tests {
 
         local_counter.reset();
         counter.reset();
-        assert_eq!(counter.get() as u64, 0);
+        assert_eq!(counter.get(), 0);
         local_counter.flush();
-        assert_eq!(counter.get() as u64, 0);
+        assert_eq!(counter.get(), 0);
     }
 
     #[test]
@@ -502,48 +1095,48 @@ mod tests {
         assert!(local_vec_1.remove_label_values(&["v1", "v2"]).is_err());
 
         local_vec_1.with_label_values(&["v1", "v2"]).inc_by(23.0);
-        assert!((local_vec_1.with_label_values(&["v1", "v2"]).get() - 23.0) <= EPSILON);
-        assert!((vec.with_label_values(&["v1", "v2"]).get() - 0.0) <= EPSILON);
+        assert!((local_vec_1.with_label_values(&["v1", "v2"]).get() - 23.0) <= f64::EPSILON);
+        assert!((vec.with_label_values(&["v1", "v2"]).get() - 0.0) <= f64::EPSILON);
 
         local_vec_1.flush();
-        assert!((local_vec_1.with_label_values(&["v1", "v2"]).get() - 0.0) <= EPSILON);
-        assert!((vec.with_label_values(&["v1", "v2"]).get() - 23.0) <= EPSILON);
+        assert!((local_vec_1.with_label_values(&["v1", "v2"]).get() - 0.0) <= f64::EPSILON);
+        assert!((vec.with_label_values(&["v1", "v2"]).get() - 23.0) <= f64::EPSILON);
 
         local_vec_1.flush();
-        assert!((local_vec_1.with_label_values(&["v1", "v2"]).get() - 0.0) <= EPSILON);
-        assert!((vec.with_label_values(&["v1", "v2"]).get() - 23.0) <= EPSILON);
+        assert!((local_vec_1.with_label_values(&["v1", "v2"]).get() - 0.0) <= f64::EPSILON);
+        assert!((vec.with_label_values(&["v1", "v2"]).get() - 23.0) <= f64::EPSILON);
 
         local_vec_1.with_label_values(&["v1", "v2"]).inc_by(11.0);
-        assert!((local_vec_1.with_label_values(&["v1", "v2"]).get() - 11.0) <= EPSILON);
-        assert!((vec.with_label_values(&["v1", "v2"]).get() - 23.0) <= EPSILON);
+        assert!((local_vec_1.with_label_values(&["v1", "v2"]).get() - 11.0) <= f64::EPSILON);
+        assert!((vec.with_label_values(&["v1", "v2"]).get() - 23.0) <= f64::EPSILON);
 
         local_vec_1.flush();
-        assert!((local_vec_1.with_label_values(&["v1", "v2"]).get() - 0.0) <= EPSILON);
-        assert!((vec.with_label_values(&["v1", "v2"]).get() - 34.0) <= EPSILON);
+        assert!((local_vec_1.with_label_values(&["v1", "v2"]).get() - 0.0) <= f64::EPSILON);
+        assert!((vec.with_label_values(&["v1", "v2"]).get() - 34.0) <= f64::EPSILON);
 
         // When calling `remove_label_values`, it is "flushed" immediately.
         assert!(local_vec_1.remove_label_values(&["v1", "v2"]).is_ok());
-        assert!((local_vec_1.with_label_values(&["v1", "v2"]).get() - 0.0) <= EPSILON);
-        assert!((vec.with_label_values(&["v1", "v2"]).get() - 0.0) <= EPSILON);
+        assert!((local_vec_1.with_label_values(&["v1", "v2"]).get() - 0.0) <= f64::EPSILON);
+        assert!((vec.with_label_values(&["v1", "v2"]).get() - 0.0) <= f64::EPSILON);
 
         local_vec_1.with_label_values(&["v1", "v2"]).inc();
         assert!(local_vec_1.remove_label_values(&["v1"]).is_err());
         assert!(local_vec_1.remove_label_values(&["v1", "v3"]).is_err());
 
         local_vec_1.with_label_values(&["v1", "v2"]).inc_by(13.0);
-        assert!((local_vec_1.with_label_values(&["v1", "v2"]).get() - 14.0) <= EPSILON);
-        assert!((vec.with_label_values(&["v1", "v2"]).get() - 0.0) <= EPSILON);
+        assert!((local_vec_1.with_label_values(&["v1", "v2"]).get() - 14.0) <= f64::EPSILON);
+        assert!((vec.with_label_values(&["v1", "v2"]).get() - 0.0) <= f64::EPSILON);
 
         local_vec_2.with_label_values(&["v1", "v2"]).inc_by(7.0);
-        assert!((local_vec_2.with_label_values(&["v1", "v2"]).get() - 7.0) <= EPSILON);
+        assert!((local_vec_2.with_label_values(&["v1", "v2"]).get() - 7.0) <= f64::EPSILON);
 
         local_vec_1.flush();
         local_vec_2.flush();
-        assert!((vec.with_label_values(&["v1", "v2"]).get() - 21.0) <= EPSILON);
+        assert!((vec.with_label_values(&["v1", "v2"]).get() - 21.0) <= f64::EPSILON);
 
         local_vec_1.flush();
         local_vec_2.flush();
-        assert!((vec.with_label_values(&["v1", "v2"]).get() - 21.0) <= EPSILON);
+        assert!((vec.with_label_values(&["v1", "v2"]).get() - 21.0) <= f64::EPSILON);
     }
 
     #[test]
@@ -27,7 +27,7 @@ fn is_valid_ident<F: FnMut(char) -> bool>(input: &str, mut charset_validator: F)
     zeroth
         .and_then(|zeroth| {
             if charset_validator(zeroth) {
-                Some(chars.all(|c| charset_validator(c) || c.is_digit(10)))
+                Some(chars.all(|c| charset_validator(c) || c.is_ascii_digit()))
             } else {
                 None
             }
@@ -118,7 +118,7 @@ impl Desc {
             if !is_valid_label_name(label_name) {
                 return Err(Error::Msg(format!(
                     "'{}' is not a valid label name",
-                    &label_name
+                    label_name
                 )));
             }
 
@@ -142,7 +142,14 @@ impl Desc {
             if !is_valid_label_name(label_name) {
                 return Err(Error::Msg(format!(
                     "'{}' is not a valid label name",
-                    &label_name
+                    label_name
+                )));
+            }
+
+            if const_labels.contains_key(label_name) {
+                return Err(Error::Msg(format!(
+                    "variable label name {} is also a const label name",
+                    label_name
                 )));
             }
 
@@ -184,6 +191,123 @@ impl Desc {
 
         Ok(desc)
     }
+
+    /// `with_help` returns a copy of the [`Desc`] with the given help string.
+    pub(crate) fn with_help(&self, help: &str) -> Result<Desc> {
+        let const_labels = self
+            .const_label_pairs
+            .iter()
+            .map(|lp| (lp.get_name().to_owned(), lp.get_value().to_owned()))
+            .collect();
+        Desc::new(
+            self.fq_name.clone(),
+            help.to_owned(),
+            self.variable_labels.clone(),
+            const_labels,
+        )
+    }
+}
+
+impl Desc {
+    /// Create a [`DescBuilder`], a fluent alternative to [`Desc::new`] that
+    /// reports label collisions and reserved label names precisely.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use prometheus::core::Desc;
+    ///
+    /// let desc = Desc::builder()
+    ///     .fq_name("requests_total")
+    ///     .help("requests help")
+    ///     .const_label("service", "api")
+    ///     .variable_label("code")
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(desc.variable_labels, vec!["code".to_owned()]);
+    ///
+    /// let err = Desc::builder()
+    ///     .fq_name("requests_total")
+    ///     .help("requests help")
+    ///     .const_label("code", "200")
+    ///     .variable_label("code")
+    ///     .build()
+    ///     .unwrap_err();
+    /// assert_eq!(
+    ///     err.to_string(),
+    ///     "Error: variable label name code is also a const label name"
+    /// );
+    /// ```
+    pub fn builder() -> DescBuilder {
+        DescBuilder::default()
+    }
+}
+
+/// A builder of [`Desc`], created by [`Desc::builder`].
+#[derive(Clone, Debug, Default)]
+pub struct DescBuilder {
+    fq_name: String,
+    help: String,
+    variable_labels: Vec<String>,
+    const_labels: Vec<(String, String)>,
+}
+
+impl DescBuilder {
+    /// `fq_name` sets the fully-qualified name of the metric.
+    pub fn fq_name<S: Into<String>>(mut self, fq_name: S) -> Self {
+        self.fq_name = fq_name.into();
+        self
+    }
+
+    /// `help` sets the help string of the metric.
+    pub fn help<S: Into<String>>(mut self, help: S) -> Self {
+        self.help = help.into();
+        self
+    }
+
+    /// `variable_label` appends a variable label name.
+    pub fn variable_label<S: Into<String>>(mut self, name: S) -> Self {
+        self.variable_labels.push(name.into());
+        self
+    }
+
+    /// `const_label` adds a const label.
+    pub fn const_label<S1: Into<String>, S2: Into<String>>(mut self, name: S1, value: S2) -> Self {
+        self.const_labels.push((name.into(), value.into()));
+        self
+    }
+
+    /// `build` validates the labels and creates the [`Desc`].
+    ///
+    /// On top of the checks of [`Desc::new`], it reports duplicate const label
+    /// names, which a map of const labels cannot hold, and label names with
+    /// the reserved `__` prefix.
+    pub fn build(self) -> Result<Desc> {
+        let mut const_labels = HashMap::with_capacity(self.const_labels.len());
+        for (name, value) in self.const_labels {
+            check_reserved_label_name(&name)?;
+            if const_labels.contains_key(&name) {
+                return Err(Error::Msg(format!("duplicate const label name {}", name)));
+            }
+            const_labels.insert(name, value);
+        }
+
+        for name in &self.variable_labels {
+            check_reserved_label_name(name)?;
+        }
+
+        Desc::new(self.fq_name, self.help, self.variable_labels, const_labels)
+    }
+}
+
+fn check_reserved_label_name(name: &str) -> Result<()> {
+    if name.starts_with("__") {
+        return Err(Error::Msg(format!(
+            "label name {} is reserved, names starting with __ are for internal use",
+            name
+        )));
+    }
+    Ok(())
 }
 
 /// An interface for describing the immutable meta-data of a [`Metric`](crate::core::Metric).
@@ -196,7 +320,7 @@ pub trait Describer {
 mod tests {
     use std::collections::HashMap;
 
-    use crate::desc::{is_valid_label_name, is_valid_metric_name, Desc};
+    use crate::desc::{is_valid_label_name, is_valid_metric_name, Desc, DescBuilder};
     use crate::errors::Error;
 
     #[test]
@@ -254,7 +378,7 @@ mod tests {
                 HashMap::new(),
             )
             .err()
-            .expect(format!("expected error for {}", name).as_ref());
+            .unwrap_or_else(|| panic!("expected error for {}", name));
             match res {
                 Error::Msg(msg) => assert_eq!(msg, format!("'{}' is not a valid label name", name)),
                 other => panic!("{}", other),
@@ -269,7 +393,7 @@ mod tests {
             labels.insert(name.into(), "value".into());
             let res = Desc::new("name".into(), "help".into(), vec![], labels)
                 .err()
-                .expect(format!("expected error for {}", name).as_ref());
+                .unwrap_or_else(|| panic!("expected error for {}", name));
             match res {
                 Error::Msg(msg) => assert_eq!(msg, format!("'{}' is not a valid label name", name)),
                 other => panic!("{}", other),
@@ -277,12 +401,84 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_variable_label_shadows_const_label() {
+        let mut labels = HashMap::new();
+        labels.insert("a".to_owned(), "value".to_owned());
+        let res = Desc::new("name".into(), "help".into(), vec!["a".into()], labels);
+        match res {
+            Err(Error::Msg(msg)) => {
+                assert_eq!(msg, "variable label name a is also a const label name")
+            }
+            other => panic!("expected error, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_desc_builder() {
+        let desc = Desc::builder()
+            .fq_name("name")
+            .help("help")
+            .const_label("b", "2")
+            .const_label("a", "1")
+            .variable_label("c")
+            .build()
+            .unwrap();
+        let expected = Desc::new(
+            "name".into(),
+            "help".into(),
+            vec!["c".into()],
+            vec![("a".into(), "1".into()), ("b".into(), "2".into())]
+                .into_iter()
+                .collect(),
+        )
+        .unwrap();
+        assert_eq!(desc.id, expected.id);
+        assert_eq!(desc.dim_hash, expected.dim_hash);
+        assert_eq!(desc.const_label_pairs, expected.const_label_pairs);
+
+        let builder = || Desc::builder().fq_name("name").help("help");
+        let tbl: Vec<(DescBuilder, &str)> = vec![
+            (
+                builder().variable_label("a").variable_label("a"),
+                "duplicate variable label name a",
+            ),
+            (
+                builder().const_label("a", "1").const_label("a", "2"),
+                "duplicate const label name a",
+            ),
+            (
+                builder().const_label("a", "1").variable_label("a"),
+                "variable label name a is also a const label name",
+            ),
+            (
+                builder().variable_label("__a"),
+                "label name __a is reserved, names starting with __ are for internal use",
+            ),
+            (
+                builder().const_label("__a", "1"),
+                "label name __a is reserved, names starting with __ are for internal use",
+            ),
+            (
+                builder().variable_label("a-b"),
+                "'a-b' is not a valid label name",
+            ),
+            (Desc::builder().fq_name("name"), "empty help string"),
+        ];
+        for (builder, expected) in tbl {
+            match builder.build() {
+                Err(Error::Msg(msg)) => assert_eq!(msg, expected),
+                other => panic!("expected error {}, got {:?}", expected, other),
+            }
+        }
+    }
+
     #[test]
     fn test_invalid_metric_name() {
         for &name in &["-dash", "9gag", "has space"] {
             let res = Desc::new(name.into(), "help".into(), vec![], HashMap::new())
                 .err()
-                .expect(format!("expected error for {}", name).as_ref());
+                .unwrap_or_else(|| panic!("expected error for {}", name));
             match res {
                 Error::Msg(msg) => {
                     assert_eq!(msg, format!("'{}' is not a valid metric name", name))
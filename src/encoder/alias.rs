@@ -0,0 +1,112 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::errors::Result;
+use crate::proto::MetricFamily;
+
+use super::Encoder;
+
+/// An implementation of an [`Encoder`] that wraps another [`Encoder`] and
+/// emits the metric families of the configured names a second time under
+/// their alias names, with the same help, type and samples.
+///
+/// This is useful to keep exposing the old name of a renamed metric during a
+/// deprecation window without duplicating the metric in code.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// use prometheus::{AliasEncoder, Counter, Encoder, TextEncoder};
+/// use prometheus::core::Collector;
+///
+/// let counter = Counter::new("new_name", "A renamed counter.").unwrap();
+/// let mut aliases = HashMap::new();
+/// aliases.insert("new_name".to_owned(), "old_name".to_owned());
+/// let encoder = AliasEncoder::new(TextEncoder::new(), aliases);
+///
+/// let mut buffer = Vec::new();
+/// encoder.encode(&counter.collect(), &mut buffer).unwrap();
+/// assert!(String::from_utf8(buffer).unwrap().contains("\nold_name 0\n"));
+/// ```
+#[derive(Debug, Default)]
+pub struct AliasEncoder<E> {
+    inner: E,
+    aliases: HashMap<String, String>,
+}
+
+impl<E: Encoder> AliasEncoder<E> {
+    /// Create a new alias encoder wrapping `inner`, `aliases` maps metric
+    /// names to their alias names.
+    pub fn new(inner: E, aliases: HashMap<String, String>) -> AliasEncoder<E> {
+        AliasEncoder { inner, aliases }
+    }
+}
+
+impl<E: Encoder> Encoder for AliasEncoder<E> {
+    fn encode<W: Write>(&self, metric_families: &[MetricFamily], writer: &mut W) -> Result<()> {
+        if !metric_families
+            .iter()
+            .any(|mf| self.aliases.contains_key(mf.get_name()))
+        {
+            return self.inner.encode(metric_families, writer);
+        }
+
+        let mut aliased = Vec::with_capacity(metric_families.len() + self.aliases.len());
+        for mf in metric_families {
+            aliased.push(mf.clone());
+            if let Some(alias) = self.aliases.get(mf.get_name()) {
+                let mut mf = mf.clone();
+                mf.set_name(alias.clone());
+                aliased.push(mf);
+            }
+        }
+        self.inner.encode(&aliased, writer)
+    }
+
+    fn format_type(&self) -> &str {
+        self.inner.format_type()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str;
+
+    use super::*;
+    use crate::counter::Counter;
+    use crate::encoder::{TextEncoder, TEXT_FORMAT};
+    use crate::gauge::Gauge;
+    use crate::metrics::Collector;
+
+    #[test]
+    fn test_alias_encoder() {
+        let counter = Counter::new("new_name", "test help").unwrap();
+        counter.inc();
+        let gauge = Gauge::new("test_gauge", "test gauge help").unwrap();
+        let mut mfs = counter.collect();
+        mfs.extend(gauge.collect());
+
+        let mut aliases = HashMap::new();
+        aliases.insert("new_name".to_owned(), "old_name".to_owned());
+        let encoder = AliasEncoder::new(TextEncoder::new(), aliases);
+        assert_eq!(encoder.format_type(), TEXT_FORMAT);
+
+        let mut writer = Vec::<u8>::new();
+        encoder.encode(&mfs, &mut writer).unwrap();
+        let ans = r##"# HELP new_name test help
+# TYPE new_name counter
+new_name 1
+# HELP old_name test help
+# TYPE old_name counter
+old_name 1
+# HELP test_gauge test gauge help
+# TYPE test_gauge gauge
+test_gauge 0
+"##;
+        assert_eq!(ans, str::from_utf8(writer.as_slice()).unwrap());
+    }
+}
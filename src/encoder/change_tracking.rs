@@ -0,0 +1,224 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::io::Write;
+use std::sync::Mutex;
+
+use fnv::FnvHasher;
+
+use crate::errors::Result;
+use crate::proto::{MetricFamily, MetricType};
+
+use super::Encoder;
+
+/// An implementation of an [`Encoder`] that wraps another [`Encoder`] and
+/// only emits the metric families which changed since its previous call,
+/// e.g. for a transport streaming deltas.
+///
+/// A metric family is changed if it is new or if its help, type, series or
+/// values differ, in which case it is emitted with all its series. Metric
+/// families which are no longer passed in are forgotten, so they are emitted
+/// again if they come back. The removal itself is not signaled, consumers
+/// which need to notice it have to track the absent families on their own.
+///
+/// To keep its memory small, only a 64-bit hash of each metric family is
+/// remembered. In the unlikely case that a changed metric family hashes the
+/// same as before, the change is not emitted until the next one, so call
+/// [`reset`](ChangeTrackingEncoder::reset) periodically if a stale value is
+/// not acceptable.
+///
+/// # Examples
+///
+/// ```
+/// use prometheus::{ChangeTrackingEncoder, Counter, Encoder, TextEncoder};
+/// use prometheus::core::Collector;
+///
+/// let counter = Counter::new("requests", "Number of requests.").unwrap();
+/// let encoder = ChangeTrackingEncoder::new(TextEncoder::new());
+///
+/// let mut buffer = Vec::new();
+/// encoder.encode(&counter.collect(), &mut buffer).unwrap();
+/// assert!(!buffer.is_empty());
+///
+/// buffer.clear();
+/// encoder.encode(&counter.collect(), &mut buffer).unwrap();
+/// assert!(buffer.is_empty());
+/// ```
+#[derive(Debug, Default)]
+pub struct ChangeTrackingEncoder<E> {
+    inner: E,
+    last_hashes: Mutex<HashMap<String, u64>>,
+}
+
+impl<E: Encoder> ChangeTrackingEncoder<E> {
+    /// Create a new change tracking encoder wrapping `inner`.
+    pub fn new(inner: E) -> ChangeTrackingEncoder<E> {
+        ChangeTrackingEncoder {
+            inner,
+            last_hashes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Forget the previously emitted metric families, so that all of them are
+    /// emitted by the next call.
+    pub fn reset(&self) {
+        self.last_hashes.lock().unwrap().clear();
+    }
+}
+
+impl<E: Encoder> Encoder for ChangeTrackingEncoder<E> {
+    fn encode<W: Write>(&self, metric_families: &[MetricFamily], writer: &mut W) -> Result<()> {
+        let mut last_hashes = self.last_hashes.lock().unwrap();
+
+        let mut hashes = HashMap::with_capacity(metric_families.len());
+        let mut changed = Vec::new();
+        for mf in metric_families {
+            let hash = hash_metric_family(mf);
+            if last_hashes.get(mf.get_name()) != Some(&hash) {
+                changed.push(mf.clone());
+            }
+            hashes.insert(mf.get_name().to_owned(), hash);
+        }
+
+        self.inner.encode(&changed, writer)?;
+        // Only remember what was emitted successfully.
+        *last_hashes = hashes;
+        Ok(())
+    }
+
+    fn format_type(&self) -> &str {
+        self.inner.format_type()
+    }
+}
+
+/// `hash_metric_family` hashes the help, type, labels and values of the metric
+/// family.
+#[allow(deprecated)]
+fn hash_metric_family(mf: &MetricFamily) -> u64 {
+    let mut h = FnvHasher::default();
+    h.write(mf.get_help().as_bytes());
+    h.write_u8(0xff);
+    h.write_i32(mf.get_field_type() as i32);
+
+    for m in mf.get_metric() {
+        h.write_usize(m.get_label().len());
+        for lp in m.get_label() {
+            h.write(lp.get_name().as_bytes());
+            h.write_u8(0xff);
+            h.write(lp.get_value().as_bytes());
+            h.write_u8(0xff);
+        }
+        h.write_i64(m.get_timestamp_ms());
+
+        match mf.get_field_type() {
+            MetricType::COUNTER => {
+                let c = m.get_counter();
+                h.write_u64(c.get_value().to_bits());
+                h.write_i64(c.get_created_timestamp().get_seconds());
+                h.write_i32(c.get_created_timestamp().get_nanos());
+            }
+            MetricType::GAUGE => h.write_u64(m.get_gauge().get_value().to_bits()),
+            MetricType::HISTOGRAM => {
+                let hist = m.get_histogram();
+                h.write_u64(hist.get_sample_count());
+                h.write_u64(hist.get_sample_sum().to_bits());
+                for b in hist.get_bucket() {
+                    h.write_u64(b.get_upper_bound().to_bits());
+                    h.write_u64(b.get_cumulative_count());
+                }
+            }
+            MetricType::SUMMARY => {
+                let s = m.get_summary();
+                h.write_u64(s.get_sample_count());
+                h.write_u64(s.get_sample_sum().to_bits());
+                for q in s.get_quantile() {
+                    h.write_u64(q.get_quantile().to_bits());
+                    h.write_u64(q.get_value().to_bits());
+                }
+            }
+            MetricType::UNTYPED => h.write_u64(m.get_untyped().get_value().to_bits()),
+        }
+    }
+
+    h.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str;
+
+    use super::*;
+    use crate::counter::{Counter, CounterVec};
+    use crate::encoder::{TextEncoder, TEXT_FORMAT};
+    use crate::gauge::Gauge;
+    use crate::metrics::{Collector, Opts};
+
+    #[test]
+    fn test_change_tracking_encoder() {
+        let counter = Counter::new("test_counter", "test help").unwrap();
+        let gauge = Gauge::new("test_gauge", "test help").unwrap();
+        let encoder = ChangeTrackingEncoder::new(TextEncoder::new());
+        assert_eq!(encoder.format_type(), TEXT_FORMAT);
+
+        let encode = |mfs: &[MetricFamily]| {
+            let mut writer = Vec::new();
+            encoder.encode(mfs, &mut writer).unwrap();
+            String::from_utf8(writer).unwrap()
+        };
+        let collect = || {
+            let mut mfs = counter.collect();
+            mfs.extend(gauge.collect());
+            mfs
+        };
+
+        let text = encode(&collect());
+        assert!(text.contains("test_counter 0\n"));
+        assert!(text.contains("test_gauge 0\n"));
+
+        // Unchanged metric families are omitted.
+        assert_eq!(encode(&collect()), "");
+
+        counter.inc();
+        let text = encode(&collect());
+        assert!(text.contains("test_counter 1\n"));
+        assert!(!text.contains("test_gauge"));
+
+        // A removed metric family is emitted again when it comes back.
+        assert_eq!(encode(&counter.collect()), "");
+        let text = encode(&collect());
+        assert!(!text.contains("test_counter"));
+        assert!(text.contains("test_gauge 0\n"));
+
+        encoder.reset();
+        let text = encode(&collect());
+        assert!(text.contains("test_counter 1\n"));
+        assert!(text.contains("test_gauge 0\n"));
+    }
+
+    #[test]
+    fn test_change_tracking_encoder_series() {
+        let vec = CounterVec::new(Opts::new("test_vec", "test help"), &["a"]).unwrap();
+        vec.with_label_values(&["1"]).inc();
+        let encoder = ChangeTrackingEncoder::new(TextEncoder::new());
+
+        let mut writer = Vec::new();
+        encoder.encode(&vec.collect(), &mut writer).unwrap();
+        assert!(!writer.is_empty());
+
+        // New and removed series change the metric family.
+        vec.with_label_values(&["2"]);
+        let mut writer = Vec::new();
+        encoder.encode(&vec.collect(), &mut writer).unwrap();
+        let text = str::from_utf8(&writer).unwrap();
+        assert!(text.contains("test_vec{a=\"1\"} 1\n"));
+        assert!(text.contains("test_vec{a=\"2\"} 0\n"));
+
+        vec.remove_label_values(&["2"]).unwrap();
+        let mut writer = Vec::new();
+        encoder.encode(&vec.collect(), &mut writer).unwrap();
+        let text = str::from_utf8(&writer).unwrap();
+        assert!(text.contains("test_vec{a=\"1\"} 1\n"));
+        assert!(!text.contains("a=\"2\""));
+    }
+}
@@ -1,14 +1,22 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
+mod alias;
+mod change_tracking;
+mod openmetrics;
 #[cfg(feature = "protobuf")]
 mod pb;
 mod text;
 
+pub use self::alias::AliasEncoder;
+pub use self::change_tracking::ChangeTrackingEncoder;
+pub use self::openmetrics::{OpenMetricsEncoder, OPENMETRICS_FORMAT};
 #[cfg(feature = "protobuf")]
 pub use self::pb::{ProtobufEncoder, PROTOBUF_FORMAT};
-pub use self::text::{TextEncoder, TEXT_FORMAT};
+pub use self::text::{ConfiguredTextEncoder, TextEncoder, TEXT_FORMAT};
 
+use std::cmp::Ordering;
 use std::io::Write;
+use std::str::FromStr;
 
 use crate::errors::{Error, Result};
 use crate::proto::MetricFamily;
@@ -23,10 +31,133 @@ pub trait Encoder {
     /// output.
     fn encode<W: Write>(&self, _: &[MetricFamily], _: &mut W) -> Result<()>;
 
+    /// `encode_one` encodes a single MetricFamily like
+    /// [`encode`](Encoder::encode), e.g. to stream metric families one at a
+    /// time. The default implementation encodes a one-element slice of `mf`
+    /// without cloning it. Format terminators, like the `# EOF` line of the
+    /// OpenMetrics format, are written as by [`encode`](Encoder::encode).
+    fn encode_one<W: Write>(&self, mf: &MetricFamily, writer: &mut W) -> Result<()> {
+        self.encode(std::slice::from_ref(mf), writer)
+    }
+
     /// `format_type` returns target format.
     fn format_type(&self) -> &str;
 }
 
+/// The exposition formats metrics can be encoded into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The Prometheus text format, see [`TextEncoder`].
+    Text,
+    /// The Prometheus protobuf format, see [`ProtobufEncoder`].
+    #[cfg(feature = "protobuf")]
+    Protobuf,
+    /// The OpenMetrics text format, see [`OpenMetricsEncoder`].
+    OpenMetrics,
+}
+
+impl Format {
+    /// `content_type` returns the `Content-Type` header value of the format.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Text => TEXT_FORMAT,
+            #[cfg(feature = "protobuf")]
+            Format::Protobuf => PROTOBUF_FORMAT,
+            Format::OpenMetrics => OPENMETRICS_FORMAT,
+        }
+    }
+
+    /// `encode` encodes the metric families into the format with the matching
+    /// [`Encoder`].
+    pub fn encode<W: Write>(self, metric_families: &[MetricFamily], writer: &mut W) -> Result<()> {
+        match self {
+            Format::Text => TextEncoder::new().encode(metric_families, writer),
+            #[cfg(feature = "protobuf")]
+            Format::Protobuf => ProtobufEncoder::new().encode(metric_families, writer),
+            Format::OpenMetrics => OpenMetricsEncoder::new().encode(metric_families, writer),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    /// Parse a format from its name, one of `text`, `protobuf` or
+    /// `openmetrics`.
+    fn from_str(s: &str) -> Result<Format> {
+        match s {
+            "text" => Ok(Format::Text),
+            #[cfg(feature = "protobuf")]
+            "protobuf" => Ok(Format::Protobuf),
+            "openmetrics" => Ok(Format::OpenMetrics),
+            _ => Err(Error::Msg(format!("unknown format {}", s))),
+        }
+    }
+}
+
+/// `negotiate` returns the [`Format`] best matching the given HTTP `Accept`
+/// header, defaulting to [`Format::Text`] when none of the accepted media types
+/// is supported.
+///
+/// Media types are tried in order of their quality value (`q=`), media types
+/// with a quality value of zero are never picked. The protobuf format is only
+/// picked when the `proto` and `encoding` parameters match
+/// [`PROTOBUF_FORMAT`]. This mirrors the `Negotiate` function of Go's `expfmt`.
+///
+/// [`Encoder`] is not object safe, so the returned [`Format`] is used to encode
+/// instead.
+///
+/// # Examples
+///
+/// ```
+/// use prometheus::{negotiate, Format};
+///
+/// let accept = "application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5";
+/// assert_eq!(negotiate(accept), Format::OpenMetrics);
+/// assert_eq!(negotiate("*/*").content_type(), prometheus::TEXT_FORMAT);
+/// ```
+pub fn negotiate(accept_header: &str) -> Format {
+    let mut media_ranges: Vec<(f64, Option<Format>)> = accept_header
+        .split(',')
+        .map(|media_range| {
+            let mut parts = media_range.split(';').map(str::trim);
+            let media_type = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let params: Vec<(&str, &str)> = parts
+                .map(|param| {
+                    let mut kv = param.splitn(2, '=').map(str::trim);
+                    (kv.next().unwrap_or_default(), kv.next().unwrap_or_default())
+                })
+                .collect();
+            let quality = params
+                .iter()
+                .find(|(key, _)| *key == "q")
+                .map_or(1.0, |(_, value)| value.parse().unwrap_or(0.0));
+
+            let format = match media_type.as_str() {
+                "text/plain" => Some(Format::Text),
+                "application/openmetrics-text" => Some(Format::OpenMetrics),
+                #[cfg(feature = "protobuf")]
+                "application/vnd.google.protobuf"
+                    if params.contains(&("proto", "io.prometheus.client.MetricFamily"))
+                        && params.contains(&("encoding", "delimited")) =>
+                {
+                    Some(Format::Protobuf)
+                }
+                _ => None,
+            };
+            (quality, format)
+        })
+        .collect();
+
+    // Stable sort, so media types of equal quality keep the header order.
+    media_ranges.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+    media_ranges
+        .into_iter()
+        .filter(|(quality, _)| *quality > 0.0)
+        .find_map(|(_, format)| format)
+        .unwrap_or(Format::Text)
+}
+
 fn check_metric_family(mf: &MetricFamily) -> Result<()> {
     if mf.get_metric().is_empty() {
         return Err(Error::Msg(format!("MetricFamily has no metrics: {:?}", mf)));
@@ -67,12 +198,93 @@ mod tests {
         let mut mfs = cv.collect();
 
         // Empty name
-        (&mut mfs[0]).clear_name();
+        mfs[0].clear_name();
         check_metric_family(&mfs[0]).unwrap_err();
         pb_encoder.encode(&mfs, &mut writer).unwrap_err();
         assert_eq!(writer.len(), 0);
     }
 
+    #[test]
+    fn test_encode_one() {
+        let cv = CounterVec::new(Opts::new("test_counter_vec", "test help"), &["a"]).unwrap();
+        cv.with_label_values(&["1"]).inc();
+        cv.with_label_values(&["2"]).inc_by(2.0);
+        let mfs = cv.collect();
+
+        let (mut all, mut one) = (Vec::new(), Vec::new());
+        TextEncoder::new().encode(&mfs, &mut all).unwrap();
+        TextEncoder::new().encode_one(&mfs[0], &mut one).unwrap();
+        assert!(!all.is_empty());
+        assert_eq!(all, one);
+
+        #[cfg(feature = "protobuf")]
+        {
+            let (mut all, mut one) = (Vec::new(), Vec::new());
+            ProtobufEncoder::new().encode(&mfs, &mut all).unwrap();
+            ProtobufEncoder::new()
+                .encode_one(&mfs[0], &mut one)
+                .unwrap();
+            assert!(!all.is_empty());
+            assert_eq!(all, one);
+        }
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let cases = vec![
+            ("", Format::Text),
+            ("*/*", Format::Text),
+            ("text/plain;version=0.0.4", Format::Text),
+            ("application/json, text/html", Format::Text),
+            (
+                "application/openmetrics-text;version=1.0.0,application/openmetrics-text;\
+                 version=0.0.1;q=0.75,text/plain;version=0.0.4;q=0.5,*/*;q=0.1",
+                Format::OpenMetrics,
+            ),
+            (
+                "text/plain;version=0.0.4;q=0.5, application/openmetrics-text; version=1.0.0",
+                Format::OpenMetrics,
+            ),
+            (
+                "application/openmetrics-text;q=0.2,text/plain;q=0.8",
+                Format::Text,
+            ),
+            ("application/openmetrics-text;q=0,*/*", Format::Text),
+            ("Application/OpenMetrics-Text", Format::OpenMetrics),
+            // Protobuf without the matching parameters is not supported.
+            ("application/vnd.google.protobuf", Format::Text),
+        ];
+        for (accept, expected) in cases {
+            assert_eq!(negotiate(accept), expected, "accept: {:?}", accept);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "protobuf")]
+    fn test_negotiate_protobuf() {
+        let cases = vec![
+            (
+                "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;\
+                 encoding=delimited;q=0.7,text/plain;version=0.0.4;q=0.3,*/*;q=0.1",
+                Format::Protobuf,
+            ),
+            (
+                "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;\
+                 encoding=delimited;q=0.3,text/plain;version=0.0.4;q=0.7",
+                Format::Text,
+            ),
+            (
+                "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;\
+                 encoding=text",
+                Format::Text,
+            ),
+            (PROTOBUF_FORMAT, Format::Protobuf),
+        ];
+        for (accept, expected) in cases {
+            assert_eq!(negotiate(accept), expected, "accept: {:?}", accept);
+        }
+    }
+
     #[test]
     fn test_bad_text_metrics() {
         let mut writer = Vec::<u8>::new();
@@ -94,7 +306,7 @@ mod tests {
         let mut mfs = cv.collect();
 
         // Empty name
-        (&mut mfs[0]).clear_name();
+        mfs[0].clear_name();
         check_metric_family(&mfs[0]).unwrap_err();
         text_encoder.encode(&mfs, &mut writer).unwrap_err();
         assert_eq!(writer.len(), 0);
@@ -0,0 +1,313 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::io::Write;
+
+use crate::errors::Result;
+use crate::histogram::BUCKET_LABEL;
+use crate::proto::{self, MetricFamily, MetricType};
+
+use super::text::{escape_string, label_pairs_to_text, POSITIVE_INF, QUANTILE};
+use super::{check_metric_family, Encoder};
+
+/// The OpenMetrics text format of metric family.
+pub const OPENMETRICS_FORMAT: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+const TOTAL_SUFFIX: &str = "_total";
+
+/// An implementation of an [`Encoder`] that converts a [`MetricFamily`] proto message
+/// into the [OpenMetrics](https://openmetrics.io/) text format.
+#[derive(Debug, Default)]
+pub struct OpenMetricsEncoder;
+
+impl OpenMetricsEncoder {
+    /// Create a new OpenMetrics encoder.
+    pub fn new() -> OpenMetricsEncoder {
+        OpenMetricsEncoder
+    }
+}
+
+impl Encoder for OpenMetricsEncoder {
+    #[allow(deprecated)]
+    fn encode<W: Write>(&self, metric_families: &[MetricFamily], writer: &mut W) -> Result<()> {
+        for mf in metric_families {
+            // Fail-fast checks.
+            check_metric_family(mf)?;
+
+            // In OpenMetrics the `_total` suffix belongs to the counter
+            // samples, not to the metric family.
+            let metric_type = mf.get_field_type();
+            let name = match metric_type {
+                MetricType::COUNTER => mf
+                    .get_name()
+                    .strip_suffix(TOTAL_SUFFIX)
+                    .unwrap_or_else(|| mf.get_name()),
+                _ => mf.get_name(),
+            };
+
+            // Write `# HELP` header.
+            let help = mf.get_help();
+            if !help.is_empty() {
+                writer.write_all(b"# HELP ")?;
+                writer.write_all(name.as_bytes())?;
+                writer.write_all(b" ")?;
+                writer.write_all(escape_string(help, true).as_bytes())?;
+                writer.write_all(b"\n")?;
+            }
+
+            // Write `# TYPE` header.
+            let lowercase_type = match metric_type {
+                MetricType::UNTYPED => "unknown".to_owned(),
+                _ => format!("{:?}", metric_type).to_lowercase(),
+            };
+            writer.write_all(b"# TYPE ")?;
+            writer.write_all(name.as_bytes())?;
+            writer.write_all(b" ")?;
+            writer.write_all(lowercase_type.as_bytes())?;
+            writer.write_all(b"\n")?;
+
+            for m in mf.get_metric() {
+                match metric_type {
+                    MetricType::COUNTER => {
+                        let c = m.get_counter();
+                        write_sample(writer, name, Some(TOTAL_SUFFIX), m, None, c.get_value())?;
+
+                        let created = c.get_created_timestamp();
+                        if created.get_seconds() != 0 || created.get_nanos() != 0 {
+                            let created =
+                                created.get_seconds() as f64 + f64::from(created.get_nanos()) / 1e9;
+                            write_sample(writer, name, Some("_created"), m, None, created)?;
+                        }
+                    }
+                    MetricType::GAUGE => {
+                        write_sample(writer, name, None, m, None, m.get_gauge().get_value())?;
+                    }
+                    MetricType::HISTOGRAM => {
+                        let h = m.get_histogram();
+
+                        let mut inf_seen = false;
+                        for b in h.get_bucket() {
+                            let upper_bound = b.get_upper_bound();
+                            write_sample(
+                                writer,
+                                name,
+                                Some("_bucket"),
+                                m,
+                                Some((BUCKET_LABEL, &format_value(upper_bound))),
+                                b.get_cumulative_count() as f64,
+                            )?;
+                            if upper_bound.is_sign_positive() && upper_bound.is_infinite() {
+                                inf_seen = true;
+                            }
+                        }
+                        if !inf_seen {
+                            write_sample(
+                                writer,
+                                name,
+                                Some("_bucket"),
+                                m,
+                                Some((BUCKET_LABEL, POSITIVE_INF)),
+                                h.get_sample_count() as f64,
+                            )?;
+                        }
+
+                        write_sample(writer, name, Some("_sum"), m, None, h.get_sample_sum())?;
+
+                        write_sample(
+                            writer,
+                            name,
+                            Some("_count"),
+                            m,
+                            None,
+                            h.get_sample_count() as f64,
+                        )?;
+                    }
+                    MetricType::SUMMARY => {
+                        let s = m.get_summary();
+
+                        for q in s.get_quantile() {
+                            write_sample(
+                                writer,
+                                name,
+                                None,
+                                m,
+                                Some((QUANTILE, &format_value(q.get_quantile()))),
+                                q.get_value(),
+                            )?;
+                        }
+
+                        write_sample(writer, name, Some("_sum"), m, None, s.get_sample_sum())?;
+
+                        write_sample(
+                            writer,
+                            name,
+                            Some("_count"),
+                            m,
+                            None,
+                            s.get_sample_count() as f64,
+                        )?;
+                    }
+                    MetricType::UNTYPED => {
+                        write_sample(writer, name, None, m, None, m.get_untyped().get_value())?;
+                    }
+                }
+            }
+        }
+
+        writer.write_all(b"# EOF\n")?;
+
+        Ok(())
+    }
+
+    fn format_type(&self) -> &str {
+        OPENMETRICS_FORMAT
+    }
+}
+
+/// `write_sample` writes a single sample in OpenMetrics text format to
+/// `writer`. Unlike the Prometheus text format, timestamps are written in
+/// seconds.
+fn write_sample(
+    writer: &mut dyn Write,
+    name: &str,
+    name_postfix: Option<&str>,
+    mc: &proto::Metric,
+    additional_label: Option<(&str, &str)>,
+    value: f64,
+) -> Result<()> {
+    writer.write_all(name.as_bytes())?;
+    if let Some(postfix) = name_postfix {
+        writer.write_all(postfix.as_bytes())?;
+    }
+
+    label_pairs_to_text(mc.get_label(), additional_label, writer)?;
+
+    writer.write_all(b" ")?;
+    writer.write_all(format_value(value).as_bytes())?;
+
+    let timestamp = mc.get_timestamp_ms();
+    if timestamp != 0 {
+        writer.write_all(b" ")?;
+        writer.write_all(format_value(timestamp as f64 / 1000.0).as_bytes())?;
+    }
+
+    writer.write_all(b"\n")?;
+
+    Ok(())
+}
+
+/// `format_value` formats a float the way OpenMetrics spells infinities.
+fn format_value(v: f64) -> String {
+    if v.is_infinite() {
+        if v.is_sign_positive() {
+            POSITIVE_INF.to_owned()
+        } else {
+            "-Inf".to_owned()
+        }
+    } else {
+        v.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str;
+
+    use super::*;
+    use crate::counter::Counter;
+    use crate::histogram::{Histogram, HistogramOpts};
+    use crate::metrics::{Collector, Opts};
+    use crate::value::make_timestamp;
+
+    #[test]
+    fn test_openmetrics_encoder_counter() {
+        let encoder = OpenMetricsEncoder::new();
+        for name in &["test_counter", "test_counter_total"] {
+            let counter_opts = Opts::new(*name, "test \"help\"").const_label("a", "1");
+            let counter = Counter::with_opts(counter_opts).unwrap();
+            counter.inc();
+
+            let mut writer = Vec::<u8>::new();
+            encoder.encode(&counter.collect(), &mut writer).unwrap();
+
+            let ans = r##"# HELP test_counter test \"help\"
+# TYPE test_counter counter
+test_counter_total{a="1"} 1
+# EOF
+"##;
+            assert_eq!(ans, str::from_utf8(writer.as_slice()).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_openmetrics_encoder_counter_created() {
+        let counter =
+            Counter::with_opts(Opts::new("test_counter", "test help").const_label("a", "1"))
+                .unwrap();
+        counter.reset();
+        counter.inc();
+
+        // Pin the created timestamp of the reset counter.
+        let mut mfs = counter.collect();
+        let created = mfs[0].mut_metric()[0].mut_counter().mut_created_timestamp();
+        assert_ne!(created.get_seconds(), 0);
+        *created = make_timestamp(1600000000.5);
+
+        let mut writer = Vec::<u8>::new();
+        OpenMetricsEncoder::new().encode(&mfs, &mut writer).unwrap();
+
+        let ans = r##"# HELP test_counter test help
+# TYPE test_counter counter
+test_counter_total{a="1"} 1
+test_counter_created{a="1"} 1600000000.5
+# EOF
+"##;
+        assert_eq!(ans, str::from_utf8(writer.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn test_openmetrics_encoder_histogram() {
+        let opts = HistogramOpts::new("test_histogram", "test help").buckets(vec![0.5, 1.0]);
+        let histogram = Histogram::with_opts(opts).unwrap();
+        histogram.observe(0.25);
+
+        let mut writer = Vec::<u8>::new();
+        let encoder = OpenMetricsEncoder::new();
+        encoder.encode(&histogram.collect(), &mut writer).unwrap();
+
+        let ans = r##"# HELP test_histogram test help
+# TYPE test_histogram histogram
+test_histogram_bucket{le="0.5"} 1
+test_histogram_bucket{le="1"} 1
+test_histogram_bucket{le="+Inf"} 1
+test_histogram_sum 0.25
+test_histogram_count 1
+# EOF
+"##;
+        assert_eq!(ans, str::from_utf8(writer.as_slice()).unwrap());
+    }
+    #[test]
+    #[allow(deprecated)]
+    fn test_openmetrics_encoder_untyped() {
+        let mut u = proto::Untyped::default();
+        u.set_value(2.5);
+        let mut m = proto::Metric::default();
+        m.set_untyped(u);
+        let mut mf = MetricFamily::default();
+        mf.set_name("test_untyped".to_owned());
+        mf.set_help("test help".to_owned());
+        mf.set_field_type(MetricType::UNTYPED);
+        mf.set_metric(from_vec!(vec![m]));
+
+        let mut writer = Vec::<u8>::new();
+        OpenMetricsEncoder::new()
+            .encode(&[mf], &mut writer)
+            .unwrap();
+
+        let ans = r##"# HELP test_untyped test help
+# TYPE test_untyped unknown
+test_untyped 2.5
+# EOF
+"##;
+        assert_eq!(ans, str::from_utf8(writer.as_slice()).unwrap());
+    }
+}
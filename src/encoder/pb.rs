@@ -43,12 +43,16 @@ impl Encoder for ProtobufEncoder {
 
 #[cfg(test)]
 mod tests {
-    use crate::counter::CounterVec;
+    use protobuf::CodedInputStream;
+
+    use crate::counter::{Counter, CounterVec};
     use crate::encoder::Encoder;
+    use crate::gauge::Gauge;
+    use crate::histogram::{Histogram, HistogramOpts};
     use crate::metrics::Opts;
+    use crate::proto::MetricFamily;
     use crate::registry;
 
-    // TODO: add more tests.
     #[rustfmt::skip]
     #[test]
     fn test_protobuf_encoder() {
@@ -74,4 +78,81 @@ mod tests {
                        240, 63];
         assert_eq!(ans, writer);
     }
+
+    #[test]
+    fn test_protobuf_encoder_created_timestamp() {
+        let counter = Counter::new("test_counter", "test counter help").unwrap();
+        counter.inc();
+        let reg = registry::Registry::new();
+        reg.register(Box::new(counter.clone())).unwrap();
+
+        let encoder = super::ProtobufEncoder::new();
+        let decode = || {
+            let mut writer = Vec::<u8>::new();
+            encoder.encode(&reg.gather(), &mut writer).unwrap();
+            let mut is = CodedInputStream::from_bytes(&writer);
+            is.read_message::<MetricFamily>().unwrap()
+        };
+
+        // A counter which was never reset has no created timestamp.
+        assert!(!decode().get_metric()[0]
+            .get_counter()
+            .has_created_timestamp());
+
+        counter.reset();
+        let mf = decode();
+        let created = mf.get_metric()[0].get_counter().get_created_timestamp();
+        let secs = created.get_seconds() as f64 + f64::from(created.get_nanos()) / 1e9;
+        assert!((secs - counter.created_at()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_protobuf_encoder_roundtrip() {
+        let counter = Counter::new("test_counter", "test counter help").unwrap();
+        counter.inc_by(3.0);
+        let gauge =
+            Gauge::with_opts(Opts::new("test_gauge", "test gauge help").const_label("a", "1"))
+                .unwrap();
+        gauge.set(-1.5);
+        let histogram = Histogram::with_opts(
+            HistogramOpts::new("test_histogram", "test histogram help").buckets(vec![0.5, 1.0]),
+        )
+        .unwrap();
+        histogram.observe(0.25);
+        histogram.observe(2.0);
+
+        let reg = registry::Registry::new();
+        reg.register(Box::new(counter)).unwrap();
+        reg.register(Box::new(gauge)).unwrap();
+        reg.register(Box::new(histogram)).unwrap();
+        let mfs = reg.gather();
+        assert_eq!(mfs.len(), 3);
+
+        let encoder = super::ProtobufEncoder::new();
+        let mut writer = Vec::<u8>::new();
+        encoder.encode(&mfs, &mut writer).unwrap();
+
+        // Each metric family is written as a length-delimited message.
+        let mut is = CodedInputStream::from_bytes(&writer);
+        let mut decoded = Vec::new();
+        while !is.eof().unwrap() {
+            decoded.push(is.read_message::<MetricFamily>().unwrap());
+        }
+        assert_eq!(decoded, mfs);
+
+        let h = decoded[2].get_metric()[0].get_histogram();
+        assert_eq!(h.get_sample_count(), 2);
+        assert_eq!(h.get_bucket()[0].get_cumulative_count(), 1);
+    }
+
+    #[test]
+    fn test_protobuf_format_type() {
+        let encoder = super::ProtobufEncoder::new();
+        assert_eq!(encoder.format_type(), super::PROTOBUF_FORMAT);
+        assert_eq!(
+            encoder.format_type(),
+            "application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; \
+             encoding=delimited"
+        );
+    }
 }
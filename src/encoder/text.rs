@@ -3,7 +3,7 @@
 use std::borrow::Cow;
 use std::io::Write;
 
-use crate::errors::Result;
+use crate::errors::{Error, Result};
 use crate::histogram::BUCKET_LABEL;
 use crate::proto::{self, MetricFamily, MetricType};
 
@@ -12,11 +12,15 @@ use super::{check_metric_family, Encoder};
 /// The text format of metric family.
 pub const TEXT_FORMAT: &str = "text/plain; version=0.0.4";
 
-const POSITIVE_INF: &str = "+Inf";
-const QUANTILE: &str = "quantile";
+pub(super) const POSITIVE_INF: &str = "+Inf";
+pub(super) const QUANTILE: &str = "quantile";
 
 /// An implementation of an [`Encoder`] that converts a [`MetricFamily`] proto message
 /// into text format.
+///
+/// Use [`emit_empty_families`](TextEncoder::emit_empty_families) or
+/// [`with_metadata`](TextEncoder::with_metadata) to get a
+/// [`ConfiguredTextEncoder`] with other than the default options.
 #[derive(Debug, Default)]
 pub struct TextEncoder;
 
@@ -25,18 +29,78 @@ impl TextEncoder {
     pub fn new() -> TextEncoder {
         TextEncoder
     }
+
+    /// Return a text encoder which encodes metric families without metrics,
+    /// see [`ConfiguredTextEncoder::emit_empty_families`].
+    pub fn emit_empty_families(self, emit_empty_families: bool) -> ConfiguredTextEncoder {
+        ConfiguredTextEncoder::default().emit_empty_families(emit_empty_families)
+    }
+
+    /// Return a text encoder which omits the `# HELP` and `# TYPE` lines, see
+    /// [`ConfiguredTextEncoder::with_metadata`].
+    pub fn with_metadata(self, with_metadata: bool) -> ConfiguredTextEncoder {
+        ConfiguredTextEncoder::default().with_metadata(with_metadata)
+    }
 }
 
 impl Encoder for TextEncoder {
+    fn encode<W: Write>(&self, metric_families: &[MetricFamily], writer: &mut W) -> Result<()> {
+        ConfiguredTextEncoder::default().encode(metric_families, writer)
+    }
+
+    fn format_type(&self) -> &str {
+        TEXT_FORMAT
+    }
+}
+
+/// A [`TextEncoder`] with options, which default to the behavior of
+/// [`TextEncoder`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConfiguredTextEncoder {
+    emit_empty_families: bool,
+    omit_metadata: bool,
+}
+
+impl ConfiguredTextEncoder {
+    /// `emit_empty_families` sets whether metric families without metrics,
+    /// e.g. a [`HistogramVec`](crate::HistogramVec) without children, are
+    /// encoded as their `# HELP` and `# TYPE` lines only, instead of being
+    /// rejected. Use it with
+    /// [`Registry::gather_with_empty`](crate::Registry::gather_with_empty),
+    /// as [`Registry::gather`](crate::Registry::gather) leaves out empty
+    /// metric families. It is disabled by default.
+    pub fn emit_empty_families(mut self, emit_empty_families: bool) -> Self {
+        self.emit_empty_families = emit_empty_families;
+        self
+    }
+
+    /// `with_metadata` sets whether the `# HELP` and `# TYPE` lines are
+    /// written. Without them only the sample lines are written, which saves
+    /// bandwidth for consumers which know the metrics already, but is not
+    /// valid for Prometheus. It is enabled by default.
+    pub fn with_metadata(mut self, with_metadata: bool) -> Self {
+        self.omit_metadata = !with_metadata;
+        self
+    }
+}
+
+impl Encoder for ConfiguredTextEncoder {
+    #[allow(deprecated)]
     fn encode<W: Write>(&self, metric_families: &[MetricFamily], writer: &mut W) -> Result<()> {
         for mf in metric_families {
             // Fail-fast checks.
-            check_metric_family(mf)?;
+            if self.emit_empty_families && mf.get_metric().is_empty() {
+                if mf.get_name().is_empty() {
+                    return Err(Error::Msg(format!("MetricFamily has no name: {:?}", mf)));
+                }
+            } else {
+                check_metric_family(mf)?;
+            }
 
             // Write `# HELP` header.
             let name = mf.get_name();
             let help = mf.get_help();
-            if !help.is_empty() {
+            if !help.is_empty() && !self.omit_metadata {
                 writer.write_all(b"# HELP ")?;
                 writer.write_all(name.as_bytes())?;
                 writer.write_all(b" ")?;
@@ -46,12 +110,14 @@ impl Encoder for TextEncoder {
 
             // Write `# TYPE` header.
             let metric_type = mf.get_field_type();
-            let lowercase_type = format!("{:?}", metric_type).to_lowercase();
-            writer.write_all(b"# TYPE ")?;
-            writer.write_all(name.as_bytes())?;
-            writer.write_all(b" ")?;
-            writer.write_all(lowercase_type.as_bytes())?;
-            writer.write_all(b"\n")?;
+            if !self.omit_metadata {
+                let lowercase_type = format!("{:?}", metric_type).to_lowercase();
+                writer.write_all(b"# TYPE ")?;
+                writer.write_all(name.as_bytes())?;
+                writer.write_all(b" ")?;
+                writer.write_all(lowercase_type.as_bytes())?;
+                writer.write_all(b"\n")?;
+            }
 
             for m in mf.get_metric() {
                 match metric_type {
@@ -127,7 +193,7 @@ impl Encoder for TextEncoder {
                         )?;
                     }
                     MetricType::UNTYPED => {
-                        unimplemented!();
+                        write_sample(writer, name, None, m, None, m.get_untyped().get_value())?;
                     }
                 }
             }
@@ -182,7 +248,7 @@ fn write_sample(
 /// written. Otherwise, the label pairs are written, escaped as required by the
 /// text format, and enclosed in '{...}'. The function returns the number of
 /// bytes written and any error encountered.
-fn label_pairs_to_text(
+pub(super) fn label_pairs_to_text(
     pairs: &[proto::LabelPair],
     additional_label: Option<(&str, &str)>,
     writer: &mut dyn Write,
@@ -228,7 +294,7 @@ fn find_first_occurence(v: &str, include_double_quote: bool) -> Option<usize> {
 ///
 /// Implementation adapted from
 /// https://lise-henry.github.io/articles/optimising_strings.html
-fn escape_string(v: &str, include_double_quote: bool) -> Cow<'_, str> {
+pub(super) fn escape_string(v: &str, include_double_quote: bool) -> Cow<'_, str> {
     let first_occurence = find_first_occurence(v, include_double_quote);
 
     if let Some(first) = first_occurence {
@@ -265,8 +331,9 @@ mod tests {
     use super::*;
     use crate::counter::Counter;
     use crate::gauge::Gauge;
-    use crate::histogram::{Histogram, HistogramOpts};
+    use crate::histogram::{Histogram, HistogramOpts, HistogramVec};
     use crate::metrics::{Collector, Opts};
+    use crate::registry::Registry;
 
     #[test]
     fn test_escape_string() {
@@ -353,6 +420,81 @@ test_histogram_count{a="1"} 1
         assert_eq!(ans.as_bytes(), writer.as_slice());
     }
 
+    #[test]
+    fn test_text_encoder_empty_family() {
+        let vec = HistogramVec::new(
+            HistogramOpts::new("test_histogram", "test help").buckets(vec![1.0]),
+            &["a"],
+        )
+        .unwrap();
+        let r = Registry::new();
+        r.register(Box::new(vec.clone())).unwrap();
+        assert!(r.gather().is_empty());
+
+        let mut writer = Vec::<u8>::new();
+        let mfs = r.gather_with_empty();
+        TextEncoder::new().encode(&mfs, &mut writer).unwrap_err();
+        let encoder = TextEncoder::new().emit_empty_families(true);
+        encoder.encode(&mfs, &mut writer).unwrap();
+        let ans = r##"# HELP test_histogram test help
+# TYPE test_histogram histogram
+"##;
+        assert_eq!(ans, str::from_utf8(writer.as_slice()).unwrap());
+
+        // Once observed, the samples follow.
+        vec.with_label_values(&["1"]).observe(0.5);
+        let mut writer = Vec::<u8>::new();
+        encoder.encode(&r.gather_with_empty(), &mut writer).unwrap();
+        let ans = r##"# HELP test_histogram test help
+# TYPE test_histogram histogram
+test_histogram_bucket{a="1",le="1"} 1
+test_histogram_bucket{a="1",le="+Inf"} 1
+test_histogram_sum{a="1"} 0.5
+test_histogram_count{a="1"} 1
+"##;
+        assert_eq!(ans, str::from_utf8(writer.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn test_text_encoder_unit_struct() {
+        let counter = Counter::new("test_counter", "test help").unwrap();
+        let mut unit = Vec::<u8>::new();
+        TextEncoder.encode(&counter.collect(), &mut unit).unwrap();
+        let mut configured = Vec::<u8>::new();
+        ConfiguredTextEncoder::default()
+            .encode(&counter.collect(), &mut configured)
+            .unwrap();
+        assert_eq!(unit, configured);
+    }
+
+    #[test]
+    fn test_text_encoder_without_metadata() {
+        let counter = Counter::new("test_counter", "test help").unwrap();
+        counter.inc();
+        let histogram = Histogram::with_opts(
+            HistogramOpts::new("test_histogram", "test help").buckets(vec![1.0]),
+        )
+        .unwrap();
+        histogram.observe(0.5);
+        let mut mfs = counter.collect();
+        mfs.extend(histogram.collect());
+
+        let mut with = Vec::<u8>::new();
+        TextEncoder::new().encode(&mfs, &mut with).unwrap();
+        let mut without = Vec::<u8>::new();
+        TextEncoder::new()
+            .with_metadata(false)
+            .encode(&mfs, &mut without)
+            .unwrap();
+
+        let with = str::from_utf8(&with).unwrap();
+        let without = str::from_utf8(&without).unwrap();
+        assert!(without.lines().all(|l| !l.starts_with('#')));
+        let samples: Vec<_> = with.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(without.lines().collect::<Vec<_>>(), samples);
+        assert_eq!(samples.len(), 5);
+    }
+
     #[test]
     fn test_text_encoder_summary() {
         use crate::proto::{Metric, Quantile, Summary};
@@ -383,7 +525,7 @@ test_histogram_count{a="1"} 1
 
         let mut writer = Vec::<u8>::new();
         let encoder = TextEncoder::new();
-        let res = encoder.encode(&vec![metric_family], &mut writer);
+        let res = encoder.encode(&[metric_family], &mut writer);
         assert!(res.is_ok());
 
         let ans = r##"# HELP test_summary This is a test summary statistic
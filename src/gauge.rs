@@ -1,14 +1,23 @@
 // Copyright 2014 The Prometheus Authors
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
+use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::sync::Arc;
+use std::time::Instant;
 
-use crate::atomic64::{Atomic, AtomicF64, AtomicI64, Number};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+
+use crate::atomic64::{Atomic, AtomicF64, AtomicI64, AtomicU64, Number};
+use crate::counter::{Counter, IntCounter};
 use crate::desc::Desc;
-use crate::errors::Result;
-use crate::metrics::{Collector, Metric, Opts};
+use crate::errors::{Error, Result};
+use crate::histogram::duration_to_seconds;
+use crate::metrics::{Collector, Metric, MetricSnapshotLine, MetricVisitor, Opts};
 use crate::proto;
+use crate::registry::{default_registry, Registry};
+use crate::timer;
 use crate::value::{Value, ValueType};
 use crate::vec::{MetricVec, MetricVecBuilder};
 
@@ -46,6 +55,34 @@ impl<P: Atomic> GenericGauge<P> {
         Self::with_opts_and_label_values(&opts, &[])
     }
 
+    /// Create a [`GenericGauge`] with the `opts` options, starting at `value`
+    /// instead of 0, e.g. to reflect a count which already exists at startup.
+    pub fn with_initial_value(opts: Opts, value: P::T) -> Result<Self> {
+        let v = Value::new(&opts, ValueType::Gauge, value, &[])?;
+        Ok(Self { v: Arc::new(v) })
+    }
+
+    /// Create a [`GenericGauge`] with the `opts` options and register it to
+    /// `registry`, returning the registered gauge.
+    pub fn register(opts: Opts, registry: &Registry) -> Result<Self>
+    where
+        P: 'static,
+    {
+        let gauge = Self::with_opts(opts)?;
+        registry.register(Box::new(gauge.clone()))?;
+        Ok(gauge)
+    }
+
+    /// Create a [`GenericGauge`] with the `opts` options and register it to the
+    /// [default registry](crate::default_registry), returning the registered
+    /// gauge.
+    pub fn register_default(opts: Opts) -> Result<Self>
+    where
+        P: 'static,
+    {
+        Self::register(opts, default_registry())
+    }
+
     fn with_opts_and_label_values(opts: &Opts, label_values: &[&str]) -> Result<Self> {
         let v = Value::new(opts, ValueType::Gauge, P::T::from_i64(0), label_values)?;
         Ok(Self { v: Arc::new(v) })
@@ -57,6 +94,16 @@ impl<P: Atomic> GenericGauge<P> {
         self.v.set(v);
     }
 
+    /// Set the gauge to an arbitrary value sampled at `timestamp_ms`
+    /// milliseconds since the Unix epoch, e.g. a value mirrored from another
+    /// system. The timestamp is exposed along with the gauge until the gauge
+    /// is changed by any other method, a timestamp of 0 removes it. A
+    /// concurrent collect may pair the new value with the previous timestamp.
+    #[inline]
+    pub fn set_with_timestamp(&self, v: P::T, timestamp_ms: i64) {
+        self.v.set_with_timestamp(v, timestamp_ms);
+    }
+
     /// Increase the gauge by 1.
     #[inline]
     pub fn inc(&self) {
@@ -70,17 +117,19 @@ impl<P: Atomic> GenericGauge<P> {
     }
 
     /// Add the given value to the gauge. (The value can be
-    /// negative, resulting in a decrement of the gauge.)
+    /// negative, resulting in a decrement of the gauge.) The value can be of
+    /// any type converting into the gauge's, e.g. a `u32` for a [`Gauge`].
     #[inline]
-    pub fn add(&self, v: P::T) {
-        self.v.inc_by(v);
+    pub fn add<N: Into<P::T>>(&self, v: N) {
+        self.v.inc_by(v.into());
     }
 
     /// Subtract the given value from the gauge. (The value can be
-    /// negative, resulting in an increment of the gauge.)
+    /// negative, resulting in an increment of the gauge.) The value can be of
+    /// any type converting into the gauge's, e.g. a `u32` for a [`Gauge`].
     #[inline]
-    pub fn sub(&self, v: P::T) {
-        self.v.dec_by(v);
+    pub fn sub<N: Into<P::T>>(&self, v: N) {
+        self.v.dec_by(v.into());
     }
 
     /// Return the gauge value.
@@ -88,6 +137,48 @@ impl<P: Atomic> GenericGauge<P> {
     pub fn get(&self) -> P::T {
         self.v.get()
     }
+
+    /// Return a readable [`MetricSnapshotLine`] of the gauge, e.g. for logging
+    /// its current value.
+    pub fn snapshot(&self) -> MetricSnapshotLine {
+        MetricSnapshotLine::new(
+            &self.v.desc.fq_name,
+            proto::MetricType::GAUGE,
+            self.v.metric(),
+        )
+    }
+
+    /// Like [`collect`](Collector::collect), but refills `mf` in place instead
+    /// of allocating a new metric family, e.g. to reuse the same buffer for
+    /// every scrape. `mf` may be empty or hold the result of a previous call
+    /// for any metric, its allocations are only reused for the same one.
+    pub fn collect_into(&self, mf: &mut proto::MetricFamily) {
+        self.v.collect_into(mf);
+    }
+
+    /// Change the help string of the gauge.
+    ///
+    /// The help can only be changed before the gauge is registered, see
+    /// [`GenericCounter::set_help`](crate::core::GenericCounter::set_help).
+    pub fn set_help(&mut self, help: &str) -> Result<()> {
+        Value::set_help(&mut self.v, help)
+    }
+}
+
+impl GenericGauge<AtomicF64> {
+    /// Return the gauge value as an `i64`, `None` if the value is not an
+    /// integer or is out of the range of `i64`, instead of silently truncating
+    /// it like an `as` cast does.
+    pub fn get_as_i64(&self) -> Option<i64> {
+        // `i64::MAX as f64` rounds up to 2^63, which is out of range.
+        const BOUND: f64 = 9_223_372_036_854_775_808.0;
+        let v = self.get();
+        if v.fract() == 0.0 && (-BOUND..BOUND).contains(&v) {
+            Some(v as i64)
+        } else {
+            None
+        }
+    }
 }
 
 impl<P: Atomic> Collector for GenericGauge<P> {
@@ -98,12 +189,24 @@ impl<P: Atomic> Collector for GenericGauge<P> {
     fn collect(&self) -> Vec<proto::MetricFamily> {
         vec![self.v.collect()]
     }
+
+    fn visit(&self, visitor: &mut dyn MetricVisitor) {
+        self.v.visit(visitor)
+    }
+
+    fn metric_type(&self, _: &Desc) -> Option<proto::MetricType> {
+        Some(self.v.val_type.metric_type())
+    }
 }
 
 impl<P: Atomic> Metric for GenericGauge<P> {
     fn metric(&self) -> proto::Metric {
         self.v.metric()
     }
+
+    fn visit(&self, _: proto::MetricType, visitor: &mut dyn MetricVisitor) {
+        self.v.visit_metric(visitor)
+    }
 }
 
 #[derive(Debug)]
@@ -160,13 +263,561 @@ impl<P: Atomic> GenericGaugeVec<P> {
     }
 }
 
+/// A [`Gauge`] that also tracks the highest value it has reached, e.g. the peak
+/// number of connections of a pool since start.
+///
+/// Besides the `name` series of the current value, it exposes a `name_max`
+/// series of the peak value, which can be reset with
+/// [`reset_peak`](PeakGauge::reset_peak).
+#[derive(Clone, Debug)]
+pub struct PeakGauge {
+    gauge: Gauge,
+    peak: Gauge,
+}
+
+impl PeakGauge {
+    /// Create a [`PeakGauge`] with the `name` and `help` arguments.
+    pub fn new<S1: Into<String>, S2: Into<String>>(name: S1, help: S2) -> Result<Self> {
+        let opts = Opts::new(name, help);
+        Self::with_opts(opts)
+    }
+
+    /// Create a [`PeakGauge`] with the `opts` options. The peak series is named
+    /// after `opts` with a `_max` suffix.
+    pub fn with_opts(opts: Opts) -> Result<Self> {
+        let mut peak_opts = opts.clone();
+        peak_opts.name = format!("{}_max", peak_opts.name);
+        peak_opts.help = format!("{} (peak value)", peak_opts.help);
+        Ok(Self {
+            gauge: Gauge::with_opts(opts)?,
+            peak: Gauge::with_opts(peak_opts)?,
+        })
+    }
+
+    /// Set the gauge to an arbitrary value.
+    #[inline]
+    pub fn set(&self, v: f64) {
+        self.gauge.set(v);
+        self.peak.v.val.fetch_max(v);
+    }
+
+    /// Increase the gauge by 1.
+    #[inline]
+    pub fn inc(&self) {
+        self.add(1.0);
+    }
+
+    /// Decrease the gauge by 1.
+    #[inline]
+    pub fn dec(&self) {
+        self.add(-1.0);
+    }
+
+    /// Add the given value to the gauge. (The value can be
+    /// negative, resulting in a decrement of the gauge.)
+    #[inline]
+    pub fn add<N: Into<f64>>(&self, v: N) {
+        let v = v.into();
+        let previous = self.gauge.v.val.fetch_add(v);
+        self.peak.v.val.fetch_max(previous + v);
+    }
+
+    /// Subtract the given value from the gauge. (The value can be
+    /// negative, resulting in an increment of the gauge.)
+    #[inline]
+    pub fn sub<N: Into<f64>>(&self, v: N) {
+        self.add(-v.into());
+    }
+
+    /// Return the gauge value.
+    #[inline]
+    pub fn get(&self) -> f64 {
+        self.gauge.get()
+    }
+
+    /// Return the highest value the gauge has reached since creation or the
+    /// last [`reset_peak`](PeakGauge::reset_peak).
+    #[inline]
+    pub fn get_peak(&self) -> f64 {
+        self.peak.get()
+    }
+
+    /// Reset the peak to the current value of the gauge.
+    pub fn reset_peak(&self) {
+        self.peak.set(self.gauge.get());
+        // The gauge may have grown past the value just stored.
+        self.peak.v.val.fetch_max(self.gauge.get());
+    }
+}
+
+impl Collector for PeakGauge {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.gauge.v.desc, &self.peak.v.desc]
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        vec![self.gauge.v.collect(), self.peak.v.collect()]
+    }
+}
+
+/// A [`Collector`] wrapping a [`Counter`] which also exposes its average rate
+/// of increase per second since the previous collect, for short windows which
+/// are awkward to compute with `rate()` in PromQL.
+///
+/// Besides the counter series, it exposes a `name_rate` gauge series, where
+/// `name` is the counter name without its `_total` suffix. The rate is
+/// computed on every [`collect`](Collector::collect), so its value depends on
+/// the scrape cadence, and it is zero on the first collect. A counter reset is
+/// treated as an increase from zero.
+#[derive(Clone, Debug)]
+pub struct RateGauge {
+    counter: Counter,
+    rate: Gauge,
+    last: Arc<Mutex<Option<(f64, Instant)>>>,
+}
+
+impl RateGauge {
+    /// Create a [`RateGauge`] wrapping `counter`. The rate series has the
+    /// const labels of the counter.
+    pub fn new(counter: Counter) -> Result<Self> {
+        let rate = Gauge::with_opts(rate_opts(
+            counter.desc()[0],
+            "_rate",
+            "per-second rate since the previous scrape",
+        ))?;
+        Ok(Self {
+            counter,
+            rate,
+            last: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Return the wrapped counter.
+    pub fn counter(&self) -> &Counter {
+        &self.counter
+    }
+
+    /// Return the rate computed by the last collect.
+    pub fn get_rate(&self) -> f64 {
+        self.rate.get()
+    }
+
+    fn update_rate(&self, now: Instant) {
+        let value = self.counter.get();
+        let mut last = self.last.lock();
+        if let Some(rate) = last.and_then(|last| increase_rate(last, value, now)) {
+            self.rate.set(rate);
+        }
+        *last = Some((value, now));
+    }
+}
+
+impl Collector for RateGauge {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut descs = self.counter.desc();
+        descs.extend(self.rate.desc());
+        descs
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        self.update_rate(Instant::now());
+        let mut mfs = self.counter.collect();
+        mfs.extend(self.rate.collect());
+        mfs
+    }
+}
+
+/// A [`Collector`] wrapping an [`IntCounter`] which also exposes an
+/// exponentially weighted moving average of its rate of increase per second,
+/// e.g. for push-based systems without a Prometheus server computing `rate()`.
+///
+/// Besides the counter series, it exposes a `name_ewma_rate` gauge series,
+/// where `name` is the counter name without its `_total` suffix. On every
+/// [`collect`](Collector::collect), the rate since the previous collect is
+/// folded into the average with the weight `alpha`, i.e.
+/// `ewma = alpha * rate + (1 - alpha) * ewma`, starting from the first rate.
+/// This is an approximation whose smoothing depends on the scrape cadence. A
+/// counter reset is treated as an increase from zero.
+#[derive(Clone, Debug)]
+pub struct EwmaRate {
+    counter: IntCounter,
+    rate: Gauge,
+    alpha: f64,
+    state: Arc<Mutex<EwmaState>>,
+}
+
+#[derive(Debug, Default)]
+struct EwmaState {
+    last: Option<(f64, Instant)>,
+    initialized: bool,
+}
+
+impl EwmaRate {
+    /// Create an [`EwmaRate`] wrapping `counter`, which weights the newest
+    /// rate by `alpha`. The rate series has the const labels of the counter.
+    ///
+    /// The function returns an error if `alpha` is not in `(0, 1]`.
+    pub fn new(counter: IntCounter, alpha: f64) -> Result<Self> {
+        if !(alpha > 0.0 && alpha <= 1.0) {
+            return Err(Error::Msg(format!(
+                "EwmaRate needs an alpha in (0, 1], alpha: {}",
+                alpha
+            )));
+        }
+
+        let rate = Gauge::with_opts(rate_opts(
+            counter.desc()[0],
+            "_ewma_rate",
+            "moving average of the per-second rate",
+        ))?;
+        Ok(Self {
+            counter,
+            rate,
+            alpha,
+            state: Arc::new(Mutex::new(EwmaState::default())),
+        })
+    }
+
+    /// Return the wrapped counter.
+    pub fn counter(&self) -> &IntCounter {
+        &self.counter
+    }
+
+    /// Return the moving average as of the last collect.
+    pub fn get_rate(&self) -> f64 {
+        self.rate.get()
+    }
+
+    fn update_rate(&self, now: Instant) {
+        let value = self.counter.get() as f64;
+        let mut state = self.state.lock();
+        if let Some(rate) = state.last.and_then(|last| increase_rate(last, value, now)) {
+            if state.initialized {
+                let ewma = self.alpha * rate + (1.0 - self.alpha) * self.rate.get();
+                self.rate.set(ewma);
+            } else {
+                self.rate.set(rate);
+                state.initialized = true;
+            }
+        }
+        state.last = Some((value, now));
+    }
+}
+
+impl Collector for EwmaRate {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut descs = self.counter.desc();
+        descs.extend(self.rate.desc());
+        descs
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        self.update_rate(Instant::now());
+        let mut mfs = self.counter.collect();
+        mfs.extend(self.rate.collect());
+        mfs
+    }
+}
+
+/// `rate_opts` returns the options of a gauge derived from the counter
+/// described by `desc`, named after the counter without its `_total` suffix
+/// followed by `suffix`, with the const labels of the counter.
+fn rate_opts(desc: &Desc, suffix: &str, help: &str) -> Opts {
+    let name = desc.fq_name.strip_suffix("_total").unwrap_or(&desc.fq_name);
+    let const_labels = desc
+        .const_label_pairs
+        .iter()
+        .map(|l| (l.get_name().to_owned(), l.get_value().to_owned()))
+        .collect();
+    Opts::new(
+        format!("{}{}", name, suffix),
+        format!("{} ({})", desc.help, help),
+    )
+    .const_labels(const_labels)
+}
+
+/// `increase_rate` returns the per-second increase of a counter from the
+/// `last` value and time to `value` at `now`, `None` if no time elapsed. A
+/// decrease is treated as a reset.
+fn increase_rate(last: (f64, Instant), value: f64, now: Instant) -> Option<f64> {
+    let (last_value, last_time) = last;
+    let elapsed = duration_to_seconds(now.saturating_duration_since(last_time));
+    if elapsed <= 0.0 {
+        return None;
+    }
+    let increase = if value < last_value {
+        value
+    } else {
+        value - last_value
+    };
+    Some(increase / elapsed)
+}
+
+/// A [`Collector`] exposing a gauge whose value is computed by a closure on
+/// every [`collect`](Collector::collect), e.g. the size of a cache owned by
+/// another subsystem.
+///
+/// The closure of [`fallible`](GaugeFunc::fallible) may fail, e.g. when
+/// reading a file under `/proc`. A failing closure omits the gauge from that
+/// scrape and increases [`error_count`](GaugeFunc::error_count), instead of
+/// reporting a stale or made-up value.
+///
+/// # Examples
+///
+/// ```
+/// use prometheus::{GaugeFunc, Opts, Registry};
+///
+/// let gauge = GaugeFunc::fallible(Opts::new("loadavg", "Load average."), || {
+///     std::fs::read_to_string("/proc/loadavg")
+///         .ok()
+///         .and_then(|s| s.split(' ').next()?.parse::<f64>().ok())
+///         .ok_or("unreadable")
+/// })
+/// .unwrap();
+/// let r = Registry::new();
+/// r.register(Box::new(gauge)).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct GaugeFunc {
+    gauge: Gauge,
+    f: Arc<dyn Fn() -> Option<f64> + Send + Sync>,
+    errors: Arc<AtomicU64>,
+}
+
+impl std::fmt::Debug for GaugeFunc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GaugeFunc")
+            .field("gauge", &self.gauge)
+            .field("errors", &self.errors)
+            .finish()
+    }
+}
+
+impl GaugeFunc {
+    /// Create a [`GaugeFunc`] with the `opts` options, whose value is `f()`.
+    pub fn new<F>(opts: Opts, f: F) -> Result<Self>
+    where
+        F: Fn() -> f64 + Send + Sync + 'static,
+    {
+        Self::with_fn(opts, Arc::new(move || Some(f())))
+    }
+
+    /// Create a [`GaugeFunc`] with the `opts` options, whose value is `f()`,
+    /// which is omitted from the scrapes where `f` fails.
+    pub fn fallible<F, E>(opts: Opts, f: F) -> Result<Self>
+    where
+        F: Fn() -> std::result::Result<f64, E> + Send + Sync + 'static,
+    {
+        Self::with_fn(opts, Arc::new(move || f().ok()))
+    }
+
+    fn with_fn(opts: Opts, f: Arc<dyn Fn() -> Option<f64> + Send + Sync>) -> Result<Self> {
+        Ok(Self {
+            gauge: Gauge::with_opts(opts)?,
+            f,
+            errors: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Return the number of collects where the closure failed.
+    pub fn error_count(&self) -> u64 {
+        self.errors.get()
+    }
+}
+
+impl Collector for GaugeFunc {
+    fn desc(&self) -> Vec<&Desc> {
+        self.gauge.desc()
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        match (self.f)() {
+            Some(v) => {
+                self.gauge.set(v);
+                self.gauge.collect()
+            }
+            None => {
+                self.errors.inc_by(1);
+                vec![]
+            }
+        }
+    }
+}
+
+/// An info metric, i.e. a gauge which always equals 1 and carries metadata,
+/// like the version of an application, as labels. Unlike a plain gauge, it has
+/// no methods to change its value or labels.
+///
+/// By convention, the name of an info metric ends with `_info`.
+///
+/// # Examples
+///
+/// ```
+/// use prometheus::{owned_labels, Info, Registry};
+///
+/// let info = Info::new(
+///     "runtime_info",
+///     "Information about the runtime.",
+///     owned_labels! {"os" => "linux"},
+/// )
+/// .unwrap();
+/// let r = Registry::new();
+/// r.register(Box::new(info)).unwrap();
+///
+/// let mfs = r.gather();
+/// assert_eq!(mfs[0].get_metric()[0].get_gauge().get_value(), 1.0);
+/// assert_eq!(mfs[0].get_metric()[0].get_label()[0].get_value(), "linux");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Info {
+    gauge: IntGauge,
+}
+
+impl Info {
+    /// Create an [`Info`] metric with the `name` and `help` arguments, carrying
+    /// `labels`.
+    pub fn new<S1: Into<String>, S2: Into<String>>(
+        name: S1,
+        help: S2,
+        labels: HashMap<String, String>,
+    ) -> Result<Self> {
+        Self::with_opts(Opts::new(name, help).const_labels(labels))
+    }
+
+    /// Create an [`Info`] metric with the `opts` options, whose const labels
+    /// are the labels it carries. An error is returned if the options have
+    /// variable labels.
+    pub fn with_opts(opts: Opts) -> Result<Self> {
+        let v = Value::new(&opts, ValueType::Gauge, 1, &[])?;
+        Ok(Self {
+            gauge: IntGauge { v: Arc::new(v) },
+        })
+    }
+}
+
+impl Collector for Info {
+    fn desc(&self) -> Vec<&Desc> {
+        self.gauge.desc()
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        self.gauge.collect()
+    }
+
+    fn visit(&self, visitor: &mut dyn MetricVisitor) {
+        Collector::visit(&self.gauge, visitor)
+    }
+}
+
+/// `build_info_collector` creates an [`Info`] metric named
+/// `<namespace>_build_info` (or `build_info` if `namespace` is empty), which
+/// carries `labels`, e.g. the version and commit.
+///
+/// An error is returned if a label name is invalid.
+///
+/// # Examples
+///
+/// ```
+/// use prometheus::{build_info_collector, owned_labels, Registry};
+///
+/// let build_info = build_info_collector(
+///     "app",
+///     owned_labels! {"version" => "1.2.3", "commit" => "a1b2c3d"},
+/// )
+/// .unwrap();
+/// let r = Registry::new();
+/// r.register(Box::new(build_info)).unwrap();
+///
+/// let mfs = r.gather();
+/// assert_eq!(mfs[0].get_name(), "app_build_info");
+/// assert_eq!(mfs[0].get_metric().len(), 1);
+/// assert_eq!(mfs[0].get_metric()[0].get_gauge().get_value(), 1.0);
+/// ```
+pub fn build_info_collector<S: Into<String>>(
+    namespace: S,
+    labels: HashMap<String, String>,
+) -> Result<Info> {
+    let opts = Opts::new("build_info", "Build information of the application.")
+        .namespace(namespace)
+        .const_labels(labels);
+    Info::with_opts(opts)
+}
+
+/// `start_time_collector` creates a constant [`Gauge`] named
+/// `<namespace>_process_start_time_seconds` (or `process_start_time_seconds` if
+/// `namespace` is empty), which equals the start time of the process in seconds
+/// since the Unix epoch.
+///
+/// With the `process` feature on Linux, the start time is read from `/proc`.
+/// On other platforms, or if it can not be read, it is approximated by the
+/// first time `start_time_collector` is called, so it should be called early
+/// on.
+pub fn start_time_collector<S: Into<String>>(namespace: S) -> Result<Gauge> {
+    let opts = Opts::new(
+        "process_start_time_seconds",
+        "Start time of the process since unix epoch in seconds.",
+    )
+    .namespace(namespace);
+    let gauge = Gauge::with_opts(opts)?;
+    gauge.set(process_start_time());
+    Ok(gauge)
+}
+
+lazy_static! {
+    static ref APPROXIMATE_START_TIME: f64 = timer::unix_secs();
+}
+
+fn process_start_time() -> f64 {
+    #[cfg(all(feature = "process", target_os = "linux"))]
+    {
+        if let Some(start_time) = crate::process_collector::self_start_time() {
+            return start_time;
+        }
+    }
+    *APPROXIMATE_START_TIME
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
 
     use super::*;
     use crate::metrics::{Collector, Opts};
 
+    #[test]
+    fn test_gauge_func_fallible() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let c = calls.clone();
+        let gauge = GaugeFunc::fallible(Opts::new("test_func", "test help"), move || {
+            let n = c.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            if n & 1 == 0 {
+                Ok(n as f64)
+            } else {
+                Err("unavailable")
+            }
+        })
+        .unwrap();
+        let r = crate::Registry::new();
+        r.register(Box::new(gauge.clone())).unwrap();
+
+        let mfs = r.gather();
+        assert_eq!(mfs.len(), 1);
+        assert_eq!(mfs[0].get_metric()[0].get_gauge().get_value(), 0.0);
+        assert!(r.gather().is_empty());
+        assert_eq!(gauge.error_count(), 1);
+        let mfs = r.gather();
+        assert_eq!(mfs[0].get_metric()[0].get_gauge().get_value(), 2.0);
+        assert_eq!(gauge.error_count(), 1);
+
+        let gauge = GaugeFunc::new(Opts::new("test_func", "test help"), || 42.0).unwrap();
+        assert_eq!(
+            gauge.collect()[0].get_metric()[0].get_gauge().get_value(),
+            42.0
+        );
+    }
+
     #[test]
     fn test_gauge() {
         let opts = Opts::new("test", "test help")
@@ -188,11 +839,197 @@ mod tests {
         assert_eq!(mfs.len(), 1);
 
         let mf = mfs.pop().unwrap();
-        let m = mf.get_metric().get(0).unwrap();
+        let m = mf.get_metric().first().unwrap();
         assert_eq!(m.get_label().len(), 2);
         assert_eq!(m.get_gauge().get_value() as u64, 42);
     }
 
+    #[test]
+    fn test_peak_gauge() {
+        let gauge = PeakGauge::with_opts(Opts::new("test_peak", "test help").const_label("a", "1"))
+            .unwrap();
+        gauge.add(5.0);
+        gauge.sub(3.0);
+        assert_eq!(gauge.get(), 2.0);
+        assert_eq!(gauge.get_peak(), 5.0);
+        gauge.set(7.0);
+        gauge.set(1.0);
+        assert_eq!(gauge.get_peak(), 7.0);
+        gauge.reset_peak();
+        assert_eq!(gauge.get_peak(), 1.0);
+
+        let mfs = gauge.collect();
+        assert_eq!(mfs.len(), 2);
+        assert_eq!(mfs[0].get_name(), "test_peak");
+        assert_eq!(mfs[1].get_name(), "test_peak_max");
+        assert_eq!(mfs[1].get_help(), "test help (peak value)");
+        let m = &mfs[1].get_metric()[0];
+        assert_eq!(m.get_label().len(), 1);
+        assert_eq!(m.get_gauge().get_value(), 1.0);
+    }
+
+    #[test]
+    fn test_rate_gauge() {
+        let counter =
+            Counter::with_opts(Opts::new("test_requests_total", "test help").const_label("a", "1"))
+                .unwrap();
+        let gauge = RateGauge::new(counter.clone()).unwrap();
+
+        let start = Instant::now();
+        counter.inc_by(10.0);
+        gauge.update_rate(start);
+        assert_eq!(gauge.get_rate(), 0.0);
+
+        counter.inc_by(30.0);
+        gauge.update_rate(start + std::time::Duration::from_secs(2));
+        assert_eq!(gauge.get_rate(), 15.0);
+
+        let mfs = gauge.collect();
+        assert_eq!(mfs.len(), 2);
+        assert_eq!(mfs[0].get_name(), "test_requests_total");
+        assert_eq!(mfs[1].get_name(), "test_requests_rate");
+        assert_eq!(mfs[1].get_field_type(), proto::MetricType::GAUGE);
+        assert_eq!(mfs[1].get_metric()[0].get_label()[0].get_value(), "1");
+    }
+
+    #[test]
+    fn test_ewma_rate() {
+        EwmaRate::new(IntCounter::new("test_total", "test help").unwrap(), 0.0).unwrap_err();
+        EwmaRate::new(IntCounter::new("test_total", "test help").unwrap(), 1.5).unwrap_err();
+
+        let counter = IntCounter::new("test_requests_total", "test help").unwrap();
+        let ewma = EwmaRate::new(counter.clone(), 0.5).unwrap();
+
+        // A constant 10 per second, after starting at 50 per second.
+        let start = Instant::now();
+        ewma.update_rate(start);
+        counter.inc_by(50);
+        ewma.update_rate(start + std::time::Duration::from_secs(1));
+        assert_eq!(ewma.get_rate(), 50.0);
+        for i in 2..20 {
+            counter.inc_by(10);
+            ewma.update_rate(start + std::time::Duration::from_secs(i));
+        }
+        assert!((ewma.get_rate() - 10.0).abs() < 1e-3);
+
+        let mfs = ewma.collect();
+        assert_eq!(mfs.len(), 2);
+        assert_eq!(mfs[1].get_name(), "test_requests_ewma_rate");
+    }
+
+    #[test]
+    fn test_peak_gauge_concurrent() {
+        let threads = 8;
+        let gauge = PeakGauge::new("test_peak_concurrent", "test help").unwrap();
+        let barrier = Arc::new(Barrier::new(threads));
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let gauge = gauge.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        gauge.inc();
+                        gauge.dec();
+                    }
+                    gauge.inc();
+                    // Every thread adds at most 1 at any time and all of them
+                    // hold 1 here, so the true maximum is the number of threads.
+                    barrier.wait();
+                    gauge.dec();
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(gauge.get(), 0.0);
+        assert_eq!(gauge.get_peak(), threads as f64);
+    }
+
+    #[test]
+    fn test_info() {
+        let mut labels = HashMap::new();
+        labels.insert("version".to_owned(), "1.2.3".to_owned());
+        labels.insert("commit".to_owned(), "a1b2c3d".to_owned());
+        let info = Info::new("test_info", "test help", labels).unwrap();
+
+        let mfs = info.collect();
+        assert_eq!(mfs.len(), 1);
+        assert_eq!(mfs[0].get_field_type(), proto::MetricType::GAUGE);
+        let m = &mfs[0].get_metric()[0];
+        assert_eq!(m.get_gauge().get_value(), 1.0);
+        let labels: Vec<_> = m
+            .get_label()
+            .iter()
+            .map(|l| (l.get_name(), l.get_value()))
+            .collect();
+        assert_eq!(labels, vec![("commit", "a1b2c3d"), ("version", "1.2.3")]);
+
+        let opts = Opts::new("test_info", "test help").variable_label("l");
+        assert!(Info::with_opts(opts).is_err());
+    }
+
+    #[test]
+    fn test_gauge_collect_into() {
+        let gauge = Gauge::with_opts(Opts::new("test", "test help").const_label("a", "1")).unwrap();
+        let mut mf = proto::MetricFamily::default();
+
+        gauge.set_with_timestamp(1.0, 42);
+        gauge.collect_into(&mut mf);
+        assert_eq!(mf, gauge.collect().pop().unwrap());
+        assert_eq!(mf.get_metric()[0].get_timestamp_ms(), 42);
+
+        gauge.set(2.0);
+        gauge.collect_into(&mut mf);
+        assert_eq!(mf, gauge.collect().pop().unwrap());
+        assert_eq!(mf.get_metric()[0].get_gauge().get_value(), 2.0);
+        assert_eq!(mf.get_metric()[0].get_timestamp_ms(), 0);
+    }
+
+    #[test]
+    fn test_gauge_with_initial_value() {
+        let gauge = IntGauge::with_initial_value(Opts::new("test", "test help"), -42).unwrap();
+        assert_eq!(gauge.get(), -42);
+        let mfs = gauge.collect();
+        assert_eq!(mfs[0].get_metric()[0].get_gauge().get_value(), -42.0);
+    }
+
+    #[test]
+    fn test_gauge_add_sub_into() {
+        let gauge = Gauge::new("test_gauge", "test help").unwrap();
+        gauge.add(3u32);
+        gauge.add(2u8);
+        gauge.sub(1i32);
+        assert_eq!(gauge.get(), 4.0);
+
+        let int_gauge = IntGauge::new("test_int_gauge", "test help").unwrap();
+        int_gauge.add(3u32);
+        int_gauge.sub(5i32);
+        assert_eq!(int_gauge.get(), -2);
+    }
+
+    #[test]
+    fn test_gauge_get_as_i64() {
+        let gauge = Gauge::new("test_gauge", "test help").unwrap();
+        assert_eq!(gauge.get_as_i64(), Some(0));
+        gauge.set(-42.0);
+        assert_eq!(gauge.get_as_i64(), Some(-42));
+        gauge.set(i64::MIN as f64);
+        assert_eq!(gauge.get_as_i64(), Some(i64::MIN));
+
+        gauge.set(1.5);
+        assert_eq!(gauge.get_as_i64(), None);
+        gauge.set(i64::MAX as f64);
+        assert_eq!(gauge.get_as_i64(), None);
+        gauge.set(-1e19);
+        assert_eq!(gauge.get_as_i64(), None);
+        for v in &[f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            gauge.set(*v);
+            assert_eq!(gauge.get_as_i64(), None);
+        }
+    }
+
     #[test]
     fn test_gauge_vec_with_labels() {
         let vec = GaugeVec::new(
@@ -237,4 +1074,63 @@ mod tests {
         assert!(vec.remove_label_values(&["v1"]).is_err());
         assert!(vec.remove_label_values(&["v1", "v3"]).is_err());
     }
+
+    #[test]
+    fn test_start_time_collector() {
+        let now = timer::unix_secs();
+        let gauge = start_time_collector("test").unwrap();
+        let mfs = gauge.collect();
+        assert_eq!(mfs[0].get_name(), "test_process_start_time_seconds");
+
+        let start_time = gauge.get();
+        assert!(start_time <= timer::unix_secs());
+        #[cfg(not(all(feature = "process", target_os = "linux")))]
+        assert!(now - start_time < 5.0, "{} {}", now, start_time);
+        #[cfg(all(feature = "process", target_os = "linux"))]
+        assert!(now - start_time < 3600.0, "{} {}", now, start_time);
+
+        // The start time does not move.
+        assert_eq!(start_time_collector("").unwrap().get(), start_time);
+    }
+
+    #[test]
+    fn test_gauge_set_with_timestamp() {
+        use crate::encoder::{Encoder, TextEncoder};
+
+        let gauge = Gauge::new("test_gauge", "test help").unwrap();
+        gauge.set_with_timestamp(42.0, 1_600_000_000_000);
+        assert_eq!(gauge.get(), 42.0);
+
+        let mfs = gauge.collect();
+        assert_eq!(mfs[0].get_metric()[0].get_timestamp_ms(), 1_600_000_000_000);
+        let mut writer = Vec::new();
+        TextEncoder::new().encode(&mfs, &mut writer).unwrap();
+        let text = String::from_utf8(writer).unwrap();
+        assert!(text.contains("test_gauge 42 1600000000000\n"), "{}", text);
+
+        // A timestamp of 0 removes it.
+        gauge.set_with_timestamp(1.0, 0);
+        assert_eq!(gauge.collect()[0].get_metric()[0].get_timestamp_ms(), 0);
+
+        // Any other change of the value removes it too.
+        for change in &[
+            &(|g: &Gauge| g.set(1.0)) as &dyn Fn(&Gauge),
+            &|g: &Gauge| g.inc(),
+            &|g: &Gauge| g.dec(),
+            &|g: &Gauge| g.add(2.0),
+            &|g: &Gauge| g.sub(2.0),
+        ] {
+            gauge.set_with_timestamp(1.0, 1_600_000_000_000);
+            change(&gauge);
+            assert_eq!(gauge.collect()[0].get_metric()[0].get_timestamp_ms(), 0);
+        }
+        gauge.set(1.0);
+        let mut writer = Vec::new();
+        TextEncoder::new()
+            .encode(&gauge.collect(), &mut writer)
+            .unwrap();
+        assert!(String::from_utf8(writer)
+            .unwrap()
+            .contains("test_gauge 1\n"));
+    }
 }
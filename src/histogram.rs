@@ -2,20 +2,26 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::convert::From;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::{From, TryFrom};
 use std::sync::{
-    atomic::{AtomicU64 as StdAtomicU64, Ordering},
+    atomic::{AtomicBool, AtomicU64 as StdAtomicU64, Ordering},
     Arc, Mutex,
 };
+use std::thread::{self, ThreadId};
 use std::time::{Duration, Instant as StdInstant};
 
-use crate::atomic64::{Atomic, AtomicF64, AtomicU64};
-use crate::desc::{Desc, Describer};
+use crate::atomic64::{Atomic, AtomicF64, AtomicI64, AtomicU64, Number};
+use crate::desc::{is_valid_label_name, Desc, Describer};
 use crate::errors::{Error, Result};
-use crate::metrics::{Collector, LocalMetric, Metric, Opts};
+use crate::metrics::{
+    Collector, InternedLabelPair, LabelValue, Labels, LocalMetric, Metric, MetricSnapshotLine,
+    MetricValue, MetricVisitor, Opts,
+};
 use crate::proto;
-use crate::value::make_label_pairs;
+use crate::registry::{default_registry, Registry};
+use crate::timer;
+use crate::value::{make_label_pairs, make_timestamp, reuse_metric_family, to_proto_label_pairs};
 use crate::vec::{MetricVec, MetricVecBuilder};
 
 /// The default [`Histogram`] buckets. The default buckets are
@@ -30,6 +36,10 @@ pub const DEFAULT_BUCKETS: &[f64; 11] = &[
 /// bucket of a histogram ("le" -> "less or equal").
 pub const BUCKET_LABEL: &str = "le";
 
+/// The maximum combined length in UTF-8 characters of the label names and
+/// values of an exemplar, as defined by OpenMetrics.
+pub const EXEMPLAR_MAX_LABEL_LENGTH: usize = 128;
+
 #[inline]
 fn check_bucket_label(label: &str) -> Result<()> {
     if label == BUCKET_LABEL {
@@ -41,11 +51,20 @@ fn check_bucket_label(label: &str) -> Result<()> {
     Ok(())
 }
 
-fn check_and_adjust_buckets(mut buckets: Vec<f64>) -> Result<Vec<f64>> {
+fn check_and_adjust_buckets(
+    mut buckets: Vec<f64>,
+    dedup: bool,
+    max_buckets: Option<usize>,
+) -> Result<Vec<f64>> {
     if buckets.is_empty() {
         buckets = Vec::from(DEFAULT_BUCKETS as &'static [f64]);
     }
 
+    if dedup {
+        // Collapse adjacent bounds which only differ by rounding errors.
+        buckets.dedup_by(|b, a| (*b - *a).abs() <= f64::EPSILON * a.abs().max(b.abs()));
+    }
+
     for (i, upper_bound) in buckets.iter().enumerate() {
         if i < (buckets.len() - 1) && *upper_bound >= buckets[i + 1] {
             return Err(Error::Msg(format!(
@@ -63,9 +82,95 @@ fn check_and_adjust_buckets(mut buckets: Vec<f64>) -> Result<Vec<f64>> {
         buckets.pop();
     }
 
+    if let Some(max) = max_buckets {
+        if buckets.len() > max {
+            return Err(Error::Msg(format!(
+                "histogram has too many buckets, buckets: {}, max: {}",
+                buckets.len(),
+                max
+            )));
+        }
+    }
+
     Ok(buckets)
 }
 
+/// A named and validated set of buckets, defined once and shared by all the
+/// histograms of a kind, e.g. API latencies, so their buckets never drift and
+/// `histogram_quantile` can aggregate them, see
+/// [`HistogramOpts::buckets_named`].
+///
+/// # Examples
+///
+/// ```
+/// use lazy_static::lazy_static;
+/// use prometheus::{BucketSchema, Histogram, HistogramOpts};
+///
+/// lazy_static! {
+///     static ref API_LATENCY: BucketSchema =
+///         BucketSchema::new("api_latency", vec![0.01, 0.1, 1.0]).unwrap();
+/// }
+///
+/// let opts = HistogramOpts::new("get_seconds", "GET latency.").buckets_named(&API_LATENCY);
+/// let histogram = Histogram::with_opts(opts).unwrap();
+/// assert_eq!(histogram.cumulative_counts()[2], (1.0, 0));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct BucketSchema {
+    name: String,
+    buckets: Arc<[f64]>,
+}
+
+impl BucketSchema {
+    /// Create a [`BucketSchema`] named `name` with the given buckets.
+    ///
+    /// The function returns an error if the buckets are not in increasing
+    /// order. A final +Inf bucket is removed, as it is implicit.
+    pub fn new<S: Into<String>>(name: S, buckets: Vec<f64>) -> Result<BucketSchema> {
+        let name = name.into();
+        if buckets.is_empty() {
+            return Err(Error::Msg(format!("bucket schema {} has no buckets", name)));
+        }
+        let buckets = check_and_adjust_buckets(buckets, false, None)?;
+        Ok(BucketSchema {
+            name,
+            buckets: buckets.into(),
+        })
+    }
+
+    /// Return the name of the schema.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the buckets of the schema.
+    pub fn buckets(&self) -> &[f64] {
+        &self.buckets
+    }
+}
+
+/// How a [`Histogram`] treats invalid observations, i.e. `NaN` and `-Inf`.
+///
+/// Such observations do not fall into any meaningful bucket and would turn the
+/// sum of observations into `NaN` or `-Inf` for the rest of the process
+/// lifetime, thus they are never recorded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InvalidObservationPolicy {
+    /// Silently drop invalid observations. This is the default.
+    #[default]
+    Ignore,
+    /// Panic in debug builds, drop invalid observations in release builds.
+    DebugPanic,
+    /// Drop invalid observations but count them, see
+    /// [`Histogram::get_invalid_count`].
+    Count,
+}
+
+#[inline]
+fn is_invalid_observation(v: f64) -> bool {
+    v.is_nan() || (v.is_infinite() && v.is_sign_negative())
+}
+
 /// A struct that bundles the options for creating a [`Histogram`] metric. It is
 /// mandatory to set Name and Help to a non-empty string. All other fields are
 /// optional and can safely be left at their zero value.
@@ -80,6 +185,16 @@ pub struct HistogramOpts {
     /// to add a highest bucket with +Inf bound, it will be added
     /// implicitly. The default value is DefBuckets.
     pub buckets: Vec<f64>,
+
+    /// How invalid observations are treated, see
+    /// [`on_invalid`](HistogramOpts::on_invalid).
+    on_invalid: InvalidObservationPolicy,
+    /// Whether nearly equal buckets are collapsed, see
+    /// [`dedup_buckets`](HistogramOpts::dedup_buckets).
+    dedup_buckets: bool,
+    /// The maximum number of buckets, see
+    /// [`max_buckets`](HistogramOpts::max_buckets).
+    max_buckets: Option<usize>,
 }
 
 impl HistogramOpts {
@@ -88,6 +203,9 @@ impl HistogramOpts {
         HistogramOpts {
             common_opts: Opts::new(name, help),
             buckets: Vec::from(DEFAULT_BUCKETS as &'static [f64]),
+            on_invalid: InvalidObservationPolicy::default(),
+            dedup_buckets: false,
+            max_buckets: None,
         }
     }
 
@@ -115,6 +233,17 @@ impl HistogramOpts {
         self
     }
 
+    /// `with_const_label_merged` returns a copy of `base` with the const label
+    /// added, to build related histograms which share their buckets but differ
+    /// by a const label.
+    pub fn with_const_label_merged<S1: Into<String>, S2: Into<String>>(
+        base: &HistogramOpts,
+        name: S1,
+        value: S2,
+    ) -> HistogramOpts {
+        base.clone().const_label(name, value)
+    }
+
     /// `variable_labels` sets the variable labels.
     pub fn variable_labels(mut self, variable_labels: Vec<String>) -> Self {
         self.common_opts = self.common_opts.variable_labels(variable_labels);
@@ -137,6 +266,40 @@ impl HistogramOpts {
         self.buckets = buckets;
         self
     }
+
+    /// `buckets_named` sets the buckets to the ones of a shared
+    /// [`BucketSchema`].
+    pub fn buckets_named(mut self, schema: &BucketSchema) -> Self {
+        self.buckets = schema.buckets().to_vec();
+        self
+    }
+
+    /// `on_invalid` sets how invalid observations (`NaN` and `-Inf`) are
+    /// treated. The default value is [`InvalidObservationPolicy::Ignore`].
+    pub fn on_invalid(mut self, policy: InvalidObservationPolicy) -> Self {
+        self.on_invalid = policy;
+        self
+    }
+
+    /// `dedup_buckets` sets whether adjacent buckets whose upper bounds are
+    /// equal within the relative precision of `f64` (i.e. [`f64::EPSILON`])
+    /// are collapsed into one instead of being rejected. This helps with
+    /// generated bucket sets that contain rounding-induced duplicates. The
+    /// default value is `false`.
+    pub fn dedup_buckets(mut self, dedup_buckets: bool) -> Self {
+        self.dedup_buckets = dedup_buckets;
+        self
+    }
+
+    /// `max_buckets` sets the maximum number of buckets, not counting the
+    /// implicit +Inf bucket. Each bucket takes memory per child of a
+    /// [`HistogramVec`], so a cap helps memory-constrained deployments to keep
+    /// buckets coarse. Creating a histogram with more buckets fails. By
+    /// default the number of buckets is unlimited.
+    pub fn max_buckets(mut self, max_buckets: usize) -> Self {
+        self.max_buckets = Some(max_buckets);
+        self
+    }
 }
 
 impl Describer for HistogramOpts {
@@ -145,11 +308,20 @@ impl Describer for HistogramOpts {
     }
 }
 
+impl AsMut<Opts> for HistogramOpts {
+    fn as_mut(&mut self) -> &mut Opts {
+        &mut self.common_opts
+    }
+}
+
 impl From<Opts> for HistogramOpts {
     fn from(opts: Opts) -> HistogramOpts {
         HistogramOpts {
             common_opts: opts,
             buckets: Vec::from(DEFAULT_BUCKETS as &'static [f64]),
+            on_invalid: InvalidObservationPolicy::default(),
+            dedup_buckets: false,
+            max_buckets: None,
         }
     }
 }
@@ -158,13 +330,13 @@ impl From<Opts> for HistogramOpts {
 ///
 /// See [`HistogramCore`] for details.
 #[derive(Debug)]
-struct Shard {
-    sum: AtomicF64,
+struct Shard<P: Atomic> {
+    sum: P,
     count: AtomicU64,
     buckets: Vec<AtomicU64>,
 }
 
-impl Shard {
+impl<P: Atomic> Shard<P> {
     fn new(num_buckets: usize) -> Self {
         let mut buckets = Vec::new();
         for _ in 0..num_buckets {
@@ -172,7 +344,7 @@ impl Shard {
         }
 
         Shard {
-            sum: AtomicF64::new(0.0),
+            sum: P::new(P::T::from_i64(0)),
             count: AtomicU64::new(0),
             buckets,
         }
@@ -263,12 +435,6 @@ impl ShardAndCount {
         ShardAndCount::split_shard_index_and_count(n)
     }
 
-    /// Increment the observation count by one leaving the most significant bit
-    /// i.e. the [`ShardIndex`] untouched.
-    fn inc(&self, ordering: Ordering) -> (ShardIndex, u64) {
-        self.inc_by(1, ordering)
-    }
-
     fn split_shard_index_and_count(n: u64) -> (ShardIndex, u64) {
         let shard = n >> 63;
         let count = n & ((1 << 63) - 1);
@@ -284,11 +450,11 @@ impl ShardAndCount {
 /// A histogram supports two main execution paths:
 ///
 /// 1. `observe` which increases the overall observation counter, updates the
-/// observation sum and increases a single bucket counter.
+///    observation sum and increases a single bucket counter.
 ///
 /// 2. `proto` (aka. collecting the metric, from now on referred to as the
-/// collect operation) which snapshots the state of the histogram and exposes it
-/// as a Protobuf struct.
+///    collect operation) which snapshots the state of the histogram and exposes it
+///    as a Protobuf struct.
 ///
 /// If an observe and a collect operation interleave, the latter could be
 /// exposing a snapshot of the histogram that does not uphold all histogram
@@ -305,10 +471,13 @@ impl ShardAndCount {
 /// `observe` operations hit the hot shard and record their observation. Collect
 /// operations switch hot and cold, wait for all `observe` calls to finish on
 /// the previously hot now cold shard and then expose the consistent snapshot.
+///
+/// The sum of observations is kept in a `P`, i.e. an [`AtomicF64`] for a
+/// [`Histogram`] and an [`AtomicI64`] for an [`IntHistogram`].
 #[derive(Debug)]
-pub struct HistogramCore {
+pub struct HistogramCore<P: Atomic = AtomicF64> {
     desc: Desc,
-    label_pairs: Vec<proto::LabelPair>,
+    label_pairs: Vec<InternedLabelPair>,
 
     /// Mutual exclusion to serialize collect operations. No two collect
     /// operations should operate on this datastructure at the same time. (See
@@ -321,13 +490,39 @@ pub struct HistogramCore {
     shard_and_count: ShardAndCount,
     /// The two shards where `shard_and_count` determines which one is the hot
     /// and which one the cold at any given point in time.
-    shards: [Shard; 2],
+    shards: [Shard<P>; 2],
 
     upper_bounds: Vec<f64>,
+
+    on_invalid: InvalidObservationPolicy,
+    /// The number of dropped invalid observations, only maintained with
+    /// [`InvalidObservationPolicy::Count`].
+    invalid_count: AtomicU64,
+    /// The number of observations above the highest bucket, which only fall
+    /// in the implicit `+Inf` bucket.
+    overflow_count: AtomicU64,
+
+    /// The most recent exemplar of each bucket, allocated by the first
+    /// `observe_with_exemplar`.
+    exemplars: Mutex<Vec<Option<proto::Exemplar>>>,
+    /// Whether any exemplar was kept, so that collecting a histogram without
+    /// exemplars does not lock `exemplars`.
+    has_exemplars: AtomicBool,
+
+    /// The cumulative bucket counts followed by the overall count as of the
+    /// previous delta collect operation. The lock also serializes delta collect
+    /// operations.
+    delta_counts: Mutex<Vec<u64>>,
+    /// The sum as of the previous delta collect operation.
+    delta_sum: P,
+
+    /// The sum of the costs passed to `observe_with_cost`, which is not
+    /// exposed by `collect`.
+    cost_sum: AtomicF64,
 }
 
-impl HistogramCore {
-    pub fn new(opts: &HistogramOpts, label_values: &[&str]) -> Result<HistogramCore> {
+impl<P: Atomic> HistogramCore<P> {
+    pub fn new(opts: &HistogramOpts, label_values: &[&str]) -> Result<HistogramCore<P>> {
         let desc = opts.describe()?;
 
         for name in &desc.variable_labels {
@@ -339,7 +534,8 @@ impl HistogramCore {
 
         let label_pairs = make_label_pairs(&desc, label_values)?;
 
-        let buckets = check_and_adjust_buckets(opts.buckets.clone())?;
+        let buckets =
+            check_and_adjust_buckets(opts.buckets.clone(), opts.dedup_buckets, opts.max_buckets)?;
 
         Ok(HistogramCore {
             desc,
@@ -350,49 +546,150 @@ impl HistogramCore {
             shard_and_count: ShardAndCount::new(),
             shards: [Shard::new(buckets.len()), Shard::new(buckets.len())],
 
+            exemplars: Mutex::new(vec![]),
+            has_exemplars: AtomicBool::new(false),
+
+            delta_counts: Mutex::new(vec![0; buckets.len() + 1]),
+            delta_sum: P::new(P::T::from_i64(0)),
+
             upper_bounds: buckets,
+
+            on_invalid: opts.on_invalid,
+            invalid_count: AtomicU64::new(0),
+            overflow_count: AtomicU64::new(0),
+
+            cost_sum: AtomicF64::new(0.0),
         })
     }
 
-    /// Record a given observation (f64) in the histogram.
+    /// Check whether the given observation is valid. Invalid observations are
+    /// handled according to the configured [`InvalidObservationPolicy`] and
+    /// must not be recorded.
+    #[inline]
+    fn check_observation(&self, v: f64, weight: u64) -> bool {
+        if !is_invalid_observation(v) {
+            return true;
+        }
+
+        match self.on_invalid {
+            InvalidObservationPolicy::Ignore => {}
+            InvalidObservationPolicy::DebugPanic => {
+                if cfg!(debug_assertions) {
+                    panic!(
+                        "invalid observation {} for histogram {}",
+                        v, self.desc.fq_name
+                    );
+                }
+            }
+            InvalidObservationPolicy::Count => self.invalid_count.inc_by(weight),
+        }
+        false
+    }
+
+    /// Record an observation which falls into the bucket of `v` and increases
+    /// the sum by `sum_delta`, as if it was observed `weight` times.
     //
     // First increase the overall observation counter and thus learn which shard
     // is the current hot shard. Subsequently on the hot shard update the
     // corresponding bucket count, adjust the shard's sum and finally increase
     // the shard's count.
-    pub fn observe(&self, v: f64) {
+    fn record(&self, v: f64, sum_delta: P::T, weight: u64) {
         // The collect code path uses `self.shard_and_count` and
         // `self.shards[x].count` to ensure not to collect data from a shard
         // while observe calls are still operating on it.
         //
-        // To ensure the above, this `inc` needs to use `Acquire` ordering to
+        // To ensure the above, this `inc_by` needs to use `Acquire` ordering to
         // force anything below this line to stay below it.
-        let (shard_index, _count) = self.shard_and_count.inc(Ordering::Acquire);
+        let (shard_index, _count) = self.shard_and_count.inc_by(weight, Ordering::Acquire);
 
-        let shard: &Shard = &self.shards[usize::from(shard_index)];
+        let shard: &Shard<P> = &self.shards[usize::from(shard_index)];
 
-        // Try find the bucket.
-        let mut iter = self
-            .upper_bounds
-            .iter()
-            .enumerate()
-            .filter(|&(_, f)| v <= *f);
-        if let Some((i, _)) = iter.next() {
-            shard.buckets[i].inc_by(1);
+        match self.bucket_index(v) {
+            Some(i) => shard.buckets[i].inc_by(weight),
+            None => self.overflow_count.inc_by(weight),
         }
 
-        shard.sum.inc_by(v);
+        shard.sum.inc_by(sum_delta);
         // Use `Release` ordering to ensure all operations above stay above.
-        shard.count.inc_by_with_ordering(1, Ordering::Release);
+        shard.count.inc_by_with_ordering(weight, Ordering::Release);
     }
 
     /// Make a snapshot of the current histogram state exposed as a Protobuf
     /// struct.
+    pub fn proto(&self) -> proto::Histogram {
+        let mut h = proto::Histogram::default();
+        self.proto_into(&mut h);
+        h
+    }
+
+    /// Like [`proto`](HistogramCore::proto), but refills `h` in place, so the
+    /// buckets of a previous call are reused.
+    pub fn proto_into(&self, h: &mut proto::Histogram) {
+        if h.get_bucket().len() != self.upper_bounds.len() {
+            h.set_bucket(from_vec!(vec![
+                proto::Bucket::default();
+                self.upper_bounds.len()
+            ]));
+        }
+
+        let exemplars = if self.has_exemplars.load(Ordering::Acquire) {
+            Some(self.exemplars.lock().expect("Lock poisoned"))
+        } else {
+            None
+        };
+        let buckets = h.mut_bucket();
+        let (sample_count, sample_sum) = self.snapshot(|i, upper_bound, cumulative_count| {
+            let b = &mut buckets[i];
+            b.set_cumulative_count(cumulative_count);
+            b.set_upper_bound(upper_bound);
+            match exemplars.as_ref().and_then(|e| e[i].as_ref()) {
+                Some(exemplar) => *b.mut_exemplar() = exemplar.clone(),
+                None => b.clear_exemplar(),
+            }
+        });
+        drop(exemplars);
+
+        // The sum is a double in the exposition formats.
+        h.set_sample_sum(sample_sum.into_f64());
+        h.set_sample_count(sample_count);
+    }
+
+    /// Make a snapshot of the increments since the previous call exposed as a
+    /// Protobuf struct. Bucket counts are still cumulative over the buckets,
+    /// i.e. each bucket counts all new observations less than or equal to its
+    /// upper bound.
+    pub fn proto_delta(&self) -> proto::Histogram {
+        let mut delta_counts = self.delta_counts.lock().expect("Lock poisoned");
+        let mut buckets = Vec::with_capacity(self.upper_bounds.len());
+        let (sample_count, sample_sum) = self.snapshot(|i, upper_bound, cumulative_count| {
+            let mut b = proto::Bucket::default();
+            // Saturate rather than underflow should a count ever go backwards.
+            b.set_cumulative_count(cumulative_count.saturating_sub(delta_counts[i]));
+            b.set_upper_bound(upper_bound);
+            buckets.push(b);
+            delta_counts[i] = cumulative_count;
+        });
+
+        let last_sample_count = delta_counts[self.upper_bounds.len()];
+        delta_counts[self.upper_bounds.len()] = sample_count;
+        let mut delta_sum = sample_sum;
+        delta_sum -= self.delta_sum.swap(sample_sum);
+
+        let mut h = proto::Histogram::default();
+        h.set_sample_sum(delta_sum.into_f64());
+        h.set_sample_count(sample_count.saturating_sub(last_sample_count));
+        h.set_bucket(from_vec!(buckets));
+        h
+    }
+
+    /// Make a snapshot of the current histogram state, calling `visit_bucket`
+    /// with the index, upper bound and cumulative count of each bucket. It
+    /// returns the overall count and sum.
     //
     // Acquire the collect lock, switch the hot and the cold shard, wait for all
     // remaining `observe` calls to finish on the previously hot now cold shard,
     // snapshot the data, update the now hot shard and reset the cold shard.
-    pub fn proto(&self) -> proto::Histogram {
+    fn snapshot<F: FnMut(usize, f64, u64)>(&self, mut visit_bucket: F) -> (u64, P::T) {
         let collect_guard = self.collect_lock.lock().expect("Lock poisoned");
 
         // `flip` needs to use AcqRel ordering to ensure the lock operation
@@ -429,16 +726,12 @@ impl HistogramCore {
 
         // Get cold shard sum and reset to 0.
         //
-        // Use `Acquire` for load and `Release` for store to ensure not to
-        // interfere with previous or upcoming collect calls.
-        let cold_shard_sum = cold_shard.sum.swap(0.0, Ordering::AcqRel);
-
-        let mut h = proto::Histogram::default();
-        h.set_sample_sum(cold_shard_sum);
-        h.set_sample_count(overall_count);
+        // The `Acquire` ordering of `compare_exchange_weak` above makes the
+        // stores of all `observe` calls on the cold shard visible, and collect
+        // calls are serialized by the lock.
+        let cold_shard_sum = cold_shard.sum.swap(P::T::from_i64(0));
 
         let mut cumulative_count = 0;
-        let mut buckets = Vec::with_capacity(self.upper_bounds.len());
         for (i, upper_bound) in self.upper_bounds.iter().enumerate() {
             // Reset the cold shard and update the hot shard.
             //
@@ -448,12 +741,8 @@ impl HistogramCore {
             hot_shard.buckets[i].inc_by(cold_bucket_count);
 
             cumulative_count += cold_bucket_count;
-            let mut b = proto::Bucket::default();
-            b.set_cumulative_count(cumulative_count);
-            b.set_upper_bound(*upper_bound);
-            buckets.push(b);
+            visit_bucket(i, *upper_bound, cumulative_count);
         }
-        h.set_bucket(from_vec!(buckets));
 
         // Update the hot shard.
         hot_shard.count.inc_by(overall_count);
@@ -461,10 +750,29 @@ impl HistogramCore {
 
         drop(collect_guard);
 
-        h
+        (overall_count, cold_shard_sum)
     }
 
-    fn sample_sum(&self) -> f64 {
+    /// Return the index of the bucket `v` falls in, i.e. the first bucket
+    /// whose upper bound is greater than or equal to `v`, `None` if it only
+    /// falls in the implicit `+Inf` bucket.
+    #[inline]
+    pub fn bucket_index(&self, v: f64) -> Option<usize> {
+        self.upper_bounds.iter().position(|f| v <= *f)
+    }
+
+    /// Return the `(upper_bound, cumulative_count)` pairs of the buckets,
+    /// followed by the implicit `+Inf` bucket.
+    pub fn cumulative_counts(&self) -> Vec<(f64, u64)> {
+        let mut counts = Vec::with_capacity(self.upper_bounds.len() + 1);
+        let (sample_count, _) = self.snapshot(|_, upper_bound, cumulative_count| {
+            counts.push((upper_bound, cumulative_count));
+        });
+        counts.push((f64::INFINITY, sample_count));
+        counts
+    }
+
+    fn sample_sum(&self) -> P::T {
         // Make sure to not overlap with any collect calls, as they might flip
         // the hot and cold shards.
         let _guard = self.collect_lock.lock().expect("Lock poisoned");
@@ -476,6 +784,113 @@ impl HistogramCore {
     fn sample_count(&self) -> u64 {
         self.shard_and_count.get().1
     }
+
+    fn invalid_count(&self) -> u64 {
+        self.invalid_count.get()
+    }
+
+    fn overflow_count(&self) -> u64 {
+        self.overflow_count.get()
+    }
+
+    fn cost_sum(&self) -> f64 {
+        self.cost_sum.get()
+    }
+}
+
+impl HistogramCore<AtomicF64> {
+    /// `add_counts` adds observations accumulated elsewhere, e.g. by a local
+    /// histogram, at once: `counts` holds the count of each bucket, or may be
+    /// empty to leave the bucket counts alone, `count` and `sum` the count and
+    /// sum of the observations.
+    fn add_counts(&self, counts: &[u64], count: u64, sum: f64) {
+        // The collect code path uses `self.shard_and_count` and
+        // `self.shards[x].count` to ensure not to collect data from a shard
+        // while observe calls are still operating on it.
+        //
+        // To ensure the above, this `inc` needs to use `Acquire` ordering
+        // to force anything below this line to stay below it.
+        let (shard_index, _count) = self.shard_and_count.inc_by(count, Ordering::Acquire);
+        let shard = &self.shards[shard_index as usize];
+
+        for (i, v) in counts.iter().enumerate() {
+            if *v > 0 {
+                shard.buckets[i].inc_by(*v);
+            }
+        }
+
+        shard.sum.inc_by(sum);
+        // Use `Release` ordering to ensure all operations above stay above.
+        shard.count.inc_by_with_ordering(count, Ordering::Release);
+    }
+
+    /// Record a given observation (f64) in the histogram.
+    #[inline]
+    pub fn observe(&self, v: f64) {
+        self.observe_weighted(v, 1)
+    }
+
+    /// Record a given observation (f64) in the histogram as if it was
+    /// observed `weight` times.
+    pub fn observe_weighted(&self, v: f64, weight: u64) {
+        if !self.check_observation(v, weight) {
+            return;
+        }
+        self.record(v, v * weight as f64, weight);
+    }
+
+    /// Record a given observation (f64) in the histogram and add `cost` to the
+    /// cost sum. Invalid observations do not add their cost.
+    pub fn observe_with_cost(&self, v: f64, cost: f64) {
+        if !self.check_observation(v, 1) {
+            return;
+        }
+        self.record(v, v, 1);
+        self.cost_sum.inc_by(cost);
+    }
+
+    /// Record a given observation (f64) in the histogram and keep the given
+    /// exemplar for the bucket it falls in. Invalid observations and
+    /// observations above the highest bucket do not keep an exemplar.
+    pub fn observe_with_exemplar(&self, v: f64, mut exemplar: proto::Exemplar) {
+        if !self.check_observation(v, 1) {
+            return;
+        }
+        self.record(v, v, 1);
+
+        if let Some(i) = self.bucket_index(v) {
+            exemplar.set_value(v);
+            let mut exemplars = self.exemplars.lock().expect("Lock poisoned");
+            if exemplars.is_empty() {
+                exemplars.resize(self.upper_bounds.len(), None);
+            }
+            exemplars[i] = Some(exemplar);
+            self.has_exemplars.store(true, Ordering::Release);
+        }
+    }
+}
+
+impl HistogramCore<AtomicI64> {
+    /// Record a given observation (i64) in the histogram.
+    pub fn observe(&self, v: i64) {
+        self.record(v as f64, v, 1);
+    }
+
+    /// Record a given observation (i64) in the histogram as if it was
+    /// observed `weight` times. It fails if `v * weight` overflows an `i64`.
+    pub fn observe_weighted(&self, v: i64, weight: u64) -> Result<()> {
+        let sum = i64::try_from(weight)
+            .ok()
+            .and_then(|weight| v.checked_mul(weight))
+            .ok_or_else(|| {
+                Error::Msg(format!(
+                    "observe_weighted overflows the sum, value: {}, weight: {}",
+                    v, weight
+                ))
+            })?;
+        self.record(v as f64, sum, weight);
+        Ok(())
+    }
 }
 
 // We have to wrap libc::timespec in order to implement std::fmt::Debug.
@@ -496,6 +911,8 @@ impl std::fmt::Debug for Timespec {
 #[derive(Debug)]
 pub enum Instant {
     Monotonic(StdInstant),
+    /// Milliseconds of the cached clock, see [`timer::cached_millis`].
+    Cached(u64),
     #[cfg(all(feature = "nightly", target_os = "linux"))]
     MonotonicCoarse(Timespec),
 }
@@ -505,6 +922,10 @@ impl Instant {
         Instant::Monotonic(StdInstant::now())
     }
 
+    pub fn now_cached() -> Instant {
+        Instant::Cached(timer::cached_millis())
+    }
+
     #[cfg(all(feature = "nightly", target_os = "linux"))]
     pub fn now_coarse() -> Instant {
         Instant::MonotonicCoarse(get_time_coarse())
@@ -516,9 +937,11 @@ impl Instant {
     }
 
     pub fn elapsed(&self) -> Duration {
-        match &*self {
+        match self {
             Instant::Monotonic(i) => i.elapsed(),
 
+            Instant::Cached(t) => Duration::from_millis(timer::cached_millis().saturating_sub(*t)),
+
             // It is different from `Instant::Monotonic`, the resolution here is millisecond.
             // The processors in an SMP system do not start all at exactly the same time
             // and therefore the timer registers are typically running at an offset.
@@ -575,6 +998,14 @@ mod coarse {
 /// This timer can be stopped and observed at most once, either automatically (when it
 /// goes out of scope) or manually.
 /// Alternatively, it can be manually stopped and discarded in order to not record its value.
+///
+/// The duration is in seconds, unless the timer was started with
+/// [`Histogram::start_timer_with_scale`], in which case the durations observed
+/// and returned by the timer are scaled.
+///
+/// A timer made manual with [`observe_on_drop`](HistogramTimer::observe_on_drop)
+/// only records when it is observed explicitly, e.g. when it is kept in a
+/// state machine which can be dropped early on an error path.
 #[must_use = "Timer should be kept in a variable otherwise it cannot observe duration"]
 #[derive(Debug)]
 pub struct HistogramTimer {
@@ -582,16 +1013,36 @@ pub struct HistogramTimer {
     histogram: Histogram,
     /// Whether the timer has already been observed once.
     observed: bool,
+    /// Whether the timer records its duration when dropped unobserved.
+    observe_on_drop: bool,
     /// Starting instant for the timer.
     start: Instant,
+    /// The factor the duration in seconds is multiplied with.
+    scale: f64,
 }
 
 impl HistogramTimer {
     fn new(histogram: Histogram) -> Self {
+        Self::with_scale(histogram, 1.0)
+    }
+
+    fn with_scale(histogram: Histogram, scale: f64) -> Self {
         Self {
             histogram,
             observed: false,
+            observe_on_drop: true,
             start: Instant::now(),
+            scale,
+        }
+    }
+
+    fn new_cached(histogram: Histogram) -> Self {
+        HistogramTimer {
+            histogram,
+            observed: false,
+            observe_on_drop: true,
+            start: Instant::now_cached(),
+            scale: 1.0,
         }
     }
 
@@ -600,14 +1051,29 @@ impl HistogramTimer {
         HistogramTimer {
             histogram,
             observed: false,
+            observe_on_drop: true,
             start: Instant::now_coarse(),
+            scale: 1.0,
         }
     }
 
+    /// `observe_on_drop` sets whether the timer records its duration when it
+    /// is dropped without being observed. It is enabled by default, a timer
+    /// with it disabled only records on
+    /// [`observe_duration`](HistogramTimer::observe_duration),
+    /// [`stop_and_record`](HistogramTimer::stop_and_record) and
+    /// [`observe_into`](HistogramTimer::observe_into).
+    pub fn observe_on_drop(mut self, observe_on_drop: bool) -> Self {
+        self.observe_on_drop = observe_on_drop;
+        self
+    }
+
     /// Observe and record timer duration (in seconds).
     ///
     /// It observes the floating-point number of seconds elapsed since the timer
-    /// started, and it records that value to the attached histogram.
+    /// started, and it records that value to the attached histogram. Like all
+    /// durations observed by the timer, it is never negative, negative values
+    /// are clamped to 0.
     pub fn observe_duration(self) {
         self.stop_and_record();
     }
@@ -630,8 +1096,23 @@ impl HistogramTimer {
         timer.observe(false)
     }
 
+    /// Observe, record and return timer duration (in seconds) into the given
+    /// histogram instead of the attached one.
+    ///
+    /// This allows to pick the histogram at the end of the timed operation,
+    /// e.g. depending on its outcome, without starting a timer per histogram.
+    /// The duration is not recorded to the attached histogram, neither now nor
+    /// on drop.
+    pub fn observe_into(self, histogram: &Histogram) -> f64 {
+        let v = self.stop_and_discard();
+        histogram.observe(v);
+        v
+    }
+
     fn observe(&mut self, record: bool) -> f64 {
-        let v = self.start.elapsed_sec();
+        // A negative latency is always a bug, e.g. a negative scale, so it is
+        // clamped to 0 rather than subtracted from the sum.
+        let v = (self.start.elapsed_sec() * self.scale).max(0.0);
         self.observed = true;
         if record {
             self.histogram.observe(v);
@@ -641,6 +1122,77 @@ impl HistogramTimer {
 }
 
 impl Drop for HistogramTimer {
+    fn drop(&mut self) {
+        if !self.observed && self.observe_on_drop {
+            self.observe(true);
+        }
+    }
+}
+
+/// Timer to measure the duration of an event once and record it into several
+/// histograms, e.g. a per-endpoint and a global latency histogram.
+///
+/// All histograms observe exactly the same duration, whatever their buckets.
+/// Like [`HistogramTimer`], this timer can be stopped and observed at most
+/// once, either automatically (when it goes out of scope) or manually, or it
+/// can be stopped and discarded in order to not record its value.
+#[must_use = "Timer should be kept in a variable otherwise it cannot observe duration"]
+#[derive(Debug)]
+pub struct MultiHistogramTimer {
+    /// The histograms for automatic recording of observations.
+    histograms: Vec<Histogram>,
+    /// Whether the timer has already been observed once.
+    observed: bool,
+    /// Starting instant for the timer.
+    start: Instant,
+}
+
+impl MultiHistogramTimer {
+    /// Start a timer recording into all the given `histograms`.
+    pub fn new(histograms: &[&Histogram]) -> Self {
+        MultiHistogramTimer {
+            histograms: histograms.iter().map(|h| (*h).clone()).collect(),
+            observed: false,
+            start: Instant::now(),
+        }
+    }
+
+    /// Observe and record timer duration (in seconds) into all the histograms.
+    pub fn observe_duration(self) {
+        self.stop_and_record();
+    }
+
+    /// Observe, record and return timer duration (in seconds).
+    ///
+    /// It observes and returns a floating-point number for seconds elapsed since
+    /// the timer started, recording that value to all the histograms.
+    pub fn stop_and_record(self) -> f64 {
+        let mut timer = self;
+        timer.observe(true)
+    }
+
+    /// Observe and return timer duration (in seconds).
+    ///
+    /// It returns a floating-point number of seconds elapsed since the timer started,
+    /// without recording to any histogram.
+    pub fn stop_and_discard(self) -> f64 {
+        let mut timer = self;
+        timer.observe(false)
+    }
+
+    fn observe(&mut self, record: bool) -> f64 {
+        let v = self.start.elapsed_sec().max(0.0);
+        self.observed = true;
+        if record {
+            for histogram in &self.histograms {
+                histogram.observe(v);
+            }
+        }
+        v
+    }
+}
+
+impl Drop for MultiHistogramTimer {
     fn drop(&mut self) {
         if !self.observed {
             self.observe(true);
@@ -665,45 +1217,313 @@ impl Drop for HistogramTimer {
 ///
 /// [1]: https://prometheus.io/docs/prometheus/latest/querying/functions/#histogram_quantile
 /// [2]: https://prometheus.io/docs/practices/histograms/
-#[derive(Clone, Debug)]
-pub struct Histogram {
-    core: Arc<HistogramCore>,
+pub type Histogram = GenericHistogram<AtomicF64>;
+
+/// The integer version of [`Histogram`], for observing integer quantities like
+/// sizes in bytes or numbers of items.
+///
+/// Its sum of observations is kept in an [`AtomicI64`], so it stays exact where
+/// the `f64` sum of a [`Histogram`] would lose precision, i.e. beyond 2^53. The
+/// sum is converted to a double when collected, as the exposition formats
+/// represent it as one, so only [`get_sample_sum`](GenericHistogram::get_sample_sum)
+/// is exact beyond 2^53. The sum wraps around on `i64` overflow.
+pub type IntHistogram = GenericHistogram<AtomicI64>;
+
+/// The underlying implementation for [`Histogram`] and [`IntHistogram`].
+#[derive(Debug)]
+pub struct GenericHistogram<P: Atomic> {
+    core: Arc<HistogramCore<P>>,
 }
 
-impl Histogram {
-    /// `with_opts` creates a [`Histogram`] with the `opts` options.
-    pub fn with_opts(opts: HistogramOpts) -> Result<Histogram> {
-        Histogram::with_opts_and_label_values(&opts, &[])
+impl<P: Atomic> Clone for GenericHistogram<P> {
+    fn clone(&self) -> Self {
+        Self {
+            core: Arc::clone(&self.core),
+        }
+    }
+}
+
+impl<P: Atomic> GenericHistogram<P> {
+    /// `with_opts` creates a histogram with the `opts` options.
+    pub fn with_opts(opts: HistogramOpts) -> Result<Self> {
+        Self::with_opts_and_label_values(&opts, &[])
+    }
+
+    /// `register` creates a histogram with the `opts` options and registers it
+    /// to `registry`, returning the registered histogram.
+    pub fn register(opts: HistogramOpts, registry: &Registry) -> Result<Self>
+    where
+        P: 'static,
+    {
+        let histogram = Self::with_opts(opts)?;
+        registry.register(Box::new(histogram.clone()))?;
+        Ok(histogram)
+    }
+
+    /// `register_default` creates a histogram with the `opts` options and
+    /// registers it to the [default registry](crate::default_registry),
+    /// returning the registered histogram.
+    pub fn register_default(opts: HistogramOpts) -> Result<Self>
+    where
+        P: 'static,
+    {
+        Self::register(opts, default_registry())
     }
 
-    fn with_opts_and_label_values(
-        opts: &HistogramOpts,
-        label_values: &[&str],
-    ) -> Result<Histogram> {
+    fn with_opts_and_label_values(opts: &HistogramOpts, label_values: &[&str]) -> Result<Self> {
         let core = HistogramCore::new(opts, label_values)?;
 
-        Ok(Histogram {
+        Ok(Self {
             core: Arc::new(core),
         })
     }
-}
 
-impl Histogram {
-    /// Add a single observation to the [`Histogram`].
-    pub fn observe(&self, v: f64) {
-        self.core.observe(v)
+    /// Return the `(upper_bound, cumulative_count)` pairs of the buckets as
+    /// exposed by [`metric`](Metric::metric), followed by a final
+    /// `(+Inf, sample_count)` pair, without building the protobuf struct.
+    pub fn cumulative_counts(&self) -> Vec<(f64, u64)> {
+        self.core.cumulative_counts()
     }
 
-    /// Return a [`HistogramTimer`] to track a duration.
-    pub fn start_timer(&self) -> HistogramTimer {
-        HistogramTimer::new(self.clone())
+    /// Return the index into the buckets of the bucket an observation of `v`
+    /// would fall in, without observing it. `None` is returned for values
+    /// above the highest bucket, which only fall in the implicit `+Inf`
+    /// bucket, and for NaN.
+    pub fn bucket_index(&self, v: f64) -> Option<usize> {
+        self.core.bucket_index(v)
     }
 
-    /// Return a [`HistogramTimer`] to track a duration.
-    /// It is faster but less precise.
-    #[cfg(feature = "nightly")]
-    pub fn start_coarse_timer(&self) -> HistogramTimer {
-        HistogramTimer::new_coarse(self.clone())
+    /// Return the number of observations above the highest bucket, which
+    /// only fall in the implicit `+Inf` bucket. A growing count means the
+    /// buckets are too small for the observed values. It is not part of the
+    /// collected metric, but can be alerted on to retune the buckets.
+    pub fn overflow_count(&self) -> u64 {
+        self.core.overflow_count()
+    }
+
+    /// Like [`collect`](Collector::collect), but refills `mf` in place instead
+    /// of allocating a new metric family, e.g. to reuse the same buffer for
+    /// every scrape. `mf` may be empty or hold the result of a previous call
+    /// for any metric, its allocations are only reused for the same one.
+    pub fn collect_into(&self, mf: &mut proto::MetricFamily) {
+        let m = reuse_metric_family(
+            mf,
+            &self.core.desc,
+            proto::MetricType::HISTOGRAM,
+            &self.core.label_pairs,
+        );
+        self.core.proto_into(m.mut_histogram());
+    }
+
+    /// Like [`collect`](Collector::collect), but only includes the buckets at
+    /// the given `indices` into the buckets, e.g. to cut the payload of a
+    /// bandwidth-constrained remote write at the cost of quantile resolution.
+    /// The selected buckets keep their cumulative counts, and the sum and
+    /// count, thus the implicit `+Inf` bucket, are always included.
+    ///
+    /// An error is returned if `indices` is not strictly increasing or
+    /// contains an index beyond the buckets.
+    pub fn collect_buckets(&self, indices: &[usize]) -> Result<proto::MetricFamily> {
+        let num_buckets = self.core.upper_bounds.len();
+        for (n, &i) in indices.iter().enumerate() {
+            if i >= num_buckets {
+                return Err(Error::Msg(format!(
+                    "bucket index {} out of range, histogram has {} buckets",
+                    i, num_buckets
+                )));
+            }
+            if n > 0 && i <= indices[n - 1] {
+                return Err(Error::Msg(
+                    "bucket indices must be strictly increasing".to_owned(),
+                ));
+            }
+        }
+
+        let mut mf = self.collect().pop().unwrap();
+        let h = mf.mut_metric()[0].mut_histogram();
+        let buckets: Vec<_> = indices.iter().map(|&i| h.get_bucket()[i].clone()).collect();
+        h.set_bucket(from_vec!(buckets));
+        Ok(mf)
+    }
+
+    /// Return the increments since the previous call, or since the histogram
+    /// was created for the first call, for sinks that expect delta rather than
+    /// cumulative temporality. Bucket counts are still cumulative over the
+    /// buckets, each bucket counts the new observations less than or equal to
+    /// its upper bound. Exemplars are not included.
+    ///
+    /// It is independent from [`collect`](Collector::collect), which keeps
+    /// reporting the totals since the histogram was created.
+    pub fn collect_delta(&self) -> proto::Histogram {
+        self.core.proto_delta()
+    }
+
+    /// Change the help string of the histogram.
+    ///
+    /// The help can only be changed before the histogram is registered, see
+    /// [`GenericCounter::set_help`](crate::core::GenericCounter::set_help).
+    pub fn set_help(&mut self, help: &str) -> Result<()> {
+        match Arc::get_mut(&mut self.core) {
+            Some(core) => {
+                core.desc = core.desc.with_help(help)?;
+                Ok(())
+            }
+            None => Err(Error::Msg(format!(
+                "help of {} can not be changed after the metric is cloned or registered",
+                self.core.desc.fq_name
+            ))),
+        }
+    }
+
+    /// Return accumulated sum of all samples.
+    pub fn get_sample_sum(&self) -> P::T {
+        self.core.sample_sum()
+    }
+
+    /// Return count of all samples.
+    pub fn get_sample_count(&self) -> u64 {
+        self.core.sample_count()
+    }
+
+    /// Return a readable [`MetricSnapshotLine`] of the histogram, e.g. for
+    /// logging its current state. The buckets are summarized by their
+    /// cumulative counts.
+    pub fn snapshot(&self) -> MetricSnapshotLine {
+        MetricSnapshotLine::new(
+            &self.core.desc.fq_name,
+            proto::MetricType::HISTOGRAM,
+            self.metric(),
+        )
+    }
+}
+
+impl Histogram {
+    /// Add a single observation to the [`Histogram`].
+    pub fn observe(&self, v: f64) {
+        self.core.observe(v)
+    }
+
+    /// Add all observations of `it` to the [`Histogram`]. They are aggregated
+    /// locally first, so the histogram is updated once per bucket rather than
+    /// once per observation.
+    pub fn observe_iter<I: IntoIterator<Item = f64>>(&self, it: I) {
+        let mut local = LocalHistogramCore::new(self.clone());
+        for v in it {
+            local.observe(v);
+        }
+        local.flush();
+    }
+
+    /// Add a single observation to the [`Histogram`] along with an exemplar,
+    /// e.g. the trace ID of the observed request.
+    ///
+    /// The exemplar replaces the previous one of the bucket the observation
+    /// falls in and is exposed by the [`ProtobufEncoder`](crate::ProtobufEncoder).
+    /// Observations above the highest bucket do not keep an exemplar.
+    ///
+    /// An error is returned and nothing is observed if a label name is invalid
+    /// or the label names and values exceed [`EXEMPLAR_MAX_LABEL_LENGTH`]
+    /// characters.
+    pub fn observe_with_exemplar(&self, v: f64, labels: &HashMap<&str, &str>) -> Result<()> {
+        let mut length = 0;
+        let mut label_pairs = Vec::with_capacity(labels.len());
+        for (name, value) in labels {
+            if !is_valid_label_name(name) {
+                return Err(Error::Msg(format!("'{}' is not a valid label name", name)));
+            }
+            length += name.chars().count() + value.chars().count();
+
+            let mut label_pair = proto::LabelPair::default();
+            label_pair.set_name((*name).to_owned());
+            label_pair.set_value((*value).to_owned());
+            label_pairs.push(label_pair);
+        }
+        if length > EXEMPLAR_MAX_LABEL_LENGTH {
+            return Err(Error::Msg(format!(
+                "exemplar labels have {} characters, more than the {} allowed",
+                length, EXEMPLAR_MAX_LABEL_LENGTH
+            )));
+        }
+        label_pairs.sort();
+
+        let mut exemplar = proto::Exemplar::default();
+        exemplar.set_label(from_vec!(label_pairs));
+        exemplar.set_timestamp(make_timestamp(timer::unix_secs()));
+        self.core.observe_with_exemplar(v, exemplar);
+        Ok(())
+    }
+
+    /// Add a single observation to the [`Histogram`] that counts as `weight`
+    /// observations, e.g. a sample retained by reservoir sampling that stands
+    /// for several others.
+    ///
+    /// The matching bucket and the observation count are increased by
+    /// `weight`, the sum is increased by `v * weight`.
+    ///
+    /// The function returns an error if `weight` is zero.
+    pub fn observe_weighted(&self, v: f64, weight: u64) -> Result<()> {
+        if weight == 0 {
+            return Err(Error::Msg(
+                "observe_weighted needs a positive weight, weight: 0".to_owned(),
+            ));
+        }
+
+        self.core.observe_weighted(v, weight);
+        Ok(())
+    }
+
+    /// Add a single observation to the [`Histogram`] along with a separate
+    /// `cost`, e.g. the size in bytes of a request, which is added to the cost
+    /// sum returned by [`get_cost_sum`](Histogram::get_cost_sum).
+    pub fn observe_with_cost(&self, v: f64, cost: f64) {
+        self.core.observe_with_cost(v, cost)
+    }
+
+    /// Add a size in bytes, e.g. `body.len()`, as a single observation to the
+    /// [`Histogram`], see [`size_buckets`] for matching buckets.
+    pub fn observe_size(&self, bytes: usize) {
+        self.core.observe(bytes as f64)
+    }
+
+    /// Add a single observation to the [`Histogram`] and return whether it
+    /// exceeds `threshold`, e.g. a latency objective, so the same value is
+    /// both observed and compared.
+    pub fn observe_and_exceeds(&self, v: f64, threshold: f64) -> bool {
+        self.core.observe(v);
+        v > threshold
+    }
+
+    /// Return a [`HistogramTimer`] to track a duration.
+    pub fn start_timer(&self) -> HistogramTimer {
+        HistogramTimer::new(self.clone())
+    }
+
+    /// Return a [`HistogramTimer`] to track a duration, which observes the
+    /// duration in seconds multiplied by `scale`, e.g. `1000.0` for a
+    /// histogram with buckets in milliseconds. A negative `scale` yields
+    /// negative durations, which are observed as 0.
+    pub fn start_timer_with_scale(&self, scale: f64) -> HistogramTimer {
+        HistogramTimer::with_scale(self.clone(), scale)
+    }
+
+    /// Return a [`HistogramTimer`] to track a duration, which reads a cached
+    /// clock instead of querying the system clock, for extremely hot code
+    /// paths on stable Rust. It is faster but less precise.
+    ///
+    /// The cached clock has a resolution of a millisecond, or worse if its
+    /// refreshing thread is not scheduled in time. It is refreshed by the
+    /// background time updater, which is started on the first use if needed
+    /// and then wakes up every millisecond for the rest of the process
+    /// lifetime.
+    pub fn start_timer_cached(&self) -> HistogramTimer {
+        HistogramTimer::new_cached(self.clone())
+    }
+
+    /// Return a [`HistogramTimer`] to track a duration.
+    /// It is faster but less precise.
+    #[cfg(feature = "nightly")]
+    pub fn start_coarse_timer(&self) -> HistogramTimer {
+        HistogramTimer::new_coarse(self.clone())
     }
 
     /// Observe execution time of a closure, in second.
@@ -731,35 +1551,76 @@ impl Histogram {
         res
     }
 
+    /// Observe the time elapsed since `earlier`, in second, e.g. when the
+    /// start of a request is captured far from where it is observed. An
+    /// `earlier` in the future is observed as 0.
+    pub fn observe_duration_since(&self, earlier: StdInstant) {
+        let elapsed = StdInstant::now().saturating_duration_since(earlier);
+        self.observe(duration_to_seconds(elapsed));
+    }
+
     /// Return a [`LocalHistogram`] for single thread usage.
     pub fn local(&self) -> LocalHistogram {
         LocalHistogram::new(self.clone())
     }
 
-    /// Return accumulated sum of all samples.
-    pub fn get_sample_sum(&self) -> f64 {
-        self.core.sample_sum()
+    /// Return count of dropped invalid observations. It is always 0 unless
+    /// the histogram was created with [`InvalidObservationPolicy::Count`].
+    pub fn get_invalid_count(&self) -> u64 {
+        self.core.invalid_count()
     }
 
-    /// Return count of all samples.
-    pub fn get_sample_count(&self) -> u64 {
-        self.core.sample_count()
+    /// Return the sum of the costs observed by
+    /// [`observe_with_cost`](Histogram::observe_with_cost). It is not part of
+    /// the collected metric, but can be used by custom encoders.
+    pub fn get_cost_sum(&self) -> f64 {
+        self.core.cost_sum()
+    }
+}
+
+impl IntHistogram {
+    /// Add a single observation to the [`IntHistogram`].
+    pub fn observe(&self, v: i64) {
+        self.core.observe(v)
+    }
+
+    /// Add a single observation to the [`IntHistogram`] that counts as
+    /// `weight` observations, see [`Histogram::observe_weighted`].
+    ///
+    /// The function returns an error if `weight` is zero or if `v * weight`
+    /// overflows an `i64`, in which case nothing is observed.
+    pub fn observe_weighted(&self, v: i64, weight: u64) -> Result<()> {
+        if weight == 0 {
+            return Err(Error::Msg(
+                "observe_weighted needs a positive weight, weight: 0".to_owned(),
+            ));
+        }
+
+        self.core.observe_weighted(v, weight)
     }
 }
 
-impl Metric for Histogram {
+impl<P: Atomic> Metric for GenericHistogram<P> {
     fn metric(&self) -> proto::Metric {
         let mut m = proto::Metric::default();
-        m.set_label(from_vec!(self.core.label_pairs.clone()));
+        m.set_label(from_vec!(to_proto_label_pairs(&self.core.label_pairs)));
 
         let h = self.core.proto();
         m.set_histogram(h);
 
         m
     }
+
+    fn visit(&self, _: proto::MetricType, visitor: &mut dyn MetricVisitor) {
+        let h = self.core.proto();
+        visitor.visit_metric(
+            Labels::Interned(&self.core.label_pairs),
+            MetricValue::Histogram(&h),
+        );
+    }
 }
 
-impl Collector for Histogram {
+impl<P: Atomic> Collector for GenericHistogram<P> {
     fn desc(&self) -> Vec<&Desc> {
         vec![&self.core.desc]
     }
@@ -773,6 +1634,19 @@ impl Collector for Histogram {
 
         vec![m]
     }
+
+    fn visit(&self, visitor: &mut dyn MetricVisitor) {
+        visitor.visit_family(
+            &self.core.desc.fq_name,
+            &self.core.desc.help,
+            proto::MetricType::HISTOGRAM,
+        );
+        Metric::visit(self, proto::MetricType::HISTOGRAM, visitor);
+    }
+
+    fn metric_type(&self, _: &Desc) -> Option<proto::MetricType> {
+        Some(proto::MetricType::HISTOGRAM)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -806,6 +1680,28 @@ impl HistogramVec {
         Ok(metric_vec as HistogramVec)
     }
 
+    /// Return a [`HistogramTimer`] tracking a duration for the histogram with
+    /// the given label values, which is created if it does not exist yet.
+    ///
+    /// An error is returned if the number of label values differs from the
+    /// number of label names, see
+    /// [`get_metric_with_label_values`](MetricVec::get_metric_with_label_values).
+    pub fn start_timer(&self, vals: &[&str]) -> Result<HistogramTimer> {
+        let histogram = self.get_metric_with_label_values(vals)?;
+        Ok(HistogramTimer::new(histogram))
+    }
+
+    /// Return the [`Histogram`] of a vector with a single label for the value
+    /// of `l`, see [`LabelValue`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the vector does not have exactly one label, like
+    /// [`with_label_values`](MetricVec::with_label_values).
+    pub fn with_label_enum<L: LabelValue>(&self, l: L) -> Histogram {
+        self.with_label_values(&[l.label_value()])
+    }
+
     /// Return a `LocalHistogramVec` for single thread usage.
     pub fn local(&self) -> LocalHistogramVec {
         let vec = self.clone();
@@ -813,6 +1709,116 @@ impl HistogramVec {
     }
 }
 
+/// A [`HistogramVec`] which keeps at most a given number of children, for
+/// label dimensions with churning values like remote peer addresses.
+///
+/// When a new child exceeds the cap, the child least recently accessed by
+/// [`with_label_values`](BoundedHistogramVec::with_label_values) or
+/// [`get_metric_with_label_values`](BoundedHistogramVec::get_metric_with_label_values)
+/// is evicted, so memory stays bounded. Observations through a kept
+/// [`Histogram`] handle do not count as accesses. Evicted series disappear
+/// from scrapes and start from zero if they come back, which breaks `rate()`
+/// continuity for them.
+#[derive(Clone, Debug)]
+pub struct BoundedHistogramVec {
+    vec: HistogramVec,
+    max_cardinality: usize,
+    lru: Arc<Mutex<LruIndex>>,
+}
+
+/// The access order of the children of a [`BoundedHistogramVec`].
+#[derive(Debug, Default)]
+struct LruIndex {
+    tick: u64,
+    /// The last access tick of each child by label values hash.
+    ticks: HashMap<u64, u64>,
+    /// The label values hash of each child by last access tick.
+    hashes: BTreeMap<u64, u64>,
+}
+
+impl BoundedHistogramVec {
+    /// Create a new [`BoundedHistogramVec`] keeping at most `max_cardinality`
+    /// children, see [`HistogramVec::new`].
+    ///
+    /// The function returns an error if `max_cardinality` is zero.
+    pub fn new(
+        opts: HistogramOpts,
+        label_names: &[&str],
+        max_cardinality: usize,
+    ) -> Result<BoundedHistogramVec> {
+        if max_cardinality == 0 {
+            return Err(Error::Msg(
+                "BoundedHistogramVec needs a positive max cardinality, max_cardinality: 0"
+                    .to_owned(),
+            ));
+        }
+
+        Ok(BoundedHistogramVec {
+            vec: HistogramVec::new(opts, label_names)?,
+            max_cardinality,
+            lru: Arc::new(Mutex::new(LruIndex::default())),
+        })
+    }
+
+    /// Return the [`Histogram`] for the given label values, created if it does
+    /// not exist yet, and mark it as most recently used. Creating a child may
+    /// evict the least recently used one.
+    ///
+    /// An error is returned if the number of label values differs from the
+    /// number of label names.
+    pub fn get_metric_with_label_values(&self, vals: &[&str]) -> Result<Histogram> {
+        let h = self.vec.v.hash_label_values(vals)?;
+
+        // The index lock is held while the child is created or evicted, so the
+        // index and the children never disagree.
+        let mut lru = self.lru.lock().unwrap();
+        let histogram = self.vec.get_metric_with_label_values(vals)?;
+
+        lru.tick += 1;
+        let tick = lru.tick;
+        if let Some(previous) = lru.ticks.insert(h, tick) {
+            lru.hashes.remove(&previous);
+        }
+        lru.hashes.insert(tick, h);
+
+        if lru.ticks.len() > self.max_cardinality {
+            let oldest_tick = *lru.hashes.keys().next().unwrap();
+            let oldest = lru.hashes.remove(&oldest_tick).unwrap();
+            lru.ticks.remove(&oldest);
+            self.vec.v.children.write().remove(&oldest);
+        }
+
+        Ok(histogram)
+    }
+
+    /// Like [`get_metric_with_label_values`](BoundedHistogramVec::get_metric_with_label_values),
+    /// but panics if the number of label values differs from the number of
+    /// label names.
+    pub fn with_label_values(&self, vals: &[&str]) -> Histogram {
+        self.get_metric_with_label_values(vals).unwrap()
+    }
+
+    /// Return the number of children.
+    pub fn len(&self) -> usize {
+        self.lru.lock().unwrap().ticks.len()
+    }
+
+    /// Return whether there are no children.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Collector for BoundedHistogramVec {
+    fn desc(&self) -> Vec<&Desc> {
+        self.vec.desc()
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        self.vec.collect()
+    }
+}
+
 /// Create `count` buckets, each `width` wide, where the lowest
 /// bucket has an upper bound of `start`. The final +Inf bucket is not counted
 /// and not included in the returned slice. The returned slice is meant to be
@@ -881,6 +1887,37 @@ pub fn exponential_buckets(start: f64, factor: f64, count: usize) -> Result<Vec<
     Ok(buckets)
 }
 
+/// Create buckets for a latency objective of `objective` seconds, e.g. for
+/// service level objective burn-rate dashboards. The buckets are centered on
+/// the objective, from a quarter up to eight times of it:
+/// `[objective / 4, objective / 2, objective, 2 * objective, 4 * objective,
+/// 8 * objective]`. The final +Inf bucket is not included.
+///
+/// The function returns an error if `objective` is zero, negative or not
+/// finite.
+pub fn slo_buckets(objective: f64) -> Result<Vec<f64>> {
+    if !(objective > 0.0 && objective.is_finite()) {
+        return Err(Error::Msg(format!(
+            "slo_buckets needs a positive finite objective, objective: {}",
+            objective
+        )));
+    }
+
+    // Multiplying by powers of two is exact, so the objective is a boundary.
+    Ok([0.25, 0.5, 1.0, 2.0, 4.0, 8.0]
+        .iter()
+        .map(|factor| objective * factor)
+        .collect())
+}
+
+/// Create buckets for sizes in bytes, e.g. of request or response bodies, to
+/// be observed with [`Histogram::observe_size`]. The buckets are powers of
+/// four from 256 bytes up to 64 MiB: `[256, 1024, 4096, .., 67108864]`. The
+/// final +Inf bucket is not included.
+pub fn size_buckets() -> Vec<f64> {
+    (0..10).map(|i| f64::from(256u32 << (2 * i))).collect()
+}
+
 /// `duration_to_seconds` converts Duration to seconds.
 #[inline]
 pub fn duration_to_seconds(d: Duration) -> f64 {
@@ -967,6 +2004,19 @@ impl LocalHistogramTimer {
         timer.observe(false)
     }
 
+    /// Observe, record and return timer duration (in seconds) into the given
+    /// local histogram instead of the attached one.
+    ///
+    /// This allows to pick the histogram at the end of the timed operation,
+    /// e.g. depending on its outcome, without starting a timer per histogram.
+    /// The duration is not recorded to the attached histogram, neither now nor
+    /// on drop.
+    pub fn observe_into(self, local: &LocalHistogram) -> f64 {
+        let v = self.stop_and_discard();
+        local.observe(v);
+        v
+    }
+
     fn observe(&mut self, record: bool) -> f64 {
         let v = self.start.elapsed_sec();
         self.observed = true;
@@ -998,16 +2048,13 @@ impl LocalHistogramCore {
     }
 
     pub fn observe(&mut self, v: f64) {
-        // Try find the bucket.
-        let mut iter = self
-            .histogram
-            .core
-            .upper_bounds
-            .iter()
-            .enumerate()
-            .filter(|&(_, f)| v <= *f);
-        if let Some((i, _)) = iter.next() {
-            self.counts[i] += 1;
+        if !self.histogram.core.check_observation(v, 1) {
+            return;
+        }
+
+        match self.histogram.core.bucket_index(v) {
+            Some(i) => self.counts[i] += 1,
+            None => self.histogram.core.overflow_count.inc_by(1),
         }
 
         self.count += 1;
@@ -1024,39 +2071,28 @@ impl LocalHistogramCore {
     }
 
     pub fn flush(&mut self) {
-        // No cached metric, return.
-        if self.count == 0 {
+        self.flush_partial(true)
+    }
+
+    /// `flush_partial` flushes the count and the sum, and the bucket counts
+    /// only if `include_buckets` is true. Otherwise they keep accumulating
+    /// until the next flush including them.
+    pub fn flush_partial(&mut self, include_buckets: bool) {
+        // No cached metric, return. The bucket counts may be left from a
+        // partial flush.
+        if self.count == 0 && !(include_buckets && self.counts.iter().any(|v| *v > 0)) {
             return;
         }
 
-        {
-            // The collect code path uses `self.shard_and_count` and
-            // `self.shards[x].count` to ensure not to collect data from a shard
-            // while observe calls are still operating on it.
-            //
-            // To ensure the above, this `inc` needs to use `Acquire` ordering
-            // to force anything below this line to stay below it.
-            let (shard_index, _count) = self
-                .histogram
-                .core
-                .shard_and_count
-                .inc_by(self.count, Ordering::Acquire);
-            let shard = &self.histogram.core.shards[shard_index as usize];
-
-            for (i, v) in self.counts.iter().enumerate() {
-                if *v > 0 {
-                    shard.buckets[i].inc_by(*v);
-                }
-            }
+        let counts: &[u64] = if include_buckets { &self.counts } else { &[] };
+        self.histogram.core.add_counts(counts, self.count, self.sum);
 
-            shard.sum.inc_by(self.sum);
-            // Use `Release` ordering to ensure all operations above stay above.
-            shard
-                .count
-                .inc_by_with_ordering(self.count, Ordering::Release);
+        if include_buckets {
+            self.clear()
+        } else {
+            self.count = 0;
+            self.sum = 0.0;
         }
-
-        self.clear()
     }
 
     fn sample_sum(&self) -> f64 {
@@ -1118,6 +2154,14 @@ impl LocalHistogram {
         res
     }
 
+    /// Observe the time elapsed since `earlier`, in second, e.g. when the
+    /// start of a request is captured far from where it is observed. An
+    /// `earlier` in the future is observed as 0.
+    pub fn observe_duration_since(&self, earlier: StdInstant) {
+        let elapsed = StdInstant::now().saturating_duration_since(earlier);
+        self.observe(duration_to_seconds(elapsed));
+    }
+
     /// Clear the local metric.
     pub fn clear(&self) {
         self.core.borrow_mut().clear();
@@ -1128,6 +2172,16 @@ impl LocalHistogram {
         self.core.borrow_mut().flush();
     }
 
+    /// Flush only the local sample count and sum to the [`Histogram`] metric.
+    /// The local bucket counts keep accumulating until the next
+    /// [`flush`](LocalHistogram::flush).
+    ///
+    /// In the meantime the [`Histogram`] is inconsistent: its bucket counts lag
+    /// behind its sample count, which skews quantiles estimated from it.
+    pub fn flush_sum_count_only(&self) {
+        self.core.borrow_mut().flush_partial(false);
+    }
+
     /// Return accumulated sum of local samples.
     pub fn get_sample_sum(&self) -> f64 {
         self.core.borrow().sample_sum()
@@ -1204,9 +2258,164 @@ impl Clone for LocalHistogramVec {
     }
 }
 
+/// A pool of [`LocalHistogram`]-like shards of a shared [`Histogram`], one per
+/// thread, for a fixed set of worker threads.
+///
+/// The shard of a thread is created on its first call to
+/// [`local`](LocalHistogramPool::local). Observing only updates atomics of the
+/// shard, which only its own thread writes to, without taking a lock.
+///
+/// The observations of a shard are moved to the shared histogram:
+///
+/// - by its own thread, when it observes once the flush interval elapsed since
+///   the shard was last flushed,
+/// - on [`PooledLocalHistogram::flush`],
+/// - for all shards, on [`flush_all`](LocalHistogramPool::flush_all), from
+///   any thread.
+///
+/// Observations of a thread which stops observing are therefore only moved by
+/// `flush_all`. Once flushed, shards without any handle left, e.g. of exited
+/// threads, are removed from the pool.
+#[derive(Debug)]
+pub struct LocalHistogramPool {
+    histogram: Histogram,
+    flush_interval_millis: u64,
+    shards: Mutex<HashMap<ThreadId, Arc<PooledShard>>>,
+}
+
+/// The observations of one thread of a [`LocalHistogramPool`] which are not
+/// flushed yet.
+#[derive(Debug)]
+struct PooledShard {
+    /// The count of each bucket.
+    counts: Vec<StdAtomicU64>,
+    count: StdAtomicU64,
+    sum: AtomicF64,
+    last_flush: StdAtomicU64,
+}
+
+impl PooledShard {
+    /// Move the observations to `histogram`.
+    fn flush(&self, histogram: &Histogram) {
+        // Observing increments the bucket count after the count, so taking the
+        // bucket counts before the count never moves a bucket count without
+        // its observation, which would make a bucket exceed the count.
+        let counts: Vec<u64> = self
+            .counts
+            .iter()
+            .map(|c| c.swap(0, Ordering::Acquire))
+            .collect();
+        let sum = self.sum.swap(0.0, Ordering::Relaxed);
+        let count = self.count.swap(0, Ordering::Relaxed);
+        self.last_flush
+            .store(timer::recent_millis(), Ordering::Relaxed);
+
+        if count > 0 || counts.iter().any(|c| *c > 0) {
+            histogram.core.add_counts(&counts, count, sum);
+        }
+    }
+}
+
+impl LocalHistogramPool {
+    /// Create a pool of local histograms of `histogram`, each of which is
+    /// flushed at most every `flush_interval` when observed.
+    pub fn new(histogram: Histogram, flush_interval: Duration) -> LocalHistogramPool {
+        timer::ensure_updater();
+        LocalHistogramPool {
+            histogram,
+            flush_interval_millis: timer::duration_to_millis(flush_interval),
+            shards: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Return the handle of the shard of the current thread, creating the
+    /// shard if the thread has none yet. Keep the handle to observe without
+    /// looking the shard up again.
+    pub fn local(&self) -> PooledLocalHistogram {
+        let mut shards = self.shards.lock().unwrap();
+        let buckets = self.histogram.core.upper_bounds.len();
+        let shard = shards
+            .entry(thread::current().id())
+            .or_insert_with(|| {
+                Arc::new(PooledShard {
+                    counts: (0..buckets).map(|_| StdAtomicU64::new(0)).collect(),
+                    count: StdAtomicU64::new(0),
+                    sum: AtomicF64::new(0.0),
+                    last_flush: StdAtomicU64::new(timer::recent_millis()),
+                })
+            })
+            .clone();
+
+        PooledLocalHistogram {
+            histogram: self.histogram.clone(),
+            shard,
+            flush_interval_millis: self.flush_interval_millis,
+        }
+    }
+
+    /// Flush the shards of all threads to the shared [`Histogram`], and remove
+    /// the shards without any handle left.
+    pub fn flush_all(&self) {
+        let mut shards = self.shards.lock().unwrap();
+        shards.retain(|_, shard| {
+            shard.flush(&self.histogram);
+            // New handles are only created by `local` under the lock, or by
+            // cloning another handle.
+            Arc::strong_count(shard) > 1
+        });
+    }
+
+    /// Return the shared [`Histogram`].
+    pub fn histogram(&self) -> &Histogram {
+        &self.histogram
+    }
+}
+
+/// The handle of the shard of a thread in a [`LocalHistogramPool`].
+///
+/// Handles of the same thread share the same shard.
+#[derive(Debug, Clone)]
+pub struct PooledLocalHistogram {
+    histogram: Histogram,
+    shard: Arc<PooledShard>,
+    flush_interval_millis: u64,
+}
+
+impl PooledLocalHistogram {
+    /// Add a single observation to the shard, and flush it if the flush
+    /// interval of the pool elapsed.
+    pub fn observe(&self, v: f64) {
+        let core = &self.histogram.core;
+        if !core.check_observation(v, 1) {
+            return;
+        }
+
+        let shard = &self.shard;
+        shard.count.fetch_add(1, Ordering::Relaxed);
+        shard.sum.inc_by(v);
+        match core.bucket_index(v) {
+            // Increment the bucket count after the count, see
+            // `PooledShard::flush`.
+            Some(i) => {
+                shard.counts[i].fetch_add(1, Ordering::Release);
+            }
+            None => core.overflow_count.inc_by(1),
+        }
+
+        let now = timer::recent_millis();
+        if now >= shard.last_flush.load(Ordering::Relaxed) + self.flush_interval_millis {
+            shard.flush(&self.histogram);
+        }
+    }
+
+    /// Flush the shard to the shared [`Histogram`].
+    pub fn flush(&self) {
+        self.shard.flush(&self.histogram);
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::f64::{EPSILON, INFINITY};
     use std::thread;
     use std::time::Duration;
 
@@ -1236,7 +2445,7 @@ mod tests {
         assert_eq!(mfs.len(), 1);
 
         let mf = mfs.pop().unwrap();
-        let m = mf.get_metric().get(0).unwrap();
+        let m = mf.get_metric().first().unwrap();
         assert_eq!(m.get_label().len(), 2);
         let proto_histogram = m.get_histogram();
         assert_eq!(proto_histogram.get_sample_count(), 3);
@@ -1250,11 +2459,11 @@ mod tests {
         assert_eq!(mfs.len(), 1);
 
         let mf = mfs.pop().unwrap();
-        let m = mf.get_metric().get(0).unwrap();
+        let m = mf.get_metric().first().unwrap();
         assert_eq!(m.get_label().len(), 0);
         let proto_histogram = m.get_histogram();
         assert_eq!(proto_histogram.get_sample_count(), 0);
-        assert!((proto_histogram.get_sample_sum() - 0.0) < EPSILON);
+        assert!((proto_histogram.get_sample_sum() - 0.0).abs() < f64::EPSILON);
         assert_eq!(proto_histogram.get_bucket().len(), buckets.len())
     }
 
@@ -1283,10 +2492,10 @@ mod tests {
         assert_eq!(mfs.len(), 1);
 
         let mf = mfs.pop().unwrap();
-        let m = mf.get_metric().get(0).unwrap();
+        let m = mf.get_metric().first().unwrap();
         let proto_histogram = m.get_histogram();
         assert_eq!(proto_histogram.get_sample_count(), 3);
-        assert!((proto_histogram.get_sample_sum() - 0.0) > EPSILON);
+        assert!((proto_histogram.get_sample_sum() - 0.0) > f64::EPSILON);
     }
 
     #[test]
@@ -1310,11 +2519,15 @@ mod tests {
             (vec![], true, DEFAULT_BUCKETS.len()),
             (vec![-2.0, -1.0, -0.5, 0.0, 0.5, 1.0, 2.0], true, 7),
             (vec![-2.0, -1.0, -0.5, 10.0, 0.5, 1.0, 2.0], false, 7),
-            (vec![-2.0, -1.0, -0.5, 0.0, 0.5, 1.0, INFINITY], true, 6),
+            (
+                vec![-2.0, -1.0, -0.5, 0.0, 0.5, 1.0, f64::INFINITY],
+                true,
+                6,
+            ),
         ];
 
         for (buckets, is_ok, length) in table {
-            let got = check_and_adjust_buckets(buckets);
+            let got = check_and_adjust_buckets(buckets, false, None);
             assert_eq!(got.is_ok(), is_ok);
             if is_ok {
                 assert_eq!(got.unwrap().len(), length);
@@ -1323,29 +2536,144 @@ mod tests {
     }
 
     #[test]
-    fn test_buckets_functions() {
-        let linear_table = vec![
-            (
-                -15.0,
-                5.0,
-                6,
-                true,
-                vec![-15.0, -10.0, -5.0, 0.0, 5.0, 10.0],
-            ),
-            (-15.0, 0.0, 6, false, vec![]),
-            (-15.0, 5.0, 0, false, vec![]),
-        ];
+    fn test_histogram_label_shadows_const_label() {
+        let opts = HistogramOpts::new("test_shadow", "test help").const_label("op", "read");
+        assert!(HistogramVec::new(opts.clone(), &["op"]).is_err());
+        assert!(HistogramVec::new(opts, &["other"]).is_ok());
+    }
 
-        for (param1, param2, param3, is_ok, vec) in linear_table {
-            let got = linear_buckets(param1, param2, param3);
-            assert_eq!(got.is_ok(), is_ok);
-            if got.is_ok() {
-                assert_eq!(got.unwrap(), vec);
-            }
-        }
+    #[test]
+    fn test_with_const_label_merged() {
+        let base = HistogramOpts::new("test_merged", "test help")
+            .const_label("a", "1")
+            .buckets(vec![1.0, 2.0, 3.0]);
+        let read =
+            Histogram::with_opts(HistogramOpts::with_const_label_merged(&base, "op", "read"))
+                .unwrap();
+        let write =
+            Histogram::with_opts(HistogramOpts::with_const_label_merged(&base, "op", "write"))
+                .unwrap();
 
-        let exponential_table = vec![
-            (100.0, 1.2, 3, true, vec![100.0, 120.0, 144.0]),
+        let labels = |h: &Histogram| {
+            h.metric()
+                .get_label()
+                .iter()
+                .map(|lp| (lp.get_name().to_owned(), lp.get_value().to_owned()))
+                .collect::<Vec<_>>()
+        };
+        let pair = |n: &str, v: &str| (n.to_owned(), v.to_owned());
+        assert_eq!(labels(&read), vec![pair("a", "1"), pair("op", "read")]);
+        assert_eq!(labels(&write), vec![pair("a", "1"), pair("op", "write")]);
+        assert_eq!(read.core.upper_bounds, write.core.upper_bounds);
+        assert_eq!(read.core.upper_bounds, base.buckets);
+        assert_eq!(base.common_opts.const_labels.len(), 1);
+    }
+
+    #[test]
+    fn test_buckets_dedup() {
+        // 0.1 * 3.0 and 0.3 differ by a rounding error.
+        let buckets = vec![0.1, 0.1 * 3.0, 0.3, 1.0, 1.0];
+        assert_ne!(buckets[1], buckets[2]);
+        check_and_adjust_buckets(buckets.clone(), false, None).unwrap_err();
+        assert_eq!(
+            check_and_adjust_buckets(buckets.clone(), true, None).unwrap(),
+            vec![0.1, 0.1 * 3.0, 1.0]
+        );
+
+        // Decreasing buckets are still rejected.
+        check_and_adjust_buckets(vec![1.0, 0.5], true, None).unwrap_err();
+
+        let opts = HistogramOpts::new("test_histogram", "test help").buckets(buckets);
+        Histogram::with_opts(opts.clone()).unwrap_err();
+        let histogram = Histogram::with_opts(opts.dedup_buckets(true)).unwrap();
+        histogram.observe(0.3);
+        let counts = histogram.cumulative_counts();
+        assert_eq!(counts.len(), 4);
+        assert_eq!(counts[1], (0.1 * 3.0, 1));
+    }
+
+    #[test]
+    fn test_histogram_observe_size() {
+        let buckets = size_buckets();
+        assert_eq!(buckets.len(), 10);
+        assert_eq!(buckets[..3], [256.0, 1024.0, 4096.0]);
+        assert_eq!(*buckets.last().unwrap(), (64 << 20) as f64);
+
+        let opts = HistogramOpts::new("test_histogram", "test help").buckets(buckets);
+        let histogram = Histogram::with_opts(opts).unwrap();
+        histogram.observe_size(1024);
+        histogram.observe_size(1025);
+        histogram.observe_size(vec![0u8; 3000].len());
+
+        assert_eq!(histogram.get_sample_sum(), 5049.0);
+        let counts = histogram.cumulative_counts();
+        assert_eq!(counts[0], (256.0, 0));
+        assert_eq!(counts[1], (1024.0, 1));
+        assert_eq!(counts[2], (4096.0, 3));
+    }
+
+    #[test]
+    fn test_bucket_schema() {
+        BucketSchema::new("test_schema", vec![]).unwrap_err();
+        BucketSchema::new("test_schema", vec![1.0, 0.5]).unwrap_err();
+
+        let schema = BucketSchema::new("test_schema", vec![0.01, 0.1, 1.0, f64::INFINITY]).unwrap();
+        assert_eq!(schema.name(), "test_schema");
+        assert_eq!(schema.buckets(), &[0.01, 0.1, 1.0]);
+
+        let get = Histogram::with_opts(
+            HistogramOpts::new("test_get_seconds", "test help").buckets_named(&schema),
+        )
+        .unwrap();
+        let put = HistogramVec::new(
+            HistogramOpts::new("test_put_seconds", "test help").buckets_named(&schema),
+            &["l"],
+        )
+        .unwrap()
+        .with_label_values(&["a"]);
+        assert_eq!(get.core.upper_bounds, put.core.upper_bounds);
+        assert_eq!(get.core.upper_bounds, schema.buckets());
+    }
+
+    #[test]
+    fn test_histogram_max_buckets() {
+        let buckets = linear_buckets(1.0, 1.0, 40).unwrap();
+        let opts = HistogramOpts::new("test_histogram", "test help").buckets(buckets);
+        Histogram::with_opts(opts.clone().max_buckets(20)).unwrap_err();
+        Histogram::with_opts(opts.clone().max_buckets(40)).unwrap();
+        Histogram::with_opts(opts).unwrap();
+
+        // The implicit +Inf bucket does not count.
+        let opts = HistogramOpts::new("test_histogram", "test help")
+            .buckets(vec![1.0, 2.0, f64::INFINITY])
+            .max_buckets(2);
+        Histogram::with_opts(opts).unwrap();
+    }
+
+    #[test]
+    fn test_buckets_functions() {
+        let linear_table = vec![
+            (
+                -15.0,
+                5.0,
+                6,
+                true,
+                vec![-15.0, -10.0, -5.0, 0.0, 5.0, 10.0],
+            ),
+            (-15.0, 0.0, 6, false, vec![]),
+            (-15.0, 5.0, 0, false, vec![]),
+        ];
+
+        for (param1, param2, param3, is_ok, vec) in linear_table {
+            let got = linear_buckets(param1, param2, param3);
+            assert_eq!(got.is_ok(), is_ok);
+            if let Ok(got) = got {
+                assert_eq!(got, vec);
+            }
+        }
+
+        let exponential_table = vec![
+            (100.0, 1.2, 3, true, vec![100.0, 120.0, 144.0]),
             (100.0, 0.5, 3, false, vec![]),
             (100.0, 1.2, 0, false, vec![]),
         ];
@@ -1353,10 +2681,24 @@ mod tests {
         for (param1, param2, param3, is_ok, vec) in exponential_table {
             let got = exponential_buckets(param1, param2, param3);
             assert_eq!(got.is_ok(), is_ok);
-            if got.is_ok() {
-                assert_eq!(got.unwrap(), vec);
+            if let Ok(got) = got {
+                assert_eq!(got, vec);
             }
         }
+
+        assert_eq!(
+            slo_buckets(0.3).unwrap(),
+            vec![0.075, 0.15, 0.3, 0.6, 1.2, 2.4]
+        );
+        for objective in &[0.001, 0.1, 0.3, 0.7, 2.5] {
+            let buckets = slo_buckets(*objective).unwrap();
+            assert!(buckets.contains(objective));
+            let opts = HistogramOpts::new("test", "test help").buckets(buckets);
+            assert!(Histogram::with_opts(opts).is_ok());
+        }
+        for objective in &[0.0, -0.3, f64::NAN, f64::INFINITY] {
+            assert!(slo_buckets(*objective).is_err());
+        }
     }
 
     #[test]
@@ -1365,7 +2707,7 @@ mod tests {
         for (millis, seconds) in tbls {
             let d = Duration::from_millis(millis);
             let v = duration_to_seconds(d);
-            assert!((v - seconds).abs() < EPSILON);
+            assert!((v - seconds).abs() < f64::EPSILON);
         }
     }
 
@@ -1385,6 +2727,122 @@ mod tests {
         assert!(vec.remove_label_values(&["v1", "v3"]).is_err());
     }
 
+    #[test]
+    fn test_bounded_histogram_vec() {
+        let opts = HistogramOpts::new("test_histogram_vec", "test help");
+        BoundedHistogramVec::new(opts.clone(), &["peer"], 0).unwrap_err();
+
+        let vec = BoundedHistogramVec::new(opts, &["peer"], 2).unwrap();
+        vec.with_label_values(&["a"]).observe(1.0);
+        vec.with_label_values(&["b"]).observe(1.0);
+        // Accessing "a" makes "b" the least recently used child.
+        vec.with_label_values(&["a"]).observe(1.0);
+        vec.with_label_values(&["c"]).observe(1.0);
+        assert_eq!(vec.len(), 2);
+        assert!(vec.get_metric_with_label_values(&["a", "b"]).is_err());
+
+        let mfs = vec.collect();
+        let mut peers: Vec<_> = mfs[0]
+            .get_metric()
+            .iter()
+            .map(|m| {
+                let count = m.get_histogram().get_sample_count();
+                (m.get_label()[0].get_value().to_owned(), count)
+            })
+            .collect();
+        peers.sort();
+        assert_eq!(peers, vec![("a".to_owned(), 2), ("c".to_owned(), 1)]);
+
+        // An evicted child starts from zero.
+        assert_eq!(vec.with_label_values(&["b"]).get_sample_count(), 0);
+        assert_eq!(vec.len(), 2);
+    }
+
+    #[test]
+    fn test_histogram_timer_observe_on_drop() {
+        let histogram =
+            Histogram::with_opts(HistogramOpts::new("test_histogram", "test help")).unwrap();
+
+        let timer = histogram.start_timer().observe_on_drop(false);
+        drop(timer);
+        assert_eq!(histogram.get_sample_count(), 0);
+
+        let timer = histogram.start_timer().observe_on_drop(false);
+        timer.observe_duration();
+        assert_eq!(histogram.get_sample_count(), 1);
+
+        let timer = histogram.start_timer();
+        drop(timer);
+        assert_eq!(histogram.get_sample_count(), 2);
+    }
+
+    #[test]
+    fn test_histogram_start_timer_cached() {
+        let histogram =
+            Histogram::with_opts(HistogramOpts::new("test_histogram", "test help")).unwrap();
+        let real = StdInstant::now();
+        let timer = histogram.start_timer_cached();
+        thread::sleep(Duration::from_millis(50));
+        let real = duration_to_seconds(real.elapsed());
+        let cached = timer.stop_and_record();
+
+        // The cached clock lags behind when its thread is not scheduled in
+        // time, which happens on loaded machines.
+        let tolerance = duration_to_seconds(timer::CACHED_CLOCK_RESOLUTION * 40);
+        assert!((cached - real).abs() <= tolerance, "{} {}", cached, real);
+        assert_eq!(histogram.get_sample_count(), 1);
+    }
+
+    #[test]
+    fn test_histogram_vec_with_label_enum() {
+        #[derive(Clone, Copy)]
+        enum Status {
+            Ok,
+            Error,
+        }
+
+        impl LabelValue for Status {
+            fn label_value(&self) -> &'static str {
+                match self {
+                    Status::Ok => "ok",
+                    Status::Error => "error",
+                }
+            }
+        }
+
+        let vec = HistogramVec::new(
+            HistogramOpts::new("test_histogram_vec", "test help"),
+            &["status"],
+        )
+        .unwrap();
+        vec.with_label_enum(Status::Ok).observe(1.0);
+        vec.with_label_enum(Status::Ok).observe(2.0);
+        vec.with_label_enum(Status::Error).observe(3.0);
+
+        assert_eq!(vec.with_label_values(&["ok"]).get_sample_count(), 2);
+        assert_eq!(vec.with_label_values(&["error"]).get_sample_sum(), 3.0);
+    }
+
+    #[test]
+    fn test_histogram_vec_start_timer() {
+        let vec = HistogramVec::new(
+            HistogramOpts::new("test_histogram_vec", "test histogram vec help"),
+            &["method"],
+        )
+        .unwrap();
+
+        vec.start_timer(&["get"]).unwrap().observe_duration();
+        {
+            let _timer = vec.start_timer(&["get"]).unwrap();
+        }
+        vec.start_timer(&["post"]).unwrap().observe_duration();
+
+        assert_eq!(vec.with_label_values(&["get"]).get_sample_count(), 2);
+        assert_eq!(vec.with_label_values(&["post"]).get_sample_count(), 1);
+        assert!(vec.start_timer(&["get", "extra"]).is_err());
+        assert!(vec.start_timer(&[]).is_err());
+    }
+
     #[test]
     fn test_histogram_vec_with_opts_buckets() {
         let labels = ["l1", "l2"];
@@ -1404,10 +2862,529 @@ mod tests {
 
         let proto_histogram = m.get_histogram();
         assert_eq!(proto_histogram.get_sample_count(), 1);
-        assert!((proto_histogram.get_sample_sum() - 1.0) < EPSILON);
+        assert!((proto_histogram.get_sample_sum() - 1.0).abs() < f64::EPSILON);
         assert_eq!(proto_histogram.get_bucket().len(), buckets.len())
     }
 
+    #[test]
+    fn test_int_histogram() {
+        let opts = HistogramOpts::new("test_int_histogram", "test help").buckets(vec![1.0, 1024.0]);
+        let histogram = IntHistogram::with_opts(opts.clone()).unwrap();
+        histogram.observe(-3);
+        histogram.observe(1);
+        histogram.observe(4096);
+        histogram.observe_weighted(512, 2).unwrap();
+        histogram.observe_weighted(512, 0).unwrap_err();
+        histogram.observe_weighted(i64::MAX / 2, 3).unwrap_err();
+        histogram.observe_weighted(1, u64::MAX).unwrap_err();
+        assert_eq!(histogram.get_sample_sum(), 5118);
+        assert_eq!(histogram.get_sample_count(), 5);
+        assert_eq!(
+            histogram.cumulative_counts(),
+            vec![(1.0, 2), (1024.0, 4), (f64::INFINITY, 5)]
+        );
+
+        let m = histogram.metric();
+        assert_eq!(m.get_histogram().get_sample_sum(), 5118.0);
+        assert_eq!(m.get_histogram().get_sample_count(), 5);
+
+        // Beyond 2^53 the sum of a float histogram loses precision, while the
+        // sum of an integer histogram stays exact.
+        let large = 1i64 << 53;
+        let float_histogram = Histogram::with_opts(opts).unwrap();
+        float_histogram.observe(large as f64);
+        histogram.observe(large - 5118);
+        for _ in 0..10 {
+            float_histogram.observe(1.0);
+            histogram.observe(1);
+        }
+        assert_eq!(float_histogram.get_sample_sum(), large as f64);
+        assert_eq!(histogram.get_sample_sum(), large + 10);
+    }
+
+    #[test]
+    fn test_histogram_collect_delta() {
+        let opts = HistogramOpts::new("test_histogram", "test help").buckets(vec![1.0, 2.0]);
+        let histogram = Histogram::with_opts(opts).unwrap();
+        let counts = |h: &proto::Histogram| -> Vec<u64> {
+            h.get_bucket()
+                .iter()
+                .map(|b| b.get_cumulative_count())
+                .collect()
+        };
+
+        histogram.observe(0.5);
+        histogram.observe(1.5);
+        let delta = histogram.collect_delta();
+        assert_eq!(counts(&delta), vec![1, 2]);
+        assert_eq!(delta.get_sample_count(), 2);
+        assert_eq!(delta.get_sample_sum(), 2.0);
+
+        histogram.observe(1.5);
+        histogram.observe(4.0);
+        // The cumulative collect is not affected.
+        let h = histogram.metric().get_histogram().clone();
+        assert_eq!(counts(&h), vec![1, 3]);
+        assert_eq!(h.get_sample_count(), 4);
+
+        let delta = histogram.collect_delta();
+        assert_eq!(counts(&delta), vec![0, 1]);
+        assert_eq!(delta.get_sample_count(), 2);
+        assert_eq!(delta.get_sample_sum(), 5.5);
+
+        let delta = histogram.collect_delta();
+        assert_eq!(counts(&delta), vec![0, 0]);
+        assert_eq!(delta.get_sample_count(), 0);
+        assert_eq!(delta.get_sample_sum(), 0.0);
+
+        // Counts above the current ones as of the previous call saturate.
+        for count in histogram.core.delta_counts.lock().unwrap().iter_mut() {
+            *count += 10;
+        }
+        let delta = histogram.collect_delta();
+        assert_eq!(counts(&delta), vec![0, 0]);
+        assert_eq!(delta.get_sample_count(), 0);
+    }
+
+    #[test]
+    fn test_histogram_collect_buckets() {
+        let opts =
+            HistogramOpts::new("test_histogram", "test help").buckets(vec![1.0, 2.0, 4.0, 8.0]);
+        let histogram = Histogram::with_opts(opts).unwrap();
+        for v in &[0.5, 1.5, 3.0, 3.0, 6.0, 10.0] {
+            histogram.observe(*v);
+        }
+
+        let mf = histogram.collect_buckets(&[1, 3]).unwrap();
+        assert_eq!(mf.get_name(), "test_histogram");
+        let h = mf.get_metric()[0].get_histogram();
+        let subset: Vec<(f64, u64)> = h
+            .get_bucket()
+            .iter()
+            .map(|b| (b.get_upper_bound(), b.get_cumulative_count()))
+            .collect();
+        let full = histogram.cumulative_counts();
+        assert_eq!(subset, vec![full[1], full[3]]);
+        assert_eq!(h.get_sample_count(), full[4].1);
+        assert_eq!(h.get_sample_sum(), 24.0);
+
+        let mf = histogram.collect_buckets(&[]).unwrap();
+        assert!(mf.get_metric()[0].get_histogram().get_bucket().is_empty());
+
+        assert!(histogram.collect_buckets(&[4]).is_err());
+        assert!(histogram.collect_buckets(&[2, 1]).is_err());
+        assert!(histogram.collect_buckets(&[1, 1]).is_err());
+    }
+
+    #[test]
+    fn test_histogram_cumulative_counts() {
+        let opts = HistogramOpts::new("test_cumulative", "test help").buckets(vec![
+            1.0,
+            2.0,
+            f64::INFINITY,
+        ]);
+        let histogram = Histogram::with_opts(opts).unwrap();
+        for v in &[0.5, 1.5, 1.5, 3.0] {
+            histogram.observe(*v);
+        }
+
+        let counts = histogram.cumulative_counts();
+        assert_eq!(counts, vec![(1.0, 1), (2.0, 3), (f64::INFINITY, 4)]);
+
+        let m = histogram.metric();
+        let proto_counts: Vec<(f64, u64)> = m
+            .get_histogram()
+            .get_bucket()
+            .iter()
+            .map(|b| (b.get_upper_bound(), b.get_cumulative_count()))
+            .collect();
+        assert_eq!(&counts[..counts.len() - 1], &proto_counts[..]);
+        assert_eq!(
+            counts.last().unwrap().1,
+            m.get_histogram().get_sample_count()
+        );
+    }
+
+    #[test]
+    fn test_histogram_timer_with_scale() {
+        let opts = HistogramOpts::new("test_millis", "test help")
+            .buckets(vec![50.0, 100.0, 200.0, 1000.0]);
+        let histogram = Histogram::with_opts(opts).unwrap();
+
+        let timer = histogram.start_timer_with_scale(1000.0);
+        thread::sleep(Duration::from_millis(100));
+        let v = timer.stop_and_record();
+        assert!((100.0..1000.0).contains(&v), "{}", v);
+
+        {
+            let _timer = histogram.start_timer_with_scale(1000.0);
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(histogram.get_sample_count(), 2);
+        let sum = histogram.get_sample_sum();
+        assert!((200.0..2000.0).contains(&sum), "{}", sum);
+        assert_eq!(histogram.cumulative_counts()[0], (50.0, 0));
+    }
+
+    #[test]
+    fn test_histogram_collect_into() {
+        let opts = HistogramOpts::new("test_histogram", "test help")
+            .const_label("a", "1")
+            .buckets(vec![1.0, 2.0]);
+        let histogram = Histogram::with_opts(opts).unwrap();
+        let mut mf = proto::MetricFamily::default();
+
+        histogram
+            .observe_with_exemplar(0.5, &HashMap::new())
+            .unwrap();
+        histogram.collect_into(&mut mf);
+        assert_eq!(mf, histogram.collect().pop().unwrap());
+        let buckets = mf.get_metric()[0].get_histogram().get_bucket().as_ptr();
+
+        // Refilling reuses the buckets and reflects the new state.
+        histogram.observe(1.5);
+        histogram.collect_into(&mut mf);
+        assert_eq!(mf, histogram.collect().pop().unwrap());
+        let h = mf.get_metric()[0].get_histogram();
+        assert_eq!(h.get_bucket().as_ptr(), buckets);
+        assert_eq!(h.get_sample_count(), 2);
+        assert_eq!(h.get_bucket()[1].get_cumulative_count(), 2);
+
+        // A buffer of another metric is replaced.
+        let counter = crate::counter::Counter::new("test_counter", "test help").unwrap();
+        counter.collect_into(&mut mf);
+        assert_eq!(mf, counter.collect().pop().unwrap());
+        histogram.collect_into(&mut mf);
+        assert_eq!(mf, histogram.collect().pop().unwrap());
+    }
+
+    #[test]
+    fn test_histogram_observe_with_cost() {
+        let opts = HistogramOpts::new("test_histogram", "test help").buckets(vec![1.0, 10.0]);
+        let histogram = Histogram::with_opts(opts).unwrap();
+
+        histogram.observe_with_cost(0.5, 100.0);
+        histogram.observe_with_cost(5.0, 2048.0);
+        histogram.observe(5.0);
+        histogram.observe_with_cost(f64::NAN, 1.0);
+
+        assert_eq!(histogram.get_sample_count(), 3);
+        assert_eq!(histogram.get_sample_sum(), 10.5);
+        assert_eq!(histogram.get_cost_sum(), 2148.0);
+
+        // The cost sum is not collected.
+        let m = histogram.metric();
+        assert_eq!(m.get_histogram().get_sample_sum(), 10.5);
+    }
+
+    #[test]
+    fn test_histogram_observe_and_exceeds() {
+        let opts = HistogramOpts::new("test_histogram", "test help").buckets(vec![0.1, 1.0]);
+        let histogram = Histogram::with_opts(opts).unwrap();
+
+        assert!(!histogram.observe_and_exceeds(0.099, 0.1));
+        assert!(!histogram.observe_and_exceeds(0.1, 0.1));
+        assert!(histogram.observe_and_exceeds(0.101, 0.1));
+
+        assert_eq!(histogram.get_sample_count(), 3);
+        assert_eq!(histogram.cumulative_counts()[0], (0.1, 2));
+    }
+
+    #[test]
+    fn test_histogram_bucket_index() {
+        let opts = HistogramOpts::new("test_histogram", "test help").buckets(vec![1.0, 2.0, 5.0]);
+        let histogram = Histogram::with_opts(opts).unwrap();
+
+        // Below, at and between the boundaries.
+        assert_eq!(histogram.bucket_index(-1.0), Some(0));
+        assert_eq!(histogram.bucket_index(1.0), Some(0));
+        assert_eq!(histogram.bucket_index(1.5), Some(1));
+        assert_eq!(histogram.bucket_index(2.0), Some(1));
+        assert_eq!(histogram.bucket_index(3.0), Some(2));
+        assert_eq!(histogram.bucket_index(5.0), Some(2));
+        // Above the highest boundary.
+        assert_eq!(histogram.bucket_index(5.1), None);
+        assert_eq!(histogram.bucket_index(f64::INFINITY), None);
+        assert_eq!(histogram.bucket_index(f64::NAN), None);
+
+        // It agrees with where observations land, without observing.
+        assert_eq!(histogram.get_sample_count(), 0);
+        histogram.observe(1.5);
+        let counts = histogram.cumulative_counts();
+        assert_eq!(counts[0].1, 0);
+        assert_eq!(counts[1].1, 1);
+    }
+
+    #[test]
+    fn test_histogram_observe_duration_since() {
+        let histogram =
+            Histogram::with_opts(HistogramOpts::new("test_seconds", "test help")).unwrap();
+        let local = histogram.local();
+
+        let earlier = StdInstant::now();
+        thread::sleep(Duration::from_millis(100));
+        histogram.observe_duration_since(earlier);
+        local.observe_duration_since(earlier);
+        local.flush();
+
+        assert_eq!(histogram.get_sample_count(), 2);
+        let sum = histogram.get_sample_sum();
+        assert!((0.2..2.0).contains(&sum), "{}", sum);
+
+        // An instant in the future is observed as 0.
+        histogram.observe_duration_since(StdInstant::now() + Duration::from_secs(60));
+        assert_eq!(histogram.get_sample_count(), 3);
+        assert_eq!(histogram.get_sample_sum(), sum);
+    }
+
+    #[test]
+    fn test_histogram_timer_negative_duration() {
+        let histogram =
+            Histogram::with_opts(HistogramOpts::new("test_seconds", "test help")).unwrap();
+
+        let timer = histogram.start_timer_with_scale(-1.0);
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(timer.stop_and_record(), 0.0);
+        {
+            let _timer = histogram.start_timer_with_scale(-1.0);
+            thread::sleep(Duration::from_millis(10));
+        }
+        histogram.observe_duration_since(StdInstant::now() + Duration::from_secs(1));
+
+        assert_eq!(histogram.get_sample_count(), 3);
+        assert_eq!(histogram.get_sample_sum(), 0.0);
+        assert_eq!(histogram.cumulative_counts()[0].1, 3);
+    }
+
+    #[test]
+    fn test_multi_histogram_timer() {
+        let opts = HistogramOpts::new("test_endpoint", "test help");
+        let endpoint = Histogram::with_opts(opts).unwrap();
+        let opts = HistogramOpts::new("test_global", "test help").buckets(vec![0.001, 1.0]);
+        let global = Histogram::with_opts(opts).unwrap();
+
+        {
+            let _timer = MultiHistogramTimer::new(&[&endpoint, &global]);
+            thread::sleep(Duration::from_millis(10));
+        }
+        let timer = MultiHistogramTimer::new(&[&endpoint, &global]);
+        let v = timer.stop_and_record();
+        assert!(v >= 0.0);
+        MultiHistogramTimer::new(&[&endpoint, &global]).stop_and_discard();
+
+        assert_eq!(endpoint.get_sample_count(), 2);
+        assert_eq!(global.get_sample_count(), 2);
+        assert!(endpoint.get_sample_sum() >= 0.01);
+        assert!((endpoint.get_sample_sum() - global.get_sample_sum()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_histogram_timer_observe_into() {
+        let opts = HistogramOpts::new("test_success", "test help");
+        let success = Histogram::with_opts(opts).unwrap();
+        let opts = HistogramOpts::new("test_failure", "test help");
+        let failure = Histogram::with_opts(opts).unwrap();
+
+        for failed in &[false, true, true] {
+            let timer = success.start_timer();
+            thread::sleep(Duration::from_millis(10));
+            let target = if *failed { &failure } else { &success };
+            assert!(timer.observe_into(target) > 0.0);
+        }
+        assert_eq!(success.get_sample_count(), 1);
+        assert_eq!(failure.get_sample_count(), 2);
+
+        let local_success = success.local();
+        let local_failure = failure.local();
+        for failed in &[false, true] {
+            let timer = local_success.start_timer();
+            let target = if *failed {
+                &local_failure
+            } else {
+                &local_success
+            };
+            timer.observe_into(target);
+        }
+        local_success.flush();
+        local_failure.flush();
+        assert_eq!(success.get_sample_count(), 2);
+        assert_eq!(failure.get_sample_count(), 3);
+    }
+
+    #[test]
+    fn test_histogram_observe_with_exemplar() {
+        let opts = HistogramOpts::new("test_exemplar", "test help").buckets(vec![1.0, 2.0]);
+        let histogram = Histogram::with_opts(opts).unwrap();
+
+        // The exemplars are only allocated once one is kept.
+        histogram.observe(0.5);
+        histogram.metric();
+        assert!(histogram.core.exemplars.lock().unwrap().is_empty());
+
+        let mut labels = HashMap::new();
+        labels.insert("trace_id", "abc");
+        histogram.observe_with_exemplar(0.5, &labels).unwrap();
+        assert_eq!(histogram.core.exemplars.lock().unwrap().len(), 2);
+        labels.insert("trace_id", "def");
+        histogram.observe_with_exemplar(0.75, &labels).unwrap();
+        // Above the highest bucket, no exemplar is kept.
+        histogram.observe_with_exemplar(3.0, &labels).unwrap();
+
+        let m = histogram.metric();
+        let proto_histogram = m.get_histogram();
+        assert_eq!(proto_histogram.get_sample_count(), 4);
+        let buckets = proto_histogram.get_bucket();
+        let exemplar = buckets[0].get_exemplar();
+        assert_eq!(exemplar.get_value(), 0.75);
+        assert_eq!(exemplar.get_label()[0].get_name(), "trace_id");
+        assert_eq!(exemplar.get_label()[0].get_value(), "def");
+        assert!(exemplar.get_timestamp().get_seconds() > 0);
+        assert!(buckets[1].get_exemplar().get_label().is_empty());
+
+        let mut labels = HashMap::new();
+        labels.insert("trace-id", "abc");
+        assert!(histogram.observe_with_exemplar(0.5, &labels).is_err());
+        let long_value = "a".repeat(EXEMPLAR_MAX_LABEL_LENGTH);
+        let mut labels = HashMap::new();
+        labels.insert("trace_id", long_value.as_str());
+        assert!(histogram.observe_with_exemplar(0.5, &labels).is_err());
+        assert_eq!(histogram.get_sample_count(), 4);
+    }
+
+    #[test]
+    #[cfg(feature = "protobuf")]
+    fn test_histogram_exemplar_protobuf_roundtrip() {
+        use crate::encoder::{Encoder, ProtobufEncoder};
+
+        let opts = HistogramOpts::new("test_exemplar", "test help").buckets(vec![1.0]);
+        let histogram = Histogram::with_opts(opts).unwrap();
+        let mut labels = HashMap::new();
+        labels.insert("trace_id", "abc");
+        histogram.observe_with_exemplar(0.5, &labels).unwrap();
+
+        let mut buf = Vec::new();
+        ProtobufEncoder::new()
+            .encode(&histogram.collect(), &mut buf)
+            .unwrap();
+        let mf: proto::MetricFamily = protobuf::CodedInputStream::from_bytes(&buf)
+            .read_message()
+            .unwrap();
+
+        let bucket = &mf.get_metric()[0].get_histogram().get_bucket()[0];
+        assert!(bucket.has_exemplar());
+        let exemplar = bucket.get_exemplar();
+        assert_eq!(exemplar.get_value(), 0.5);
+        assert_eq!(exemplar.get_label()[0].get_name(), "trace_id");
+        assert_eq!(exemplar.get_label()[0].get_value(), "abc");
+        assert!(exemplar.get_timestamp().get_seconds() > 0);
+    }
+
+    #[test]
+    fn test_histogram_observe_weighted() {
+        let buckets = vec![1.0, 2.0, 3.0];
+        let weighted = Histogram::with_opts(
+            HistogramOpts::new("test_weighted", "test help").buckets(buckets.clone()),
+        )
+        .unwrap();
+        let repeated =
+            Histogram::with_opts(HistogramOpts::new("test_repeated", "test help").buckets(buckets))
+                .unwrap();
+
+        assert!(weighted.observe_weighted(1.5, 0).is_err());
+
+        weighted.observe_weighted(1.5, 3).unwrap();
+        weighted.observe_weighted(5.0, 1).unwrap();
+        for _ in 0..3 {
+            repeated.observe(1.5);
+        }
+        repeated.observe(5.0);
+
+        let weighted = weighted.metric();
+        let weighted = weighted.get_histogram();
+        let repeated = repeated.metric();
+        let repeated = repeated.get_histogram();
+        assert_eq!(weighted.get_sample_count(), 4);
+        assert_eq!(weighted.get_sample_count(), repeated.get_sample_count());
+        assert!((weighted.get_sample_sum() - repeated.get_sample_sum()).abs() < f64::EPSILON);
+        assert_eq!(weighted.get_bucket(), repeated.get_bucket());
+    }
+
+    #[test]
+    fn test_histogram_overflow_count() {
+        let opts = HistogramOpts::new("test_histogram", "test help").buckets(vec![1.0, 2.0]);
+        let histogram = Histogram::with_opts(opts).unwrap();
+        for v in &[0.5, 2.0, 2.5, 10.0, f64::INFINITY] {
+            histogram.observe(*v);
+        }
+        histogram.observe_weighted(3.0, 2).unwrap();
+        assert_eq!(histogram.overflow_count(), 5);
+
+        let local = histogram.local();
+        local.observe(1.5);
+        local.observe(4.0);
+        assert_eq!(histogram.overflow_count(), 6);
+
+        let ih =
+            IntHistogram::with_opts(HistogramOpts::new("test_int", "test help").buckets(vec![1.0]))
+                .unwrap();
+        ih.observe(2);
+        assert_eq!(ih.overflow_count(), 1);
+    }
+
+    #[test]
+    fn test_histogram_invalid_observations() {
+        let invalid = [f64::NAN, f64::NEG_INFINITY];
+        for policy in &[
+            InvalidObservationPolicy::Ignore,
+            InvalidObservationPolicy::Count,
+        ] {
+            let opts = HistogramOpts::new("test_invalid", "test help")
+                .buckets(vec![1.0])
+                .on_invalid(*policy);
+            let histogram = Histogram::with_opts(opts).unwrap();
+            let local = histogram.local();
+
+            histogram.observe(0.5);
+            for v in &invalid {
+                histogram.observe(*v);
+                local.observe(*v);
+            }
+            local.flush();
+
+            let m = histogram.metric();
+            let proto_histogram = m.get_histogram();
+            assert_eq!(proto_histogram.get_sample_count(), 1);
+            assert!((proto_histogram.get_sample_sum() - 0.5).abs() < f64::EPSILON);
+            assert_eq!(proto_histogram.get_bucket()[0].get_cumulative_count(), 1);
+
+            let expected_invalid_count = match policy {
+                InvalidObservationPolicy::Count => 4,
+                _ => 0,
+            };
+            assert_eq!(histogram.get_invalid_count(), expected_invalid_count);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "invalid observation NaN")]
+    fn test_histogram_invalid_observation_nan_debug_panic() {
+        let opts = HistogramOpts::new("test_invalid", "test help")
+            .on_invalid(InvalidObservationPolicy::DebugPanic);
+        let histogram = Histogram::with_opts(opts).unwrap();
+        histogram.observe(f64::NAN);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "invalid observation -inf")]
+    fn test_histogram_invalid_observation_neg_inf_debug_panic() {
+        let opts = HistogramOpts::new("test_invalid", "test help")
+            .on_invalid(InvalidObservationPolicy::DebugPanic);
+        let histogram = Histogram::with_opts(opts).unwrap();
+        histogram.observe(f64::NEG_INFINITY);
+    }
+
     #[test]
     fn test_histogram_local() {
         let buckets = vec![1.0, 2.0, 3.0];
@@ -1416,11 +3393,11 @@ mod tests {
         let histogram = Histogram::with_opts(opts).unwrap();
         let local = histogram.local();
 
-        let check = |count, sum| {
+        let check = |count, sum: f64| {
             let m = histogram.metric();
             let proto_histogram = m.get_histogram();
             assert_eq!(proto_histogram.get_sample_count(), count);
-            assert!((proto_histogram.get_sample_sum() - sum) < EPSILON);
+            assert!((proto_histogram.get_sample_sum() - sum).abs() < f64::EPSILON);
         };
 
         local.observe(1.0);
@@ -1439,6 +3416,163 @@ mod tests {
         check(3, 7.0);
     }
 
+    #[test]
+    fn test_histogram_register() {
+        let r = Registry::new();
+        let histogram =
+            Histogram::register(HistogramOpts::new("test_histogram", "test help"), &r).unwrap();
+        histogram.observe(1.0);
+        let int_histogram =
+            IntHistogram::register(HistogramOpts::new("test_int_histogram", "test help"), &r)
+                .unwrap();
+        int_histogram.observe(2);
+
+        let mfs = r.gather();
+        assert_eq!(mfs.len(), 2);
+        assert_eq!(mfs[0].get_metric()[0].get_histogram().get_sample_sum(), 1.0);
+        assert_eq!(mfs[1].get_metric()[0].get_histogram().get_sample_sum(), 2.0);
+    }
+
+    #[test]
+    fn test_histogram_observe_iter() {
+        let opts =
+            HistogramOpts::new("test_observe_iter", "test help").buckets(vec![1.0, 2.0, 3.0]);
+        let histogram = Histogram::with_opts(opts.clone()).unwrap();
+        let expected = Histogram::with_opts(opts).unwrap();
+
+        let values: Vec<f64> = (0..100).map(|i| f64::from(i) * 0.05).collect();
+        histogram.observe_iter(values.iter().copied());
+        for v in &values {
+            expected.observe(*v);
+        }
+
+        assert_eq!(histogram.get_sample_count(), expected.get_sample_count());
+        assert!((histogram.get_sample_sum() - expected.get_sample_sum()).abs() < 1e-9);
+        assert_eq!(histogram.cumulative_counts(), expected.cumulative_counts());
+
+        histogram.observe_iter(Vec::new());
+        assert_eq!(histogram.get_sample_count(), 100);
+    }
+
+    #[test]
+    fn test_local_histogram_pool() {
+        let opts = HistogramOpts::new("test_histogram_pool", "test histogram pool help")
+            .buckets(vec![1.0, 2.0, 3.0]);
+        let histogram = Histogram::with_opts(opts).unwrap();
+        let pool = Arc::new(LocalHistogramPool::new(
+            histogram.clone(),
+            Duration::from_secs(3600),
+        ));
+
+        let threads = 4;
+        let n = 1000;
+        let handlers: Vec<_> = (0..threads)
+            .map(|_| {
+                let pool = Arc::clone(&pool);
+                thread::spawn(move || {
+                    let local = pool.local();
+                    for _ in 0..n {
+                        local.observe(2.0);
+                    }
+                    // Handles of the same thread share the local histogram.
+                    pool.local().observe(2.0);
+                })
+            })
+            .collect();
+        for h in handlers {
+            h.join().unwrap();
+        }
+
+        assert_eq!(histogram.get_sample_count(), 0);
+        pool.flush_all();
+        assert_eq!(histogram.get_sample_count(), threads * (n + 1));
+        assert_eq!(histogram.get_sample_sum(), 2.0 * (threads * (n + 1)) as f64);
+        assert_eq!(histogram.cumulative_counts()[1], (2.0, threads * (n + 1)));
+
+        let local = pool.local();
+        local.observe(1.0);
+        local.flush();
+        assert_eq!(pool.histogram().get_sample_count(), threads * (n + 1) + 1);
+    }
+
+    #[test]
+    fn test_local_histogram_pool_prune_and_flush_concurrently() {
+        let opts = HistogramOpts::new("test_histogram_pool", "test histogram pool help")
+            .buckets(vec![1.0, 2.0]);
+        let histogram = Histogram::with_opts(opts).unwrap();
+        let pool = Arc::new(LocalHistogramPool::new(
+            histogram.clone(),
+            Duration::from_secs(3600),
+        ));
+
+        let threads = 4;
+        let n = 10_000;
+        let handlers: Vec<_> = (0..threads)
+            .map(|_| {
+                let pool = Arc::clone(&pool);
+                thread::spawn(move || {
+                    let local = pool.local();
+                    for _ in 0..n {
+                        local.observe(1.5);
+                    }
+                })
+            })
+            .collect();
+
+        // Flushing while observing never moves more bucket counts than
+        // observations.
+        for _ in 0..1000 {
+            pool.flush_all();
+            let count = histogram.get_sample_count();
+            assert!(histogram.cumulative_counts()[1].1 <= count);
+        }
+        for h in handlers {
+            h.join().unwrap();
+        }
+
+        // The shards of the exited threads are removed once flushed.
+        pool.flush_all();
+        assert!(pool.shards.lock().unwrap().is_empty());
+        assert_eq!(histogram.get_sample_count(), threads * n);
+        assert_eq!(histogram.cumulative_counts()[1], (2.0, threads * n));
+
+        // A thread still holding a handle keeps its shard.
+        let local = pool.local();
+        local.observe(0.5);
+        pool.flush_all();
+        assert_eq!(pool.shards.lock().unwrap().len(), 1);
+        assert_eq!(histogram.get_sample_count(), threads * n + 1);
+    }
+
+    #[test]
+    fn test_histogram_local_flush_sum_count_only() {
+        let opts =
+            HistogramOpts::new("test_histogram_partial", "test help").buckets(vec![1.0, 2.0, 3.0]);
+        let histogram = Histogram::with_opts(opts).unwrap();
+        let local = histogram.local();
+
+        local.observe(1.0);
+        local.observe(4.0);
+        local.flush_sum_count_only();
+        assert_eq!(local.get_sample_count(), 0);
+        assert_eq!(histogram.get_sample_count(), 2);
+        assert_eq!(histogram.get_sample_sum(), 5.0);
+        assert_eq!(histogram.cumulative_counts()[0], (1.0, 0));
+
+        // The bucket counts are flushed later, without counting twice.
+        local.observe(2.0);
+        local.flush_sum_count_only();
+        local.flush();
+        assert_eq!(
+            histogram.cumulative_counts(),
+            vec![(1.0, 1), (2.0, 2), (3.0, 2), (f64::INFINITY, 3)]
+        );
+        assert_eq!(histogram.get_sample_sum(), 7.0);
+
+        local.flush();
+        assert_eq!(histogram.get_sample_count(), 3);
+    }
+
     #[test]
     fn test_histogram_vec_local() {
         let vec = HistogramVec::new(
@@ -1451,11 +3585,11 @@ mod tests {
         vec.remove_label_values(&["v1", "v2"]).unwrap_err();
         local_vec.remove_label_values(&["v1", "v2"]).unwrap_err();
 
-        let check = |count, sum| {
+        let check = |count, sum: f64| {
             let ms = vec.collect()[0].take_metric();
             let proto_histogram = ms[0].get_histogram();
             assert_eq!(proto_histogram.get_sample_count(), count);
-            assert!((proto_histogram.get_sample_sum() - sum) < EPSILON);
+            assert!((proto_histogram.get_sample_sum() - sum).abs() < f64::EPSILON);
         };
 
         {
@@ -0,0 +1,203 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::convert::Infallible;
+use std::net::{SocketAddr, TcpListener};
+
+use hyper::header::{ACCEPT, ALLOW, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::server::conn::AddrIncoming;
+use hyper::server::Builder;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+
+use crate::encoder::negotiate;
+use crate::errors::{Error, Result};
+use crate::registry::Registry;
+
+/// The path the metrics are served at by [`serve_metrics`].
+pub const METRICS_PATH: &str = "/metrics";
+
+/// `serve_metrics` serves the metrics gathered from `registry` at `addr`
+/// until the server fails, e.g. for Prometheus to scrape them.
+///
+/// `GET` and `HEAD` requests to [`METRICS_PATH`] are answered in the format
+/// negotiated from their `Accept` header, see [`negotiate`](crate::negotiate),
+/// other methods with `405 Method Not Allowed` and other paths with
+/// `404 Not Found`.
+///
+/// The returned future must be run on a Tokio runtime.
+///
+/// # Examples
+///
+/// ```no_run
+/// use prometheus::{serve_metrics, Registry};
+///
+/// # async fn run() -> prometheus::Result<()> {
+/// let registry = Registry::new();
+/// serve_metrics(([127, 0, 0, 1], 9898).into(), registry).await
+/// # }
+/// ```
+pub async fn serve_metrics(addr: SocketAddr, registry: Registry) -> Result<()> {
+    let builder = Server::try_bind(&addr)
+        .map_err(|e| Error::Msg(format!("failed to bind {}: {}", addr, e)))?;
+    serve(builder, registry).await
+}
+
+/// `serve_metrics_on` is like [`serve_metrics`], but serves the metrics on an
+/// already bound `listener`, e.g. one bound to port 0 whose address is
+/// needed before serving.
+///
+/// The returned future must be run on a Tokio runtime.
+pub async fn serve_metrics_on(listener: TcpListener, registry: Registry) -> Result<()> {
+    let builder = Server::from_tcp(listener)
+        .map_err(|e| Error::Msg(format!("failed to use listener: {}", e)))?;
+    serve(builder, registry).await
+}
+
+async fn serve(builder: Builder<AddrIncoming>, registry: Registry) -> Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let registry = registry.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let registry = registry.clone();
+                async move { Ok::<_, Infallible>(handle(registry, req).await) }
+            }))
+        }
+    });
+
+    builder
+        .serve(make_service)
+        .await
+        .map_err(|e| Error::Msg(format!("failed to serve metrics: {}", e)))
+}
+
+async fn handle(registry: Registry, req: Request<Body>) -> Response<Body> {
+    if req.uri().path() != METRICS_PATH {
+        return status_response(StatusCode::NOT_FOUND);
+    }
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        let mut response = status_response(StatusCode::METHOD_NOT_ALLOWED);
+        response
+            .headers_mut()
+            .insert(ALLOW, "GET, HEAD".parse().unwrap());
+        return response;
+    }
+
+    let accept = req
+        .headers()
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    // Collectors may block, e.g. on reading `/proc`, so gather off the
+    // runtime's worker threads.
+    let encoded = tokio::task::spawn_blocking(move || registry.gather_encoded(negotiate(&accept)))
+        .await
+        .unwrap_or_else(|e| Err(Error::Msg(format!("failed to gather metrics: {}", e))));
+    let (buffer, content_type) = match encoded {
+        Ok(encoded) => encoded,
+        Err(e) => {
+            let mut response = Response::new(Body::from(e.to_string()));
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            return response;
+        }
+    };
+
+    let builder = Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .header(CONTENT_LENGTH, buffer.len());
+    let body = if req.method() == Method::HEAD {
+        Body::empty()
+    } else {
+        Body::from(buffer)
+    };
+    builder.body(body).unwrap()
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    use super::*;
+    use crate::counter::Counter;
+    use crate::encoder::{OPENMETRICS_FORMAT, TEXT_FORMAT};
+
+    fn request(addr: SocketAddr, request: &str) -> String {
+        // The listener is already bound, so the connection is queued even if
+        // the server does not accept yet.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_serve_metrics() {
+        let registry = Registry::new();
+        let counter = Counter::new("test_counter", "test help").unwrap();
+        counter.inc();
+        registry.register(Box::new(counter)).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.spawn(serve_metrics_on(listener, registry));
+
+        let response = request(
+            addr,
+            "GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains(TEXT_FORMAT), "{}", response);
+        assert!(response.ends_with("test_counter 1\n"), "{}", response);
+
+        let response = request(
+            addr,
+            "GET /metrics HTTP/1.1\r\nHost: localhost\r\nAccept: application/openmetrics-text\r\n\
+             Connection: close\r\n\r\n",
+        );
+        assert!(response.contains(OPENMETRICS_FORMAT), "{}", response);
+        assert!(response.ends_with("# EOF\n"), "{}", response);
+
+        let response = request(
+            addr,
+            "HEAD /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains(TEXT_FORMAT), "{}", response);
+        assert!(response.ends_with("\r\n\r\n"), "{}", response);
+
+        let response = request(
+            addr,
+            "POST /metrics HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\
+             Connection: close\r\n\r\n",
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"),
+            "{}",
+            response
+        );
+        assert!(
+            response.to_lowercase().contains("allow: get, head"),
+            "{}",
+            response
+        );
+
+        let response = request(
+            addr,
+            "GET /other HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{}",
+            response
+        );
+    }
+}
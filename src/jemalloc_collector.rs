@@ -0,0 +1,104 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Monitor the jemalloc allocator.
+//!
+//! This module is only available with the `jemalloc` feature, and reads the
+//! allocator statistics with
+//! [tikv-jemalloc-ctl](https://docs.rs/tikv-jemalloc-ctl). The statistics are
+//! only meaningful if jemalloc is the global allocator of the process, e.g. via
+//! [tikv-jemallocator](https://docs.rs/tikv-jemallocator).
+
+use tikv_jemalloc_ctl::{epoch, stats};
+
+use crate::desc::Desc;
+use crate::gauge::Gauge;
+use crate::metrics::{Collector, Opts};
+use crate::proto;
+
+/// Reads one statistic of jemalloc.
+type StatReader = fn() -> tikv_jemalloc_ctl::Result<usize>;
+
+/// A collector which exports the memory statistics of jemalloc, see the
+/// [jemalloc manual](http://jemalloc.net/jemalloc.3.html) for their meaning.
+///
+/// The statistics are refreshed on each collection. Statistics that can not be
+/// read are left out of the collected metric families.
+#[derive(Debug)]
+pub struct JemallocCollector {
+    descs: Vec<Desc>,
+    stats: Vec<(StatReader, Gauge)>,
+}
+
+impl JemallocCollector {
+    /// Create a `JemallocCollector` with the given namespace.
+    pub fn new<S: Into<String>>(namespace: S) -> JemallocCollector {
+        let namespace = namespace.into();
+        let table: [(StatReader, &str, &str); 6] = [
+            (
+                stats::allocated::read,
+                "jemalloc_allocated_bytes",
+                "Total number of bytes allocated by the application.",
+            ),
+            (
+                stats::active::read,
+                "jemalloc_active_bytes",
+                "Total number of bytes in active pages allocated by the application.",
+            ),
+            (
+                stats::metadata::read,
+                "jemalloc_metadata_bytes",
+                "Total number of bytes dedicated to jemalloc metadata.",
+            ),
+            (
+                stats::resident::read,
+                "jemalloc_resident_bytes",
+                "Total number of bytes in physically resident data pages mapped \
+                 by the allocator.",
+            ),
+            (
+                stats::mapped::read,
+                "jemalloc_mapped_bytes",
+                "Total number of bytes in active extents mapped by the allocator.",
+            ),
+            (
+                stats::retained::read,
+                "jemalloc_retained_bytes",
+                "Total number of bytes in virtual memory mappings that were \
+                 retained rather than being returned to the operating system.",
+            ),
+        ];
+
+        let mut descs = Vec::new();
+        let mut stats = Vec::new();
+        for (read, name, help) in table.iter() {
+            let gauge =
+                Gauge::with_opts(Opts::new(*name, *help).namespace(namespace.clone())).unwrap();
+            descs.extend(gauge.desc().into_iter().cloned());
+            stats.push((*read, gauge));
+        }
+
+        JemallocCollector { descs, stats }
+    }
+}
+
+impl Collector for JemallocCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        // Statistics are cached by jemalloc, advance the epoch to refresh them.
+        if epoch::advance().is_err() {
+            return Vec::new();
+        }
+
+        let mut mfs = Vec::with_capacity(self.stats.len());
+        for (read, gauge) in &self.stats {
+            if let Ok(value) = read() {
+                gauge.set(value as f64);
+                mfs.extend(gauge.collect());
+            }
+        }
+        mfs
+    }
+}
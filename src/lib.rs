@@ -104,13 +104,18 @@ additional functionality.
 
 # Features
 
-This library supports four features:
+This library supports nine features:
 
+* `command`: For re-exposing the metrics printed by an external command.
 * `gen`: To generate protobuf client with the latest protobuf version instead of
   using the pre-generated client.
+* `http`: For serving the metrics over HTTP.
+* `jemalloc`: For collecting jemalloc allocator stats.
 * `nightly`: Enable nightly only features.
+* `opentelemetry`: Enable converting metrics into OpenTelemetry data points.
 * `process`: For collecting process info.
 * `push`: Enable push support.
+* `test-util`: Enable helpers for asserting on metrics in tests.
 
 */
 
@@ -157,10 +162,18 @@ mod encoder;
 mod errors;
 mod gauge;
 mod histogram;
+#[cfg(feature = "http")]
+mod http;
+mod lint;
 mod metrics;
+mod mirror;
 #[cfg(feature = "push")]
 mod push;
+mod quantile;
 mod registry;
+mod slo;
+#[cfg(feature = "test-util")]
+mod test_util;
 mod value;
 mod vec;
 
@@ -168,6 +181,19 @@ mod vec;
 #[doc(hidden)]
 pub mod timer;
 
+// Public for the `register_local_*` macros.
+#[doc(hidden)]
+pub use lazy_static::lazy_static as __lazy_static;
+
+#[cfg(feature = "command")]
+pub mod command_collector;
+
+#[cfg(all(feature = "jemalloc", not(target_env = "msvc")))]
+pub mod jemalloc_collector;
+
+#[cfg(feature = "opentelemetry")]
+pub mod otel;
+
 #[cfg(all(feature = "process", target_os = "linux"))]
 pub mod process_collector;
 
@@ -178,9 +204,13 @@ pub mod local {
 
     */
     pub use super::counter::{
-        CounterWithValueType, LocalCounter, LocalCounterVec, LocalIntCounter, LocalIntCounterVec,
+        CounterWithValueType, FlushableLocalCounter, FlushableLocalIntCounter, LocalCounter,
+        LocalCounterVec, LocalIntCounter, LocalIntCounterVec,
+    };
+    pub use super::histogram::{
+        LocalHistogram, LocalHistogramPool, LocalHistogramTimer, LocalHistogramVec,
+        PooledLocalHistogram,
     };
-    pub use super::histogram::{LocalHistogram, LocalHistogramTimer, LocalHistogramVec};
     pub use super::metrics::{LocalMetric, MayFlush};
 
     pub use super::auto_flush::{
@@ -195,33 +225,59 @@ pub mod core {
 
     */
 
-    pub use super::atomic64::*;
+    pub use super::atomic64::{Atomic, AtomicF64, AtomicI64, AtomicU64, Number};
     pub use super::counter::{
-        GenericCounter, GenericCounterVec, GenericLocalCounter, GenericLocalCounterVec,
+        GenericCounter, GenericCounterVec, GenericFlushableLocalCounter, GenericLocalCounter,
+        GenericLocalCounterVec,
     };
-    pub use super::desc::{Desc, Describer};
+    pub use super::desc::{Desc, DescBuilder, Describer};
     pub use super::gauge::{GenericGauge, GenericGaugeVec};
-    pub use super::metrics::{Collector, Metric, Opts};
-    pub use super::vec::{MetricVec, MetricVecBuilder};
+    pub use super::histogram::GenericHistogram;
+    pub use super::metrics::{
+        Collector, InternedLabelPair, LabelValue, Labels, LabelsIter, Metric, MetricSnapshotLine,
+        MetricValue, MetricVisitor, Opts,
+    };
+    pub use super::vec::{LabelSet, MetricVec, MetricVecBuilder};
 }
 
 pub use self::counter::{Counter, CounterVec, IntCounter, IntCounterVec};
-pub use self::encoder::Encoder;
 #[cfg(feature = "protobuf")]
 pub use self::encoder::ProtobufEncoder;
-pub use self::encoder::TextEncoder;
 #[cfg(feature = "protobuf")]
-pub use self::encoder::{PROTOBUF_FORMAT, TEXT_FORMAT};
+pub use self::encoder::PROTOBUF_FORMAT;
+pub use self::encoder::{negotiate, Encoder, Format};
+pub use self::encoder::{
+    AliasEncoder, ChangeTrackingEncoder, ConfiguredTextEncoder, OpenMetricsEncoder, TextEncoder,
+};
+pub use self::encoder::{OPENMETRICS_FORMAT, TEXT_FORMAT};
 pub use self::errors::{Error, Result};
-pub use self::gauge::{Gauge, GaugeVec, IntGauge, IntGaugeVec};
-pub use self::histogram::DEFAULT_BUCKETS;
-pub use self::histogram::{exponential_buckets, linear_buckets};
-pub use self::histogram::{Histogram, HistogramOpts, HistogramTimer, HistogramVec};
-pub use self::metrics::Opts;
+pub use self::gauge::{
+    build_info_collector, start_time_collector, EwmaRate, Gauge, GaugeFunc, GaugeVec, Info,
+    IntGauge, IntGaugeVec, PeakGauge, RateGauge,
+};
+pub use self::histogram::{exponential_buckets, linear_buckets, size_buckets, slo_buckets};
+pub use self::histogram::{
+    BoundedHistogramVec, BucketSchema, Histogram, HistogramOpts, HistogramTimer, HistogramVec,
+    IntHistogram, InvalidObservationPolicy, MultiHistogramTimer,
+};
+pub use self::histogram::{DEFAULT_BUCKETS, EXEMPLAR_MAX_LABEL_LENGTH};
+#[cfg(feature = "http")]
+pub use self::http::{serve_metrics, serve_metrics_on, METRICS_PATH};
+pub use self::lint::LintWarning;
+pub use self::metrics::{const_labels_from_env, Opts};
+pub use self::mirror::MirrorCollector;
 #[cfg(feature = "push")]
 pub use self::push::{
-    hostname_grouping_key, push_add_collector, push_add_metrics, push_collector, push_metrics,
-    BasicAuthentication,
+    hostname, hostname_grouping_key, push_add_collector, push_add_metrics, push_collector,
+    push_metrics, BasicAuthentication,
+};
+pub use self::quantile::QuantileEstimator;
+pub use self::registry::{
+    default_registry, gather, gather_encoded, merge_gathered, register,
+    register_default_collectors, set_default_registry, unregister,
 };
-pub use self::registry::Registry;
-pub use self::registry::{default_registry, gather, register, unregister};
+pub use self::registry::{MetricSnapshot, Registry, ScrapeMeta};
+pub use self::slo::{instrument, SloRecorder};
+#[cfg(feature = "test-util")]
+pub use self::test_util::{gather_as_map, reset_default_registry, SampleKey};
+pub use self::vec::STALE_MARKER_BITS;
@@ -0,0 +1,140 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::fmt;
+
+use crate::proto::MetricType;
+
+const TOTAL_SUFFIX: &str = "_total";
+
+/// Unit suffixes which should be replaced by their base unit, as Prometheus
+/// metrics are expected to use base units only.
+const NON_BASE_UNITS: &[(&str, &str)] = &[
+    ("nanoseconds", "seconds"),
+    ("microseconds", "seconds"),
+    ("milliseconds", "seconds"),
+    ("ns", "seconds"),
+    ("us", "seconds"),
+    ("ms", "seconds"),
+    ("minutes", "seconds"),
+    ("hours", "seconds"),
+    ("days", "seconds"),
+    ("kilobytes", "bytes"),
+    ("megabytes", "bytes"),
+    ("gigabytes", "bytes"),
+    ("kb", "bytes"),
+    ("mb", "bytes"),
+    ("gb", "bytes"),
+    ("percent", "ratio"),
+];
+
+/// A problem with the naming or documentation of a metric family, returned by
+/// [`Registry::lint`](crate::Registry::lint).
+#[derive(Clone, Debug, PartialEq)]
+pub enum LintWarning {
+    /// The help string is empty or only whitespace.
+    MissingHelp {
+        /// The name of the metric family.
+        name: String,
+    },
+    /// A counter does not end in `_total`, which the OpenMetrics format
+    /// appends, so its name differs between formats.
+    CounterWithoutTotal {
+        /// The name of the metric family.
+        name: String,
+    },
+    /// A metric which is not a counter ends in `_total`.
+    TotalWithoutCounter {
+        /// The name of the metric family.
+        name: String,
+        /// The type of the metric family.
+        metric_type: MetricType,
+    },
+    /// The name ends in a unit which is not a base unit.
+    NonBaseUnit {
+        /// The name of the metric family.
+        name: String,
+        /// The unit the name ends in.
+        unit: String,
+        /// The base unit to use instead.
+        base_unit: &'static str,
+    },
+}
+
+impl LintWarning {
+    /// The name of the metric family the warning is about.
+    pub fn name(&self) -> &str {
+        match self {
+            LintWarning::MissingHelp { name }
+            | LintWarning::CounterWithoutTotal { name }
+            | LintWarning::TotalWithoutCounter { name, .. }
+            | LintWarning::NonBaseUnit { name, .. } => name,
+        }
+    }
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintWarning::MissingHelp { name } => write!(f, "{}: missing help string", name),
+            LintWarning::CounterWithoutTotal { name } => {
+                write!(f, "{}: counter name should end in {}", name, TOTAL_SUFFIX)
+            }
+            LintWarning::TotalWithoutCounter { name, metric_type } => write!(
+                f,
+                "{}: {:?} name should not end in {}",
+                name, metric_type, TOTAL_SUFFIX
+            ),
+            LintWarning::NonBaseUnit {
+                name,
+                unit,
+                base_unit,
+            } => write!(
+                f,
+                "{}: unit {} should be the base unit {}",
+                name, unit, base_unit
+            ),
+        }
+    }
+}
+
+/// `lint_descs` returns the warnings for the given metric names, help strings
+/// and types if known, in their order.
+pub(crate) fn lint_descs<'a, I>(descs: I) -> Vec<LintWarning>
+where
+    I: IntoIterator<Item = (&'a str, &'a str, Option<MetricType>)>,
+{
+    let mut warnings = Vec::new();
+    for (name, help, metric_type) in descs {
+        if help.trim().is_empty() {
+            warnings.push(LintWarning::MissingHelp {
+                name: name.to_owned(),
+            });
+        }
+
+        let has_total = name.ends_with(TOTAL_SUFFIX);
+        match (metric_type, has_total) {
+            (Some(MetricType::COUNTER), false) => warnings.push(LintWarning::CounterWithoutTotal {
+                name: name.to_owned(),
+            }),
+            (Some(MetricType::COUNTER), true)
+            | (Some(MetricType::UNTYPED), _)
+            | (None, _)
+            | (_, false) => {}
+            (Some(metric_type), true) => warnings.push(LintWarning::TotalWithoutCounter {
+                name: name.to_owned(),
+                metric_type,
+            }),
+        }
+
+        let base = name.strip_suffix(TOTAL_SUFFIX).unwrap_or(name);
+        let unit = base.rfind('_').map(|i| &base[i + 1..]);
+        if let Some((unit, base_unit)) = NON_BASE_UNITS.iter().find(|(u, _)| Some(*u) == unit) {
+            warnings.push(LintWarning::NonBaseUnit {
+                name: name.to_owned(),
+                unit: (*unit).to_owned(),
+                base_unit,
+            });
+        }
+    }
+    warnings
+}
@@ -36,6 +36,49 @@ macro_rules! labels {
     };
 }
 
+/// Create owned labels with specified name-value pairs.
+///
+/// Unlike [`labels!`], names and values are converted into `String`s, so the
+/// result can be passed straight to [`Opts::const_labels`](crate::Opts::const_labels)
+/// or [`HistogramOpts::const_labels`](crate::HistogramOpts::const_labels).
+///
+/// # Examples
+///
+/// ```
+/// # use std::collections::HashMap;
+/// # use prometheus::{owned_labels, Opts};
+/// # fn main() {
+/// let region = String::from("us-east");
+/// let labels = owned_labels!{
+///     "test" => "hello",
+///     "region" => region,
+/// };
+/// assert_eq!(labels.len(), 2);
+/// assert_eq!(labels.get("region").unwrap(), "us-east");
+///
+/// let opts = Opts::new("test_opts", "test opts help").const_labels(labels);
+/// assert_eq!(opts.const_labels.get("test").unwrap(), "hello");
+///
+/// let labels: HashMap<String, String> = owned_labels!{};
+/// assert!(labels.is_empty());
+/// # }
+/// ```
+#[macro_export]
+macro_rules! owned_labels {
+    ( $( $ KEY : expr => $ VALUE : expr ),* $(,)? ) => {
+        {
+            use std::collections::HashMap;
+
+            let mut lbs = HashMap::<String, String>::new();
+            $(
+                lbs.insert($KEY.into(), $VALUE.into());
+            )*
+
+            lbs
+        }
+    };
+}
+
 /// Create an [`Opts`].
 ///
 /// # Examples
@@ -175,6 +218,115 @@ macro_rules! register_int_counter {
     }};
 }
 
+#[macro_export]
+#[doc(hidden)]
+macro_rules! __register_local {
+    ($TYPE:ident, $REGISTER:ident, $OPTS:expr) => {{
+        $crate::__lazy_static! {
+            static ref SHARED: ::std::sync::Mutex<::std::vec::Vec<$crate::$TYPE>> =
+                ::std::sync::Mutex::new(::std::vec::Vec::new());
+        }
+        let opts = $OPTS;
+        let desc = $crate::core::Describer::describe(&opts).ok();
+        let mut shared = SHARED
+            .lock()
+            .unwrap_or_else(::std::sync::PoisonError::into_inner);
+        // Only reuse a metric registered for the same opts.
+        let cached = desc.and_then(|desc| {
+            shared
+                .iter()
+                .find(|metric| {
+                    let cached = $crate::core::Collector::desc(*metric)[0];
+                    cached.id == desc.id && cached.dim_hash == desc.dim_hash
+                })
+                .cloned()
+        });
+        match cached {
+            ::std::option::Option::Some(metric) => ::std::result::Result::Ok(metric.local()),
+            ::std::option::Option::None => $crate::$REGISTER!(opts).map(|metric| {
+                let local = metric.local();
+                shared.push(metric);
+                local
+            }),
+        }
+    }};
+}
+
+/// Create a [`Counter`], registers it to default registry and returns its
+/// [`LocalCounter`].
+///
+/// The shared [`Counter`] is only registered by the first successful call of
+/// each macro invocation with the same opts, later calls with those opts
+/// return another local of it, e.g. in a `thread_local!` initializer running
+/// once per thread. Calls with other opts register another counter, or fail
+/// if its name is already registered.
+///
+/// # Examples
+///
+/// ```
+/// # use prometheus::local::LocalCounter;
+/// # use prometheus::register_local_counter;
+/// thread_local! {
+///     static LOCAL_COUNTER: LocalCounter =
+///         register_local_counter!("test_macro_local_counter", "help").unwrap();
+/// }
+///
+/// let inc = || LOCAL_COUNTER.with(|c| {
+///     c.inc();
+///     c.flush();
+/// });
+/// inc();
+/// std::thread::spawn(inc).join().unwrap();
+///
+/// let mfs = prometheus::gather();
+/// let mf = mfs
+///     .iter()
+///     .find(|mf| mf.get_name() == "test_macro_local_counter")
+///     .unwrap();
+/// assert_eq!(mf.get_metric()[0].get_counter().get_value(), 2.0);
+///
+/// // Each name registers its own counter.
+/// let local = |name: &str| register_local_counter!(name, "help");
+/// local("test_macro_local_counter_a").unwrap().inc();
+/// local("test_macro_local_counter_b").unwrap().inc();
+/// local("test_macro_local_counter_a").unwrap().inc();
+/// let mfs = prometheus::gather();
+/// let value = |name: &str| {
+///     let mf = mfs.iter().find(|mf| mf.get_name() == name).unwrap();
+///     mf.get_metric()[0].get_counter().get_value()
+/// };
+/// assert_eq!(value("test_macro_local_counter_a"), 2.0);
+/// assert_eq!(value("test_macro_local_counter_b"), 1.0);
+/// ```
+///
+/// View docs of `register_local_histogram` for more details.
+#[macro_export(local_inner_macros)]
+macro_rules! register_local_counter {
+    ($OPTS:expr) => {{
+        __register_local!(Counter, register_counter, $OPTS)
+    }};
+
+    ($NAME:expr, $HELP:expr) => {{
+        register_local_counter!(opts!($NAME, $HELP))
+    }};
+}
+
+/// Create an [`IntCounter`], registers it to default registry and returns its
+/// [`LocalIntCounter`].
+///
+/// View docs of `register_local_counter` and `register_local_histogram` for
+/// details and examples.
+#[macro_export(local_inner_macros)]
+macro_rules! register_local_int_counter {
+    ($OPTS:expr) => {{
+        __register_local!(IntCounter, register_int_counter, $OPTS)
+    }};
+
+    ($NAME:expr, $HELP:expr) => {{
+        register_local_int_counter!(opts!($NAME, $HELP))
+    }};
+}
+
 #[macro_export]
 #[doc(hidden)]
 macro_rules! __register_counter_vec {
@@ -358,6 +510,65 @@ macro_rules! register_histogram {
     }};
 }
 
+/// Create a [`Histogram`], registers it to default registry and returns its
+/// [`LocalHistogram`].
+///
+/// A [`LocalHistogram`] is not `Sync`, it buffers observations of the calling
+/// thread until flushed, while the shared [`Histogram`] stays registered. The
+/// shared [`Histogram`] is only registered by the first successful call of
+/// each macro invocation with the same opts, later calls with those opts
+/// return another local of it, e.g. in a `thread_local!` initializer running
+/// once per thread. Calls with other opts register another histogram, or
+/// fail if its name is already registered.
+///
+/// # Examples
+///
+/// ```
+/// # use prometheus::local::LocalHistogram;
+/// # use prometheus::register_local_histogram;
+/// # fn main() {
+/// thread_local! {
+///     static LOCAL_HISTOGRAM: LocalHistogram =
+///         register_local_histogram!("test_macro_local_histogram", "help").unwrap();
+/// }
+///
+/// let observe = || {
+///     for i in 0..10 {
+///         LOCAL_HISTOGRAM.with(|h| {
+///             h.observe(i as f64);
+///             // Flush periodically to make observations visible to scrapes.
+///             if i % 5 == 4 {
+///                 h.flush();
+///             }
+///         });
+///     }
+/// };
+/// observe();
+/// std::thread::spawn(observe).join().unwrap();
+///
+/// let mfs = prometheus::gather();
+/// let mf = mfs
+///     .iter()
+///     .find(|mf| mf.get_name() == "test_macro_local_histogram")
+///     .unwrap();
+/// assert_eq!(mf.get_metric()[0].get_histogram().get_sample_count(), 20);
+/// # }
+/// ```
+#[macro_export(local_inner_macros)]
+macro_rules! register_local_histogram {
+    ($NAME:expr, $HELP:expr) => {
+        register_local_histogram!(histogram_opts!($NAME, $HELP))
+    };
+
+    ($NAME:expr, $HELP:expr, $BUCKETS:expr) => {
+        register_local_histogram!(histogram_opts!($NAME, $HELP, $BUCKETS))
+    };
+
+    ($HOPTS:expr) => {{
+        __register_local!(Histogram, register_histogram, $HOPTS)
+    }};
+}
+
 /// Create a [`HistogramVec`] and registers to default registry.
 ///
 /// # Examples
@@ -3,10 +3,12 @@
 
 use std::cmp::{Eq, Ord, Ordering, PartialOrd};
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
 
 use crate::desc::{Desc, Describer};
 use crate::errors::Result;
-use crate::proto::{self, LabelPair};
+use crate::proto::{self, LabelPair, MetricType};
 use crate::timer;
 use std::cell::Cell;
 
@@ -19,12 +21,331 @@ pub trait Collector: Sync + Send {
 
     /// Collect metrics.
     fn collect(&self) -> Vec<proto::MetricFamily>;
+
+    /// Visit metrics without building metric families where possible. The
+    /// default implementation visits the result of
+    /// [`collect`](Collector::collect).
+    fn visit(&self, visitor: &mut dyn MetricVisitor) {
+        for mf in self.collect() {
+            visit_metric_family(&mf, visitor);
+        }
+    }
+
+    /// Collect the staleness markers of the series removed since the previous
+    /// call, and clear them. Only
+    /// [`Registry::gather_with_stale`](crate::Registry::gather_with_stale)
+    /// calls it. The default implementation returns no metric families.
+    fn collect_stale(&self) -> Vec<proto::MetricFamily> {
+        vec![]
+    }
+
+    /// Return the type of the metrics of the given descriptor if it is known
+    /// without collecting, for [`Registry::lint`](crate::Registry::lint) to
+    /// check the naming conventions of the type. The default implementation
+    /// returns `None`.
+    fn metric_type(&self, _desc: &Desc) -> Option<MetricType> {
+        None
+    }
+}
+
+impl<C: Collector + ?Sized> Collector for Arc<C> {
+    fn desc(&self) -> Vec<&Desc> {
+        (**self).desc()
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        (**self).collect()
+    }
+
+    fn visit(&self, visitor: &mut dyn MetricVisitor) {
+        (**self).visit(visitor)
+    }
+
+    fn collect_stale(&self) -> Vec<proto::MetricFamily> {
+        (**self).collect_stale()
+    }
+
+    fn metric_type(&self, desc: &Desc) -> Option<MetricType> {
+        (**self).metric_type(desc)
+    }
 }
 
 /// An interface models a single sample value with its meta data being exported to Prometheus.
 pub trait Metric: Sync + Send + Clone {
     /// Return the protocol Metric.
     fn metric(&self) -> proto::Metric;
+
+    /// Visit the metric as a metric of the given type without building the
+    /// protocol Metric where possible. The default implementation visits the
+    /// result of [`metric`](Metric::metric).
+    fn visit(&self, metric_type: MetricType, visitor: &mut dyn MetricVisitor) {
+        let m = self.metric();
+        visitor.visit_metric(
+            Labels::Proto(m.get_label()),
+            MetricValue::of(metric_type, &m),
+        );
+    }
+}
+
+/// The value of a metric visited by a [`MetricVisitor`].
+#[derive(Clone, Copy, Debug)]
+pub enum MetricValue<'a> {
+    /// The value of a counter.
+    Counter(f64),
+    /// The value of a gauge.
+    Gauge(f64),
+    /// The value of an untyped metric.
+    Untyped(f64),
+    /// The state of a histogram.
+    Histogram(&'a proto::Histogram),
+    /// The state of a summary.
+    Summary(&'a proto::Summary),
+}
+
+impl<'a> MetricValue<'a> {
+    /// `of` returns the value of the given type of the protocol Metric.
+    #[allow(deprecated)]
+    pub fn of(metric_type: MetricType, m: &'a proto::Metric) -> Self {
+        match metric_type {
+            MetricType::COUNTER => MetricValue::Counter(m.get_counter().get_value()),
+            MetricType::GAUGE => MetricValue::Gauge(m.get_gauge().get_value()),
+            MetricType::UNTYPED => MetricValue::Untyped(m.get_untyped().get_value()),
+            MetricType::HISTOGRAM => MetricValue::Histogram(m.get_histogram()),
+            MetricType::SUMMARY => MetricValue::Summary(m.get_summary()),
+        }
+    }
+}
+
+/// A readable summary of a single metric with its name, labels and value,
+/// e.g. for logging the current state of a metric, returned by
+/// [`Counter::snapshot`](crate::core::GenericCounter::snapshot) and alike.
+///
+/// It is displayed as `name{label="value"} 42`, histograms as
+/// `name count=3 sum=1.5 buckets=[0.1:1, 1:3]` with cumulative bucket counts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricSnapshotLine {
+    name: String,
+    metric_type: MetricType,
+    metric: proto::Metric,
+}
+
+impl MetricSnapshotLine {
+    pub(crate) fn new(name: &str, metric_type: MetricType, metric: proto::Metric) -> Self {
+        MetricSnapshotLine {
+            name: name.to_owned(),
+            metric_type,
+            metric,
+        }
+    }
+
+    /// Return the name of the metric.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the labels of the metric, including const labels.
+    pub fn labels(&self) -> &[LabelPair] {
+        self.metric.get_label()
+    }
+
+    /// Return the value of the metric.
+    pub fn value(&self) -> MetricValue<'_> {
+        MetricValue::of(self.metric_type, &self.metric)
+    }
+}
+
+impl fmt::Display for MetricSnapshotLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        for (i, l) in self.labels().iter().enumerate() {
+            let sep = if i == 0 { "{" } else { "," };
+            write!(f, "{}{}={:?}", sep, l.get_name(), l.get_value())?;
+        }
+        if !self.labels().is_empty() {
+            f.write_str("}")?;
+        }
+
+        match self.value() {
+            MetricValue::Counter(v) | MetricValue::Gauge(v) | MetricValue::Untyped(v) => {
+                write!(f, " {}", v)
+            }
+            MetricValue::Histogram(h) => {
+                write!(
+                    f,
+                    " count={} sum={} buckets=[",
+                    h.get_sample_count(),
+                    h.get_sample_sum()
+                )?;
+                for (i, b) in h.get_bucket().iter().enumerate() {
+                    let sep = if i == 0 { "" } else { ", " };
+                    write!(
+                        f,
+                        "{}{}:{}",
+                        sep,
+                        b.get_upper_bound(),
+                        b.get_cumulative_count()
+                    )?;
+                }
+                f.write_str("]")
+            }
+            MetricValue::Summary(s) => write!(
+                f,
+                " count={} sum={}",
+                s.get_sample_count(),
+                s.get_sample_sum()
+            ),
+        }
+    }
+}
+
+/// An interface for consuming metrics with borrowed names, labels and values,
+/// see [`Registry::visit`](crate::Registry::visit).
+pub trait MetricVisitor {
+    /// Called once per visited metric family, before its metrics.
+    fn visit_family(&mut self, name: &str, help: &str, metric_type: MetricType);
+
+    /// Called for each metric of the last visited metric family.
+    fn visit_metric(&mut self, labels: Labels<'_>, value: MetricValue<'_>);
+}
+
+/// A label pair of a metric of this crate. Its name is interned, i.e. shared by
+/// all metrics with a label of that name, so the children of a large
+/// [`MetricVec`](crate::core::MetricVec) do not each own a copy of the label
+/// names.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InternedLabelPair {
+    name: Arc<str>,
+    value: String,
+}
+
+impl InternedLabelPair {
+    pub(crate) fn new(name: Arc<str>, value: String) -> InternedLabelPair {
+        InternedLabelPair { name, value }
+    }
+
+    /// Return the label name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the label value.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// `to_proto` returns the label pair as a protocol `LabelPair`.
+    pub(crate) fn to_proto(&self) -> LabelPair {
+        let mut lp = LabelPair::default();
+        lp.set_name(self.name.to_string());
+        lp.set_value(self.value.clone());
+        lp
+    }
+}
+
+/// The labels of a metric lent to a [`MetricVisitor`].
+#[derive(Clone, Copy, Debug)]
+pub enum Labels<'a> {
+    /// The labels of a protocol Metric, e.g. collected by a custom
+    /// [`Collector`].
+    Proto(&'a [LabelPair]),
+    /// The labels of a metric of this crate.
+    Interned(&'a [InternedLabelPair]),
+}
+
+impl<'a> Labels<'a> {
+    /// Return the number of labels.
+    pub fn len(&self) -> usize {
+        match self {
+            Labels::Proto(pairs) => pairs.len(),
+            Labels::Interned(pairs) => pairs.len(),
+        }
+    }
+
+    /// Return whether there are no labels.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return an iterator over the `(name, value)` pairs, sorted by name.
+    pub fn iter(&self) -> LabelsIter<'a> {
+        LabelsIter(*self, 0)
+    }
+}
+
+/// An iterator over the `(name, value)` pairs of [`Labels`].
+#[derive(Clone, Debug)]
+pub struct LabelsIter<'a>(Labels<'a>, usize);
+
+impl<'a> Iterator for LabelsIter<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pair = match self.0 {
+            Labels::Proto(pairs) => pairs.get(self.1).map(|lp| (lp.get_name(), lp.get_value())),
+            Labels::Interned(pairs) => pairs.get(self.1).map(|lp| (lp.name(), lp.value())),
+        };
+        self.1 += 1;
+        pair
+    }
+}
+
+/// `labels_eq` returns whether the protocol label pairs equal the interned
+/// ones.
+pub(crate) fn labels_eq(pairs: &[LabelPair], interned: &[InternedLabelPair]) -> bool {
+    pairs.len() == interned.len()
+        && pairs
+            .iter()
+            .zip(interned)
+            .all(|(lp, ilp)| lp.get_name() == ilp.name() && lp.get_value() == ilp.value())
+}
+
+fn visit_metric_family(mf: &proto::MetricFamily, visitor: &mut dyn MetricVisitor) {
+    if mf.get_metric().is_empty() {
+        return;
+    }
+
+    let metric_type = mf.get_field_type();
+    visitor.visit_family(mf.get_name(), mf.get_help(), metric_type);
+    for m in mf.get_metric() {
+        visitor.visit_metric(
+            Labels::Proto(m.get_label()),
+            MetricValue::of(metric_type, m),
+        );
+    }
+}
+
+/// An interface for enums used as label values, which brings some of the type
+/// safety of `make_static_metric!` to plain metric vectors, see
+/// [`HistogramVec::with_label_enum`](crate::HistogramVec::with_label_enum).
+///
+/// # Examples
+///
+/// ```
+/// use prometheus::core::LabelValue;
+/// use prometheus::{HistogramOpts, HistogramVec};
+///
+/// enum Method {
+///     Get,
+///     Post,
+/// }
+///
+/// impl LabelValue for Method {
+///     fn label_value(&self) -> &'static str {
+///         match self {
+///             Method::Get => "get",
+///             Method::Post => "post",
+///         }
+///     }
+/// }
+///
+/// let vec = HistogramVec::new(HistogramOpts::new("request_seconds", "Latency."), &["method"])
+///     .unwrap();
+/// vec.with_label_enum(Method::Post).observe(0.1);
+/// assert_eq!(vec.with_label_values(&["post"]).get_sample_count(), 1);
+/// ```
+pub trait LabelValue {
+    /// Return the label value of `self`.
+    fn label_value(&self) -> &'static str;
 }
 
 /// An interface models a Metric only usable in single thread environment.
@@ -164,6 +485,12 @@ impl Opts {
     }
 }
 
+impl AsMut<Opts> for Opts {
+    fn as_mut(&mut self) -> &mut Opts {
+        self
+    }
+}
+
 impl Describer for Opts {
     fn describe(&self) -> Result<Desc> {
         Desc::new(
@@ -189,6 +516,32 @@ impl PartialOrd for LabelPair {
     }
 }
 
+/// `const_labels_from_env` reads const labels from environment variables, e.g.
+/// the region or version of a service, for [`Opts::const_labels`]. Each entry
+/// of `mapping` is a label name and the name of the environment variable
+/// holding its value. Labels of unset or non-unicode variables are left out.
+///
+/// # Examples
+///
+/// ```
+/// use prometheus::{const_labels_from_env, Opts};
+///
+/// std::env::set_var("EXAMPLE_REGION", "us-east");
+/// let labels = const_labels_from_env(&[("region", "EXAMPLE_REGION"), ("zone", "EXAMPLE_ZONE")]);
+/// let opts = Opts::new("requests_total", "Total requests.").const_labels(labels);
+/// assert_eq!(opts.const_labels.len(), 1);
+/// assert_eq!(opts.const_labels["region"], "us-east");
+/// ```
+pub fn const_labels_from_env(mapping: &[(&str, &str)]) -> HashMap<String, String> {
+    mapping
+        .iter()
+        .filter_map(|(name, var)| {
+            let value = std::env::var(var).ok()?;
+            Some(((*name).to_owned(), value))
+        })
+        .collect()
+}
+
 /// `build_fq_name` joins the given three name components by "_". Empty name
 /// components are ignored. If the name parameter itself is empty, an empty
 /// string is returned, no matter what. [`Metric`] implementations included in this
@@ -259,8 +612,52 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_const_labels_from_env() {
+        std::env::set_var("PROMETHEUS_TEST_REGION", "us-east");
+        std::env::set_var("PROMETHEUS_TEST_VERSION", "");
+        std::env::remove_var("PROMETHEUS_TEST_MISSING");
+
+        let labels = const_labels_from_env(&[
+            ("region", "PROMETHEUS_TEST_REGION"),
+            ("version", "PROMETHEUS_TEST_VERSION"),
+            ("instance", "PROMETHEUS_TEST_MISSING"),
+        ]);
+        let mut expected = HashMap::new();
+        expected.insert("region".to_owned(), "us-east".to_owned());
+        expected.insert("version".to_owned(), "".to_owned());
+        assert_eq!(labels, expected);
+    }
+
     #[test]
     fn test_different_generic_types() {
         Opts::new(format!("{}_{}", "string", "label"), "&str_label");
     }
+
+    #[test]
+    fn test_metric_snapshot_line() {
+        use crate::{Histogram, HistogramOpts, IntCounter, IntGauge};
+
+        let counter =
+            IntCounter::with_opts(Opts::new("test_counter", "test help").const_label("a", "1"))
+                .unwrap();
+        counter.inc_by(42);
+        let line = counter.snapshot();
+        assert_eq!(line.name(), "test_counter");
+        assert_eq!(line.labels().len(), 1);
+        assert_eq!(line.to_string(), r#"test_counter{a="1"} 42"#);
+
+        let gauge = IntGauge::new("test_gauge", "test help").unwrap();
+        gauge.set(-3);
+        assert_eq!(gauge.snapshot().to_string(), "test_gauge -3");
+
+        let opts = HistogramOpts::new("test_histogram", "test help").buckets(vec![0.1, 1.0]);
+        let histogram = Histogram::with_opts(opts).unwrap();
+        histogram.observe(0.05);
+        histogram.observe(0.5);
+        histogram.observe(5.0);
+        let line = histogram.snapshot().to_string();
+        assert!(line.starts_with("test_histogram count=3 sum=5.55"));
+        assert!(line.ends_with("buckets=[0.1:1, 1:2]"));
+    }
 }
@@ -0,0 +1,143 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+use crate::desc::Desc;
+use crate::errors::{Error, Result};
+use crate::metrics::Collector;
+use crate::proto;
+
+/// A [`Collector`] that wraps the [`Collector`] of a single metric and exposes
+/// it under additional names, with the same help, type and samples, e.g. to
+/// keep the old name of a metric during a migration without maintaining two
+/// metrics.
+///
+/// Unlike [`AliasEncoder`](crate::AliasEncoder), the additional names are
+/// described to the registry, so conflicts with other metrics are detected on
+/// registration.
+///
+/// # Examples
+///
+/// ```
+/// use prometheus::{Counter, MirrorCollector, Registry};
+///
+/// let counter = Counter::new("new_requests_total", "Total requests.").unwrap();
+/// let mirror =
+///     MirrorCollector::new(counter.clone(), vec!["old_requests_total".to_owned()]).unwrap();
+/// let r = Registry::new();
+/// r.register(Box::new(mirror)).unwrap();
+///
+/// counter.inc();
+/// assert_eq!(r.gather().len(), 2);
+/// ```
+#[derive(Debug)]
+pub struct MirrorCollector<C> {
+    inner: C,
+    descs: Vec<Desc>,
+}
+
+impl<C: Collector> MirrorCollector<C> {
+    /// Create a [`MirrorCollector`] exposing the metric of `inner` under its
+    /// own name and every name of `extra_names`.
+    ///
+    /// The function returns an error if `inner` does not describe exactly one
+    /// metric or an extra name is invalid.
+    pub fn new(inner: C, extra_names: Vec<String>) -> Result<MirrorCollector<C>> {
+        let desc = match inner.desc().as_slice() {
+            [desc] => (*desc).clone(),
+            descs => {
+                return Err(Error::Msg(format!(
+                    "MirrorCollector needs a collector of a single metric, got {} descriptors",
+                    descs.len()
+                )))
+            }
+        };
+
+        let mut descs = Vec::with_capacity(extra_names.len());
+        for name in extra_names {
+            let const_labels = desc
+                .const_label_pairs
+                .iter()
+                .map(|l| (l.get_name().to_owned(), l.get_value().to_owned()))
+                .collect();
+            descs.push(Desc::new(
+                name,
+                desc.help.clone(),
+                desc.variable_labels.clone(),
+                const_labels,
+            )?);
+        }
+
+        Ok(MirrorCollector { inner, descs })
+    }
+
+    /// Return the wrapped collector.
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+}
+
+impl<C: Collector> Collector for MirrorCollector<C> {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut descs = self.inner.desc();
+        descs.extend(self.descs.iter());
+        descs
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        let mfs = self.inner.collect();
+        let mut mirrored = Vec::with_capacity(mfs.len() * (self.descs.len() + 1));
+        for mf in mfs {
+            for desc in &self.descs {
+                let mut mirror = mf.clone();
+                mirror.set_name(desc.fq_name.clone());
+                mirrored.push(mirror);
+            }
+            mirrored.push(mf);
+        }
+        mirrored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counter::IntCounterVec;
+    use crate::gauge::PeakGauge;
+    use crate::metrics::Opts;
+    use crate::registry::Registry;
+
+    #[test]
+    fn test_mirror_collector() {
+        assert!(
+            MirrorCollector::new(PeakGauge::new("test_peak", "test help").unwrap(), vec![])
+                .is_err()
+        );
+
+        let counter =
+            IntCounterVec::new(Opts::new("test_new_total", "test help"), &["op"]).unwrap();
+        let names = vec!["test_old_total".to_owned(), "test_older_total".to_owned()];
+        let mirror = MirrorCollector::new(counter.clone(), names).unwrap();
+        assert_eq!(mirror.desc().len(), 3);
+
+        let r = Registry::new();
+        r.register(Box::new(mirror)).unwrap();
+        counter.with_label_values(&["read"]).inc();
+
+        let mfs = r.gather();
+        let names: Vec<_> = mfs.iter().map(|mf| mf.get_name()).collect();
+        assert_eq!(
+            names,
+            vec!["test_new_total", "test_old_total", "test_older_total"]
+        );
+        for mf in &mfs {
+            let m = &mf.get_metric()[0];
+            assert_eq!(m.get_label()[0].get_value(), "read");
+            assert_eq!(m.get_counter().get_value(), 1.0);
+        }
+
+        // The mirrored names are taken.
+        let old = Opts::new("test_old_total", "test help");
+        assert!(r
+            .register(Box::new(IntCounterVec::new(old, &["op"]).unwrap()))
+            .is_err());
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Bridge gathered metrics to [OpenTelemetry](https://opentelemetry.io/).
+//!
+//! This module is only available with the `opentelemetry` feature. It converts
+//! [`MetricFamily`] protos into OpenTelemetry shaped data points, so that a
+//! service can emit both formats while migrating.
+
+use opentelemetry::KeyValue;
+
+use crate::proto::{LabelPair, MetricFamily, MetricType};
+
+/// A converted metric, one per [`MetricFamily`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    /// The name of the metric.
+    pub name: String,
+    /// The help string of the metric.
+    pub description: String,
+    /// The data points of the metric.
+    pub data: Data,
+}
+
+/// The data points of a [`Metric`], by OpenTelemetry aggregation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Data {
+    /// A cumulative, monotonic sum, converted from a counter.
+    Sum(Vec<NumberDataPoint>),
+    /// A gauge, converted from a gauge.
+    Gauge(Vec<NumberDataPoint>),
+    /// A cumulative explicit-bucket histogram, converted from a histogram.
+    Histogram(Vec<HistogramDataPoint>),
+}
+
+/// A single numeric data point.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NumberDataPoint {
+    /// The label pairs of the sample, as attributes.
+    pub attributes: Vec<KeyValue>,
+    /// The value of the sample.
+    pub value: f64,
+}
+
+/// A single explicit-bucket histogram data point.
+///
+/// Unlike Prometheus buckets, `bucket_counts` are not cumulative and contain
+/// one more entry than `bounds`, the last one counting observations above the
+/// highest bound.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistogramDataPoint {
+    /// The label pairs of the sample, as attributes.
+    pub attributes: Vec<KeyValue>,
+    /// The number of observations.
+    pub count: u64,
+    /// The sum of observations.
+    pub sum: f64,
+    /// The upper bounds of the buckets, excluding `+Inf`.
+    pub bounds: Vec<f64>,
+    /// The number of observations per bucket.
+    pub bucket_counts: Vec<u64>,
+}
+
+/// `to_otel` converts the given metric families into OpenTelemetry shaped
+/// metrics. Counters, gauges and histograms are converted, metric families of
+/// any other type are skipped.
+pub fn to_otel(mfs: &[MetricFamily]) -> Vec<Metric> {
+    mfs.iter()
+        .filter_map(|mf| {
+            let data = match mf.get_field_type() {
+                MetricType::COUNTER => Data::Sum(
+                    mf.get_metric()
+                        .iter()
+                        .map(|m| NumberDataPoint {
+                            attributes: to_attributes(m.get_label()),
+                            value: m.get_counter().get_value(),
+                        })
+                        .collect(),
+                ),
+                MetricType::GAUGE => Data::Gauge(
+                    mf.get_metric()
+                        .iter()
+                        .map(|m| NumberDataPoint {
+                            attributes: to_attributes(m.get_label()),
+                            value: m.get_gauge().get_value(),
+                        })
+                        .collect(),
+                ),
+                MetricType::HISTOGRAM => Data::Histogram(
+                    mf.get_metric()
+                        .iter()
+                        .map(|m| {
+                            let h = m.get_histogram();
+                            let mut bounds = Vec::with_capacity(h.get_bucket().len());
+                            let mut bucket_counts = Vec::with_capacity(h.get_bucket().len() + 1);
+                            let mut cumulative_count = 0;
+                            for b in h.get_bucket() {
+                                let upper_bound = b.get_upper_bound();
+                                if upper_bound.is_sign_positive() && upper_bound.is_infinite() {
+                                    // The +Inf bucket is implicit in OpenTelemetry.
+                                    continue;
+                                }
+                                bounds.push(upper_bound);
+                                // Inconsistent counts of a custom collector
+                                // saturate rather than underflow.
+                                bucket_counts.push(
+                                    b.get_cumulative_count().saturating_sub(cumulative_count),
+                                );
+                                cumulative_count = b.get_cumulative_count();
+                            }
+                            bucket_counts
+                                .push(h.get_sample_count().saturating_sub(cumulative_count));
+
+                            HistogramDataPoint {
+                                attributes: to_attributes(m.get_label()),
+                                count: h.get_sample_count(),
+                                sum: h.get_sample_sum(),
+                                bounds,
+                                bucket_counts,
+                            }
+                        })
+                        .collect(),
+                ),
+                MetricType::SUMMARY | MetricType::UNTYPED => return None,
+            };
+
+            Some(Metric {
+                name: mf.get_name().to_owned(),
+                description: mf.get_help().to_owned(),
+                data,
+            })
+        })
+        .collect()
+}
+
+fn to_attributes(label_pairs: &[LabelPair]) -> Vec<KeyValue> {
+    label_pairs
+        .iter()
+        .map(|lp| KeyValue::new(lp.get_name().to_owned(), lp.get_value().to_owned()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counter::CounterVec;
+    use crate::gauge::Gauge;
+    use crate::histogram::{Histogram, HistogramOpts};
+    use crate::metrics::{Collector, Opts};
+
+    #[test]
+    fn test_counter_to_otel() {
+        let vec = CounterVec::new(Opts::new("test_counter", "test help"), &["a"]).unwrap();
+        vec.with_label_values(&["1"]).inc_by(3.0);
+
+        let metrics = to_otel(&vec.collect());
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].name, "test_counter");
+        assert_eq!(metrics[0].description, "test help");
+        assert_eq!(
+            metrics[0].data,
+            Data::Sum(vec![NumberDataPoint {
+                attributes: vec![KeyValue::new("a", "1")],
+                value: 3.0,
+            }])
+        );
+    }
+
+    #[test]
+    fn test_gauge_to_otel() {
+        let gauge = Gauge::new("test_gauge", "test help").unwrap();
+        gauge.set(-1.5);
+
+        let metrics = to_otel(&gauge.collect());
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(
+            metrics[0].data,
+            Data::Gauge(vec![NumberDataPoint {
+                attributes: vec![],
+                value: -1.5,
+            }])
+        );
+    }
+
+    #[test]
+    fn test_histogram_to_otel() {
+        let histogram = Histogram::with_opts(
+            HistogramOpts::new("test_histogram", "test help")
+                .const_label("a", "1")
+                .buckets(vec![1.0, 2.0]),
+        )
+        .unwrap();
+        histogram.observe(0.5);
+        histogram.observe(0.5);
+        histogram.observe(1.5);
+        histogram.observe(3.0);
+
+        let metrics = to_otel(&histogram.collect());
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(
+            metrics[0].data,
+            Data::Histogram(vec![HistogramDataPoint {
+                attributes: vec![KeyValue::new("a", "1")],
+                count: 4,
+                sum: 5.5,
+                bounds: vec![1.0, 2.0],
+                bucket_counts: vec![2, 1, 1],
+            }])
+        );
+    }
+
+    #[test]
+    fn test_inconsistent_histogram_to_otel() {
+        let mut mfs = Histogram::with_opts(
+            HistogramOpts::new("test_histogram", "test help").buckets(vec![1.0, 2.0]),
+        )
+        .unwrap()
+        .collect();
+        // Cumulative counts which decrease, as a custom collector may emit.
+        let h = mfs[0].mut_metric()[0].mut_histogram();
+        h.mut_bucket()[0].set_cumulative_count(3);
+        h.mut_bucket()[1].set_cumulative_count(2);
+        h.set_sample_count(1);
+
+        let metrics = to_otel(&mfs);
+        match &metrics[0].data {
+            Data::Histogram(points) => assert_eq!(points[0].bucket_counts, vec![3, 0, 0]),
+            data => panic!("unexpected data {:?}", data),
+        }
+    }
+}
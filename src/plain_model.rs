@@ -65,6 +65,7 @@ impl Gauge {
 #[derive(PartialEq, Clone, Default, Debug)]
 pub struct Counter {
     value: f64,
+    created_timestamp: Timestamp,
 }
 
 impl Counter {
@@ -81,6 +82,18 @@ impl Counter {
     pub fn get_value(&self) -> f64 {
         self.value
     }
+
+    pub fn set_created_timestamp(&mut self, v: Timestamp) {
+        self.created_timestamp = v;
+    }
+
+    pub fn get_created_timestamp(&self) -> &Timestamp {
+        &self.created_timestamp
+    }
+
+    pub fn mut_created_timestamp(&mut self) -> &mut Timestamp {
+        &mut self.created_timestamp
+    }
 }
 
 #[derive(PartialEq, Clone, Default, Debug)]
@@ -211,6 +224,10 @@ impl Histogram {
         self.bucket = v;
     }
 
+    pub fn mut_bucket(&mut self) -> &mut Vec<Bucket> {
+        &mut self.bucket
+    }
+
     pub fn get_bucket(&self) -> &[Bucket] {
         &self.bucket
     }
@@ -220,6 +237,7 @@ impl Histogram {
 pub struct Bucket {
     cumulative_count: u64,
     upper_bound: f64,
+    exemplar: Exemplar,
 }
 
 impl Bucket {
@@ -243,6 +261,79 @@ impl Bucket {
     pub fn get_upper_bound(&self) -> f64 {
         self.upper_bound
     }
+
+    pub fn clear_exemplar(&mut self) {
+        self.exemplar = Exemplar::default();
+    }
+
+    pub fn set_exemplar(&mut self, v: Exemplar) {
+        self.exemplar = v;
+    }
+
+    pub fn mut_exemplar(&mut self) -> &mut Exemplar {
+        &mut self.exemplar
+    }
+
+    pub fn get_exemplar(&self) -> &Exemplar {
+        &self.exemplar
+    }
+}
+
+#[derive(PartialEq, Clone, Default, Debug)]
+pub struct Exemplar {
+    label: Vec<LabelPair>,
+    value: f64,
+    timestamp: Timestamp,
+}
+
+impl Exemplar {
+    pub fn set_label(&mut self, v: Vec<LabelPair>) {
+        self.label = v;
+    }
+
+    pub fn get_label(&self) -> &[LabelPair] {
+        &self.label
+    }
+
+    pub fn set_value(&mut self, v: f64) {
+        self.value = v;
+    }
+
+    pub fn get_value(&self) -> f64 {
+        self.value
+    }
+
+    pub fn set_timestamp(&mut self, v: Timestamp) {
+        self.timestamp = v;
+    }
+
+    pub fn get_timestamp(&self) -> &Timestamp {
+        &self.timestamp
+    }
+}
+
+#[derive(PartialEq, Clone, Default, Debug)]
+pub struct Timestamp {
+    seconds: i64,
+    nanos: i32,
+}
+
+impl Timestamp {
+    pub fn set_seconds(&mut self, v: i64) {
+        self.seconds = v;
+    }
+
+    pub fn get_seconds(&self) -> i64 {
+        self.seconds
+    }
+
+    pub fn set_nanos(&mut self, v: i32) {
+        self.nanos = v;
+    }
+
+    pub fn get_nanos(&self) -> i32 {
+        self.nanos
+    }
 }
 
 #[derive(PartialEq, Clone, Default, Debug)]
@@ -283,6 +374,10 @@ impl Metric {
         self.gauge = v;
     }
 
+    pub fn mut_gauge(&mut self) -> &mut Gauge {
+        &mut self.gauge
+    }
+
     pub fn get_gauge(&self) -> &Gauge {
         &self.gauge
     }
@@ -291,6 +386,10 @@ impl Metric {
         self.counter = v;
     }
 
+    pub fn mut_counter(&mut self) -> &mut Counter {
+        &mut self.counter
+    }
+
     pub fn get_counter(&self) -> &Counter {
         &self.counter
     }
@@ -323,6 +422,10 @@ impl Metric {
         self.histogram = v;
     }
 
+    pub fn mut_histogram(&mut self) -> &mut Histogram {
+        &mut self.histogram
+    }
+
     pub fn get_histogram(&self) -> &Histogram {
         &self.histogram
     }
@@ -331,6 +434,10 @@ impl Metric {
         self.timestamp_ms = v;
     }
 
+    pub fn clear_timestamp_ms(&mut self) {
+        self.timestamp_ms = 0;
+    }
+
     pub fn get_timestamp_ms(&self) -> i64 {
         self.timestamp_ms
     }
@@ -4,6 +4,8 @@
 //!
 //! This module only supports **Linux** platform.
 
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 
 use lazy_static::lazy_static;
@@ -17,15 +19,101 @@ use crate::proto;
 /// The `pid_t` data type represents process IDs.
 pub use libc::pid_t;
 
-/// Six metrics per ProcessCollector.
-const METRICS_NUMBER: usize = 6;
+/// Seven metrics per ProcessCollector.
+const METRICS_NUMBER: usize = 7;
+
+/// Selects the metrics collected by a [`ProcessCollector`], e.g. to leave out
+/// metrics which can not be read in a restricted environment or to save
+/// syscalls per scrape. All metrics are enabled by default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProcessCollectorOpts {
+    /// Collect `process_cpu_seconds_total`.
+    pub cpu: bool,
+    /// Collect `process_virtual_memory_bytes` and
+    /// `process_resident_memory_bytes`.
+    pub memory: bool,
+    /// Collect `process_open_fds` and `process_max_fds`.
+    pub fds: bool,
+    /// Collect `process_start_time_seconds`.
+    pub start_time: bool,
+    /// Collect `process_threads`.
+    pub threads: bool,
+}
+
+impl Default for ProcessCollectorOpts {
+    fn default() -> Self {
+        ProcessCollectorOpts {
+            cpu: true,
+            memory: true,
+            fds: true,
+            start_time: true,
+            threads: true,
+        }
+    }
+}
+
+impl ProcessCollectorOpts {
+    /// Create a `ProcessCollectorOpts` with all metrics enabled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a `ProcessCollectorOpts` with all metrics disabled, to enable
+    /// only the needed ones.
+    pub fn none() -> Self {
+        ProcessCollectorOpts {
+            cpu: false,
+            memory: false,
+            fds: false,
+            start_time: false,
+            threads: false,
+        }
+    }
+
+    /// `cpu` sets whether the cpu metric is collected.
+    pub fn cpu(mut self, enabled: bool) -> Self {
+        self.cpu = enabled;
+        self
+    }
+
+    /// `memory` sets whether the memory metrics are collected.
+    pub fn memory(mut self, enabled: bool) -> Self {
+        self.memory = enabled;
+        self
+    }
+
+    /// `fds` sets whether the file descriptor metrics are collected.
+    pub fn fds(mut self, enabled: bool) -> Self {
+        self.fds = enabled;
+        self
+    }
+
+    /// `start_time` sets whether the start time metric is collected.
+    pub fn start_time(mut self, enabled: bool) -> Self {
+        self.start_time = enabled;
+        self
+    }
+
+    /// `threads` sets whether the thread count metric is collected.
+    pub fn threads(mut self, enabled: bool) -> Self {
+        self.threads = enabled;
+        self
+    }
+}
 
 /// A collector which exports the current state of
-/// process metrics including cpu, memory and file descriptor usage as well as
-/// the process start time for the given process id.
+/// process metrics including cpu, memory and file descriptor usage, the number
+/// of threads as well as the process start time for the given process id.
+/// Which of them are collected can be chosen with [`ProcessCollectorOpts`].
+///
+/// Metrics that can not be read, e.g. because `/proc` is not mounted in a
+/// container, are left out of the collected metric families instead of failing
+/// the whole collection.
 #[derive(Debug)]
 pub struct ProcessCollector {
     pid: pid_t,
+    proc_root: PathBuf,
+    opts: ProcessCollectorOpts,
     descs: Vec<Desc>,
     cpu_total: Mutex<Counter>,
     open_fds: Gauge,
@@ -33,11 +121,22 @@ pub struct ProcessCollector {
     vsize: Gauge,
     rss: Gauge,
     start_time: Gauge,
+    threads: Gauge,
 }
 
 impl ProcessCollector {
     /// Create a `ProcessCollector` with the given process id and namespace.
     pub fn new<S: Into<String>>(pid: pid_t, namespace: S) -> ProcessCollector {
+        ProcessCollector::with_opts(pid, namespace, ProcessCollectorOpts::default())
+    }
+
+    /// Create a `ProcessCollector` with the given process id and namespace,
+    /// which only describes and collects the metrics enabled by `opts`.
+    pub fn with_opts<S: Into<String>>(
+        pid: pid_t,
+        namespace: S,
+        opts: ProcessCollectorOpts,
+    ) -> ProcessCollector {
         let namespace = namespace.into();
         let mut descs = Vec::new();
 
@@ -50,14 +149,18 @@ impl ProcessCollector {
             .namespace(namespace.clone()),
         )
         .unwrap();
-        descs.extend(cpu_total.desc().into_iter().cloned());
+        if opts.cpu {
+            descs.extend(cpu_total.desc().into_iter().cloned());
+        }
 
         let open_fds = Gauge::with_opts(
             Opts::new("process_open_fds", "Number of open file descriptors.")
                 .namespace(namespace.clone()),
         )
         .unwrap();
-        descs.extend(open_fds.desc().into_iter().cloned());
+        if opts.fds {
+            descs.extend(open_fds.desc().into_iter().cloned());
+        }
 
         let max_fds = Gauge::with_opts(
             Opts::new(
@@ -67,7 +170,9 @@ impl ProcessCollector {
             .namespace(namespace.clone()),
         )
         .unwrap();
-        descs.extend(max_fds.desc().into_iter().cloned());
+        if opts.fds {
+            descs.extend(max_fds.desc().into_iter().cloned());
+        }
 
         let vsize = Gauge::with_opts(
             Opts::new(
@@ -77,7 +182,9 @@ impl ProcessCollector {
             .namespace(namespace.clone()),
         )
         .unwrap();
-        descs.extend(vsize.desc().into_iter().cloned());
+        if opts.memory {
+            descs.extend(vsize.desc().into_iter().cloned());
+        }
 
         let rss = Gauge::with_opts(
             Opts::new(
@@ -87,7 +194,9 @@ impl ProcessCollector {
             .namespace(namespace.clone()),
         )
         .unwrap();
-        descs.extend(rss.desc().into_iter().cloned());
+        if opts.memory {
+            descs.extend(rss.desc().into_iter().cloned());
+        }
 
         let start_time = Gauge::with_opts(
             Opts::new(
@@ -98,10 +207,23 @@ impl ProcessCollector {
             .namespace(namespace.clone()),
         )
         .unwrap();
-        descs.extend(start_time.desc().into_iter().cloned());
+        if opts.start_time {
+            descs.extend(start_time.desc().into_iter().cloned());
+        }
+
+        let threads = Gauge::with_opts(
+            Opts::new("process_threads", "Number of OS threads in the process.")
+                .namespace(namespace.clone()),
+        )
+        .unwrap();
+        if opts.threads {
+            descs.extend(threads.desc().into_iter().cloned());
+        }
 
         ProcessCollector {
             pid,
+            proc_root: PathBuf::from("/proc"),
+            opts,
             descs,
             cpu_total: Mutex::new(cpu_total),
             open_fds,
@@ -109,6 +231,7 @@ impl ProcessCollector {
             vsize,
             rss,
             start_time,
+            threads,
         }
     }
 
@@ -117,6 +240,12 @@ impl ProcessCollector {
         let pid = unsafe { libc::getpid() };
         ProcessCollector::new(pid, "")
     }
+
+    /// Read the process information from `proc_root` instead of `/proc`.
+    pub fn proc_root<P: Into<PathBuf>>(mut self, proc_root: P) -> ProcessCollector {
+        self.proc_root = proc_root.into();
+        self
+    }
 }
 
 impl Collector for ProcessCollector {
@@ -125,59 +254,82 @@ impl Collector for ProcessCollector {
     }
 
     fn collect(&self) -> Vec<proto::MetricFamily> {
-        let p = match procfs::process::Process::new(self.pid) {
-            Ok(p) => p,
-            Err(..) => {
-                // we can't construct a Process object, so there's no stats to gather
-                return Vec::new();
-            }
-        };
-
-        // file descriptors
-        if let Ok(fd_count) = p.fd_count() {
-            self.open_fds.set(fd_count as f64);
-        }
-        if let Ok(limits) = p.limits() {
-            if let procfs::process::LimitValue::Value(max) = limits.max_open_files.soft_limit {
-                self.max_fds.set(max as f64)
-            }
-        }
-
-        // memory
-        self.vsize.set(p.stat.vsize as f64);
-        self.rss.set(p.stat.rss as f64 * *PAGESIZE);
+        let proc_dir = self.proc_root.join(self.pid.to_string());
+        let mut mfs = Vec::with_capacity(METRICS_NUMBER);
 
-        // proc_start_time
-        if let Some(boot_time) = *BOOT_TIME {
-            self.start_time
-                .set(p.stat.starttime as f64 / *CLK_TCK + boot_time);
-        }
+        // cpu, memory, proc_start_time and threads all come from `stat`.
+        let opts = &self.opts;
+        let stat = if opts.cpu || opts.memory || opts.start_time || opts.threads {
+            procfs::process::Process::new_with_root(proc_dir.clone())
+                .ok()
+                .map(|p| p.stat)
+        } else {
+            None
+        };
 
         // cpu
-        let cpu_total_mfs = {
+        if let (true, Some(stat)) = (opts.cpu, &stat) {
             let cpu_total = self.cpu_total.lock().unwrap();
-            let total = (p.stat.utime + p.stat.stime) as f64 / *CLK_TCK;
+            let total = (stat.utime + stat.stime) as f64 / *CLK_TCK;
             let past = cpu_total.get();
             let delta = total - past;
             if delta > 0.0 {
                 cpu_total.inc_by(delta);
             }
 
-            cpu_total.collect()
-        };
+            mfs.extend(cpu_total.collect());
+        }
+
+        // file descriptors
+        if opts.fds {
+            if let Ok(fd_count) = fs::read_dir(proc_dir.join("fd")).map(Iterator::count) {
+                self.open_fds.set(fd_count as f64);
+                mfs.extend(self.open_fds.collect());
+            }
+            if let Some(max) = read_max_open_files(&proc_dir) {
+                self.max_fds.set(max);
+                mfs.extend(self.max_fds.collect());
+            }
+        }
+
+        if let Some(ref stat) = stat {
+            // memory
+            if opts.memory {
+                self.vsize.set(stat.vsize as f64);
+                mfs.extend(self.vsize.collect());
+                self.rss.set(stat.rss as f64 * *PAGESIZE);
+                mfs.extend(self.rss.collect());
+            }
+
+            // proc_start_time
+            if let (true, Some(boot_time)) = (opts.start_time, *BOOT_TIME) {
+                self.start_time
+                    .set(stat.starttime as f64 / *CLK_TCK + boot_time);
+                mfs.extend(self.start_time.collect());
+            }
+
+            // threads
+            if opts.threads {
+                self.threads.set(stat.num_threads as f64);
+                mfs.extend(self.threads.collect());
+            }
+        }
 
-        // collect MetricFamilys.
-        let mut mfs = Vec::with_capacity(METRICS_NUMBER);
-        mfs.extend(cpu_total_mfs);
-        mfs.extend(self.open_fds.collect());
-        mfs.extend(self.max_fds.collect());
-        mfs.extend(self.vsize.collect());
-        mfs.extend(self.rss.collect());
-        mfs.extend(self.start_time.collect());
         mfs
     }
 }
 
+/// `read_max_open_files` reads the soft limit of open file descriptors from
+/// the `limits` file of a process, `None` if it is unlimited or can not be read.
+fn read_max_open_files(proc_dir: &Path) -> Option<f64> {
+    let limits = fs::read_to_string(proc_dir.join("limits")).ok()?;
+    limits
+        .lines()
+        .find_map(|line| line.strip_prefix("Max open files"))
+        .and_then(|limit| limit.split_whitespace().next())
+        .and_then(|soft_limit| soft_limit.parse().ok())
+}
+
 lazy_static! {
     // getconf CLK_TCK
     static ref CLK_TCK: f64 = {
@@ -198,6 +350,13 @@ lazy_static! {
     static ref BOOT_TIME: Option<f64> = procfs::boot_time_secs().ok().map(|i| i as f64);
 }
 
+/// `self_start_time` reads the start time of the calling process in seconds
+/// since the Unix epoch.
+pub(crate) fn self_start_time() -> Option<f64> {
+    let stat = procfs::process::Process::myself().ok()?.stat;
+    Some(stat.starttime as f64 / *CLK_TCK + (*BOOT_TIME)?)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -208,7 +367,7 @@ mod tests {
     fn test_process_collector() {
         let pc = ProcessCollector::for_self();
         {
-            // Six metrics per process collector.
+            // Seven metrics per process collector.
             let descs = pc.desc();
             assert_eq!(descs.len(), super::METRICS_NUMBER);
             let mfs = pc.collect();
@@ -219,4 +378,58 @@ mod tests {
         let res = r.register(Box::new(pc));
         assert!(res.is_ok());
     }
+
+    #[test]
+    fn test_process_collector_opts() {
+        let opts = ProcessCollectorOpts::none().memory(true);
+        let pc = ProcessCollector::with_opts(unsafe { libc::getpid() }, "", opts);
+        let names = |mfs: Vec<proto::MetricFamily>| -> Vec<String> {
+            mfs.iter().map(|mf| mf.get_name().to_owned()).collect()
+        };
+        let expected = vec![
+            "process_virtual_memory_bytes".to_owned(),
+            "process_resident_memory_bytes".to_owned(),
+        ];
+        let descs: Vec<_> = pc.desc().iter().map(|d| d.fq_name.clone()).collect();
+        assert_eq!(descs, expected);
+        assert_eq!(names(pc.collect()), expected);
+
+        let pc = ProcessCollector::with_opts(0, "", ProcessCollectorOpts::none());
+        assert!(pc.desc().is_empty());
+        assert!(pc.collect().is_empty());
+    }
+
+    #[test]
+    fn test_process_collector_missing_stat() {
+        let proc_root =
+            std::env::temp_dir().join(format!("prometheus_test_proc_{}", std::process::id()));
+        let proc_dir = proc_root.join("42");
+        fs::create_dir_all(proc_dir.join("fd")).unwrap();
+        for fd in &["0", "1", "2"] {
+            fs::write(proc_dir.join("fd").join(fd), "").unwrap();
+        }
+        fs::write(
+            proc_dir.join("limits"),
+            "Limit                     Soft Limit           Hard Limit           Units     \n\
+             Max open files            1024                 4096                 files     \n",
+        )
+        .unwrap();
+
+        let pc = ProcessCollector::new(42, "").proc_root(&proc_root);
+        let mfs = pc.collect();
+        fs::remove_dir_all(&proc_root).unwrap();
+
+        // Only the file descriptor metrics can be read without `stat`.
+        let values: Vec<_> = mfs
+            .iter()
+            .map(|mf| (mf.get_name(), mf.get_metric()[0].get_gauge().get_value()))
+            .collect();
+        assert_eq!(
+            values,
+            vec![("process_open_fds", 3.0), ("process_max_fds", 1024.0)]
+        );
+
+        // Nothing can be read from a missing `/proc`.
+        assert!(pc.collect().is_empty());
+    }
 }
@@ -12,6 +12,7 @@ use reqwest::{Method, StatusCode, Url};
 
 use lazy_static::lazy_static;
 
+use crate::desc::is_valid_label_name;
 use crate::encoder::{Encoder, ProtobufEncoder};
 use crate::errors::{Error, Result};
 use crate::metrics::Collector;
@@ -42,8 +43,9 @@ pub struct BasicAuthentication {
 /// url, using the provided job name and the (optional) further grouping labels
 /// (the grouping map may be nil). See the Pushgateway documentation for
 /// detailed implications of the job and other grouping labels. Neither the job
-/// name nor any grouping label value may contain a "/". The metrics pushed must
-/// not contain a job label of their own nor any of the grouping labels.
+/// name nor any grouping label value may contain a "/", and grouping label names
+/// must be valid label names. The metrics pushed must not contain a job label
+/// of their own nor any of the grouping labels.
 ///
 /// You can use just host:port or ip:port as url, in which case 'http://' is
 /// added automatically. You can also include the schema in the URL. However, do
@@ -88,37 +90,7 @@ fn push<S: BuildHasher>(
     // Suppress clippy warning needless_pass_by_value.
     let grouping = grouping;
 
-    let mut push_url = if url.contains("://") {
-        url.to_owned()
-    } else {
-        format!("http://{}", url)
-    };
-
-    if push_url.ends_with('/') {
-        push_url.pop();
-    }
-
-    let mut url_components = Vec::new();
-    if job.contains('/') {
-        return Err(Error::Msg(format!("job contains '/': {}", job)));
-    }
-
-    // TODO: escape job
-    url_components.push(job.to_owned());
-
-    for (ln, lv) in &grouping {
-        // TODO: check label name
-        if lv.contains('/') {
-            return Err(Error::Msg(format!(
-                "value of grouping label {} contains '/': {}",
-                ln, lv
-            )));
-        }
-        url_components.push(ln.to_owned());
-        url_components.push(lv.to_owned());
-    }
-
-    push_url = format!("{}/metrics/job/{}", push_url, url_components.join("/"));
+    let push_url = build_push_url(url, job, &grouping)?;
 
     let encoder = ProtobufEncoder::new();
     let mut buf = Vec::new();
@@ -149,10 +121,7 @@ fn push<S: BuildHasher>(
     }
 
     let mut builder = HTTP_CLIENT
-        .request(
-            Method::from_str(method).unwrap(),
-            Url::from_str(&push_url).unwrap(),
-        )
+        .request(Method::from_str(method).unwrap(), push_url.clone())
         .header(CONTENT_TYPE, encoder.format_type())
         .body(buf);
 
@@ -173,6 +142,56 @@ fn push<S: BuildHasher>(
     }
 }
 
+/// `build_push_url` checks the job name and the grouping labels, and builds the
+/// URL of their group on the Pushgateway at `url`. The job name and the label
+/// values are escaped as URL path segments.
+fn build_push_url<S: BuildHasher>(
+    url: &str,
+    job: &str,
+    grouping: &HashMap<String, String, S>,
+) -> Result<Url> {
+    let mut push_url = if url.contains("://") {
+        url.to_owned()
+    } else {
+        format!("http://{}", url)
+    };
+
+    if push_url.ends_with('/') {
+        push_url.pop();
+    }
+
+    if job.is_empty() {
+        return Err(Error::Msg("job name is empty".to_owned()));
+    }
+    if job.contains('/') {
+        return Err(Error::Msg(format!("job contains '/': {}", job)));
+    }
+
+    let mut url_components = vec!["metrics", "job", job];
+    for (ln, lv) in grouping {
+        if !is_valid_label_name(ln) {
+            return Err(Error::Msg(format!("invalid grouping label name {:?}", ln)));
+        }
+        if lv.contains('/') {
+            return Err(Error::Msg(format!(
+                "value of grouping label {} contains '/': {}",
+                ln, lv
+            )));
+        }
+        url_components.push(ln);
+        url_components.push(lv);
+    }
+
+    let mut push_url = Url::from_str(&push_url)
+        .map_err(|e| Error::Msg(format!("invalid push url {}: {}", push_url, e)))?;
+    push_url
+        .path_segments_mut()
+        .map_err(|_| Error::Msg(format!("invalid push url {}", url)))?
+        .pop_if_empty()
+        .extend(url_components);
+    Ok(push_url)
+}
+
 fn push_from_collector<S: BuildHasher>(
     job: &str,
     grouping: HashMap<String, String, S>,
@@ -222,9 +241,18 @@ const DEFAULT_GROUP_LABEL_PAIR: (&str, &str) = ("instance", "unknown");
 /// returned map is created upon each call so that the caller is free to add more
 /// labels to the map.
 ///
-/// Note: This function returns `instance = "unknown"` in Windows.
-#[cfg(not(target_os = "windows"))]
+/// Note: This function returns `instance = "unknown"` in Windows, or if the
+/// hostname can not be used as grouping label value, see [`hostname`].
 pub fn hostname_grouping_key() -> HashMap<String, String> {
+    let hostname = hostname().unwrap_or_else(|| DEFAULT_GROUP_LABEL_PAIR.1.to_owned());
+    labels! {DEFAULT_GROUP_LABEL_PAIR.0.to_owned() => hostname,}
+}
+
+/// `hostname` returns the hostname of the machine, `None` if it can not be
+/// read, e.g. in Windows, or if it is not a valid grouping label value, i.e.
+/// if it is empty, is not valid UTF-8 or contains a '/'.
+#[cfg(not(target_os = "windows"))]
+pub fn hostname() -> Option<String> {
     // Host names are limited to 255 bytes.
     //   ref: http://pubs.opengroup.org/onlinepubs/7908799/xns/gethostname.html
     let max_len = 256;
@@ -235,22 +263,33 @@ pub fn hostname_grouping_key() -> HashMap<String, String> {
             max_len as libc::size_t,
         )
     } {
-        0 => {
-            let last_char = name.iter().position(|byte| *byte == 0).unwrap_or(max_len);
-            labels! {
-                DEFAULT_GROUP_LABEL_PAIR.0.to_owned() => str::from_utf8(&name[..last_char])
-                                            .unwrap_or(DEFAULT_GROUP_LABEL_PAIR.1).to_owned(),
-            }
-        }
-        _ => {
-            labels! {DEFAULT_GROUP_LABEL_PAIR.0.to_owned() => DEFAULT_GROUP_LABEL_PAIR.1.to_owned(),}
-        }
+        0 => hostname_from_bytes(&name),
+        _ => None,
     }
 }
 
+/// `hostname` returns the hostname of the machine, `None` if it can not be
+/// read, e.g. in Windows, or if it is not a valid grouping label value, i.e.
+/// if it is empty, is not valid UTF-8 or contains a '/'.
 #[cfg(target_os = "windows")]
-pub fn hostname_grouping_key() -> HashMap<String, String> {
-    labels! {DEFAULT_GROUP_LABEL_PAIR.0.to_owned() => DEFAULT_GROUP_LABEL_PAIR.1.to_owned(),}
+pub fn hostname() -> Option<String> {
+    None
+}
+
+/// `hostname_from_bytes` decodes a hostname filled in by `gethostname`, which
+/// is terminated by the first NUL if it is shorter than the buffer.
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+fn hostname_from_bytes(name: &[u8]) -> Option<String> {
+    let len = name
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(name.len());
+    match str::from_utf8(&name[..len]) {
+        Ok(hostname) if !hostname.is_empty() && !hostname.contains('/') => {
+            Some(hostname.to_owned())
+        }
+        _ => None,
+    }
 }
 
 #[cfg(test)]
@@ -264,6 +303,19 @@ mod tests {
         assert!(!map.is_empty());
     }
 
+    #[test]
+    fn test_hostname_from_bytes() {
+        // The hostname ends at the first NUL, or at the end of the buffer.
+        assert_eq!(hostname_from_bytes(b"host-1\0\0\0").unwrap(), "host-1");
+        assert_eq!(hostname_from_bytes(b"host-1\0garbage").unwrap(), "host-1");
+        assert_eq!(hostname_from_bytes(b"host-1").unwrap(), "host-1");
+
+        // Hostnames which can not be used as label values are rejected.
+        assert_eq!(hostname_from_bytes(b"\0host-1"), None);
+        assert_eq!(hostname_from_bytes(b"host\xff-1\0"), None);
+        assert_eq!(hostname_from_bytes(b"host/1\0"), None);
+    }
+
     #[test]
     fn test_push_bad_label_name() {
         let table = vec![
@@ -284,4 +336,33 @@ mod tests {
             assert!(format!("{}", res.unwrap_err()).contains(case.1));
         }
     }
+
+    #[test]
+    fn test_build_push_url() {
+        let mut grouping = HashMap::new();
+        grouping.insert("instance".to_owned(), "host 1".to_owned());
+        let url = build_push_url("127.0.0.1:9091/", "my job?", &grouping).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "http://127.0.0.1:9091/metrics/job/my%20job%3F/instance/host%201"
+        );
+        let url = build_push_url("https://example.com/pushgateway", "job", &grouping).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://example.com/pushgateway/metrics/job/job/instance/host%201"
+        );
+
+        // Invalid job names.
+        for job in &["", "my/job"] {
+            build_push_url("127.0.0.1:9091", job, &grouping).unwrap_err();
+        }
+
+        // Invalid grouping label name.
+        let mut grouping = HashMap::new();
+        grouping.insert("my-label".to_owned(), "value".to_owned());
+        let err = build_push_url("127.0.0.1:9091", "job", &grouping).unwrap_err();
+        assert!(format!("{}", err).contains("invalid grouping label name \"my-label\""));
+        let res = push_metrics("test", grouping, "mockurl", vec![], None);
+        assert!(format!("{}", res.unwrap_err()).contains("invalid grouping label name"));
+    }
 }
@@ -0,0 +1,260 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::cmp::Ordering;
+
+use crate::errors::{Error, Result};
+
+/// The number of observations buffered before they are merged into the
+/// compressed samples.
+const BUFFER_CAPACITY: usize = 500;
+
+/// An estimator of quantiles over a stream of observations in bounded memory,
+/// e.g. for logging the median and tail latency of ad-hoc measurements. It is
+/// not a metric and is not registered anywhere.
+///
+/// It implements the targeted quantiles algorithm of Cormode, Korn,
+/// Muthukrishnan and Srivastava (CKMS). It is created with objectives, pairs of
+/// a quantile and its allowed error: for the objective `(q, e)`, the rank of
+/// the value returned for `q` is within `(q - e) * n` and `(q + e) * n` for `n`
+/// observations. Other quantiles can be queried but have no error bound.
+///
+/// # Examples
+///
+/// ```
+/// use prometheus::QuantileEstimator;
+///
+/// let mut estimator = QuantileEstimator::new(&[(0.5, 0.05), (0.9, 0.01)]).unwrap();
+/// for v in 1..=100 {
+///     estimator.insert(f64::from(v));
+/// }
+/// assert_eq!(estimator.count(), 100);
+/// assert_eq!(estimator.quantile(0.9), 90.0);
+/// ```
+#[derive(Clone, Debug)]
+pub struct QuantileEstimator {
+    objectives: Vec<(f64, f64)>,
+    /// The compressed samples, sorted by value.
+    samples: Vec<Sample>,
+    /// The number of observations merged into `samples`.
+    n: f64,
+    /// The observations not merged yet.
+    buffer: Vec<f64>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+    value: f64,
+    /// The difference between the lowest possible rank of this sample and the
+    /// previous one.
+    width: f64,
+    /// The difference between the highest and the lowest possible rank.
+    delta: f64,
+}
+
+impl QuantileEstimator {
+    /// Create a [`QuantileEstimator`] with the given objectives, pairs of a
+    /// quantile and its allowed error.
+    ///
+    /// The function returns an error if a quantile is not in `(0, 1)` or an
+    /// error is not in `[0, 1)`.
+    pub fn new(objectives: &[(f64, f64)]) -> Result<QuantileEstimator> {
+        for &(q, e) in objectives {
+            if !(q > 0.0 && q < 1.0 && (0.0..1.0).contains(&e)) {
+                return Err(Error::Msg(format!(
+                    "quantile objectives need a quantile in (0, 1) and an error in [0, 1), \
+                     quantile: {}, error: {}",
+                    q, e
+                )));
+            }
+        }
+
+        Ok(QuantileEstimator {
+            objectives: objectives.to_vec(),
+            samples: Vec::new(),
+            n: 0.0,
+            buffer: Vec::with_capacity(BUFFER_CAPACITY),
+        })
+    }
+
+    /// Insert an observation. `NaN` is ignored.
+    pub fn insert(&mut self, v: f64) {
+        if v.is_nan() {
+            return;
+        }
+        self.buffer.push(v);
+        if self.buffer.len() == BUFFER_CAPACITY {
+            self.flush();
+        }
+    }
+
+    /// Return the estimated value of the quantile `q`, `NaN` if nothing has
+    /// been observed.
+    ///
+    /// As long as fewer observations than the internal buffer holds have been
+    /// inserted, the quantile is exact.
+    pub fn quantile(&mut self, q: f64) -> f64 {
+        if self.samples.is_empty() {
+            if self.buffer.is_empty() {
+                return f64::NAN;
+            }
+            self.sort_buffer();
+            let i = (self.buffer.len() as f64 * q).ceil() as usize;
+            return self.buffer[i.saturating_sub(1).min(self.buffer.len() - 1)];
+        }
+
+        self.flush();
+        let t = (q * self.n).ceil();
+        let t = t + (self.invariant(t) / 2.0).ceil();
+        let mut prev = self.samples[0];
+        let mut r = 0.0;
+        for s in &self.samples[1..] {
+            r += prev.width;
+            if r + s.width + s.delta > t {
+                return prev.value;
+            }
+            prev = *s;
+        }
+        prev.value
+    }
+
+    /// Return the number of observations.
+    pub fn count(&self) -> u64 {
+        self.n as u64 + self.buffer.len() as u64
+    }
+
+    /// Forget all observations.
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.buffer.clear();
+        self.n = 0.0;
+    }
+
+    fn sort_buffer(&mut self) {
+        self.buffer
+            .sort_unstable_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    }
+
+    /// `flush` merges the buffered observations into the samples.
+    fn flush(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        self.sort_buffer();
+        let buffer = std::mem::take(&mut self.buffer);
+        self.merge(&buffer);
+        self.buffer = buffer;
+        self.buffer.clear();
+        self.compress();
+    }
+
+    fn merge(&mut self, sorted: &[f64]) {
+        let mut r = 0.0;
+        let mut i = 0;
+        for &v in sorted {
+            while i < self.samples.len() && self.samples[i].value <= v {
+                r += self.samples[i].width;
+                i += 1;
+            }
+            let delta = if i == self.samples.len() {
+                0.0
+            } else {
+                (self.invariant(r).floor() - 1.0).max(0.0)
+            };
+            self.samples.insert(
+                i,
+                Sample {
+                    value: v,
+                    width: 1.0,
+                    delta,
+                },
+            );
+            i += 1;
+            self.n += 1.0;
+            r += 1.0;
+        }
+    }
+
+    fn compress(&mut self) {
+        if self.samples.len() < 2 {
+            return;
+        }
+
+        let mut xi = self.samples.len() - 1;
+        let mut r = self.n - 1.0 - self.samples[xi].width;
+        for i in (0..self.samples.len() - 1).rev() {
+            let c = self.samples[i];
+            let x = self.samples[xi];
+            if c.width + x.width + x.delta <= self.invariant(r) {
+                self.samples[xi].width += c.width;
+                self.samples.remove(i);
+                xi -= 1;
+            } else {
+                xi = i;
+            }
+            r -= c.width;
+        }
+    }
+
+    /// `invariant` returns the allowed difference between the highest and the
+    /// lowest possible rank of a sample at rank `r`.
+    fn invariant(&self, r: f64) -> f64 {
+        let mut m = f64::MAX;
+        for &(q, e) in &self.objectives {
+            let f = if q * self.n <= r {
+                2.0 * e * r / q
+            } else {
+                2.0 * e * (self.n - r) / (1.0 - q)
+            };
+            m = m.min(f);
+        }
+        m
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantile_estimator() {
+        QuantileEstimator::new(&[(1.0, 0.01)]).unwrap_err();
+        QuantileEstimator::new(&[(0.5, -0.01)]).unwrap_err();
+
+        let mut estimator = QuantileEstimator::new(&[(0.5, 0.05), (0.9, 0.01)]).unwrap();
+        assert!(estimator.quantile(0.5).is_nan());
+
+        // A permutation of 0..n, so the value of each observation is its rank.
+        let n: u32 = 10007;
+        for i in 0..n {
+            estimator.insert(f64::from((i * 7919) % n));
+        }
+        assert_eq!(estimator.count(), u64::from(n));
+
+        let n = f64::from(n);
+        let median = estimator.quantile(0.5);
+        assert!((median - 0.5 * n).abs() <= 0.05 * n, "{}", median);
+        let p90 = estimator.quantile(0.9);
+        assert!((p90 - 0.9 * n).abs() <= 0.01 * n, "{}", p90);
+        // The samples are compressed.
+        assert!(
+            estimator.samples.len() < 1000,
+            "{}",
+            estimator.samples.len()
+        );
+
+        estimator.reset();
+        assert_eq!(estimator.count(), 0);
+    }
+
+    #[test]
+    fn test_quantile_estimator_small() {
+        let mut estimator = QuantileEstimator::new(&[(0.5, 0.05)]).unwrap();
+        for v in &[3.0, 1.0, f64::NAN, 2.0, 5.0, 4.0] {
+            estimator.insert(*v);
+        }
+        assert_eq!(estimator.count(), 5);
+        assert_eq!(estimator.quantile(0.5), 3.0);
+        assert_eq!(estimator.quantile(0.0), 1.0);
+        assert_eq!(estimator.quantile(1.0), 5.0);
+    }
+}
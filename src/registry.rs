@@ -5,11 +5,15 @@ use std::collections::btree_map::Entry as BEntry;
 use std::collections::hash_map::Entry as HEntry;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 
+use crate::encoder::Format;
 use crate::errors::{Error, Result};
-use crate::metrics::Collector;
+use crate::gauge::Gauge;
+use crate::lint::{lint_descs, LintWarning};
+use crate::metrics::{Collector, MetricVisitor};
 use crate::proto;
 
 use cfg_if::cfg_if;
@@ -17,6 +21,9 @@ use lazy_static::lazy_static;
 
 struct RegistryCore {
     pub collectors_by_id: HashMap<u64, Box<dyn Collector>>,
+    /// The ids of `collectors_by_id` in registration order, in which they are
+    /// collected.
+    pub collector_ids: Vec<u64>,
     pub dim_hashes_by_name: HashMap<String, u64>,
     pub desc_ids: HashSet<u64>,
     /// Optional common labels for all registered collectors.
@@ -83,12 +90,59 @@ impl RegistryCore {
             HEntry::Vacant(vc) => {
                 self.desc_ids.extend(desc_id_set);
                 vc.insert(c);
+                self.collector_ids.push(collector_id);
                 Ok(())
             }
             HEntry::Occupied(_) => Err(Error::AlreadyReg),
         }
     }
 
+    fn register_all(&mut self, collectors: Vec<Box<dyn Collector>>) -> Result<()> {
+        let dim_hashes_by_name = self.dim_hashes_by_name.clone();
+        let mut registered = Vec::with_capacity(collectors.len());
+
+        for c in collectors {
+            let mut desc_ids = Vec::new();
+            let mut collector_id: u64 = 0;
+            for desc in c.desc() {
+                if !desc_ids.contains(&desc.id) {
+                    desc_ids.push(desc.id);
+                    collector_id = collector_id.wrapping_add(desc.id);
+                }
+            }
+
+            if let Err(e) = self.register(c) {
+                // Roll back, including the label dimensions of new names.
+                for (collector_id, desc_ids) in registered {
+                    self.collectors_by_id.remove(&collector_id);
+                    self.collector_ids.retain(|id| *id != collector_id);
+                    for id in desc_ids {
+                        self.desc_ids.remove(&id);
+                    }
+                }
+                self.dim_hashes_by_name = dim_hashes_by_name;
+                return Err(e);
+            }
+            registered.push((collector_id, desc_ids));
+        }
+
+        Ok(())
+    }
+
+    /// `is_registered` returns whether a collector with the same set of
+    /// descriptors as `c` is registered.
+    fn is_registered(&self, c: &dyn Collector) -> bool {
+        let mut id_set = Vec::new();
+        let mut collector_id: u64 = 0;
+        for desc in c.desc() {
+            if !id_set.contains(&desc.id) {
+                id_set.push(desc.id);
+                collector_id = collector_id.wrapping_add(desc.id);
+            }
+        }
+        self.collectors_by_id.contains_key(&collector_id)
+    }
+
     fn unregister(&mut self, c: Box<dyn Collector>) -> Result<()> {
         let mut id_set = Vec::new();
         let mut collector_id: u64 = 0;
@@ -105,6 +159,7 @@ impl RegistryCore {
                 c.desc()
             )));
         }
+        self.collector_ids.retain(|id| *id != collector_id);
 
         for id in id_set {
             self.desc_ids.remove(&id);
@@ -115,14 +170,57 @@ impl RegistryCore {
         Ok(())
     }
 
-    fn gather(&self) -> Vec<proto::MetricFamily> {
+    #[cfg(feature = "test-util")]
+    fn unregister_all(&mut self) {
+        self.collectors_by_id.clear();
+        self.collector_ids.clear();
+        self.desc_ids.clear();
+        self.dim_hashes_by_name.clear();
+    }
+
+    /// `collectors` returns the registered collectors in registration order.
+    fn collectors(&self) -> impl Iterator<Item = &Box<dyn Collector>> {
+        self.collector_ids
+            .iter()
+            .map(move |id| &self.collectors_by_id[id])
+    }
+
+    fn gather(&self, prune_empty: bool) -> (Vec<proto::MetricFamily>, Option<Error>) {
+        self.gather_filtered(prune_empty, None, false)
+    }
+
+    /// `gather_filtered` gathers the metric families like `gather`, but if
+    /// `only` is given, only from the collectors describing a metric of that
+    /// name without the registry prefix, and only the family of that name. If
+    /// `stale` is true, the staleness markers of the collectors are merged
+    /// into their families.
+    fn gather_filtered(
+        &self,
+        prune_empty: bool,
+        only: Option<&str>,
+        stale: bool,
+    ) -> (Vec<proto::MetricFamily>, Option<Error>) {
         let mut mf_by_name = BTreeMap::new();
+        let mut conflict = None;
+
+        for c in self.collectors() {
+            if let Some(name) = only {
+                if c.desc().iter().all(|desc| desc.fq_name != name) {
+                    continue;
+                }
+            }
 
-        for c in self.collectors_by_id.values() {
-            let mfs = c.collect();
+            let mut mfs = c.collect();
+            if stale {
+                mfs.extend(c.collect_stale());
+            }
             for mut mf in mfs {
                 // Prune empty MetricFamilies.
-                if mf.get_metric().is_empty() {
+                if prune_empty && mf.get_metric().is_empty() {
+                    continue;
+                }
+
+                if only.is_some_and(|name| mf.get_name() != name) {
                     continue;
                 }
 
@@ -133,13 +231,27 @@ impl RegistryCore {
                     }
                     BEntry::Occupied(mut entry) => {
                         let existent_mf = entry.get_mut();
-                        let existent_metrics = existent_mf.mut_metric();
-
-                        // TODO: check type.
-                        // TODO: check consistency.
-                        for metric in mf.take_metric().into_iter() {
-                            existent_metrics.push(metric);
+                        if existent_mf.get_field_type() != mf.get_field_type()
+                            || existent_mf.get_help() != mf.get_help()
+                        {
+                            // Keep the family of the collector registered
+                            // first, report the first conflict only.
+                            if conflict.is_none() {
+                                conflict = Some(Error::Msg(format!(
+                                    "collected metric family {} has type {:?} and help {:?}, \
+                                     inconsistent with type {:?} and help {:?} collected before",
+                                    mf.get_name(),
+                                    mf.get_field_type(),
+                                    mf.get_help(),
+                                    existent_mf.get_field_type(),
+                                    existent_mf.get_help(),
+                                )));
+                            }
+                            continue;
                         }
+
+                        // Extending reserves room for all metrics at once.
+                        existent_mf.mut_metric().extend(mf.take_metric());
                     }
                 }
             }
@@ -181,9 +293,9 @@ impl RegistryCore {
         }
 
         // Write out MetricFamilies sorted by their name.
-        mf_by_name
-            .into_iter()
-            .map(|(_, mut m)| {
+        let mfs = mf_by_name
+            .into_values()
+            .map(|mut m| {
                 // Add registry namespace prefix, if any.
                 if let Some(ref namespace) = self.prefix {
                     let prefixed = format!("{}_{}", namespace, m.get_name());
@@ -203,14 +315,17 @@ impl RegistryCore {
                         .collect();
 
                     for metric in m.mut_metric().iter_mut() {
-                        let mut labels: Vec<_> = metric.take_label().into();
-                        labels.append(&mut pairs.clone());
-                        metric.set_label(labels.into());
+                        let old_labels = metric.take_label();
+                        let mut labels = Vec::with_capacity(old_labels.len() + pairs.len());
+                        labels.extend(old_labels);
+                        labels.extend(pairs.iter().cloned());
+                        metric.set_label(from_vec!(labels));
                     }
                 }
                 m
             })
-            .collect()
+            .collect();
+        (mfs, conflict)
     }
 }
 
@@ -225,6 +340,7 @@ impl Default for Registry {
     fn default() -> Registry {
         let r = RegistryCore {
             collectors_by_id: HashMap::new(),
+            collector_ids: Vec::new(),
             dim_hashes_by_name: HashMap::new(),
             desc_ids: HashSet::new(),
             labels: None,
@@ -276,6 +392,22 @@ impl Registry {
         self.r.write().register(c)
     }
 
+    /// `register_all` registers all the given [`Collector`]s or none of them,
+    /// e.g. the metrics of a subsystem. If one of them can not be registered,
+    /// see [`register`](Registry::register), the ones before it are
+    /// unregistered again and its error is returned.
+    pub fn register_all(&self, collectors: Vec<Box<dyn Collector>>) -> Result<()> {
+        self.r.write().register_all(collectors)
+    }
+
+    /// `register_arc` registers a [`Collector`] held behind an `Arc`, keeping
+    /// the shared ownership of the caller. See [`register`](Registry::register)
+    /// for the errors returned. It can be unregistered with another clone of
+    /// the `Arc`.
+    pub fn register_arc(&self, c: Arc<dyn Collector>) -> Result<()> {
+        self.register(Box::new(c))
+    }
+
     /// `unregister` unregisters the [`Collector`] that equals the [`Collector`] passed
     /// in as an argument.  (Two Collectors are considered equal if their
     /// Describe method yields the same set of descriptors.) The function
@@ -284,47 +416,362 @@ impl Registry {
         self.r.write().unregister(c)
     }
 
+    /// `unregister_all` unregisters all collectors, and forgets the label
+    /// dimensions of the registered metric names.
+    #[cfg(feature = "test-util")]
+    pub(crate) fn unregister_all(&self) {
+        self.r.write().unregister_all()
+    }
+
+    /// `num_collectors` returns the number of registered Collectors, without
+    /// collecting them, e.g. for a readiness probe.
+    pub fn num_collectors(&self) -> usize {
+        self.r.read().collectors_by_id.len()
+    }
+
+    /// `num_descs` returns the number of descriptors of the registered
+    /// Collectors, i.e. the number of metrics, without collecting them.
+    pub fn num_descs(&self) -> usize {
+        self.r.read().desc_ids.len()
+    }
+
     /// `gather` calls the Collect method of the registered Collectors and then
     /// gathers the collected metrics into a lexicographically sorted slice
     /// of MetricFamily protobufs.
+    ///
+    /// Metric families of the same name collected by different Collectors are
+    /// merged into a single one. If their types or help strings are
+    /// inconsistent, only the one of the Collector registered first is kept,
+    /// see [`try_gather`](Registry::try_gather) to detect this.
     pub fn gather(&self) -> Vec<proto::MetricFamily> {
-        self.r.read().gather()
+        self.r.read().gather(true).0
+    }
+
+    /// `gather_with_empty` gathers the metrics like
+    /// [`gather`](Registry::gather), but keeps metric families without
+    /// metrics, e.g. of a [`HistogramVec`](crate::HistogramVec) without
+    /// children. Most encoders reject them, see
+    /// [`TextEncoder::emit_empty_families`](crate::TextEncoder::emit_empty_families)
+    /// to encode them as header lines only.
+    pub fn gather_with_empty(&self) -> Vec<proto::MetricFamily> {
+        self.r.read().gather(false).0
+    }
+
+    /// `gather_with_stale` gathers the metrics like
+    /// [`gather`](Registry::gather), and also emits one sample holding the
+    /// Prometheus staleness marker for each series removed by
+    /// [`MetricVec::remove_label_values_stale`](crate::core::MetricVec::remove_label_values_stale)
+    /// since the previous call, clearing them. It is meant for the single
+    /// push or remote write loop of a registry, other ways of gathering leave
+    /// the markers untouched.
+    ///
+    /// The text format renders the marker as an ordinary `NaN`, only the
+    /// protobuf format keeps the exact bits of
+    /// [`STALE_MARKER_BITS`](crate::STALE_MARKER_BITS).
+    pub fn gather_with_stale(&self) -> Vec<proto::MetricFamily> {
+        self.r.read().gather_filtered(true, None, true).0
+    }
+
+    /// `gather_one` gathers the metric family of the given fully-qualified
+    /// name, including the registry prefix if any, e.g. for a debugging
+    /// endpoint of a single metric. It returns `None` if no metric of that name
+    /// is registered or it has no metrics.
+    ///
+    /// Only the collectors describing a metric of that name are collected, the
+    /// families of the same name collected by several of them are merged like
+    /// by [`gather`](Registry::gather).
+    pub fn gather_one(&self, name: &str) -> Option<proto::MetricFamily> {
+        let r = self.r.read();
+        let name = match r.prefix {
+            Some(ref prefix) => name.strip_prefix(prefix.as_str())?.strip_prefix('_')?,
+            None => name,
+        };
+        r.gather_filtered(true, Some(name), false).0.pop()
+    }
+
+    /// `gather_with_meta` gathers the metrics like [`gather`](Registry::gather),
+    /// and also returns how long gathering took and how many samples it
+    /// produced, e.g. for push based setups to monitor their own collection.
+    pub fn gather_with_meta(&self) -> (Vec<proto::MetricFamily>, ScrapeMeta) {
+        let start = Instant::now();
+        let mfs = self.gather();
+        let duration = start.elapsed();
+        let sample_count = mfs.iter().map(sample_count).sum();
+        (
+            mfs,
+            ScrapeMeta {
+                duration,
+                sample_count,
+            },
+        )
+    }
+
+    /// `visit` visits the metrics of all registered Collectors with borrowed
+    /// names, labels and values. Unlike [`gather`](Registry::gather), it does
+    /// not build, merge or sort metric families, which makes it cheaper for
+    /// read-only consumers like custom encoders. The registry prefix and common
+    /// labels are not applied, and metric families are visited in no
+    /// particular order, possibly several times for the same name.
+    pub fn visit(&self, visitor: &mut dyn MetricVisitor) {
+        let core = self.r.read();
+        for c in core.collectors_by_id.values() {
+            c.visit(visitor);
+        }
+    }
+
+    /// `try_gather` gathers the metrics like [`gather`](Registry::gather), but
+    /// returns an error if Collectors collected metric families of the same
+    /// name with inconsistent types or help strings.
+    pub fn try_gather(&self) -> Result<Vec<proto::MetricFamily>> {
+        match self.r.read().gather(true) {
+            (_, Some(err)) => Err(err),
+            (mfs, None) => Ok(mfs),
+        }
+    }
+
+    /// `gather_encoded` gathers the metrics and encodes them into the given
+    /// [`Format`]. It returns the encoded bytes along with the matching
+    /// `Content-Type` header value.
+    pub fn gather_encoded(&self, format: Format) -> Result<(Vec<u8>, &'static str)> {
+        let mut buffer = Vec::new();
+        format.encode(&self.gather(), &mut buffer)?;
+        Ok((buffer, format.content_type()))
+    }
+
+    /// `snapshot` gathers the metrics once into a [`MetricSnapshot`], which can
+    /// then be encoded into several formats.
+    pub fn snapshot(&self) -> MetricSnapshot {
+        MetricSnapshot {
+            metric_families: self.gather(),
+        }
+    }
+
+    /// `lint` checks the names and help strings of the registered metric
+    /// families against the Prometheus naming conventions, e.g. for tests to
+    /// fail on undocumented metrics. It returns the warnings sorted by metric
+    /// family name, an empty vector if everything is fine.
+    ///
+    /// Only the descriptors of the registered Collectors are checked, nothing
+    /// is collected. The type based checks are skipped for Collectors which
+    /// do not report the type of their metrics by
+    /// [`Collector::metric_type`](crate::core::Collector::metric_type).
+    pub fn lint(&self) -> Vec<LintWarning> {
+        let core = self.r.read();
+        let mut descs = BTreeMap::new();
+        for c in core.collectors() {
+            for desc in c.desc() {
+                let name = match core.prefix {
+                    Some(ref prefix) => format!("{}_{}", prefix, desc.fq_name),
+                    None => desc.fq_name.clone(),
+                };
+                // Descriptors of the same name share their help string.
+                descs
+                    .entry(name)
+                    .or_insert_with(|| (desc.help.as_str(), c.metric_type(desc)));
+            }
+        }
+        lint_descs(
+            descs
+                .iter()
+                .map(|(name, (help, metric_type))| (name.as_str(), *help, *metric_type)),
+        )
+    }
+}
+
+/// Meta information about a gathering, returned by
+/// [`Registry::gather_with_meta`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ScrapeMeta {
+    /// The time it took to collect and gather the metrics.
+    pub duration: Duration,
+    /// The number of samples of the gathered metrics, as exposed in the text
+    /// format, e.g. a histogram with 2 buckets has 5 samples including the +Inf
+    /// bucket.
+    pub sample_count: usize,
+}
+
+impl ScrapeMeta {
+    /// Return the meta information as the `scrape_duration_seconds` and
+    /// `scrape_samples_scraped` gauges, as added by Prometheus servers to
+    /// scraped targets.
+    pub fn metric_families(&self) -> Vec<proto::MetricFamily> {
+        let duration = Gauge::new(
+            "scrape_duration_seconds",
+            "Time it took to gather the metrics.",
+        )
+        .unwrap();
+        duration.set(self.duration.as_secs_f64());
+        let samples = Gauge::new(
+            "scrape_samples_scraped",
+            "Number of samples of the gathered metrics.",
+        )
+        .unwrap();
+        samples.set(self.sample_count as f64);
+
+        let mut mfs = duration.collect();
+        mfs.extend(samples.collect());
+        mfs
+    }
+}
+
+/// `sample_count` returns the number of samples of the metric family in the
+/// text format.
+fn sample_count(mf: &proto::MetricFamily) -> usize {
+    mf.get_metric()
+        .iter()
+        .map(|m| match mf.get_field_type() {
+            proto::MetricType::HISTOGRAM => {
+                let buckets = m.get_histogram().get_bucket();
+                let inf_seen = buckets.iter().any(|b| {
+                    let upper_bound = b.get_upper_bound();
+                    upper_bound.is_sign_positive() && upper_bound.is_infinite()
+                });
+                // The buckets, the implicit +Inf bucket, sum and count.
+                buckets.len() + if inf_seen { 2 } else { 3 }
+            }
+            // The quantiles, sum and count.
+            proto::MetricType::SUMMARY => m.get_summary().get_quantile().len() + 2,
+            proto::MetricType::COUNTER | proto::MetricType::GAUGE | proto::MetricType::UNTYPED => 1,
+        })
+        .sum()
+}
+
+/// A frozen result of [`Registry::snapshot`].
+///
+/// It decouples collection from encoding, so that the metrics gathered by a
+/// single collection can be rendered into multiple formats, e.g. text for a
+/// scraper and protobuf for storage, with consistent values.
+#[derive(Clone, Debug, Default)]
+pub struct MetricSnapshot {
+    metric_families: Vec<proto::MetricFamily>,
+}
+
+impl MetricSnapshot {
+    /// Return the gathered metric families.
+    pub fn metric_families(&self) -> &[proto::MetricFamily] {
+        &self.metric_families
+    }
+
+    /// Consume the snapshot and return the gathered metric families.
+    pub fn into_metric_families(self) -> Vec<proto::MetricFamily> {
+        self.metric_families
+    }
+
+    /// Encode the snapshot into the given [`Format`].
+    pub fn encode(&self, format: Format) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        format.encode(&self.metric_families, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Encode the snapshot into the Prometheus text format.
+    pub fn to_text(&self) -> Result<Vec<u8>> {
+        self.encode(Format::Text)
+    }
+
+    /// Encode the snapshot into the Prometheus protobuf format.
+    #[cfg(feature = "protobuf")]
+    pub fn to_protobuf(&self) -> Result<Vec<u8>> {
+        self.encode(Format::Protobuf)
+    }
+
+    /// Encode the snapshot into the OpenMetrics text format.
+    pub fn to_openmetrics(&self) -> Result<Vec<u8>> {
+        self.encode(Format::OpenMetrics)
     }
 }
 
 cfg_if! {
     if #[cfg(all(feature = "process", target_os="linux"))] {
-        fn register_default_process_collector(reg: &Registry) -> Result<()> {
+        fn default_process_collectors() -> Result<Vec<Box<dyn Collector>>> {
             use crate::process_collector::ProcessCollector;
 
-            let pc = ProcessCollector::for_self();
-            reg.register(Box::new(pc))
+            Ok(vec![Box::new(ProcessCollector::for_self())])
+        }
+    } else if #[cfg(feature = "process")] {
+        fn default_process_collectors() -> Result<Vec<Box<dyn Collector>>> {
+            use crate::gauge::start_time_collector;
+
+            // Only the start time is available on other platforms.
+            Ok(vec![Box::new(start_time_collector("")?)])
         }
     } else {
-        fn register_default_process_collector(_: &Registry) -> Result<()> {
-            Ok(())
+        fn default_process_collectors() -> Result<Vec<Box<dyn Collector>>> {
+            Ok(vec![])
         }
     }
 }
 
+/// The registry installed by `set_default_registry`, if any, and whether the
+/// default registry was already initialized, in which case it can no longer be
+/// replaced.
+#[derive(Default)]
+struct DefaultRegistrySlot {
+    initialized: bool,
+    installed: Option<Registry>,
+}
+
 // Default registry for rust-prometheus.
 lazy_static! {
+    static ref DEFAULT_REGISTRY_SLOT: Mutex<DefaultRegistrySlot> =
+        Mutex::new(DefaultRegistrySlot::default());
     static ref DEFAULT_REGISTRY: Registry = {
+        let mut slot = DEFAULT_REGISTRY_SLOT.lock();
+        slot.initialized = true;
+        if let Some(reg) = slot.installed.take() {
+            return reg;
+        }
+
         let reg = Registry::default();
 
         // Register a default process collector.
-        register_default_process_collector(&reg).unwrap();
+        reg.register_all(default_process_collectors().unwrap())
+            .unwrap();
 
         reg
     };
 }
 
+// Serializes the tests which use the default registry, as
+// `reset_default_registry` may run concurrently with them.
+#[cfg(test)]
+lazy_static! {
+    pub(crate) static ref DEFAULT_REGISTRY_TEST_LOCK: std::sync::Mutex<()> =
+        std::sync::Mutex::new(());
+}
+
 /// Default registry (global static).
 pub fn default_registry() -> &'static Registry {
     lazy_static::initialize(&DEFAULT_REGISTRY);
     &DEFAULT_REGISTRY
 }
 
+/// Install `registry` as the [default registry](default_registry) used by
+/// [`register`], [`gather`] and the `register_*!` macros, e.g. for an
+/// application embedding several libraries to isolate their metrics from other
+/// users of the process-wide default. The default process collector is not
+/// registered to the installed registry.
+///
+/// It must be called once, before anything uses the default registry,
+/// including the first metric registered by a `lazy_static`, which may run
+/// earlier than expected. An error is returned if the default registry is
+/// already in use or if a registry was installed before.
+pub fn set_default_registry(registry: Registry) -> Result<()> {
+    let mut slot = DEFAULT_REGISTRY_SLOT.lock();
+    if slot.initialized {
+        return Err(Error::Msg(
+            "the default registry is already in use and can not be replaced".to_owned(),
+        ));
+    }
+    if slot.installed.is_some() {
+        return Err(Error::Msg(
+            "a default registry has already been installed".to_owned(),
+        ));
+    }
+    slot.installed = Some(registry);
+    Ok(())
+}
+
 /// Registers a new [`Collector`] to be included in metrics collection. It
 /// returns an error if the descriptors provided by the [`Collector`] are invalid or
 /// if they - in combination with descriptors of already registered Collectors -
@@ -347,15 +794,74 @@ pub fn gather() -> Vec<proto::MetricFamily> {
     DEFAULT_REGISTRY.gather()
 }
 
+/// Return all `MetricFamily` of `DEFAULT_REGISTRY` encoded into the given
+/// [`Format`], along with the matching `Content-Type` header value.
+pub fn gather_encoded(format: Format) -> Result<(Vec<u8>, &'static str)> {
+    DEFAULT_REGISTRY.gather_encoded(format)
+}
+
+/// `merge_gathered` merges the metric families gathered from two registries,
+/// e.g. of two subsystems, into one list sorted by name like
+/// [`Registry::gather`] returns. The metric families of `b` whose name is also
+/// used in `a` are renamed to `<b_prefix>_<name>`, keeping their labels, so
+/// the names do not clash. The renamed names are not checked again.
+pub fn merge_gathered(
+    a: Vec<proto::MetricFamily>,
+    b: Vec<proto::MetricFamily>,
+    b_prefix: &str,
+) -> Vec<proto::MetricFamily> {
+    let names: HashSet<String> = a.iter().map(|mf| mf.get_name().to_owned()).collect();
+    let mut merged = a;
+    merged.reserve(b.len());
+    for mut mf in b {
+        if names.contains(mf.get_name()) {
+            let name = format!("{}_{}", b_prefix, mf.get_name());
+            mf.set_name(name);
+        }
+        merged.push(mf);
+    }
+    merged.sort_by(|m1, m2| m1.get_name().cmp(m2.get_name()));
+    merged
+}
+
+/// Register the collectors most applications want to `registry` in one call:
+/// the process collector of the current process, as registered to the
+/// [default registry](default_registry), and a `build_info` gauge carrying
+/// `build_labels`, see [`build_info_collector`](crate::build_info_collector).
+///
+/// The process collector is only available with the `process` feature, on
+/// platforms other than Linux only its start time is registered, and it is
+/// skipped without the feature. It is also skipped if it is registered
+/// already, as it is to the default registry. Either all collectors are
+/// registered or, on error, none of them.
+pub fn register_default_collectors(
+    registry: &Registry,
+    build_labels: HashMap<String, String>,
+) -> Result<()> {
+    let mut collectors = Vec::new();
+    for c in default_process_collectors()? {
+        if !registry.r.read().is_registered(&*c) {
+            collectors.push(c);
+        }
+    }
+    let build_info = crate::gauge::build_info_collector("", build_labels)?;
+    collectors.push(Box::new(build_info));
+    registry.register_all(collectors)
+}
+
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
     use std::thread;
 
     use super::*;
     use crate::counter::{Counter, CounterVec};
     use crate::desc::Desc;
-    use crate::metrics::{Collector, Opts};
+    #[cfg(feature = "protobuf")]
+    use crate::encoder::PROTOBUF_FORMAT;
+    use crate::encoder::{Encoder, TextEncoder, OPENMETRICS_FORMAT, TEXT_FORMAT};
+    use crate::gauge::Gauge;
+    use crate::histogram::{Histogram, HistogramOpts};
+    use crate::metrics::{Collector, Labels, MetricValue, Opts};
     use crate::proto;
 
     #[test]
@@ -386,8 +892,48 @@ mod tests {
         counter_vec.with_label_values(&["1", "2"]).inc();
     }
 
+    #[test]
+    fn test_gather_with_meta() {
+        let r = Registry::new();
+        let counter = Counter::new("test_counter", "test help").unwrap();
+        let vec = CounterVec::new(Opts::new("test_vec", "test help"), &["a"]).unwrap();
+        let histogram = Histogram::with_opts(
+            HistogramOpts::new("test_histogram", "test help").buckets(vec![1.0, 2.0]),
+        )
+        .unwrap();
+        r.register(Box::new(counter.clone())).unwrap();
+        r.register(Box::new(vec.clone())).unwrap();
+        r.register(Box::new(histogram.clone())).unwrap();
+        vec.with_label_values(&["1"]).inc();
+        vec.with_label_values(&["2"]).inc();
+        histogram.observe(1.5);
+
+        let (mfs, meta) = r.gather_with_meta();
+        // 1 counter, 2 children of the vec, 3 buckets including +Inf, sum and count.
+        assert_eq!(meta.sample_count, 8);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&mfs, &mut buffer).unwrap();
+        let samples = String::from_utf8(buffer)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .count();
+        assert_eq!(meta.sample_count, samples);
+
+        let meta_mfs = meta.metric_families();
+        assert_eq!(meta_mfs[0].get_name(), "scrape_duration_seconds");
+        assert_eq!(
+            meta_mfs[0].get_metric()[0].get_gauge().get_value(),
+            meta.duration.as_secs_f64()
+        );
+        assert_eq!(meta_mfs[1].get_name(), "scrape_samples_scraped");
+        assert_eq!(meta_mfs[1].get_metric()[0].get_gauge().get_value(), 8.0);
+    }
+
     #[test]
     fn test_default_registry() {
+        let _guard = DEFAULT_REGISTRY_TEST_LOCK.lock().unwrap();
         let counter = Counter::new("test", "test help").unwrap();
 
         assert!(register(Box::new(counter.clone())).is_ok());
@@ -567,6 +1113,256 @@ mod tests {
         r.register(Box::new(mc)).unwrap();
     }
 
+    struct FamilyCollector {
+        descs: Vec<Desc>,
+        mfs: Vec<proto::MetricFamily>,
+    }
+
+    impl Collector for FamilyCollector {
+        fn desc(&self) -> Vec<&Desc> {
+            self.descs.iter().collect()
+        }
+
+        fn collect(&self) -> Vec<proto::MetricFamily> {
+            self.mfs.clone()
+        }
+    }
+
+    #[test]
+    fn test_gather_merge_metric_families() {
+        let r = Registry::new();
+        for subsystem in &["a", "b"] {
+            let counter = Counter::with_opts(
+                Opts::new("test_counter", "test help").const_label("subsystem", *subsystem),
+            )
+            .unwrap();
+            counter.inc();
+            r.register(Box::new(counter)).unwrap();
+        }
+
+        let mfs = r.try_gather().unwrap();
+        assert_eq!(mfs.len(), 1);
+        assert_eq!(mfs[0].get_name(), "test_counter");
+        let metrics = mfs[0].get_metric();
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].get_label()[0].get_value(), "a");
+        assert_eq!(metrics[1].get_label()[0].get_value(), "b");
+
+        // A custom collector emitting an inconsistent family of the same name.
+        let gauge = Gauge::new("test_counter", "test help").unwrap();
+        gauge.set(1.0);
+        let collector = FamilyCollector {
+            descs: vec![Desc::new(
+                "test_gauge".to_owned(),
+                "test help".to_owned(),
+                vec![],
+                HashMap::new(),
+            )
+            .unwrap()],
+            mfs: gauge.collect(),
+        };
+        r.register(Box::new(collector)).unwrap();
+
+        let err = r.try_gather().unwrap_err();
+        assert!(err.to_string().contains("inconsistent"), "{}", err);
+        let mfs = r.gather();
+        assert_eq!(mfs.len(), 1);
+    }
+
+    #[test]
+    fn test_gather_conflict_keeps_first_registered() {
+        // Collectors emitting the family `test_family` as a counter or as a
+        // gauge, under distinct descriptors.
+        let collector = |kind: &str| {
+            let desc = Desc::new(
+                format!("test_{}", kind),
+                "test help".to_owned(),
+                vec![],
+                HashMap::new(),
+            )
+            .unwrap();
+            let mfs = if kind.starts_with("counter") {
+                Counter::new("test_family", "test help").unwrap().collect()
+            } else {
+                Gauge::new("test_family", "test help").unwrap().collect()
+            };
+            Box::new(FamilyCollector {
+                descs: vec![desc],
+                mfs,
+            })
+        };
+
+        for (first, second, kept) in &[
+            ("counter", "gauge", proto::MetricType::COUNTER),
+            ("gauge", "counter", proto::MetricType::GAUGE),
+        ] {
+            // Register more collectors than the two, so that a registration
+            // order matching the id order by chance is unlikely.
+            for i in 0..10 {
+                let r = Registry::new();
+                r.register(collector(&format!("{}_{}", first, i))).unwrap();
+                r.register(collector(&format!("{}_{}", second, i))).unwrap();
+                for j in 0..5 {
+                    r.register(collector(&format!("{}_{}_{}", second, i, j)))
+                        .unwrap();
+                }
+
+                let mfs = r.gather();
+                assert_eq!(mfs.len(), 1);
+                assert_eq!(mfs[0].get_field_type(), *kept);
+                let err = r.try_gather().unwrap_err();
+                assert!(
+                    err.to_string()
+                        .contains(&format!("inconsistent with type {:?}", kept)),
+                    "{}",
+                    err
+                );
+            }
+        }
+    }
+
+    type Sample = (String, Vec<(String, String)>, f64);
+
+    #[derive(Default)]
+    struct SampleVisitor {
+        family: String,
+        samples: Vec<Sample>,
+    }
+
+    impl MetricVisitor for SampleVisitor {
+        fn visit_family(&mut self, name: &str, _: &str, _: proto::MetricType) {
+            self.family = name.to_owned();
+        }
+
+        fn visit_metric(&mut self, labels: Labels<'_>, value: MetricValue<'_>) {
+            let labels = labels
+                .iter()
+                .map(|(name, value)| (name.to_owned(), value.to_owned()))
+                .collect();
+            let value = match value {
+                MetricValue::Counter(v) | MetricValue::Gauge(v) | MetricValue::Untyped(v) => v,
+                MetricValue::Histogram(h) => h.get_sample_count() as f64,
+                MetricValue::Summary(s) => s.get_sample_count() as f64,
+            };
+            self.samples.push((self.family.clone(), labels, value));
+        }
+    }
+
+    #[test]
+    fn test_register_arc() {
+        let r = Registry::new();
+        let counter = Counter::new("test_counter", "test help").unwrap();
+        let collector: Arc<dyn Collector> = Arc::new(counter.clone());
+        r.register_arc(collector.clone()).unwrap();
+        assert!(r.register_arc(collector.clone()).is_err());
+
+        counter.inc();
+        let mfs = r.gather();
+        assert_eq!(mfs.len(), 1);
+        assert_eq!(mfs[0].get_metric()[0].get_counter().get_value(), 1.0);
+        assert_eq!(collector.collect().len(), 1);
+
+        r.unregister(Box::new(collector.clone())).unwrap();
+        assert!(r.gather().is_empty());
+        assert_eq!(Arc::strong_count(&collector), 1);
+    }
+
+    #[test]
+    fn test_visit() {
+        let r = Registry::new();
+        let counter = Counter::new("test_counter", "test help").unwrap();
+        let vec = CounterVec::new(Opts::new("test_vec", "test help"), &["a"]).unwrap();
+        let empty_vec = CounterVec::new(Opts::new("test_empty", "test help"), &["a"]).unwrap();
+        let histogram =
+            Histogram::with_opts(HistogramOpts::new("test_histogram", "test help")).unwrap();
+        r.register(Box::new(counter.clone())).unwrap();
+        r.register(Box::new(vec.clone())).unwrap();
+        r.register(Box::new(empty_vec)).unwrap();
+        r.register(Box::new(histogram.clone())).unwrap();
+
+        counter.inc();
+        vec.with_label_values(&["1"]).inc_by(2.0);
+        vec.with_label_values(&["2"]).inc_by(3.0);
+        histogram.observe(1.0);
+
+        let mut visitor = SampleVisitor::default();
+        r.visit(&mut visitor);
+        visitor.samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let label = |v: &str| vec![("a".to_owned(), v.to_owned())];
+        assert_eq!(
+            visitor.samples,
+            vec![
+                ("test_counter".to_owned(), vec![], 1.0),
+                ("test_histogram".to_owned(), vec![], 1.0),
+                ("test_vec".to_owned(), label("1"), 2.0),
+                ("test_vec".to_owned(), label("2"), 3.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gather_encoded() {
+        let r = Registry::new();
+        let counter = Counter::new("test_counter", "test help").unwrap();
+        r.register(Box::new(counter.clone())).unwrap();
+        counter.inc();
+
+        let (buf, content_type) = r.gather_encoded("text".parse().unwrap()).unwrap();
+        assert_eq!(content_type, TEXT_FORMAT);
+        assert!(String::from_utf8(buf)
+            .unwrap()
+            .ends_with("# TYPE test_counter counter\ntest_counter 1\n"));
+
+        let (buf, content_type) = r.gather_encoded("openmetrics".parse().unwrap()).unwrap();
+        assert_eq!(content_type, OPENMETRICS_FORMAT);
+        assert!(String::from_utf8(buf)
+            .unwrap()
+            .ends_with("test_counter_total 1\n# EOF\n"));
+
+        #[cfg(feature = "protobuf")]
+        {
+            let (buf, content_type) = r.gather_encoded("protobuf".parse().unwrap()).unwrap();
+            assert_eq!(content_type, PROTOBUF_FORMAT);
+            assert!(!buf.is_empty());
+        }
+
+        assert!("json".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let r = Registry::new();
+        let counter = Counter::new("test_counter", "test help").unwrap();
+        r.register(Box::new(counter.clone())).unwrap();
+        counter.inc_by(2.0);
+
+        let snapshot = r.snapshot();
+        // Later updates are not part of the snapshot.
+        counter.inc();
+
+        let text = String::from_utf8(snapshot.to_text().unwrap()).unwrap();
+        assert!(text.ends_with("\ntest_counter 2\n"));
+        let text = String::from_utf8(snapshot.to_openmetrics().unwrap()).unwrap();
+        assert!(text.contains("\ntest_counter_total 2\n"));
+
+        #[cfg(feature = "protobuf")]
+        {
+            let buf = snapshot.to_protobuf().unwrap();
+            let mut is = protobuf::CodedInputStream::from_bytes(&buf);
+            let mf = is.read_message::<proto::MetricFamily>().unwrap();
+            assert_eq!(mf.get_metric()[0].get_counter().get_value(), 2.0);
+        }
+
+        assert_eq!(snapshot.metric_families().len(), 1);
+        assert_eq!(
+            r.snapshot().into_metric_families()[0].get_metric()[0]
+                .get_counter()
+                .get_value(),
+            3.0
+        );
+    }
+
     #[test]
     fn test_prune_empty_metric_family() {
         let counter_vec =
@@ -577,4 +1373,236 @@ mod tests {
         counter_vec.with_label_values(&["1", "2"]).inc();
         assert!(!r.gather().is_empty());
     }
+
+    #[test]
+    fn test_register_default_collectors() {
+        let r = Registry::new();
+        let mut labels = HashMap::new();
+        labels.insert("version".to_owned(), "1.2.3".to_owned());
+        register_default_collectors(&r, labels).unwrap();
+
+        let mfs = r.gather();
+        let build_info = mfs.iter().find(|mf| mf.get_name() == "build_info").unwrap();
+        let m = &build_info.get_metric()[0];
+        assert_eq!(m.get_gauge().get_value(), 1.0);
+        assert_eq!(m.get_label()[0].get_value(), "1.2.3");
+
+        #[cfg(all(feature = "process", target_os = "linux"))]
+        assert!(mfs.iter().any(|mf| mf.get_name().starts_with("process_")));
+
+        // Registering them twice is an error.
+        assert!(register_default_collectors(&r, HashMap::new()).is_err());
+
+        // On error, none of them is registered.
+        let r = Registry::new();
+        let build_info = crate::gauge::build_info_collector("", HashMap::new()).unwrap();
+        r.register(Box::new(build_info)).unwrap();
+        assert!(register_default_collectors(&r, HashMap::new()).is_err());
+        assert_eq!(r.num_collectors(), 1);
+    }
+
+    #[test]
+    fn test_register_default_collectors_default_registry() {
+        let _guard = DEFAULT_REGISTRY_TEST_LOCK.lock().unwrap();
+        let r = default_registry();
+        let num_collectors = r.num_collectors();
+        let mut labels = HashMap::new();
+        labels.insert("test_default_collectors".to_owned(), "1".to_owned());
+        let build_info = crate::gauge::build_info_collector("", labels.clone()).unwrap();
+
+        // The process collector of the default registry is kept.
+        register_default_collectors(r, labels).unwrap();
+        assert_eq!(r.num_collectors(), num_collectors + 1);
+        r.unregister(Box::new(build_info)).unwrap();
+    }
+
+    #[test]
+    fn test_merge_gathered() {
+        let a = Registry::new();
+        a.register(Box::new(Counter::new("requests", "a requests").unwrap()))
+            .unwrap();
+        a.register(Box::new(Counter::new("a_only", "test help").unwrap()))
+            .unwrap();
+        let b = Registry::new();
+        let requests = CounterVec::new(Opts::new("requests", "b requests"), &["l"]).unwrap();
+        requests.with_label_values(&["v"]).inc();
+        b.register(Box::new(requests)).unwrap();
+        b.register(Box::new(Counter::new("b_only", "test help").unwrap()))
+            .unwrap();
+
+        let mfs = merge_gathered(a.gather(), b.gather(), "sub");
+        let names: Vec<_> = mfs.iter().map(|mf| mf.get_name()).collect();
+        assert_eq!(names, vec!["a_only", "b_only", "requests", "sub_requests"]);
+
+        // Only the colliding family of b is renamed, keeping its labels.
+        assert_eq!(mfs[2].get_help(), "a requests");
+        assert_eq!(mfs[3].get_help(), "b requests");
+        let m = &mfs[3].get_metric()[0];
+        assert_eq!(m.get_label()[0].get_name(), "l");
+        assert_eq!(m.get_counter().get_value(), 1.0);
+    }
+
+    #[test]
+    fn test_registry_gather_one() {
+        struct PanicCollector {
+            desc: Desc,
+        }
+
+        impl Collector for PanicCollector {
+            fn desc(&self) -> Vec<&Desc> {
+                vec![&self.desc]
+            }
+
+            fn collect(&self) -> Vec<proto::MetricFamily> {
+                panic!("unrelated collector collected")
+            }
+        }
+
+        let r = Registry::new_custom(Some("ns".to_owned()), None).unwrap();
+        for v in &["1", "2"] {
+            let opts = Opts::new("test_requests_total", "test help").const_label("x", *v);
+            let counter = Counter::with_opts(opts).unwrap();
+            counter.inc();
+            r.register(Box::new(counter)).unwrap();
+        }
+        let desc = Desc::new(
+            "test_other".to_owned(),
+            "test help".to_owned(),
+            vec![],
+            HashMap::new(),
+        )
+        .unwrap();
+        r.register(Box::new(PanicCollector { desc })).unwrap();
+
+        let mf = r.gather_one("ns_test_requests_total").unwrap();
+        assert_eq!(mf.get_name(), "ns_test_requests_total");
+        assert_eq!(mf.get_metric().len(), 2);
+        assert_eq!(mf.get_metric()[0].get_label()[0].get_value(), "1");
+
+        assert!(r.gather_one("test_requests_total").is_none());
+        assert!(r.gather_one("ns_test_missing").is_none());
+    }
+
+    #[test]
+    fn test_registry_num_collectors() {
+        let r = Registry::new();
+        assert_eq!((r.num_collectors(), r.num_descs()), (0, 0));
+
+        let counter = Counter::new("test_total", "test help").unwrap();
+        r.register(Box::new(counter.clone())).unwrap();
+        // A peak gauge has two descriptors.
+        let gauge = crate::gauge::PeakGauge::new("test_peak", "test help").unwrap();
+        r.register(Box::new(gauge)).unwrap();
+        assert_eq!((r.num_collectors(), r.num_descs()), (2, 3));
+
+        r.unregister(Box::new(counter)).unwrap();
+        assert_eq!((r.num_collectors(), r.num_descs()), (1, 2));
+    }
+
+    #[test]
+    fn test_registry_register_all() {
+        let r = Registry::new();
+        let existing = Counter::new("test_existing_total", "test help").unwrap();
+        r.register(Box::new(existing.clone())).unwrap();
+
+        let collectors: Vec<Box<dyn Collector>> = vec![
+            Box::new(Counter::new("test_a_total", "test help").unwrap()),
+            Box::new(Gauge::new("test_b", "test help").unwrap()),
+            Box::new(existing),
+            Box::new(Gauge::new("test_c", "test help").unwrap()),
+        ];
+        assert!(matches!(r.register_all(collectors), Err(Error::AlreadyReg)));
+        assert_eq!(r.gather().len(), 1);
+
+        // Nothing is left behind, not even the label dimensions.
+        let a = CounterVec::new(Opts::new("test_a_total", "test help"), &["l"]).unwrap();
+        a.with_label_values(&["1"]).inc();
+        let collectors: Vec<Box<dyn Collector>> = vec![
+            Box::new(a),
+            Box::new(Gauge::new("test_b", "test help").unwrap()),
+        ];
+        r.register_all(collectors).unwrap();
+        assert_eq!(r.gather().len(), 3);
+    }
+
+    #[test]
+    fn test_registry_lint() {
+        let r = Registry::new();
+        let good = Counter::new("test_requests_total", "test help").unwrap();
+        r.register(Box::new(good)).unwrap();
+        assert!(r.lint().is_empty());
+
+        let counter = Counter::new("test_requests", " ").unwrap();
+        let gauge = Gauge::new("test_queue_total", "test help").unwrap();
+        let opts = HistogramOpts::new("test_latency_milliseconds", "test help");
+        let histogram = Histogram::with_opts(opts).unwrap();
+        r.register(Box::new(counter)).unwrap();
+        r.register(Box::new(gauge)).unwrap();
+        r.register(Box::new(histogram)).unwrap();
+
+        let warnings = r.lint();
+        assert_eq!(
+            warnings,
+            vec![
+                LintWarning::NonBaseUnit {
+                    name: "test_latency_milliseconds".to_owned(),
+                    unit: "milliseconds".to_owned(),
+                    base_unit: "seconds",
+                },
+                LintWarning::TotalWithoutCounter {
+                    name: "test_queue_total".to_owned(),
+                    metric_type: proto::MetricType::GAUGE,
+                },
+                LintWarning::MissingHelp {
+                    name: "test_requests".to_owned(),
+                },
+                LintWarning::CounterWithoutTotal {
+                    name: "test_requests".to_owned(),
+                },
+            ]
+        );
+        assert_eq!(warnings[2].name(), "test_requests");
+        assert_eq!(
+            warnings[3].to_string(),
+            "test_requests: counter name should end in _total"
+        );
+
+        // Only the descriptors are checked, nothing is collected.
+        struct PanicCollector {
+            desc: Desc,
+        }
+
+        impl Collector for PanicCollector {
+            fn desc(&self) -> Vec<&Desc> {
+                vec![&self.desc]
+            }
+
+            fn collect(&self) -> Vec<proto::MetricFamily> {
+                panic!("linting collected")
+            }
+        }
+
+        let r = Registry::new_custom(Some("ns".to_owned()), None).unwrap();
+        let desc = Desc::new(
+            "test_bytes_total".to_owned(),
+            " ".to_owned(),
+            vec![],
+            HashMap::new(),
+        )
+        .unwrap();
+        r.register(Box::new(PanicCollector { desc })).unwrap();
+        let vec = CounterVec::new(Opts::new("test_errors", "test help"), &["l"]).unwrap();
+        r.register(Box::new(vec)).unwrap();
+        assert_eq!(
+            r.lint(),
+            vec![
+                LintWarning::MissingHelp {
+                    name: "ns_test_bytes_total".to_owned(),
+                },
+                LintWarning::CounterWithoutTotal {
+                    name: "ns_test_errors".to_owned(),
+                },
+            ]
+        );
+    }
 }
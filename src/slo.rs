@@ -0,0 +1,209 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::time::Duration;
+
+use crate::counter::IntCounter;
+use crate::desc::Desc;
+use crate::histogram::{duration_to_seconds, Histogram};
+use crate::metrics::Collector;
+use crate::proto;
+
+/// A helper to track a latency service level objective, which keeps a latency
+/// [`Histogram`], a counter of total events and a counter of good events in
+/// lockstep.
+///
+/// An event is good if it succeeded within the latency objective. The ratio of
+/// good events to total events is the service level indicator, recording
+/// every event through an `SloRecorder` makes sure the denominator is never
+/// forgotten.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use prometheus::{Histogram, IntCounter, SloRecorder};
+///
+/// let slo = SloRecorder::new(
+///     Histogram::with_opts(prometheus::histogram_opts!("request_seconds", "Request latency.")).unwrap(),
+///     IntCounter::new("requests_total", "Total requests.").unwrap(),
+///     IntCounter::new("requests_good_total", "Requests served within 100ms.").unwrap(),
+///     Duration::from_millis(100),
+/// );
+///
+/// slo.record(Duration::from_millis(20));
+/// slo.record(Duration::from_millis(250));
+/// slo.record_error();
+/// assert_eq!(slo.total().get(), 3);
+/// assert_eq!(slo.good().get(), 1);
+/// ```
+#[derive(Clone, Debug)]
+pub struct SloRecorder {
+    latency: Histogram,
+    total: IntCounter,
+    good: IntCounter,
+    objective: Duration,
+}
+
+impl SloRecorder {
+    /// Create an `SloRecorder` recording into the given metrics. Events which
+    /// take at most `objective` are counted as good.
+    pub fn new(
+        latency: Histogram,
+        total: IntCounter,
+        good: IntCounter,
+        objective: Duration,
+    ) -> SloRecorder {
+        SloRecorder {
+            latency,
+            total,
+            good,
+            objective,
+        }
+    }
+
+    /// Record a successful event which took `duration`. It is observed by the
+    /// latency histogram and counted as total, and as good if it met the
+    /// objective.
+    pub fn record(&self, duration: Duration) {
+        self.latency.observe(duration_to_seconds(duration));
+        self.total.inc();
+        if duration <= self.objective {
+            self.good.inc();
+        }
+    }
+
+    /// Record a failed event. It is only counted as total.
+    pub fn record_error(&self) {
+        self.total.inc();
+    }
+
+    /// Return the latency histogram.
+    pub fn latency(&self) -> &Histogram {
+        &self.latency
+    }
+
+    /// Return the counter of total events.
+    pub fn total(&self) -> &IntCounter {
+        &self.total
+    }
+
+    /// Return the counter of good events.
+    pub fn good(&self) -> &IntCounter {
+        &self.good
+    }
+}
+
+impl Collector for SloRecorder {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut descs = self.latency.desc();
+        descs.extend(self.total.desc());
+        descs.extend(self.good.desc());
+        descs
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        let mut mfs = self.latency.collect();
+        mfs.extend(self.total.collect());
+        mfs.extend(self.good.collect());
+        mfs
+    }
+}
+
+/// Call `f`, observe its duration in seconds into `histogram` and count its
+/// outcome, i.e. increment `ok` if it returns `Ok` and `err` if it returns
+/// `Err`. The result of `f` is returned unchanged.
+///
+/// The duration is observed whatever the outcome, even if `f` panics, in
+/// which case neither counter is incremented.
+///
+/// # Examples
+///
+/// ```
+/// use prometheus::{Histogram, HistogramOpts, IntCounter};
+///
+/// let latency = Histogram::with_opts(HistogramOpts::new("call_seconds", "Call latency.")).unwrap();
+/// let ok = IntCounter::new("calls_ok_total", "Successful calls.").unwrap();
+/// let err = IntCounter::new("calls_err_total", "Failed calls.").unwrap();
+///
+/// let r: Result<u32, String> = prometheus::instrument(&latency, &ok, &err, || Ok(42));
+/// assert_eq!(r, Ok(42));
+/// assert_eq!(ok.get(), 1);
+/// ```
+pub fn instrument<T, E, F>(
+    histogram: &Histogram,
+    ok: &IntCounter,
+    err: &IntCounter,
+    f: F,
+) -> Result<T, E>
+where
+    F: FnOnce() -> Result<T, E>,
+{
+    // The timer observes on drop, so a panicking `f` is observed as well.
+    let timer = histogram.start_timer();
+    let res = f();
+    timer.observe_duration();
+    match res {
+        Ok(_) => ok.inc(),
+        Err(_) => err.inc(),
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::HistogramOpts;
+    use crate::registry::Registry;
+
+    #[test]
+    fn test_slo_recorder() {
+        let slo = SloRecorder::new(
+            Histogram::with_opts(
+                HistogramOpts::new("test_seconds", "test help").buckets(vec![0.05, 0.1, 0.5]),
+            )
+            .unwrap(),
+            IntCounter::new("test_total", "test help").unwrap(),
+            IntCounter::new("test_good_total", "test help").unwrap(),
+            Duration::from_millis(100),
+        );
+
+        let n = 100;
+        for i in 0..n {
+            slo.record(Duration::from_millis(i * 3));
+        }
+        slo.record_error();
+
+        // Events of at most 100ms are good, i.e. 0ms, 3ms, .., 99ms.
+        assert_eq!(slo.total().get(), n + 1);
+        assert_eq!(slo.good().get(), 34);
+        assert_eq!(slo.latency().get_sample_count(), n);
+        assert_eq!(slo.latency().cumulative_counts()[1], (0.1, 34));
+
+        let r = Registry::new();
+        r.register(Box::new(slo)).unwrap();
+        assert_eq!(r.gather().len(), 3);
+    }
+
+    #[test]
+    fn test_instrument() {
+        let histogram =
+            Histogram::with_opts(HistogramOpts::new("test_seconds", "test help")).unwrap();
+        let ok = IntCounter::new("test_ok_total", "test help").unwrap();
+        let err = IntCounter::new("test_err_total", "test help").unwrap();
+
+        let r: Result<u32, &str> = instrument(&histogram, &ok, &err, || Ok(1));
+        assert_eq!(r, Ok(1));
+        let r: Result<u32, &str> = instrument(&histogram, &ok, &err, || Err("failed"));
+        assert_eq!(r, Err("failed"));
+        assert_eq!(histogram.get_sample_count(), 2);
+        assert_eq!((ok.get(), err.get()), (1, 1));
+
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _: Result<u32, &str> = instrument(&histogram, &ok, &err, || panic!("boom"));
+        }));
+        assert!(res.is_err());
+        assert_eq!(histogram.get_sample_count(), 3);
+        assert_eq!((ok.get(), err.get()), (1, 1));
+    }
+}
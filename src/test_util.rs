@@ -0,0 +1,250 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Helpers for asserting on metrics in tests.
+//!
+//! This module is only available with the `test-util` feature.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::histogram::BUCKET_LABEL;
+use crate::proto::{self, MetricFamily, MetricType};
+use crate::registry::{default_registry, Registry};
+
+const QUANTILE_LABEL: &str = "quantile";
+const POSITIVE_INF: &str = "+Inf";
+
+/// The key of a sample returned by [`gather_as_map`]: the sample name and its
+/// labels sorted by label name.
+pub type SampleKey = (String, BTreeMap<String, String>);
+
+/// `gather_as_map` gathers all metrics of the given [`Registry`] and flattens
+/// them into a map from sample name and labels to sample value.
+///
+/// Counters and gauges produce a single sample named after the metric family.
+/// Histograms produce a `<name>_bucket` sample per bucket (labeled with `le`,
+/// including the implicit `+Inf` bucket) as well as `<name>_sum` and
+/// `<name>_count` samples. Summaries produce one `<name>` sample per quantile
+/// (labeled with `quantile`) as well as `<name>_sum` and `<name>_count`.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::BTreeMap;
+///
+/// use prometheus::{gather_as_map, IntCounterVec, Opts, Registry};
+///
+/// let r = Registry::new();
+/// let vec = IntCounterVec::new(Opts::new("requests", "help"), &["code"]).unwrap();
+/// r.register(Box::new(vec.clone())).unwrap();
+/// vec.with_label_values(&["200"]).inc_by(5);
+///
+/// let mut labels = BTreeMap::new();
+/// labels.insert("code".to_owned(), "200".to_owned());
+/// assert_eq!(gather_as_map(&r)[&("requests".to_owned(), labels)], 5.0);
+/// ```
+pub fn gather_as_map(registry: &Registry) -> HashMap<SampleKey, f64> {
+    let mut samples = HashMap::new();
+    for mf in registry.gather() {
+        flatten_metric_family(&mf, &mut samples);
+    }
+    samples
+}
+
+/// `reset_default_registry` unregisters all collectors from the
+/// [default registry](crate::default_registry), including the process collector
+/// registered with the `process` feature, so that tests sharing a process do
+/// not see each other's metrics or fail to register with `AlreadyReg`.
+///
+/// It is meant for tests only. It is not synchronized with scrapes or tests
+/// using the default registry concurrently, which may observe it half-way
+/// empty or lose their registrations.
+pub fn reset_default_registry() {
+    default_registry().unregister_all();
+}
+
+fn flatten_metric_family(mf: &MetricFamily, samples: &mut HashMap<SampleKey, f64>) {
+    let name = mf.get_name();
+    for m in mf.get_metric() {
+        let labels: BTreeMap<String, String> = m
+            .get_label()
+            .iter()
+            .map(|lp| (lp.get_name().to_owned(), lp.get_value().to_owned()))
+            .collect();
+        let mut insert = |postfix: &str, extra: Option<(&str, String)>, value: f64| {
+            let mut labels = labels.clone();
+            if let Some((k, v)) = extra {
+                labels.insert(k.to_owned(), v);
+            }
+            samples.insert((format!("{}{}", name, postfix), labels), value);
+        };
+
+        match mf.get_field_type() {
+            MetricType::COUNTER => insert("", None, m.get_counter().get_value()),
+            MetricType::GAUGE => insert("", None, m.get_gauge().get_value()),
+            MetricType::HISTOGRAM => {
+                let h: &proto::Histogram = m.get_histogram();
+                for b in h.get_bucket() {
+                    let upper_bound = b.get_upper_bound();
+                    if upper_bound.is_sign_positive() && upper_bound.is_infinite() {
+                        // The +Inf bucket is always added below.
+                        continue;
+                    }
+                    insert(
+                        "_bucket",
+                        Some((BUCKET_LABEL, upper_bound.to_string())),
+                        b.get_cumulative_count() as f64,
+                    );
+                }
+                insert(
+                    "_bucket",
+                    Some((BUCKET_LABEL, POSITIVE_INF.to_owned())),
+                    h.get_sample_count() as f64,
+                );
+                insert("_sum", None, h.get_sample_sum());
+                insert("_count", None, h.get_sample_count() as f64);
+            }
+            MetricType::SUMMARY => {
+                let s: &proto::Summary = m.get_summary();
+                for q in s.get_quantile() {
+                    insert(
+                        "",
+                        Some((QUANTILE_LABEL, q.get_quantile().to_string())),
+                        q.get_value(),
+                    );
+                }
+                insert("_sum", None, s.get_sample_sum());
+                insert("_count", None, s.get_sample_count() as f64);
+            }
+            MetricType::UNTYPED => insert("", None, m.get_untyped().get_value()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counter::{Counter, CounterVec};
+    use crate::desc::{Desc, Describer};
+    use crate::gauge::Gauge;
+    use crate::histogram::{Histogram, HistogramOpts};
+    use crate::metrics::{Collector, Opts};
+
+    fn key(name: &str, labels: &[(&str, &str)]) -> SampleKey {
+        let labels = labels
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+            .collect();
+        (name.to_owned(), labels)
+    }
+
+    #[test]
+    fn test_reset_default_registry() {
+        let _guard = crate::registry::DEFAULT_REGISTRY_TEST_LOCK.lock().unwrap();
+
+        let counter = Counter::new("test_reset_counter", "test help").unwrap();
+        let vec = CounterVec::new(Opts::new("test_reset_vec", "test help"), &["a"]).unwrap();
+        crate::register(Box::new(counter.clone())).unwrap();
+        crate::register(Box::new(vec.clone())).unwrap();
+        counter.inc();
+        vec.with_label_values(&["1"]).inc();
+        assert!(crate::gather().len() >= 2);
+
+        reset_default_registry();
+        assert!(crate::gather().is_empty());
+
+        // The names are free again, even with other label dimensions.
+        let vec = CounterVec::new(Opts::new("test_reset_vec", "test help"), &["b"]).unwrap();
+        crate::register(Box::new(counter.clone())).unwrap();
+        crate::register(Box::new(vec)).unwrap();
+        reset_default_registry();
+        assert!(crate::gather().is_empty());
+    }
+
+    #[test]
+    fn test_gather_as_map_counter() {
+        let r = Registry::new();
+        let counter = Counter::new("test_counter", "test help").unwrap();
+        let vec = CounterVec::new(Opts::new("test_vec", "test help"), &["b", "a"]).unwrap();
+        r.register(Box::new(counter.clone())).unwrap();
+        r.register(Box::new(vec.clone())).unwrap();
+
+        counter.inc_by(3.0);
+        vec.with_label_values(&["2", "1"]).inc_by(5.0);
+
+        let samples = gather_as_map(&r);
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[&key("test_counter", &[])], 3.0);
+        assert_eq!(samples[&key("test_vec", &[("a", "1"), ("b", "2")])], 5.0);
+    }
+
+    #[test]
+    fn test_gather_as_map_gauge() {
+        let r = Registry::new();
+        let gauge =
+            Gauge::with_opts(Opts::new("test_gauge", "test help").const_label("a", "1")).unwrap();
+        r.register(Box::new(gauge.clone())).unwrap();
+
+        gauge.set(-42.0);
+
+        let samples = gather_as_map(&r);
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[&key("test_gauge", &[("a", "1")])], -42.0);
+    }
+
+    #[test]
+    fn test_gather_as_map_histogram() {
+        let r = Registry::new();
+        let histogram = Histogram::with_opts(
+            HistogramOpts::new("test_histogram", "test help").buckets(vec![1.0, 2.0]),
+        )
+        .unwrap();
+        r.register(Box::new(histogram.clone())).unwrap();
+
+        histogram.observe(0.5);
+        histogram.observe(1.5);
+        histogram.observe(3.0);
+
+        let samples = gather_as_map(&r);
+        assert_eq!(samples.len(), 5);
+        assert_eq!(samples[&key("test_histogram_bucket", &[("le", "1")])], 1.0);
+        assert_eq!(samples[&key("test_histogram_bucket", &[("le", "2")])], 2.0);
+        assert_eq!(
+            samples[&key("test_histogram_bucket", &[("le", "+Inf")])],
+            3.0
+        );
+        assert_eq!(samples[&key("test_histogram_sum", &[])], 5.0);
+        assert_eq!(samples[&key("test_histogram_count", &[])], 3.0);
+    }
+
+    #[test]
+    fn test_gather_as_map_untyped() {
+        struct UntypedCollector {
+            desc: Desc,
+        }
+
+        impl Collector for UntypedCollector {
+            fn desc(&self) -> Vec<&Desc> {
+                vec![&self.desc]
+            }
+
+            fn collect(&self) -> Vec<MetricFamily> {
+                let mut m = proto::Metric::default();
+                m.mut_untyped().set_value(7.0);
+                let mut mf = MetricFamily::default();
+                mf.set_name(self.desc.fq_name.clone());
+                mf.set_help(self.desc.help.clone());
+                mf.set_field_type(MetricType::UNTYPED);
+                mf.set_metric(from_vec!(vec![m]));
+                vec![mf]
+            }
+        }
+
+        let r = Registry::new();
+        let desc = Opts::new("test_untyped", "test help").describe().unwrap();
+        r.register(Box::new(UntypedCollector { desc })).unwrap();
+
+        let samples = gather_as_map(&r);
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[&key("test_untyped", &[])], 7.0);
+    }
+}
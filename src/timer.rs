@@ -1,6 +1,7 @@
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::thread;
-use std::time::{Duration, Instant};
+use std::sync::{Mutex, Once};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use lazy_static::lazy_static;
 
@@ -34,6 +35,14 @@ pub fn now_millis() -> u64 {
     }
 }
 
+/// Returns seconds since the Unix epoch.
+pub(crate) fn unix_secs() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default()
+}
+
 /// Returns recent returned value by `now_millis`.
 pub fn recent_millis() -> u64 {
     RECENT.load(Ordering::Relaxed)
@@ -41,26 +50,89 @@ pub fn recent_millis() -> u64 {
 
 lazy_static! {
     static ref UPDATER_IS_RUNNING: AtomicBool = AtomicBool::new(false);
+    static ref UPDATER: Mutex<Option<Thread>> = Mutex::new(None);
 }
 
 const CHECK_UPDATE_INTERVAL: Duration = Duration::from_millis(200);
 
+/// The interval the background updater wakes up at, in milliseconds.
+static UPDATER_TICK: AtomicU64 = AtomicU64::new(CHECK_UPDATE_INTERVAL.as_millis() as u64);
+
+/// Whether the background updater calls `now_millis`, see `ensure_updater`.
+static UPDATES_RECENT: AtomicBool = AtomicBool::new(false);
+
 /// Ensures background updater is running, which will call `now_millis` periodically.
 pub fn ensure_updater() {
+    UPDATES_RECENT.store(true, Ordering::Relaxed);
+    spawn_updater();
+}
+
+/// Starts the background updater, which refreshes the cached clock every tick
+/// and calls `now_millis` every `CHECK_UPDATE_INTERVAL` once `ensure_updater`
+/// has been called.
+fn spawn_updater() {
     if UPDATER_IS_RUNNING
         .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
         .is_ok()
     {
-        std::thread::Builder::new()
+        let mut updater = UPDATER.lock().unwrap();
+        let handle = std::thread::Builder::new()
             .name("time updater".to_owned())
-            .spawn(|| loop {
-                thread::sleep(CHECK_UPDATE_INTERVAL);
-                now_millis();
+            .spawn(|| {
+                let mut next_update = elapsed_millis() + duration_to_millis(CHECK_UPDATE_INTERVAL);
+                loop {
+                    thread::park_timeout(Duration::from_millis(
+                        UPDATER_TICK.load(Ordering::Relaxed),
+                    ));
+                    let t = elapsed_millis();
+                    CACHED.store(t, Ordering::Relaxed);
+                    if t >= next_update {
+                        if UPDATES_RECENT.load(Ordering::Relaxed) {
+                            now_millis();
+                        }
+                        next_update = t + duration_to_millis(CHECK_UPDATE_INTERVAL);
+                    }
+                }
             })
             .unwrap();
+        *updater = Some(handle.thread().clone());
     }
 }
 
+/// Returns milliseconds since ANCHOR.
+fn elapsed_millis() -> u64 {
+    duration_to_millis(Instant::now().saturating_duration_since(*ANCHOR))
+}
+
+/// Milliseconds since ANCHOR, refreshed by the background updater.
+static CACHED: AtomicU64 = AtomicU64::new(0);
+static CACHED_CLOCK: Once = Once::new();
+
+/// The interval the cached clock is refreshed at.
+pub const CACHED_CLOCK_RESOLUTION: Duration = Duration::from_millis(1);
+
+/// Returns milliseconds since ANCHOR as of the last refresh of the cached
+/// clock, which is started by the first call.
+///
+/// The cached clock is refreshed by the background updater of
+/// `recent_millis`, which the first call starts if needed and makes wake up
+/// every `CACHED_CLOCK_RESOLUTION` for the rest of the process lifetime.
+pub fn cached_millis() -> u64 {
+    CACHED_CLOCK.call_once(|| {
+        CACHED.store(elapsed_millis(), Ordering::Relaxed);
+        UPDATER_TICK.store(
+            duration_to_millis(CACHED_CLOCK_RESOLUTION),
+            Ordering::Relaxed,
+        );
+        spawn_updater();
+        // The updater may be sleeping for a whole `CHECK_UPDATE_INTERVAL`.
+        if let Some(updater) = UPDATER.lock().unwrap().as_ref() {
+            updater.unpark();
+        }
+    });
+    CACHED.load(Ordering::Relaxed)
+}
+
 #[cfg(test)]
 mod tests {
     use std::thread;
@@ -84,4 +156,11 @@ mod tests {
         thread::sleep(super::CHECK_UPDATE_INTERVAL * 2);
         assert!(super::recent_millis() > now);
     }
+
+    #[test]
+    fn test_cached_millis() {
+        let start = super::cached_millis();
+        thread::sleep(Duration::from_millis(20));
+        assert!(super::cached_millis() > start);
+    }
 }
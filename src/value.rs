@@ -1,10 +1,21 @@
 // Copyright 2014 The Prometheus Authors
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
-use crate::atomic64::{Atomic, Number};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+#[cfg(feature = "protobuf")]
+use protobuf::well_known_types::Timestamp;
+
+use crate::atomic64::{Atomic, AtomicI64, Number};
 use crate::desc::{Desc, Describer};
 use crate::errors::{Error, Result};
-use crate::proto::{Counter, Gauge, LabelPair, Metric, MetricFamily, MetricType};
+use crate::metrics::{labels_eq, InternedLabelPair, Labels, MetricValue, MetricVisitor};
+#[cfg(not(feature = "protobuf"))]
+use crate::proto::Timestamp;
+use crate::proto::{LabelPair, Metric, MetricFamily, MetricType};
 
 /// `ValueType` is an enumeration of metric types that represent a simple value
 /// for [`Counter`] and [`Gauge`].
@@ -33,10 +44,30 @@ pub struct Value<P: Atomic> {
     pub desc: Desc,
     pub val: P,
     pub val_type: ValueType,
-    pub label_pairs: Vec<LabelPair>,
+    /// The label pairs of the metric, built once by [`make_label_pairs`].
+    pub label_pairs: Vec<InternedLabelPair>,
+    /// Milliseconds since the Unix epoch at which the value was sampled, 0 if
+    /// it has none, in which case the scraper assigns the scrape time.
+    pub timestamp_ms: AtomicI64,
 }
 
 impl<P: Atomic> Value<P> {
+    /// `set_help` changes the help string of the value, which is only possible
+    /// as long as it is not shared, i.e. before the metric holding it is
+    /// cloned or registered.
+    pub fn set_help(value: &mut Arc<Value<P>>, help: &str) -> Result<()> {
+        match Arc::get_mut(value) {
+            Some(v) => {
+                v.desc = v.desc.with_help(help)?;
+                Ok(())
+            }
+            None => Err(Error::Msg(format!(
+                "help of {} can not be changed after the metric is cloned or registered",
+                value.desc.fq_name
+            ))),
+        }
+    }
+
     pub fn new<D: Describer>(
         describer: &D,
         val_type: ValueType,
@@ -51,6 +82,7 @@ impl<P: Atomic> Value<P> {
             val: P::new(val),
             val_type,
             label_pairs,
+            timestamp_ms: AtomicI64::new(0),
         })
     }
 
@@ -62,11 +94,51 @@ impl<P: Atomic> Value<P> {
     #[inline]
     pub fn set(&self, val: P::T) {
         self.val.set(val);
+        self.clear_timestamp();
+    }
+
+    /// Set the value together with the time it was sampled at, in milliseconds
+    /// since the Unix epoch. The timestamp is exposed along with the value
+    /// until the value is changed by any other method, a timestamp of 0
+    /// removes it.
+    ///
+    /// The value and the timestamp are stored separately, so a concurrent
+    /// collect may pair the new value with the previous timestamp or the other
+    /// way around.
+    #[inline]
+    pub fn set_with_timestamp(&self, val: P::T, timestamp_ms: i64) {
+        self.val.set(val);
+        self.timestamp_ms.set(timestamp_ms);
+    }
+
+    /// `clear_timestamp` removes the timestamp of a previous
+    /// `set_with_timestamp`, as it does not apply to a changed value. It only
+    /// writes if there is a timestamp, to keep updates without one cheap.
+    #[inline]
+    fn clear_timestamp(&self) {
+        if self.timestamp_ms.get() != 0 {
+            self.timestamp_ms.set(0);
+        }
+    }
+
+    /// Reset the value to 0.
+    pub fn reset(&self) {
+        self.val.set(P::T::from_i64(0));
+        self.clear_timestamp();
+    }
+
+    /// Like [`reset`](Value::reset), but reads and resets the value in one
+    /// atomic operation, returning the value before the reset.
+    pub fn swap_reset(&self) -> P::T {
+        let val = Atomic::swap(&self.val, P::T::from_i64(0));
+        self.clear_timestamp();
+        val
     }
 
     #[inline]
     pub fn inc_by(&self, val: P::T) {
         self.val.inc_by(val);
+        self.clear_timestamp();
     }
 
     #[inline]
@@ -74,6 +146,15 @@ impl<P: Atomic> Value<P> {
         self.inc_by(P::T::from_i64(1));
     }
 
+    /// `inc_by_return` increments the value and returns the new value.
+    #[inline]
+    pub fn inc_by_return(&self, val: P::T) -> P::T {
+        let mut new = self.val.fetch_add(val);
+        self.clear_timestamp();
+        new += val;
+        new
+    }
+
     #[inline]
     pub fn dec(&self) {
         self.dec_by(P::T::from_i64(1));
@@ -81,28 +162,32 @@ impl<P: Atomic> Value<P> {
 
     #[inline]
     pub fn dec_by(&self, val: P::T) {
-        self.val.dec_by(val)
+        self.val.dec_by(val);
+        self.clear_timestamp();
     }
 
     pub fn metric(&self) -> Metric {
         let mut m = Metric::default();
-        m.set_label(from_vec!(self.label_pairs.clone()));
+        m.set_label(from_vec!(to_proto_label_pairs(&self.label_pairs)));
+        self.fill_metric(&mut m);
+        m
+    }
 
-        let val = self.get();
-        match self.val_type {
-            ValueType::Counter => {
-                let mut counter = Counter::default();
-                counter.set_value(val.into_f64());
-                m.set_counter(counter);
-            }
-            ValueType::Gauge => {
-                let mut gauge = Gauge::default();
-                gauge.set_value(val.into_f64());
-                m.set_gauge(gauge);
-            }
+    /// `fill_metric` sets the value and timestamps of `m`, overwriting the
+    /// previous ones in place.
+    fn fill_metric(&self, m: &mut Metric) {
+        let timestamp_ms = self.timestamp_ms.get();
+        if timestamp_ms != 0 {
+            m.set_timestamp_ms(timestamp_ms);
+        } else {
+            m.clear_timestamp_ms();
         }
 
-        m
+        let val = self.get().into_f64();
+        match self.val_type {
+            ValueType::Counter => m.mut_counter().set_value(val),
+            ValueType::Gauge => m.mut_gauge().set_value(val),
+        }
     }
 
     pub fn collect(&self) -> MetricFamily {
@@ -113,9 +198,107 @@ impl<P: Atomic> Value<P> {
         m.set_metric(from_vec!(vec![self.metric()]));
         m
     }
+
+    /// `collect_into` is like [`collect`](Value::collect), but refills `mf`
+    /// in place, so the allocations of a previous call are reused.
+    pub fn collect_into(&self, mf: &mut MetricFamily) {
+        let m = reuse_metric_family(
+            mf,
+            &self.desc,
+            self.val_type.metric_type(),
+            &self.label_pairs,
+        );
+        self.fill_metric(m);
+    }
+
+    pub fn visit_metric(&self, visitor: &mut dyn MetricVisitor) {
+        let val = self.get().into_f64();
+        let value = match self.val_type {
+            ValueType::Counter => MetricValue::Counter(val),
+            ValueType::Gauge => MetricValue::Gauge(val),
+        };
+        visitor.visit_metric(Labels::Interned(&self.label_pairs), value);
+    }
+
+    pub fn visit(&self, visitor: &mut dyn MetricVisitor) {
+        visitor.visit_family(
+            &self.desc.fq_name,
+            &self.desc.help,
+            self.val_type.metric_type(),
+        );
+        self.visit_metric(visitor);
+    }
+}
+
+/// `reuse_metric_family` prepares `mf` to hold the only metric of `desc` with
+/// the given type and label pairs, returning that metric. The name, help,
+/// metric and labels are only replaced if they differ, so that refilling a
+/// metric family for the same metric does not allocate.
+pub fn reuse_metric_family<'a>(
+    mf: &'a mut MetricFamily,
+    desc: &Desc,
+    metric_type: MetricType,
+    label_pairs: &[InternedLabelPair],
+) -> &'a mut Metric {
+    if mf.get_name() != desc.fq_name {
+        mf.set_name(desc.fq_name.clone());
+    }
+    if mf.get_help() != desc.help {
+        mf.set_help(desc.help.clone());
+    }
+    // A metric of another type may still have the value of that type set.
+    if mf.get_field_type() != metric_type || mf.get_metric().len() != 1 {
+        mf.set_field_type(metric_type);
+        mf.set_metric(from_vec!(vec![Metric::default()]));
+    }
+
+    let m = &mut mf.mut_metric()[0];
+    if !labels_eq(m.get_label(), label_pairs) {
+        m.set_label(from_vec!(to_proto_label_pairs(label_pairs)));
+    }
+    m
+}
+
+/// `make_timestamp` converts seconds since the Unix epoch to a protobuf
+/// timestamp.
+pub fn make_timestamp(secs: f64) -> Timestamp {
+    let mut timestamp = Timestamp::default();
+    timestamp.set_seconds(secs.floor() as i64);
+    timestamp.set_nanos(((secs - secs.floor()) * 1e9) as i32);
+    timestamp
 }
 
-pub fn make_label_pairs(desc: &Desc, label_values: &[&str]) -> Result<Vec<LabelPair>> {
+lazy_static! {
+    static ref LABEL_NAMES: Mutex<HashSet<Arc<str>>> = Mutex::new(HashSet::new());
+}
+
+/// `intern_label_name` returns the shared copy of the label name `name`. Label
+/// names come from the metric definitions rather than from the observed data,
+/// so there are few of them and the interned names are never freed.
+fn intern_label_name(name: &str) -> Arc<str> {
+    let mut names = LABEL_NAMES.lock();
+    if let Some(name) = names.get(name) {
+        return name.clone();
+    }
+    let name: Arc<str> = Arc::from(name);
+    names.insert(name.clone());
+    name
+}
+
+/// `to_proto_label_pairs` converts interned label pairs to protocol ones.
+pub fn to_proto_label_pairs(label_pairs: &[InternedLabelPair]) -> Vec<LabelPair> {
+    label_pairs
+        .iter()
+        .map(InternedLabelPair::to_proto)
+        .collect()
+}
+
+/// `make_label_pairs` pairs the variable labels of `desc` with
+/// `label_values` and merges them with the const label pairs. The label pairs
+/// are always sorted by name, whether the metric has variable labels, const
+/// labels or both. Their names are interned, so that the children of a vec
+/// share them.
+pub fn make_label_pairs(desc: &Desc, label_values: &[&str]) -> Result<Vec<InternedLabelPair>> {
     if desc.variable_labels.len() != label_values.len() {
         return Err(Error::InconsistentCardinality {
             expect: desc.variable_labels.len(),
@@ -128,21 +311,73 @@ pub fn make_label_pairs(desc: &Desc, label_values: &[&str]) -> Result<Vec<LabelP
         return Ok(vec![]);
     }
 
-    if desc.variable_labels.is_empty() {
-        return Ok(desc.const_label_pairs.clone());
-    }
-
     let mut label_pairs = Vec::with_capacity(total_len);
-    for (i, n) in desc.variable_labels.iter().enumerate() {
-        let mut label_pair = LabelPair::default();
-        label_pair.set_name(n.clone());
-        label_pair.set_value(label_values[i].to_owned());
-        label_pairs.push(label_pair);
+    for (n, v) in desc.variable_labels.iter().zip(label_values) {
+        label_pairs.push(InternedLabelPair::new(
+            intern_label_name(n),
+            (*v).to_owned(),
+        ));
     }
-
-    for label_pair in &desc.const_label_pairs {
-        label_pairs.push(label_pair.clone());
+    for lp in &desc.const_label_pairs {
+        label_pairs.push(InternedLabelPair::new(
+            intern_label_name(lp.get_name()),
+            lp.get_value().to_owned(),
+        ));
+    }
+    // Const label pairs are sorted when the desc is built.
+    if !desc.variable_labels.is_empty() {
+        label_pairs.sort();
     }
-    label_pairs.sort();
     Ok(label_pairs)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_make_label_pairs_sorted() {
+        let names = |pairs: Vec<InternedLabelPair>| {
+            pairs
+                .iter()
+                .map(|lp| lp.name().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        let desc = Desc::new(
+            "test".to_owned(),
+            "test help".to_owned(),
+            vec!["c".to_owned(), "a".to_owned(), "b".to_owned()],
+            HashMap::new(),
+        )
+        .unwrap();
+        let pairs = make_label_pairs(&desc, &["3", "1", "2"]).unwrap();
+        assert_eq!(names(pairs.clone()), vec!["a", "b", "c"]);
+        assert_eq!(pairs[0].value(), "1");
+
+        let mut const_labels = HashMap::new();
+        const_labels.insert("d".to_owned(), "4".to_owned());
+        const_labels.insert("b".to_owned(), "2".to_owned());
+        let desc = Desc::new(
+            "test".to_owned(),
+            "test help".to_owned(),
+            vec!["c".to_owned(), "a".to_owned()],
+            const_labels.clone(),
+        )
+        .unwrap();
+        let pairs = make_label_pairs(&desc, &["3", "1"]).unwrap();
+        assert_eq!(names(pairs), vec!["a", "b", "c", "d"]);
+
+        let desc = Desc::new(
+            "test".to_owned(),
+            "test help".to_owned(),
+            vec![],
+            const_labels,
+        )
+        .unwrap();
+        let pairs = make_label_pairs(&desc, &[]).unwrap();
+        assert_eq!(names(pairs), vec!["b", "d"]);
+    }
+}
@@ -3,15 +3,22 @@
 
 use std::collections::HashMap;
 use std::hash::Hasher;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 
 use fnv::FnvHasher;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 
 use crate::desc::{Desc, Describer};
 use crate::errors::{Error, Result};
-use crate::metrics::{Collector, Metric};
-use crate::proto::{MetricFamily, MetricType};
+use crate::metrics::{Collector, Metric, MetricVisitor, Opts};
+use crate::proto::{self, LabelPair, MetricFamily, MetricType};
+
+/// The bit pattern of the NaN value Prometheus uses to mark a series as
+/// stale, as emitted for the series removed by
+/// [`MetricVec::remove_label_values_stale`]. It differs from the NaN of
+/// [`f64::NAN`], use `f64::to_bits` to tell them apart.
+pub const STALE_MARKER_BITS: u64 = 0x7ff0_0000_0000_0002;
 
 /// An interface for building a metric vector.
 pub trait MetricVecBuilder: Send + Sync + Clone {
@@ -31,6 +38,14 @@ pub(crate) struct MetricVecCore<T: MetricVecBuilder> {
     pub metric_type: MetricType,
     pub new_metric: T,
     pub opts: T::P,
+    /// The hashes and label pairs of the series removed by
+    /// `delete_label_values_stale` since the previous `collect_stale`.
+    pub stale: Mutex<Vec<(u64, Vec<LabelPair>)>>,
+    /// Whether `with_label_values` and `with` panic on a cardinality
+    /// mismatch instead of returning a detached metric.
+    pub panic_on_cardinality_mismatch: AtomicBool,
+    /// The number of cardinality mismatches which did not panic.
+    pub cardinality_mismatches: AtomicU64,
 }
 
 impl<T: MetricVecBuilder> MetricVecCore<T> {
@@ -49,6 +64,65 @@ impl<T: MetricVecBuilder> MetricVecCore<T> {
         m
     }
 
+    pub fn visit(&self, visitor: &mut dyn MetricVisitor) {
+        let children = self.children.read();
+        if children.is_empty() {
+            return;
+        }
+
+        visitor.visit_family(&self.desc.fq_name, &self.desc.help, self.metric_type);
+        for child in children.values() {
+            child.visit(self.metric_type, visitor);
+        }
+    }
+
+    /// `collect_stale` collects a metric family with one sample holding the
+    /// stale marker for each series removed since the previous call, and
+    /// clears them. It returns `None` if there are none.
+    pub fn collect_stale(&self) -> Option<MetricFamily> {
+        let children = self.children.read();
+        let stale = self.take_stale(&children);
+        if stale.is_empty() {
+            return None;
+        }
+
+        let mut m = MetricFamily::default();
+        m.set_name(self.desc.fq_name.clone());
+        m.set_help(self.desc.help.clone());
+        m.set_field_type(self.metric_type);
+
+        let stale_marker = f64::from_bits(STALE_MARKER_BITS);
+        let metrics: Vec<proto::Metric> = stale
+            .into_iter()
+            .map(|label_pairs| {
+                let mut metric = proto::Metric::default();
+                metric.set_label(from_vec!(label_pairs));
+                if self.metric_type == MetricType::COUNTER {
+                    metric.mut_counter().set_value(stale_marker);
+                } else {
+                    metric.mut_gauge().set_value(stale_marker);
+                }
+                metric
+            })
+            .collect();
+        m.set_metric(from_vec!(metrics));
+        Some(m)
+    }
+
+    /// `take_stale` takes the label pairs of the series removed since the
+    /// previous call, skipping those which were created again since.
+    fn take_stale(&self, children: &HashMap<u64, T::M>) -> Vec<Vec<LabelPair>> {
+        let mut stale = self.stale.lock();
+        if stale.is_empty() {
+            return vec![];
+        }
+        stale
+            .drain(..)
+            .filter(|(h, _)| !children.contains_key(h))
+            .map(|(_, label_pairs)| label_pairs)
+            .collect()
+    }
+
     pub fn get_metric_with_label_values(&self, vals: &[&str]) -> Result<T::M> {
         let h = self.hash_label_values(vals)?;
 
@@ -81,6 +155,26 @@ impl<T: MetricVecBuilder> MetricVecCore<T> {
         Ok(())
     }
 
+    pub fn delete_label_values_stale(&self, vals: &[&str]) -> Result<()> {
+        if self.metric_type != MetricType::COUNTER && self.metric_type != MetricType::GAUGE {
+            return Err(Error::Msg(format!(
+                "stale markers are only supported for counters and gauges, {} is a {:?}",
+                self.desc.fq_name, self.metric_type
+            )));
+        }
+        let h = self.hash_label_values(vals)?;
+
+        let mut children = self.children.write();
+        let metric = match children.remove(&h) {
+            Some(metric) => metric,
+            None => return Err(Error::Msg(format!("missing label values {:?}", vals))),
+        };
+        let label_pairs = metric.metric().get_label().to_vec();
+        self.stale.lock().push((h, label_pairs));
+
+        Ok(())
+    }
+
     pub fn delete(&self, labels: &HashMap<&str, &str>) -> Result<()> {
         let h = self.hash_labels(labels)?;
 
@@ -92,6 +186,14 @@ impl<T: MetricVecBuilder> MetricVecCore<T> {
         Ok(())
     }
 
+    pub fn replace_label_values(&self, vals: &[&str]) -> Result<T::M> {
+        let h = self.hash_label_values(vals)?;
+
+        let metric = self.new_metric.build(&self.opts, vals)?;
+        self.children.write().insert(h, metric.clone());
+        Ok(metric)
+    }
+
     /// `reset` deletes all metrics in this vector.
     pub fn reset(&self) {
         self.children.write().clear();
@@ -193,6 +295,9 @@ impl<T: MetricVecBuilder> MetricVec<T> {
             metric_type,
             new_metric,
             opts,
+            stale: Mutex::new(vec![]),
+            panic_on_cardinality_mismatch: AtomicBool::new(true),
+            cardinality_mismatches: AtomicU64::new(0),
         };
 
         Ok(MetricVec { v: Arc::new(v) })
@@ -242,7 +347,9 @@ impl<T: MetricVecBuilder> MetricVec<T> {
     }
 
     /// `with_label_values` works as `get_metric_with_label_values`, but panics if an error
-    /// occurs.
+    /// occurs. See
+    /// [`set_panic_on_cardinality_mismatch`](MetricVec::set_panic_on_cardinality_mismatch)
+    /// to not panic on a wrong number of label values.
     ///
     /// # Examples
     ///
@@ -255,14 +362,56 @@ impl<T: MetricVecBuilder> MetricVec<T> {
     /// vec.with_label_values(&["404", "POST"]).inc()
     /// ```
     pub fn with_label_values(&self, vals: &[&str]) -> T::M {
-        self.get_metric_with_label_values(vals).unwrap()
+        let res = self.get_metric_with_label_values(vals);
+        self.unwrap_or_detached(res)
     }
 
     /// `with` works as `get_metric_with`, but panics if an error occurs. The method allows
     /// neat syntax like:
     ///     httpReqs.with(Labels{"status":"404", "method":"POST"}).inc()
     pub fn with(&self, labels: &HashMap<&str, &str>) -> T::M {
-        self.get_metric_with(labels).unwrap()
+        let res = self.get_metric_with(labels);
+        self.unwrap_or_detached(res)
+    }
+
+    /// `set_panic_on_cardinality_mismatch` sets whether
+    /// [`with_label_values`](MetricVec::with_label_values) and
+    /// [`with`](MetricVec::with) panic when given a wrong number of labels.
+    /// It is enabled by default, which is the right choice for static label
+    /// sets where a mismatch is a programming error.
+    ///
+    /// When disabled, a mismatch returns a detached metric instead, which is
+    /// usable but never exported, and is counted in
+    /// [`cardinality_mismatches`](MetricVec::cardinality_mismatches). This
+    /// keeps a service with dynamic label sets available at the cost of
+    /// silently losing the affected updates, so make sure to alert on the
+    /// count. The setting is shared by all clones of this vector.
+    pub fn set_panic_on_cardinality_mismatch(&self, panic: bool) {
+        self.v
+            .panic_on_cardinality_mismatch
+            .store(panic, Ordering::Relaxed);
+    }
+
+    /// Return the number of cardinality mismatches which returned a detached
+    /// metric instead of panicking, see
+    /// [`set_panic_on_cardinality_mismatch`](MetricVec::set_panic_on_cardinality_mismatch).
+    pub fn cardinality_mismatches(&self) -> u64 {
+        self.v.cardinality_mismatches.load(Ordering::Relaxed)
+    }
+
+    fn unwrap_or_detached(&self, res: Result<T::M>) -> T::M {
+        match res {
+            Err(Error::InconsistentCardinality { .. })
+                if !self.v.panic_on_cardinality_mismatch.load(Ordering::Relaxed) =>
+            {
+                self.v
+                    .cardinality_mismatches
+                    .fetch_add(1, Ordering::Relaxed);
+                let vals = vec![""; self.v.desc.variable_labels.len()];
+                self.v.new_metric.build(&self.v.opts, &vals).unwrap()
+            }
+            res => res.unwrap(),
+        }
     }
 
     /// `remove_label_values` removes the metric where the variable labels are the same
@@ -281,6 +430,22 @@ impl<T: MetricVecBuilder> MetricVec<T> {
         self.v.delete_label_values(vals)
     }
 
+    /// `remove_label_values_stale` works as
+    /// [`remove_label_values`](MetricVec::remove_label_values), but the next
+    /// [`Registry::gather_with_stale`](crate::Registry::gather_with_stale)
+    /// emits the removed series once more with the Prometheus staleness marker
+    /// as value, a NaN with the bits of
+    /// [`STALE_MARKER_BITS`](crate::STALE_MARKER_BITS), so that consumers of
+    /// pushed or remotely written metrics stop showing its last value right
+    /// away. Other ways of collecting do not emit or clear the marker. No
+    /// marker is emitted if the series is created again before.
+    ///
+    /// It returns an error for vectors other than counters and gauges, whose
+    /// values can not hold the marker.
+    pub fn remove_label_values_stale(&self, vals: &[&str]) -> Result<()> {
+        self.v.delete_label_values_stale(vals)
+    }
+
     /// `remove` removes the metric where the variable labels are the same as those
     /// passed in as labels. It returns true if a metric was deleted.
     ///
@@ -293,10 +458,158 @@ impl<T: MetricVecBuilder> MetricVec<T> {
         self.v.delete(labels)
     }
 
+    /// `replace_label_values` replaces the metric where the variable labels
+    /// are the same as those passed in as labels (same order as the
+    /// VariableLabels in Desc) with a new zeroed one, and returns the new
+    /// metric. A metric is created if there was none.
+    ///
+    /// Unlike [`remove_label_values`](MetricVec::remove_label_values) followed
+    /// by [`with_label_values`](MetricVec::with_label_values), the metric is
+    /// replaced under a single lock acquisition, so a concurrent collection
+    /// sees either the old or the new metric and never misses it. Handles to
+    /// the old metric are detached from the vector.
+    ///
+    /// It returns an error if the number of label values is not the same as the
+    /// number of VariableLabels in Desc.
+    pub fn replace_label_values(&self, vals: &[&str]) -> Result<T::M> {
+        self.v.replace_label_values(vals)
+    }
+
+    /// `label_values_hash` returns the hash of the label values (same order as
+    /// the VariableLabels in Desc) which keys the metrics of this vector, e.g.
+    /// to key a side cache the same way as
+    /// [`LocalHistogramVec`](crate::local::LocalHistogramVec) does.
+    ///
+    /// The hash is stable within a process run, but not across versions of
+    /// this crate.
+    ///
+    /// It returns an error if the number of label values is not the same as the
+    /// number of VariableLabels in Desc.
+    pub fn label_values_hash(&self, vals: &[&str]) -> Result<u64> {
+        self.v.hash_label_values(vals)
+    }
+
     /// `reset` deletes all metrics in this vector.
     pub fn reset(&self) {
         self.v.reset()
     }
+
+    /// `label_set` returns a [`LabelSet`] builder for the variable labels of
+    /// this vector, which reports unknown and missing label names more clearly
+    /// than [`get_metric_with`](MetricVec::get_metric_with).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use prometheus::{CounterVec, Opts};
+    /// let vec = CounterVec::new(
+    ///     Opts::new("requests_total", "Number of requests."),
+    ///     &["code", "http_method"]
+    /// ).unwrap();
+    ///
+    /// let mut labels = vec.label_set();
+    /// labels.set("code", "404").unwrap();
+    /// assert!(labels.set("method", "POST").is_err());
+    /// labels.set("http_method", "POST").unwrap();
+    /// vec.with(&labels.build().unwrap()).inc();
+    /// ```
+    pub fn label_set<'a>(&self) -> LabelSet<'_, 'a> {
+        LabelSet {
+            fq_name: &self.v.desc.fq_name,
+            names: &self.v.desc.variable_labels,
+            labels: HashMap::new(),
+        }
+    }
+}
+
+/// A builder of label maps for a [`MetricVec`], see
+/// [`MetricVec::label_set`].
+///
+/// Label names are checked against the variable labels of the vector when
+/// they are set, and [`build`](LabelSet::build) checks that none is missing.
+#[derive(Debug)]
+pub struct LabelSet<'v, 'a> {
+    fq_name: &'v str,
+    names: &'v [String],
+    labels: HashMap<&'a str, &'a str>,
+}
+
+impl<'v, 'a> LabelSet<'v, 'a> {
+    /// `set` sets the value of the label `name`, replacing the previous value
+    /// if any. An error is returned if `name` is not a variable label of the
+    /// vector.
+    pub fn set(&mut self, name: &'a str, value: &'a str) -> Result<()> {
+        if !self.names.iter().any(|n| n == name) {
+            return Err(Error::Msg(format!(
+                "label name {} is not a variable label of {}, expected one of {:?}",
+                name, self.fq_name, self.names
+            )));
+        }
+        self.labels.insert(name, value);
+        Ok(())
+    }
+
+    /// `build` returns the label map. An error is returned if a variable label
+    /// of the vector has not been set.
+    pub fn build(self) -> Result<HashMap<&'a str, &'a str>> {
+        let missing: Vec<_> = self
+            .names
+            .iter()
+            .filter(|n| !self.labels.contains_key(n.as_str()))
+            .collect();
+        if !missing.is_empty() {
+            return Err(Error::Msg(format!(
+                "label names {:?} of {} are not set",
+                missing, self.fq_name
+            )));
+        }
+        Ok(self.labels)
+    }
+}
+
+impl<T: MetricVecBuilder> MetricVec<T>
+where
+    T::P: AsMut<Opts>,
+{
+    /// `clone_with_const_label` creates a new, empty [`MetricVec`] with the
+    /// same options and variable labels as this one, plus the given const
+    /// label. The returned vector shares no metrics with this one, which makes
+    /// it handy for per-shard or per-tenant metric families.
+    ///
+    /// An error is returned if `name` is already used as a const or variable
+    /// label name.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use prometheus::{CounterVec, Opts};
+    /// let vec = CounterVec::new(
+    ///     Opts::new("requests_total", "Number of requests."),
+    ///     &["code"]
+    /// ).unwrap();
+    /// let shard_0 = vec.clone_with_const_label("shard", "0").unwrap();
+    /// shard_0.with_label_values(&["200"]).inc();
+    /// assert_eq!(vec.with_label_values(&["200"]).get() as u64, 0);
+    /// ```
+    pub fn clone_with_const_label(&self, name: &str, value: &str) -> Result<Self> {
+        let mut opts = self.v.opts.clone();
+        {
+            let common_opts = opts.as_mut();
+            if common_opts.const_labels.contains_key(name)
+                || common_opts.variable_labels.iter().any(|l| l == name)
+            {
+                return Err(Error::Msg(format!(
+                    "label name {} is already used by {}",
+                    name, self.v.desc.fq_name
+                )));
+            }
+            common_opts
+                .const_labels
+                .insert(name.to_owned(), value.to_owned());
+        }
+
+        MetricVec::create(self.v.metric_type, self.v.new_metric.clone(), opts)
+    }
 }
 
 impl<T: MetricVecBuilder> Collector for MetricVec<T> {
@@ -307,15 +620,87 @@ impl<T: MetricVecBuilder> Collector for MetricVec<T> {
     fn collect(&self) -> Vec<MetricFamily> {
         vec![self.v.collect()]
     }
+
+    fn visit(&self, visitor: &mut dyn MetricVisitor) {
+        self.v.visit(visitor)
+    }
+
+    fn collect_stale(&self) -> Vec<MetricFamily> {
+        self.v.collect_stale().into_iter().collect()
+    }
+
+    fn metric_type(&self, _: &Desc) -> Option<MetricType> {
+        Some(self.v.metric_type)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
 
     use crate::counter::CounterVec;
     use crate::gauge::GaugeVec;
-    use crate::metrics::{Metric, Opts};
+    use crate::metrics::{Collector, Labels, Metric, MetricValue, MetricVisitor, Opts};
+    use crate::proto::MetricType;
+    use crate::vec::STALE_MARKER_BITS;
+
+    #[test]
+    fn test_vec_no_panic_on_cardinality_mismatch() {
+        let vec = CounterVec::new(
+            Opts::new("test_couter_vec", "test counter vec help"),
+            &["l1", "l2"],
+        )
+        .unwrap();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            vec.with_label_values(&["v1"]);
+        }));
+        assert!(res.is_err());
+
+        vec.set_panic_on_cardinality_mismatch(false);
+        vec.with_label_values(&["v1"]).inc();
+        vec.with_label_values(&["v1", "v2", "v3"]).inc();
+        let mut labels = HashMap::new();
+        labels.insert("l1", "v1");
+        vec.with(&labels).inc();
+        assert_eq!(vec.cardinality_mismatches(), 3);
+        // The detached metrics are not exported.
+        assert!(vec.collect()[0].get_metric().is_empty());
+
+        vec.with_label_values(&["v1", "v2"]).inc();
+        assert_eq!(vec.collect()[0].get_metric().len(), 1);
+        assert_eq!(vec.cardinality_mismatches(), 3);
+    }
+
+    #[test]
+    fn test_vec_label_names_interned() {
+        let vec = CounterVec::new(
+            Opts::new("test_couter_vec", "test counter vec help").const_label("c", "1"),
+            &["l1", "l2"],
+        )
+        .unwrap();
+        for i in 0..1000 {
+            vec.with_label_values(&[&i.to_string(), "v2"]).inc();
+        }
+
+        // All children share one copy of each label name.
+        struct NameVisitor(std::collections::HashSet<*const u8>);
+        impl MetricVisitor for NameVisitor {
+            fn visit_family(&mut self, _: &str, _: &str, _: MetricType) {}
+
+            fn visit_metric(&mut self, labels: Labels<'_>, _: MetricValue<'_>) {
+                assert!(matches!(labels, Labels::Interned(_)));
+                for (name, _) in labels.iter() {
+                    self.0.insert(name.as_ptr());
+                }
+            }
+        }
+        let mut visitor = NameVisitor(Default::default());
+        vec.visit(&mut visitor);
+        assert_eq!(visitor.0.len(), 3);
+    }
 
     #[test]
     fn test_counter_vec_with_labels() {
@@ -410,6 +795,128 @@ mod tests {
         assert!(vec.remove_label_values(&["v1", "v3"]).is_err());
     }
 
+    #[test]
+    fn test_replace_label_values() {
+        let vec = CounterVec::new(
+            Opts::new("test_replace_vec", "test counter vec help"),
+            &["l1", "l2"],
+        )
+        .unwrap();
+        let old = vec.with_label_values(&["v1", "v2"]);
+        old.inc_by(3.0);
+
+        let new = vec.replace_label_values(&["v1", "v2"]).unwrap();
+        assert_eq!(new.get(), 0.0);
+        old.inc();
+        new.inc();
+        assert_eq!(vec.with_label_values(&["v1", "v2"]).get(), 1.0);
+        assert!(vec.replace_label_values(&["v1"]).is_err());
+
+        // A concurrent collection always sees the child.
+        let stop = Arc::new(AtomicBool::new(false));
+        let collector = {
+            let vec = vec.clone();
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    assert_eq!(vec.collect()[0].get_metric().len(), 1);
+                }
+            })
+        };
+        for _ in 0..1000 {
+            vec.replace_label_values(&["v1", "v2"]).unwrap().inc();
+        }
+        stop.store(true, Ordering::Relaxed);
+        collector.join().unwrap();
+        assert_eq!(vec.with_label_values(&["v1", "v2"]).get(), 1.0);
+    }
+
+    #[test]
+    fn test_label_values_hash() {
+        let vec = CounterVec::new(
+            Opts::new("test_hash_vec", "test counter vec help"),
+            &["l1", "l2"],
+        )
+        .unwrap();
+        let other = CounterVec::new(
+            Opts::new("test_other_hash_vec", "test counter vec help"),
+            &["l3", "l4"],
+        )
+        .unwrap();
+
+        let h = vec.label_values_hash(&["v1", "v2"]).unwrap();
+        assert_eq!(vec.label_values_hash(&["v1", "v2"]).unwrap(), h);
+        assert_eq!(other.label_values_hash(&["v1", "v2"]).unwrap(), h);
+        assert_ne!(vec.label_values_hash(&["v2", "v1"]).unwrap(), h);
+        assert!(vec.label_values_hash(&["v1"]).is_err());
+
+        vec.with_label_values(&["v1", "v2"]).inc();
+        assert!(vec.v.children.read().contains_key(&h));
+    }
+
+    #[test]
+    fn test_label_set() {
+        let vec = CounterVec::new(
+            Opts::new("test_counter_vec", "test counter vec help"),
+            &["l1", "l2"],
+        )
+        .unwrap();
+
+        // Unknown label.
+        let mut labels = vec.label_set();
+        let err = labels.set("l3", "v3").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("label name l3 is not a variable label"));
+
+        // Missing label.
+        labels.set("l1", "v1").unwrap();
+        let err = vec.label_set().build().unwrap_err();
+        assert!(err.to_string().contains("[\"l1\", \"l2\"]"));
+        let err = labels.build().unwrap_err();
+        assert!(err.to_string().contains("[\"l2\"]"));
+
+        let mut labels = vec.label_set();
+        labels.set("l2", "v1").unwrap();
+        labels.set("l1", "v1").unwrap();
+        labels.set("l2", "v2").unwrap();
+        let labels = labels.build().unwrap();
+        assert_eq!(labels.len(), 2);
+        vec.with(&labels).inc();
+        assert_eq!(vec.with_label_values(&["v1", "v2"]).get() as u64, 1);
+    }
+
+    #[test]
+    fn test_clone_with_const_label() {
+        let vec = CounterVec::new(
+            Opts::new("test_vec", "test counter vec help").const_label("a", "1"),
+            &["l1"],
+        )
+        .unwrap();
+
+        assert!(vec.clone_with_const_label("a", "2").is_err());
+        assert!(vec.clone_with_const_label("l1", "2").is_err());
+
+        let shard_0 = vec.clone_with_const_label("shard", "0").unwrap();
+        let shard_1 = vec.clone_with_const_label("shard", "1").unwrap();
+        assert_ne!(shard_0.desc()[0].id, shard_1.desc()[0].id);
+        assert_eq!(shard_0.desc()[0].variable_labels, vec!["l1".to_owned()]);
+
+        shard_0.with_label_values(&["v1"]).inc();
+        shard_1.with_label_values(&["v1"]).inc_by(2.0);
+        assert_eq!(shard_0.with_label_values(&["v1"]).get() as u64, 1);
+        assert_eq!(shard_1.with_label_values(&["v1"]).get() as u64, 2);
+        assert_eq!(vec.with_label_values(&["v1"]).get() as u64, 0);
+
+        let m = shard_1.with_label_values(&["v1"]).metric();
+        let label_pairs: Vec<_> = m
+            .get_label()
+            .iter()
+            .map(|lp| (lp.get_name(), lp.get_value()))
+            .collect();
+        assert_eq!(label_pairs, vec![("a", "1"), ("l1", "v1"), ("shard", "1")]);
+    }
+
     #[test]
     fn test_vec_get_metric_with() {
         let vec = CounterVec::new(
@@ -431,4 +938,49 @@ mod tests {
             assert_eq!(lp.get_value(), labels[lp.get_name()]);
         }
     }
+
+    #[test]
+    fn test_counter_vec_remove_label_values_stale() {
+        let vec = CounterVec::new(Opts::new("test_counter_vec", "test help"), &["l"]).unwrap();
+        vec.with_label_values(&["a"]).inc();
+        vec.with_label_values(&["b"]).inc();
+
+        let r = crate::Registry::new();
+        r.register(Box::new(vec.clone())).unwrap();
+
+        vec.remove_label_values_stale(&["a"]).unwrap();
+        assert!(vec.remove_label_values_stale(&["a"]).is_err());
+
+        // Plain collecting neither emits nor clears the marker.
+        assert_eq!(vec.collect()[0].get_metric().len(), 1);
+        assert_eq!(r.gather()[0].get_metric().len(), 1);
+        r.lint();
+
+        // The removed series is emitted once with the stale marker.
+        let mf = r.gather_with_stale().pop().unwrap();
+        assert_eq!(mf.get_metric().len(), 2);
+        let stale: Vec<_> = mf
+            .get_metric()
+            .iter()
+            .filter(|m| m.get_counter().get_value().to_bits() == STALE_MARKER_BITS)
+            .collect();
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].get_label()[0].get_value(), "a");
+        assert_eq!(r.gather_with_stale()[0].get_metric().len(), 1);
+
+        // No marker is emitted for a series created again.
+        vec.remove_label_values_stale(&["b"]).unwrap();
+        vec.with_label_values(&["b"]);
+        let mf = r.gather_with_stale().pop().unwrap();
+        assert_eq!(mf.get_metric().len(), 1);
+        assert_eq!(mf.get_metric()[0].get_counter().get_value(), 0.0);
+
+        let histogram_vec = crate::histogram::HistogramVec::new(
+            crate::histogram::HistogramOpts::new("test_histogram_vec", "test help"),
+            &["l"],
+        )
+        .unwrap();
+        histogram_vec.with_label_values(&["a"]);
+        assert!(histogram_vec.remove_label_values_stale(&["a"]).is_err());
+    }
 }
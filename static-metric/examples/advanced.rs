@@ -41,7 +41,6 @@ lazy_static! {
 /// 1. using alternative metric types (i.e. IntCounter)
 /// 2. specifying different label order compared to the definition
 /// 3. using non-identifiers as values
-
 fn main() {
     HTTP_COUNTER.post.http1.foo.inc_by(4);
     assert_eq!(
@@ -5,6 +5,7 @@
 Use metric enums to reuse possible values of a label.
 
 */
+#![allow(clippy::disallowed_names)]
 
 use prometheus::*;
 
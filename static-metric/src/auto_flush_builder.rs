@@ -445,6 +445,7 @@ impl<'a> MetricBuilderContext<'a> {
         let body = self.build_inner_impl_from_body(&prev_labels_ident);
 
         quote! {
+            #[allow(clippy::too_many_arguments)]
             pub fn from(
                 #(
                     #prev_labels_ident: &str,
@@ -714,7 +715,6 @@ impl<'a> MetricBuilderContext<'a> {
                         })#local_suffix_call,
                     }
                 } else {
-                    let prev_labels_ident = prev_labels_ident;
                     quote! {
                         #name: #member_type::from(
                             #(
@@ -0,0 +1,26 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+// The default registry is process-wide, so installing a custom one is tested
+// in its own test binary, where nothing used the default registry before.
+
+use prometheus::{default_registry, register, set_default_registry, IntCounter, Registry};
+
+#[test]
+fn test_set_default_registry() {
+    let registry = Registry::new_custom(Some("embedded".to_owned()), None).unwrap();
+    set_default_registry(registry.clone()).unwrap();
+    assert!(set_default_registry(Registry::new()).is_err());
+
+    let counter = IntCounter::new("requests", "Number of requests.").unwrap();
+    counter.inc();
+    register(Box::new(counter)).unwrap();
+
+    let mfs = registry.gather();
+    assert_eq!(mfs.len(), 1);
+    assert_eq!(mfs[0].get_name(), "embedded_requests");
+    assert_eq!(prometheus::gather(), mfs);
+    assert_eq!(default_registry().gather(), mfs);
+
+    // Once the default registry is in use, it can not be replaced.
+    assert!(set_default_registry(Registry::new()).is_err());
+}
@@ -0,0 +1,36 @@
+// Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
+
+// The jemalloc statistics are only meaningful with jemalloc as the global
+// allocator, which is installed in this test binary only.
+
+#![cfg(not(target_env = "msvc"))]
+
+use prometheus::core::Collector;
+use prometheus::jemalloc_collector::JemallocCollector;
+use prometheus::Registry;
+
+#[global_allocator]
+static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[test]
+fn test_jemalloc_collector() {
+    let jc = JemallocCollector::new("");
+    let _buf = vec![0u8; 1024 * 1024];
+
+    let mfs = jc.collect();
+    assert_eq!(mfs.len(), jc.desc().len());
+    for mf in &mfs {
+        let value = mf.get_metric()[0].get_gauge().get_value();
+        if mf.get_name() != "jemalloc_retained_bytes" {
+            assert!(value > 0.0, "{} is {}", mf.get_name(), value);
+        }
+    }
+    let allocated = mfs
+        .iter()
+        .find(|mf| mf.get_name() == "jemalloc_allocated_bytes")
+        .unwrap();
+    assert!(allocated.get_metric()[0].get_gauge().get_value() >= 1024.0 * 1024.0);
+
+    let r = Registry::new();
+    r.register(Box::new(jc)).unwrap();
+}